@@ -0,0 +1,48 @@
+// These benchmarks measure the multi-threaded insert throughput of a sharded
+// ARCache as the number of shards grows. Each thread performs a write transaction
+// per insert, so with a single shard all threads serialise on one writer.
+
+extern crate concread;
+extern crate criterion;
+extern crate crossbeam_utils;
+
+use concread::arcache::sharded::ShardedARCache;
+use concread::arcache::ARCache;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use crossbeam_utils::thread::scope;
+
+const THREADS: usize = 4;
+const INSERTS_PER_THREAD: usize = 1024;
+const CAPACITY: usize = THREADS * INSERTS_PER_THREAD;
+
+pub fn sharded_insert_scaling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sharded_insert_scaling");
+    for shards in [1, 2, 4, 8, 16].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(shards), shards, |b, &shards| {
+            b.iter_batched(
+                || ARCache::new_sharded(shards, CAPACITY),
+                |cache| insert_parallel(&cache),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn insert_parallel(cache: &ShardedARCache<usize, usize>) {
+    scope(|scope| {
+        for t in 0..THREADS {
+            scope.spawn(move |_| {
+                for k in (t * INSERTS_PER_THREAD)..((t + 1) * INSERTS_PER_THREAD) {
+                    let mut wr_txn = cache.write(&k);
+                    wr_txn.insert(k, k);
+                    wr_txn.commit();
+                }
+            });
+        }
+    })
+    .unwrap();
+}
+
+criterion_group!(sharded, sharded_insert_scaling);
+criterion_main!(sharded);
@@ -0,0 +1,189 @@
+// These benchmarks measure the cost of a write that must copy every leaf of
+// the tree, because a reader still holds the previous version. Each leaf copy
+// clones all of its keys, so the benchmarks compare large keys that are deep
+// copied (String) with those that implement CheapClone (Arc<str>). A wrapper of
+// Arc<str> that does not implement CheapClone is copied at the same cost, as the
+// trait is only a marker.
+//
+// The bulk insert benchmarks compare a large insert into an empty tree with and
+// without a prior call to reserve.
+//
+// The append benchmarks compare appending a sorted sequence of keys with an
+// AppendCursor to repeated calls to insert.
+//
+// The range scan benchmarks compare repeated short range queries that each
+// allocate their position with those that reuse a RangeScratch.
+
+extern crate concread;
+extern crate criterion;
+
+use concread::bptree::iter::RangeScratch;
+use concread::bptree::*;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::sync::Arc;
+
+const KEY_COUNT: usize = 1024;
+const KEY_LEN: usize = 256;
+const BULK_COUNT: usize = 65536;
+const SCAN_COUNT: usize = 1024;
+const SCAN_LEN: usize = 16;
+
+pub fn leaf_clone_string_keys(c: &mut Criterion) {
+    c.bench_function("leaf_clone_string_keys", |b| {
+        b.iter_batched(
+            || prepare(|s| s),
+            |(map, keys)| touch_all(&map, &keys),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+pub fn leaf_clone_arc_str_keys(c: &mut Criterion) {
+    c.bench_function("leaf_clone_arc_str_keys", |b| {
+        b.iter_batched(
+            || prepare(|s| Arc::<str>::from(s.as_str())),
+            |(map, keys)| touch_all(&map, &keys),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+pub fn leaf_clone_unmarked_arc_str_keys(c: &mut Criterion) {
+    c.bench_function("leaf_clone_unmarked_arc_str_keys", |b| {
+        b.iter_batched(
+            || prepare(|s| Unmarked(Arc::from(s.as_str()))),
+            |(map, keys)| touch_all(&map, &keys),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+// An Arc<str> key without the CheapClone marker.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Unmarked(Arc<str>);
+
+fn prepare<K, F>(f: F) -> (BptreeMap<K, usize>, Vec<K>)
+where
+    K: Ord + Clone + std::fmt::Debug + Sync + Send + 'static,
+    F: Fn(String) -> K,
+{
+    let keys: Vec<K> = (0..KEY_COUNT)
+        .map(|i| f(format!("{:0width$}", i, width = KEY_LEN)))
+        .collect();
+    let map = BptreeMap::new();
+    let mut wr = map.write();
+    wr.extend(keys.iter().cloned().map(|k| (k, 0)));
+    wr.commit();
+    (map, keys)
+}
+
+fn touch_all<K>(map: &BptreeMap<K, usize>, keys: &[K])
+where
+    K: Ord + Clone + std::fmt::Debug + Sync + Send + 'static,
+{
+    // Hold a reader so that every leaf must be copied by the writer.
+    let _rd = map.read();
+    let mut wr = map.write();
+    for k in keys {
+        if let Some(v) = wr.get_mut(k) {
+            *v += 1;
+        }
+    }
+    wr.commit();
+}
+
+pub fn bulk_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bulk_insert");
+    for reserve in [false, true].iter() {
+        let name = if *reserve { "reserve" } else { "no_reserve" };
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                BptreeMap::<usize, usize>::new,
+                |map| {
+                    let mut wr = map.write();
+                    if *reserve {
+                        wr.reserve(BULK_COUNT);
+                    }
+                    for k in 0..BULK_COUNT {
+                        wr.insert(k, k);
+                    }
+                    wr.commit();
+                    map
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+pub fn sorted_append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sorted_append");
+    for cursor in [false, true].iter() {
+        let name = if *cursor { "append_cursor" } else { "insert" };
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                BptreeMap::<usize, usize>::new,
+                |map| {
+                    let mut wr = map.write();
+                    if *cursor {
+                        let mut append = wr.append_cursor();
+                        for k in 0..BULK_COUNT {
+                            append.push(k, k);
+                        }
+                    } else {
+                        for k in 0..BULK_COUNT {
+                            wr.insert(k, k);
+                        }
+                    }
+                    wr.commit();
+                    map
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+pub fn range_scan(c: &mut Criterion) {
+    let map = BptreeMap::new();
+    let mut wr = map.write();
+    wr.extend((0..BULK_COUNT).map(|k| (k, k)));
+    wr.commit();
+
+    let mut group = c.benchmark_group("range_scan");
+    for reuse in [false, true].iter() {
+        let name = if *reuse { "range_into" } else { "range" };
+        group.bench_function(name, |b| {
+            let rd = map.read();
+            let mut scratch = RangeScratch::new();
+            b.iter(|| {
+                let mut sum = 0;
+                for i in 0..SCAN_COUNT {
+                    let lo = (i * 61) % (BULK_COUNT - SCAN_LEN);
+                    let range = lo..(lo + SCAN_LEN);
+                    sum += if *reuse {
+                        rd.range_into(range, &mut scratch)
+                            .map(|(_, v)| *v)
+                            .sum::<usize>()
+                    } else {
+                        rd.range(range).map(|(_, v)| *v).sum::<usize>()
+                    };
+                }
+                sum
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    leaf_clone,
+    leaf_clone_string_keys,
+    leaf_clone_arc_str_keys,
+    leaf_clone_unmarked_arc_str_keys
+);
+criterion_group!(bulk, bulk_insert, sorted_append);
+criterion_group!(scan, range_scan);
+criterion_main!(leaf_clone, bulk, scan);
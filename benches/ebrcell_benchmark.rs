@@ -0,0 +1,62 @@
+// These benchmarks measure the read throughput of an EbrCell. A new read transaction
+// for each access pins the epoch every time, where a pinned reader pins it once and
+// can be refreshed to observe new values. A local reader only repins the epoch on a
+// refresh if the value has changed.
+
+extern crate concread;
+extern crate criterion;
+
+use concread::ebrcell::EbrCell;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const READS: usize = 1024;
+
+pub fn ebrcell_read(c: &mut Criterion) {
+    let cell = EbrCell::new(1usize);
+    let mut group = c.benchmark_group("ebrcell_read");
+    group.bench_function("read", |b| {
+        b.iter(|| {
+            let mut sum = 0;
+            for _ in 0..READS {
+                sum += *cell.read();
+            }
+            sum
+        })
+    });
+    group.bench_function("read_pinned_refresh", |b| {
+        b.iter(|| {
+            let mut rd = cell.read_pinned();
+            let mut sum = 0;
+            for _ in 0..READS {
+                rd.refresh();
+                sum += *rd.current();
+            }
+            sum
+        })
+    });
+    group.bench_function("local_reader_refresh", |b| {
+        b.iter(|| {
+            let mut rd = cell.local_reader();
+            let mut sum = 0;
+            for _ in 0..READS {
+                rd.refresh();
+                sum += *rd.current();
+            }
+            sum
+        })
+    });
+    group.bench_function("read_pinned", |b| {
+        b.iter(|| {
+            let rd = cell.read_pinned();
+            let mut sum = 0;
+            for _ in 0..READS {
+                sum += *rd.current();
+            }
+            sum
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(ebrcell, ebrcell_read);
+criterion_main!(ebrcell);
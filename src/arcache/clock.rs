@@ -0,0 +1,75 @@
+//! The source of time of a cache, used for aging and access tracking. See
+//! `ARCacheBuilder::with_clock`.
+
+use parking_lot::Mutex;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A monotonic source of time for a cache. Every time that the cache records or
+/// compares, such as the aging of hit counts and the time of the last access of an
+/// item, is read from its clock.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time. This must never be less than a time returned before.
+    fn now(&self) -> Instant;
+}
+
+/// The clock of a cache unless another is given, which reads `Instant::now`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when it is advanced, so that tests of aging and access
+/// times can be exact rather than sleeping. Clones share the same time, so one clone
+/// can be given to the cache and another kept to advance it.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    /// Create a clock, stopped at the current time.
+    pub fn new() -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Move the time of this clock, and of every clone of it, forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        *self.now.lock() += d;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ManualClock};
+    use std::time::Duration;
+
+    #[test]
+    fn test_cache_manual_clock() {
+        let clock = ManualClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+        assert!(clock.now() == start);
+        shared.advance(Duration::from_secs(5));
+        assert!(clock.now() == start + Duration::from_secs(5));
+    }
+}
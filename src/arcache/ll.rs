@@ -11,6 +11,11 @@ where
     head: *mut LLNode<K>,
     tail: *mut LLNode<K>,
     size: usize,
+    // The sum of the weights of all nodes in the set.
+    weight: usize,
+    // Incremented when a node is removed, or a node other than the newest is
+    // reweighted. A position found by walking the set remains valid until it changes.
+    changes: usize,
     // tag: usize,
 }
 
@@ -22,6 +27,7 @@ where
     pub(crate) k: MaybeUninit<K>,
     next: *mut LLNode<K>,
     prev: *mut LLNode<K>,
+    weight: usize,
     // tag: usize,
 }
 
@@ -47,6 +53,8 @@ where
             head,
             tail,
             size: 0,
+            weight: 0,
+            changes: 0,
             // tag,
         }
     }
@@ -60,6 +68,15 @@ where
         }
     }
 
+    // Iterate over the node pointers of this set, from head (oldest) to tail.
+    pub(crate) fn iter_nodes(&self) -> impl Iterator<Item = *mut LLNode<K>> {
+        let tail = self.tail;
+        std::iter::successors(Some(unsafe { (*self.head).next }), |n| {
+            Some(unsafe { (**n).next })
+        })
+        .take_while(move |n| *n != tail)
+    }
+
     // Append a k to the set, and return it's pointer.
     pub(crate) fn append_k(&mut self, k: K) -> *mut LLNode<K> {
         let n = LLNode::new(k);
@@ -71,6 +88,7 @@ where
     pub(crate) fn append_n(&mut self, n: *mut LLNode<K>) {
         // Who is to the left of tail?
         self.size += 1;
+        self.weight += unsafe { (*n).weight };
         unsafe {
             // must be untagged
             // assert!((*n).tag == 0);
@@ -134,6 +152,8 @@ where
         }
 
         self.size -= 1;
+        self.weight -= unsafe { (*n).weight };
+        self.changes += 1;
         unsafe {
             let prev = (*n).prev;
             let next = (*n).next;
@@ -153,6 +173,46 @@ where
         self.size
     }
 
+    pub(crate) fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub(crate) fn changes(&self) -> usize {
+        self.changes
+    }
+
+    // The oldest node of this set.
+    pub(crate) fn head_node(&self) -> Option<*mut LLNode<K>> {
+        let next = unsafe { (*self.head).next };
+        if next == self.tail {
+            None
+        } else {
+            Some(next)
+        }
+    }
+
+    // The node after n, toward the tail, of this set.
+    pub(crate) fn next_node(&self, n: *mut LLNode<K>) -> Option<*mut LLNode<K>> {
+        let next = unsafe { (*n).next };
+        if next == self.tail {
+            None
+        } else {
+            Some(next)
+        }
+    }
+
+    // Change the weight of a node that is a member of this set.
+    pub(crate) fn set_weight(&mut self, n: *mut LLNode<K>, weight: usize) {
+        debug_assert!(self.size > 0);
+        unsafe {
+            if n != (*self.tail).prev && (*n).weight != weight {
+                self.changes += 1;
+            }
+            self.weight = self.weight - (*n).weight + weight;
+            (*n).weight = weight;
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn peek_head(&self) -> Option<&K> {
         debug_assert!(!self.head.is_null());
@@ -213,12 +273,14 @@ where
             k: MaybeUninit::uninit(),
             next: ptr::null_mut(),
             prev: ptr::null_mut(),
+            weight: 0,
             // tag: 0,
         }));
         let tail = Box::into_raw(Box::new(LLNode {
             k: MaybeUninit::uninit(),
             next: ptr::null_mut(),
             prev: head,
+            weight: 0,
             // tag: 0,
         }));
         unsafe {
@@ -236,11 +298,17 @@ where
             k: MaybeUninit::new(k),
             next: ptr::null_mut(),
             prev: ptr::null_mut(),
+            weight: 1,
             // tag,
         });
         Box::into_raw(b)
     }
 
+    #[inline]
+    pub(crate) fn weight(&self) -> usize {
+        self.weight
+    }
+
     #[inline]
     fn free(v: *mut Self) {
         debug_assert!(!v.is_null());
@@ -327,6 +395,11 @@ mod tests {
         assert!(ll.peek_head().unwrap().as_ref() == &3);
         assert!(ll.peek_tail().unwrap().as_ref() == &1);
 
+        // Reweight a node.
+        assert!(ll.weight() == 4);
+        ll.set_weight(n2, 3);
+        assert!(ll.weight() == 6);
+
         // pop from head
         let _n3 = ll.pop();
         assert!(ll.len() == 3);
@@ -336,6 +409,7 @@ mod tests {
         // cut a node out from any (head, mid, tail)
         ll.extract(n2);
         assert!(ll.len() == 2);
+        assert!(ll.weight() == 2);
         assert!(ll.peek_head().unwrap().as_ref() == &4);
         assert!(ll.peek_tail().unwrap().as_ref() == &1);
 
@@ -361,4 +435,26 @@ mod tests {
         ll.append_n(n3);
         ll.append_n(n4);
     }
+
+    #[test]
+    fn test_cache_arc_ll_changes() {
+        let mut ll: LL<Box<usize>> = LL::new();
+        assert!(ll.head_node().is_none());
+        let n1 = ll.append_k(Box::new(1));
+        let n2 = ll.append_k(Box::new(2));
+        let walk: Vec<_> = std::iter::successors(ll.head_node(), |n| ll.next_node(*n)).collect();
+        assert!(walk == [n1, n2]);
+
+        // Appending and reweighting the newest node leave a walked position valid.
+        let changes = ll.changes();
+        let n3 = ll.append_k(Box::new(3));
+        ll.set_weight(n3, 2);
+        ll.set_weight(n1, 1);
+        assert!(ll.changes() == changes);
+        ll.set_weight(n1, 2);
+        assert!(ll.changes() == changes + 1);
+        ll.touch(n1);
+        assert!(ll.changes() > changes + 1);
+        assert!(ll.head_node() == Some(n2) && ll.next_node(n1).is_none());
+    }
 }
@@ -0,0 +1,336 @@
+//! Read-through loading of missing items from an async source. See
+//! `ARCache::get_or_load_async`.
+
+use super::ARCache;
+use parking_lot::Mutex;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::Hash;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+pub(crate) enum LoadState<V> {
+    // The load is in progress, and these tasks are waiting on it.
+    Pending(Vec<Waker>),
+    Ready(Option<V>),
+    // The loading future was dropped before it completed.
+    Abandoned,
+}
+
+pub(crate) type LoadSlot<V> = Arc<Mutex<LoadState<V>>>;
+
+enum Stage<V> {
+    Start,
+    Leader(LoadSlot<V>),
+    Waiter(LoadSlot<V>),
+    Done,
+}
+
+/// The future returned by `ARCache::get_or_load_async`, which resolves to the value
+/// of the key, or `None` if it was not in the cache and could not be loaded.
+pub struct GetOrLoad<'a, K, V, F>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+    F: Future<Output = Option<V>>,
+{
+    cache: &'a ARCache<K, V>,
+    k: K,
+    loader: Option<Pin<Box<F>>>,
+    stage: Stage<V>,
+}
+
+// The key is never pinned, and the loader is boxed, so this may be moved freely.
+impl<'a, K, V, F> Unpin for GetOrLoad<'a, K, V, F>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+    F: Future<Output = Option<V>>,
+{
+}
+
+impl<'a, K, V, F> GetOrLoad<'a, K, V, F>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+    F: Future<Output = Option<V>>,
+{
+    pub(crate) fn new(cache: &'a ARCache<K, V>, k: K, loader: F) -> Self {
+        GetOrLoad {
+            cache,
+            k,
+            loader: Some(Box::pin(loader)),
+            stage: Stage::Start,
+        }
+    }
+
+    // Release the slot of this key, waking every task that waits on it.
+    fn release(&mut self, slot: &LoadSlot<V>, state: LoadState<V>) {
+        {
+            let mut loads = self.cache.loads.lock();
+            if loads.get(&self.k).map(|s| Arc::ptr_eq(s, slot)) == Some(true) {
+                loads.remove(&self.k);
+            }
+        }
+        if let LoadState::Pending(wakers) = mem::replace(&mut *slot.lock(), state) {
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+impl<'a, K, V, F> Future for GetOrLoad<'a, K, V, F>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+    F: Future<Output = Option<V>>,
+{
+    type Output = Option<V>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<V>> {
+        let this = self.get_mut();
+        loop {
+            match &this.stage {
+                Stage::Start => {
+                    // The cache is checked while the loads are locked, as a leader only
+                    // releases its slot once the value is committed to the cache.
+                    let mut loads = this.cache.loads.lock();
+                    if let Some(slot) = loads.get(&this.k) {
+                        this.stage = Stage::Waiter(slot.clone());
+                        continue;
+                    }
+                    if let Some(v) = this.cache.read().get(&this.k) {
+                        this.stage = Stage::Done;
+                        return Poll::Ready(Some(v.clone()));
+                    }
+                    let slot = Arc::new(Mutex::new(LoadState::Pending(Vec::new())));
+                    loads.insert(this.k.clone(), slot.clone());
+                    this.stage = Stage::Leader(slot);
+                }
+                Stage::Leader(slot) => {
+                    let slot = slot.clone();
+                    let loader = this.loader.as_mut().expect("The loader has completed");
+                    let r = match loader.as_mut().poll(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(r) => r,
+                    };
+                    this.loader = None;
+                    this.stage = Stage::Done;
+                    // Include the value before waking the waiters, so that they and any
+                    // later reader will find it.
+                    if let Some(v) = &r {
+                        let mut wr_txn = this.cache.write();
+                        wr_txn.insert(this.k.clone(), v.clone());
+                        wr_txn.commit();
+                    }
+                    this.release(&slot, LoadState::Ready(r.clone()));
+                    return Poll::Ready(r);
+                }
+                Stage::Waiter(slot) => {
+                    let slot = slot.clone();
+                    let mut state = slot.lock();
+                    match &mut *state {
+                        LoadState::Pending(wakers) => {
+                            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                                wakers.push(cx.waker().clone());
+                            }
+                            return Poll::Pending;
+                        }
+                        LoadState::Ready(r) => {
+                            let r = r.clone();
+                            this.stage = Stage::Done;
+                            return Poll::Ready(r);
+                        }
+                        LoadState::Abandoned => {
+                            // The leader was dropped, so start again, which may make this
+                            // the leader with its own loader.
+                            this.stage = Stage::Start;
+                        }
+                    }
+                }
+                Stage::Done => panic!("GetOrLoad polled after completion"),
+            }
+        }
+    }
+}
+
+impl<'a, K, V, F> Drop for GetOrLoad<'a, K, V, F>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+    F: Future<Output = Option<V>>,
+{
+    fn drop(&mut self) {
+        // A leader that is dropped before it completes must not leave the waiters
+        // hanging.
+        if let Stage::Leader(slot) = mem::replace(&mut self.stage, Stage::Done) {
+            self.release(&slot, LoadState::Abandoned);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arcache::ARCache;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+
+    use crossbeam_utils::thread::scope;
+
+    // A minimal executor that parks the thread until the future is woken.
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let mut f = Box::pin(f);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(r) => return r,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    // A loader that is pending until the gate is opened.
+    struct Gated<'a> {
+        gate: &'a AtomicBool,
+        wakers: &'a parking_lot::Mutex<Vec<Waker>>,
+        v: Option<usize>,
+    }
+
+    impl<'a> Future for Gated<'a> {
+        type Output = Option<usize>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<usize>> {
+            let mut wakers = self.wakers.lock();
+            if self.gate.load(Ordering::Acquire) {
+                Poll::Ready(self.v)
+            } else {
+                wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    // Counts each loader that is actually polled.
+    struct Counted<'a> {
+        loads: &'a AtomicUsize,
+        counted: bool,
+        inner: Gated<'a>,
+    }
+
+    impl<'a> Future for Counted<'a> {
+        type Output = Option<usize>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<usize>> {
+            if !self.counted {
+                self.counted = true;
+                self.loads.fetch_add(1, Ordering::SeqCst);
+            }
+            Pin::new(&mut self.inner).poll(cx)
+        }
+    }
+
+    fn async_loader<'a>(
+        loads: &'a AtomicUsize,
+        gate: &'a AtomicBool,
+        wakers: &'a parking_lot::Mutex<Vec<Waker>>,
+    ) -> Counted<'a> {
+        Counted {
+            loads,
+            counted: false,
+            inner: Gated {
+                gate,
+                wakers,
+                v: Some(10),
+            },
+        }
+    }
+
+    #[test]
+    fn test_cache_get_or_load_async_single_flight() {
+        let arc: ARCache<usize, usize> = ARCache::new_size(16, 0);
+        let gate = AtomicBool::new(false);
+        let wakers = parking_lot::Mutex::new(Vec::new());
+        let loads = AtomicUsize::new(0);
+        let started = AtomicUsize::new(0);
+
+        scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|_| {
+                        started.fetch_add(1, Ordering::SeqCst);
+                        block_on(arc.get_or_load_async(1, async_loader(&loads, &gate, &wakers)))
+                    })
+                })
+                .collect();
+            // Wait for every task to be waiting on the load before it completes.
+            while started.load(Ordering::SeqCst) < 4 || arc.loads.lock().is_empty() {
+                thread::yield_now();
+            }
+            thread::sleep(std::time::Duration::from_millis(50));
+            {
+                let mut wakers = wakers.lock();
+                gate.store(true, Ordering::Release);
+                wakers.drain(..).for_each(Waker::wake);
+            }
+            for h in handles {
+                assert!(h.join().unwrap() == Some(10));
+            }
+        })
+        .unwrap();
+
+        assert!(loads.load(Ordering::SeqCst) == 1);
+        assert!(arc.loads.lock().is_empty());
+        // The value was included, so later calls do not load.
+        assert!(arc.read().get(&1) == Some(&10));
+        assert!(
+            block_on(arc.get_or_load_async(1, async_loader(&loads, &gate, &wakers))) == Some(10)
+        );
+        assert!(loads.load(Ordering::SeqCst) == 1);
+    }
+
+    #[test]
+    fn test_cache_get_or_load_async_abandoned() {
+        let arc: ARCache<usize, usize> = ARCache::new_size(16, 0);
+        let gate = AtomicBool::new(false);
+        let wakers = parking_lot::Mutex::new(Vec::new());
+        let loads = AtomicUsize::new(0);
+
+        // The leader starts the load, then is dropped before it completes.
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut leader = arc.get_or_load_async(1, async_loader(&loads, &gate, &wakers));
+        assert!(Pin::new(&mut leader).poll(&mut cx).is_pending());
+        let mut waiter = arc.get_or_load_async(1, async_loader(&loads, &gate, &wakers));
+        assert!(Pin::new(&mut waiter).poll(&mut cx).is_pending());
+        assert!(loads.load(Ordering::SeqCst) == 1);
+        std::mem::drop(leader);
+
+        // The waiter takes over with its own loader.
+        gate.store(true, Ordering::Release);
+        assert!(block_on(waiter) == Some(10));
+        assert!(loads.load(Ordering::SeqCst) == 2);
+
+        // A failed load is not included.
+        let none = Gated {
+            gate: &gate,
+            wakers: &wakers,
+            v: None,
+        };
+        assert!(block_on(arc.get_or_load_async(2, none)).is_none());
+        assert!(arc.read().get(&2).is_none());
+    }
+}
@@ -10,14 +10,28 @@
 //! writers that are serialised. This formally means that this is an ACID
 //! compliant Cache.
 
+mod clock;
 mod ll;
+#[cfg(feature = "async")]
+mod load;
+pub mod sharded;
+mod sketch;
+
+pub use self::clock::{Clock, ManualClock, SystemClock};
+#[cfg(feature = "async")]
+pub use self::load::GetOrLoad;
 
 use self::ll::{LLNode, LL};
+#[cfg(feature = "async")]
+use self::load::LoadSlot;
+use self::sharded::ShardedARCache;
+use self::sketch::FrequencySketch;
 // use crate::collections::bptree::*;
 use crate::cowcell::{CowCell, CowCellReadTxn};
 use crate::hashmap::*;
 use crossbeam::channel::{unbounded, Receiver, Sender};
 use parking_lot::{Mutex, RwLock};
+use rand::RngCore;
 use std::collections::HashMap as Map;
 
 use std::borrow::Borrow;
@@ -25,10 +39,12 @@ use std::cell::UnsafeCell;
 use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::marker::PhantomData;
 use std::mem;
 use std::ops::Deref;
 use std::ops::DerefMut;
-use std::time::Instant;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 // const READ_THREAD_MIN: usize = 8;
 const READ_THREAD_RATIO: usize = 16;
@@ -62,6 +78,428 @@ pub struct CacheStats {
     pub all_seen_keys: usize,
 }
 
+/// A summary of the weights of the items resident in the cache, as returned by
+/// `ARCacheReadTxn::size_histogram`. Quantiles are the smallest weight that at least
+/// that fraction of items are no heavier than. If the cache is empty, every field is
+/// zero.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SizeHistogram {
+    /// The number of resident items.
+    pub count: usize,
+    /// The sum of the weights of all resident items.
+    pub total: usize,
+    /// The weight of the lightest item.
+    pub min: usize,
+    /// The weight of the heaviest item.
+    pub max: usize,
+    /// The mean weight of an item.
+    pub mean: f64,
+    /// The median weight.
+    pub p50: usize,
+    /// The 90th percentile weight.
+    pub p90: usize,
+    /// The 99th percentile weight.
+    pub p99: usize,
+}
+
+impl SizeHistogram {
+    fn from_weights(mut weights: Vec<usize>) -> Self {
+        if weights.is_empty() {
+            return SizeHistogram::default();
+        }
+        weights.sort_unstable();
+        let count = weights.len();
+        let total = weights.iter().sum();
+        // Nearest rank, so that each quantile is a weight that was observed.
+        let quantile = |q: f64| weights[((q * count as f64).ceil() as usize).max(1) - 1];
+        SizeHistogram {
+            count,
+            total,
+            min: weights[0],
+            max: weights[count - 1],
+            mean: total as f64 / count as f64,
+            p50: quantile(0.5),
+            p90: quantile(0.9),
+            p99: quantile(0.99),
+        }
+    }
+}
+
+/// The list of the cache that a key is tracked by, as reported by
+/// `ARCacheReadTxn::entry_state`. The names of the lists of the ARC paper are given in
+/// brackets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryList {
+    /// Resident, and not accessed since it was included (T1).
+    Recent,
+    /// Resident, and accessed since it was included (T2).
+    Frequent,
+    /// Evicted from the recent list. A miss on it grows the share of the recent list (B1).
+    GhostRecent,
+    /// Evicted from the frequent list. A miss on it grows the share of the frequent list
+    /// (B2).
+    GhostFrequent,
+    /// Removed, or forgotten by a ghost list, and remembered until no reader could
+    /// still observe an earlier value.
+    Haunted,
+}
+
+/// The tracked state of a key of the cache, as returned by
+/// `ARCacheReadTxn::entry_state`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryState {
+    /// The list that the key is in.
+    pub list: EntryList,
+    /// The number of hits of the key while it was resident, as aged by
+    /// `ARCacheBuilder::set_aging`. For a key that is no longer resident, this is the
+    /// count when it was evicted or removed.
+    pub count: usize,
+    /// The time the key was last accessed, if it is resident and the cache was built
+    /// with `ARCacheBuilder::set_track_access`.
+    pub last_access: Option<Instant>,
+}
+
+/// The replacement policy that an `ARCache` operates with. This is selected with
+/// `ARCacheBuilder::policy`, and defaults to `Policy::Arc`.
+///
+/// Not all fields of `CacheStats` are meaningful under each policy:
+///
+/// * `Arc` - all statistics are maintained.
+/// * `Lru` - all items are in the recent set, so `freq`, `freq_evicts` and
+///   `p_weight` are always zero.
+/// * `Lfu` - all items are in the frequent set, so `recent`, `recent_evicts` and
+///   `p_weight` are always zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Adaptive replacement, balancing between recently and frequently used items
+    /// with the assistance of ghost sets.
+    #[default]
+    Arc,
+    /// Least recently used. The least recently used item is evicted first, and no
+    /// ghost sets are maintained.
+    Lru,
+    /// Least frequently used. The item with the lowest number of hits is evicted first,
+    /// with ties broken by the least recently used. No ghost sets are maintained.
+    Lfu,
+}
+
+/// How an `ARCache` handles the insert of a value whose weight alone exceeds the capacity
+/// of the cache. This is selected with `ARCacheBuilder::on_oversize`, and defaults to
+/// `OversizePolicy::Reject`. To handle such a value on a single insert instead, see
+/// `ARCacheWriteTxn::try_insert`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// The value is not included. Any previous value of the key is removed from the
+    /// cache, so that it is not returned in place of the new value.
+    #[default]
+    Reject,
+    /// The value is included, and evicts every other resident item, so that it is the
+    /// sole resident of the cache. It is accounted as exactly filling the cache, so it
+    /// is evicted by the next item that is included.
+    Admit,
+    /// The insert panics.
+    Panic,
+}
+
+/// A builder for configuring an `ARCache`. You must provide the size of the cache, either
+/// with `set_size` or `set_expected_workload`, before calling `build`.
+///
+/// # Examples
+/// ```
+/// use concread::arcache::{ARCache, ARCacheBuilder, Policy};
+///
+/// let arc: ARCache<usize, usize> = ARCacheBuilder::new()
+///     .set_size(128, 0)
+///     .policy(Policy::Lru)
+///     .build()
+///     .expect("Invalid cache parameters");
+/// ```
+#[derive(Clone, Debug)]
+pub struct ARCacheBuilder<K, V> {
+    // (max, read_max)
+    size: Option<(usize, usize)>,
+    policy: Policy,
+    weigher: fn(&V) -> usize,
+    aging: Option<Duration>,
+    track_access: bool,
+    expired: Option<fn(&V) -> bool>,
+    eviction_budget: Option<usize>,
+    oversize: OversizePolicy,
+    admission: bool,
+    seeds: Option<Seeds>,
+    clock: Option<Arc<dyn Clock>>,
+    phantom_k: PhantomData<K>,
+}
+
+// The keys of the hashers of a cache, drawn from the source given to
+// ARCacheBuilder::with_rng.
+#[derive(Clone, Copy, Debug)]
+struct Seeds {
+    cache: u64,
+    local: [u64; 4],
+}
+
+// The map of the items included by a transaction, which are drained in the order of
+// this map on commit.
+type LocalMap<K, V> = Map<K, V, ahash::RandomState>;
+
+fn unit_weight<V>(_v: &V) -> usize {
+    1
+}
+
+fn weak_expired<T>(v: &Weak<T>) -> bool {
+    v.strong_count() == 0
+}
+
+impl<K, V> Default for ARCacheBuilder<K, V> {
+    fn default() -> Self {
+        ARCacheBuilder {
+            size: None,
+            policy: Policy::default(),
+            weigher: unit_weight,
+            aging: None,
+            track_access: false,
+            expired: None,
+            eviction_budget: None,
+            oversize: OversizePolicy::default(),
+            admission: false,
+            seeds: None,
+            clock: None,
+            phantom_k: PhantomData,
+        }
+    }
+}
+
+impl<K, V> ARCacheBuilder<K, V>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+{
+    /// Create a new builder with the default `Policy::Arc`, and no size.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Derive the size of the cache from the expected workload. See `ARCache::new` for
+    /// the meaning of these parameters.
+    pub fn set_expected_workload(
+        self,
+        total: usize,
+        threads: usize,
+        ex_ro_miss: usize,
+        ex_rw_miss: usize,
+        read_cache: bool,
+    ) -> Self {
+        let size = calc_size(total, threads, ex_ro_miss, ex_rw_miss, read_cache);
+        ARCacheBuilder {
+            size: Some(size),
+            ..self
+        }
+    }
+
+    /// Set the size of the main cache to `max` items, and each reader thread local
+    /// cache to `read_max` items. See `ARCache::new_size`.
+    pub fn set_size(self, max: usize, read_max: usize) -> Self {
+        ARCacheBuilder {
+            size: Some((max, read_max)),
+            ..self
+        }
+    }
+
+    /// Select the replacement policy of the cache.
+    pub fn policy(self, policy: Policy) -> Self {
+        ARCacheBuilder { policy, ..self }
+    }
+
+    /// Set the function used to determine the weight of a value. The sizes given to
+    /// the builder are then measured in units of this weight rather than in items. By
+    /// default every value has a weight of 1.
+    pub fn set_weigher(self, weigher: fn(&V) -> usize) -> Self {
+        ARCacheBuilder { weigher, ..self }
+    }
+
+    /// Age the hit counts of items, halving them once for each `halflife` that passes.
+    /// This allows items that were once popular but are no longer accessed to become
+    /// candidates for eviction. Aging is applied by the writer during commit, so the
+    /// counts are only updated as often as writes occur.
+    ///
+    /// Hit counts only determine eviction under `Policy::Lfu`.
+    pub fn set_aging(self, halflife: Duration) -> Self {
+        ARCacheBuilder {
+            aging: Some(halflife),
+            ..self
+        }
+    }
+
+    /// Record the time that each resident item was last accessed, which can then be
+    /// retrieved with `ARCacheReadTxn::last_access`. This is disabled by default, as
+    /// it adds work to every commit.
+    ///
+    /// Accesses are recorded by the writer during commit, with the time of the start of
+    /// the transaction that made the access.
+    pub fn set_track_access(self, track_access: bool) -> Self {
+        ARCacheBuilder {
+            track_access,
+            ..self
+        }
+    }
+
+    /// Limit each commit to evicting at most `n` resident items. When more items than
+    /// this must be evicted, the remainder are deferred to later commits, so the cost of
+    /// any single commit stays bounded after a large insert.
+    ///
+    /// This means the cache may transiently exceed its capacity, converging back to it
+    /// over the following commits. By default there is no limit.
+    pub fn set_eviction_budget(self, n: usize) -> Self {
+        ARCacheBuilder {
+            eviction_budget: Some(n),
+            ..self
+        }
+    }
+
+    /// Select how the insert of a value whose weight alone exceeds the capacity of the
+    /// cache is handled. See `OversizePolicy`.
+    pub fn on_oversize(self, oversize: OversizePolicy) -> Self {
+        ARCacheBuilder { oversize, ..self }
+    }
+
+    /// Draw the keys of the hashers of the cache from `rng`, rather than randomly. The
+    /// items included by a transaction are applied in the order of their hashes, and
+    /// the admission filter counts accesses by hash, so two caches built from sources
+    /// of the same seed make identical eviction choices under the same sequence of
+    /// operations. This is intended for reproducible tests.
+    ///
+    /// The cache draws from `rng` only once, as it is built, and makes no other use of
+    /// randomness. As with `HashMapBuilder::with_seed`, predictable keys leave the
+    /// cache open to hash flooding by an attacker who chooses its keys.
+    pub fn with_rng<R: RngCore>(self, mut rng: R) -> Self {
+        let seeds = Seeds {
+            cache: rng.next_u64(),
+            local: [
+                rng.next_u64(),
+                rng.next_u64(),
+                rng.next_u64(),
+                rng.next_u64(),
+            ],
+        };
+        ARCacheBuilder {
+            seeds: Some(seeds),
+            ..self
+        }
+    }
+
+    /// Filter the admission of new items by how often their keys are accessed. When
+    /// including an item would evict another, it is only included if its key is
+    /// estimated to have been accessed more often than that of the victim. Keys that
+    /// are accessed once, such as by a scan, then do not displace a frequently used set
+    /// of items. Items that replace a resident item are always included.
+    ///
+    /// Accesses are counted in a sketch of a fixed size, proportional to the capacity
+    /// of the cache, and the counts are halved periodically so that old accesses are
+    /// forgotten. The victim is the least recently used item of the set that the policy
+    /// would evict from first, so under `Policy::Lfu` it approximates the true victim.
+    pub fn with_admission_filter(self) -> Self {
+        ARCacheBuilder {
+            admission: true,
+            ..self
+        }
+    }
+
+    /// Read the time of the cache from `clock`, rather than from `SystemClock`. Aging
+    /// and the times of access are all measured by this clock, so a `ManualClock` lets
+    /// tests advance time exactly, rather than sleeping.
+    pub fn with_clock<C: Clock + 'static>(self, clock: C) -> Self {
+        ARCacheBuilder {
+            clock: Some(Arc::new(clock)),
+            ..self
+        }
+    }
+
+    /// Build the cache. If no size was provided, the size of the main cache is zero,
+    /// the aging halflife is zero, or the eviction budget is zero then `None` is returned.
+    pub fn build(self) -> Option<ARCache<K, V>> {
+        if self.aging == Some(Duration::from_secs(0)) || self.eviction_budget == Some(0) {
+            return None;
+        }
+        match self.size {
+            Some((max, read_max)) if max > 0 => Some(ARCache::init(
+                max,
+                read_max,
+                self.policy,
+                self.weigher,
+                self.aging,
+                self.track_access,
+                self.expired,
+                self.eviction_budget,
+                self.oversize,
+                self.admission,
+                self.seeds,
+                self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl<K, T> ARCacheBuilder<K, Weak<T>>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    T: Sync + Send + 'static,
+{
+    /// Treat values whose target has been dropped as absent. A `Weak` value does not
+    /// keep its target alive, so an item may expire while it is resident. Expired items
+    /// are a miss on `get`, and are removed from the cache during commit.
+    ///
+    /// So that a commit does not check every resident item, it only removes expired
+    /// items from the oldest end of each set, up to the eviction budget, and stops at
+    /// the first that has not expired. An item that expires while newer items are
+    /// ahead of it is removed once it becomes the oldest, or is evicted as normal.
+    pub fn weak_values(self) -> Self {
+        ARCacheBuilder {
+            expired: Some(weak_expired::<T>),
+            ..self
+        }
+    }
+}
+
+/// The error returned by `ARCacheWriteTxn::try_insert` when a value can never fit in
+/// the cache. The rejected key and value are handed back to the caller.
+#[derive(Debug)]
+pub struct InsertRejected<K, V> {
+    /// The key that was rejected.
+    pub k: K,
+    /// The value that was rejected.
+    pub v: V,
+}
+
+impl<K, V> InsertRejected<K, V> {
+    /// Consume the error, returning the rejected key and value.
+    pub fn into_inner(self) -> (K, V) {
+        (self.k, self.v)
+    }
+}
+
+fn calc_size(
+    total: usize,
+    threads: usize,
+    ex_ro_miss: usize,
+    ex_rw_miss: usize,
+    read_cache: bool,
+) -> (usize, usize) {
+    let total = isize::try_from(total).unwrap();
+    let threads = isize::try_from(threads).unwrap();
+    let ro_miss = isize::try_from(ex_ro_miss).unwrap();
+    let wr_miss = isize::try_from(ex_rw_miss).unwrap();
+    let ratio = isize::try_from(READ_THREAD_RATIO).unwrap();
+    // I'd like to thank wolfram alpha ... for this magic.
+    let max = -((ratio * ((ro_miss * threads) + wr_miss - total)) / (ratio + threads));
+    let read_max = if read_cache { max / ratio } else { 0 };
+
+    let max = usize::try_from(max).unwrap();
+    let read_max = usize::try_from(read_max).unwrap();
+    (max, read_max)
+}
+
 enum ThreadCacheItem<V> {
     Present(V, bool),
     Removed(bool),
@@ -79,6 +517,8 @@ where
 {
     k: K,
     txid: u64,
+    // Number of hits observed while resident.
+    count: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -145,6 +585,31 @@ where
     haunted: LL<CacheItemInner<K>>,
     rx: Receiver<CacheEvent<K, V>>,
     min_txid: u64,
+    policy: Policy,
+    // The hit count halflife, and when counts were last halved.
+    aging: Option<Duration>,
+    last_aged: Instant,
+    // The most resident items that a single commit may evict.
+    eviction_budget: usize,
+    // The access frequencies that new items are admitted by, if filtered.
+    admission: Option<FrequencySketch>,
+    // The victim found by the last admission, from which the next one may resume.
+    admit_cursor: Option<AdmitCursor<K>>,
+}
+
+// A position in the order of eviction, as found by admit. The weight before the node
+// is of the nodes that precede it in both sets. If it is in the second set, that
+// includes the first set as it weighed when the position was found.
+struct AdmitCursor<K>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+{
+    node: *mut LLNode<CacheItemInner<K>>,
+    in_second: bool,
+    before: usize,
+    first_freq: bool,
+    first_weight: usize,
+    changes: (usize, usize),
 }
 
 struct ArcShared<K, V>
@@ -171,12 +636,25 @@ where
     // Use a unified tree, allows simpler movement of items between the
     // cache types.
     cache: HashMap<K, CacheItem<K, V>>,
+    // The time each resident item was last accessed, if access times are tracked. This
+    // is kept apart from the items so caches that don't track access pay nothing. The
+    // time of an item is removed when it leaves residency, so the table is bounded by
+    // the resident items.
+    access: Option<HashMap<K, Instant>>,
     // This is normally only ever taken in "read" mode, so it's effectively
     // an uncontended barrier.
     shared: RwLock<ArcShared<K, V>>,
     // These are only taken during a quiesce
     inner: Mutex<ArcInner<K, V>>,
     stats: CowCell<CacheStats>,
+    weigher: fn(&V) -> usize,
+    expired: Option<fn(&V) -> bool>,
+    oversize: OversizePolicy,
+    local_seeds: Option<[u64; 4]>,
+    clock: Arc<dyn Clock>,
+    // The keys that are being loaded by get_or_load_async.
+    #[cfg(feature = "async")]
+    loads: Mutex<Map<K, LoadSlot<V>>>,
 }
 
 unsafe impl<
@@ -199,7 +677,7 @@ where
 {
     // cache of our missed items to send forward.
     // On drop we drain this to the channel
-    set: Map<K, *mut LLNode<(K, V)>>,
+    set: LocalMap<K, *mut LLNode<(K, V)>>,
     read_size: usize,
     tlru: LL<(K, V)>,
 }
@@ -215,6 +693,7 @@ where
     caller: &'a ARCache<K, V>,
     // ro_txn to cache
     cache: HashMapReadTxn<'a, K, CacheItem<K, V>>,
+    access: Option<HashMapReadTxn<'a, K, Instant>>,
     tlocal: Option<ReadCache<K, V>>,
     // tx channel to send forward events.
     tx: Sender<CacheEvent<K, V>>,
@@ -248,9 +727,11 @@ where
     cache: HashMapWriteTxn<'a, K, CacheItem<K, V>>,
     // Cache of missed items (w_ dirty/clean)
     // On COMMIT we drain this to the main cache
-    tlocal: Map<K, ThreadCacheItem<V>>,
+    tlocal: LocalMap<K, ThreadCacheItem<V>>,
     hit: UnsafeCell<Vec<u64>>,
     clear: UnsafeCell<bool>,
+    // The target that this commit shrinks the resident items to, if any.
+    shrink: Option<usize>,
 }
 
 /*
@@ -267,11 +748,21 @@ impl<
 {
     fn to_vref(&self) -> Option<&V> {
         match &self {
-            CacheItem::Freq(_, v) | CacheItem::Rec(_, v) => Some(&v),
+            CacheItem::Freq(_, v) | CacheItem::Rec(_, v) => Some(v),
             _ => None,
         }
     }
 
+    // Record an access of this item at t, if it is resident and access times are
+    // tracked, keeping the latest time seen.
+    fn touch(&self, k: &K, t: Instant, access: &mut Option<HashMapWriteTxn<K, Instant>>) {
+        if let (CacheItem::Freq(..) | CacheItem::Rec(..), Some(access)) = (self, access) {
+            if !matches!(access.get(k), Some(at) if *at >= t) {
+                access.insert(k.clone(), t);
+            }
+        }
+    }
+
     #[cfg(test)]
     fn to_state(&self) -> CacheState {
         match &self {
@@ -328,17 +819,51 @@ macro_rules! drain_ll_to_ghost {
     }};
 }
 
+macro_rules! drain_ll_to_haunted {
+    (
+        $cache:expr,
+        $ll:expr,
+        $haunted:expr,
+        $txid:expr
+    ) => {{
+        while $ll.len() > 0 {
+            let n = $ll.pop();
+            debug_assert!(!n.is_null());
+            $haunted.append_n(n);
+            unsafe {
+                // Set the item's evict txid.
+                (*n).as_mut().txid = $txid;
+            }
+            match $cache.get_mut(unsafe { &(*n).as_mut().k }) {
+                Some(ci) => {
+                    // Now change the state.
+                    *ci = CacheItem::Haunted(n);
+                }
+                None => {
+                    // Impossible state!
+                    unreachable!();
+                }
+            }
+        } // end while
+    }};
+}
+
 macro_rules! evict_to_len {
     (
         $cache:expr,
         $ll:expr,
         $to_ll:expr,
         $size:expr,
+        $budget:expr,
+        $evicts:expr,
+        $access:expr,
         $txid:expr
     ) => {{
-        debug_assert!($ll.len() >= $size);
+        debug_assert!($ll.weight() >= $size);
 
-        while $ll.len() > $size {
+        while $ll.weight() > $size && *$budget > 0 {
+            *$budget -= 1;
+            *$evicts += 1;
             let n = $ll.pop();
             debug_assert!(!n.is_null());
             let mut r = $cache.get_mut(unsafe { &(*n).as_mut().k });
@@ -376,6 +901,7 @@ macro_rules! evict_to_len {
                     unreachable!();
                 }
             }
+            Self::forget_access($access, unsafe { &(*n).as_ref().k });
         }
     }};
 }
@@ -386,11 +912,14 @@ macro_rules! evict_to_haunted_len {
         $ll:expr,
         $to_ll:expr,
         $size:expr,
+        $budget:expr,
+        $access:expr,
         $txid:expr
     ) => {{
-        debug_assert!($ll.len() >= $size);
+        debug_assert!($ll.weight() >= $size);
 
-        while $ll.len() > $size {
+        while $ll.weight() > $size && *$budget > 0 {
+            *$budget -= 1;
             let n = $ll.pop();
             debug_assert!(!n.is_null());
             $to_ll.append_n(n);
@@ -410,10 +939,236 @@ macro_rules! evict_to_haunted_len {
                     unreachable!();
                 }
             };
+            Self::forget_access($access, unsafe { &(*n).as_ref().k });
         }
     }};
 }
 
+impl<
+        K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+        V: Clone + Debug + Sync + Send + 'static,
+    > ArcInner<K, V>
+{
+    // Include a key that is not in the cache, into the set that the policy
+    // considers resident.
+    fn include_k(&mut self, k: K, txid: u64, v: V, w: usize) -> CacheItem<K, V> {
+        let cii = CacheItemInner { k, txid, count: 1 };
+        match self.policy {
+            Policy::Lfu => {
+                let llp = self.freq.append_k(cii);
+                self.freq.set_weight(llp, w);
+                CacheItem::Freq(llp, v)
+            }
+            Policy::Arc | Policy::Lru => {
+                let llp = self.rec.append_k(cii);
+                self.rec.set_weight(llp, w);
+                CacheItem::Rec(llp, v)
+            }
+        }
+    }
+
+    // Include a node that has been extracted from a non-resident set.
+    fn include_n(
+        &mut self,
+        llp: *mut LLNode<CacheItemInner<K>>,
+        v: V,
+        w: usize,
+    ) -> CacheItem<K, V> {
+        unsafe { (*llp).as_mut().count = 1 };
+        match self.policy {
+            Policy::Lfu => {
+                self.freq.append_n(llp);
+                self.freq.set_weight(llp, w);
+                CacheItem::Freq(llp, v)
+            }
+            Policy::Arc | Policy::Lru => {
+                self.rec.append_n(llp);
+                self.rec.set_weight(llp, w);
+                CacheItem::Rec(llp, v)
+            }
+        }
+    }
+
+    // A hit on an item in the recent set. Under ARC this promotes it to frequent.
+    fn hit_rec(&mut self, llp: *mut LLNode<CacheItemInner<K>>, v: V, w: usize) -> CacheItem<K, V> {
+        unsafe { (*llp).as_mut().count += 1 };
+        self.rec.set_weight(llp, w);
+        match self.policy {
+            Policy::Lru => {
+                self.rec.touch(llp);
+                CacheItem::Rec(llp, v)
+            }
+            Policy::Arc | Policy::Lfu => {
+                self.rec.extract(llp);
+                self.freq.append_n(llp);
+                CacheItem::Freq(llp, v)
+            }
+        }
+    }
+
+    // A hit on an item in the frequent set.
+    fn hit_freq(&mut self, llp: *mut LLNode<CacheItemInner<K>>, w: usize) {
+        unsafe { (*llp).as_mut().count += 1 };
+        self.freq.set_weight(llp, w);
+        self.freq.touch(llp);
+    }
+
+    // Count an access of an item, if new items are filtered by their frequency.
+    fn record_access(&mut self, k_hash: u64) {
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(k_hash);
+        }
+    }
+
+    // Record the access of an item that is to be included, and determine if the
+    // admission filter allows it. See `ARCacheBuilder::with_admission_filter`.
+    fn admit(
+        &mut self,
+        cache: &mut HashMapWriteTxn<K, CacheItem<K, V>>,
+        k: &K,
+        w: usize,
+        max: usize,
+    ) -> bool {
+        let k_hash = cache.prehash(k);
+        self.record_access(k_hash);
+        let sketch = match self.admission.as_ref() {
+            Some(sketch) => sketch,
+            None => return true,
+        };
+        // Replacing a resident item, or filling free space, evicts nothing.
+        let resident = !matches!(cache.get(k), None | Some(CacheItem::Haunted(_)));
+        let excess = (self.rec.weight() + self.freq.weight() + w).saturating_sub(max);
+        if resident || excess == 0 {
+            return true;
+        }
+        // Items included earlier in this commit are not yet evicted, so the victim is
+        // the item that the eviction of the whole excess reaches. The walk to it resumes
+        // from the last victim while neither set has changed ahead of it, so a commit
+        // of many admissions walks the order once.
+        let first_freq = match self.policy {
+            Policy::Arc => self.rec.weight() <= self.p,
+            Policy::Lfu => true,
+            Policy::Lru => false,
+        };
+        let (first, second) = if first_freq {
+            (&self.freq, &self.rec)
+        } else {
+            (&self.rec, &self.freq)
+        };
+        let changes = (first.changes(), second.changes());
+        let resume = self.admit_cursor.take().and_then(|c| {
+            // The first set may only have grown at its tail since.
+            let before = if c.in_second {
+                c.before + first.weight() - c.first_weight
+            } else {
+                c.before
+            };
+            if c.first_freq == first_freq && c.changes == changes && before < excess {
+                Some((Some(c.node), c.in_second, before))
+            } else {
+                None
+            }
+        });
+        let (mut node, mut in_second, mut before) =
+            resume.unwrap_or_else(|| match first.head_node() {
+                Some(n) => (Some(n), false, 0),
+                None => (second.head_node(), true, 0),
+            });
+        while let Some(n) = node {
+            let nw = unsafe { (*n).weight() };
+            if before + nw >= excess {
+                break;
+            }
+            before += nw;
+            node = if in_second {
+                second.next_node(n)
+            } else {
+                first.next_node(n).or_else(|| {
+                    in_second = true;
+                    second.head_node()
+                })
+            };
+        }
+        match node {
+            Some(n) => {
+                self.admit_cursor = Some(AdmitCursor {
+                    node: n,
+                    in_second,
+                    before,
+                    first_freq,
+                    first_weight: first.weight(),
+                    changes,
+                });
+                let victim_hash = cache.prehash(unsafe { &(*n).as_ref().k });
+                sketch.estimate(k_hash) > sketch.estimate(victim_hash)
+            }
+            None => true,
+        }
+    }
+
+    // Halve the hit counts of resident items for each halflife elapsed since they
+    // were last aged.
+    fn age(&mut self, now: Instant) {
+        let halflife = match self.aging {
+            Some(halflife) => halflife,
+            None => return,
+        };
+        let elapsed = now.saturating_duration_since(self.last_aged);
+        let halvings = elapsed.as_nanos() / halflife.as_nanos();
+        if halvings == 0 {
+            return;
+        }
+        // Shifting out every bit leaves a count of zero.
+        let shift = u32::try_from(halvings).unwrap_or(u32::MAX);
+        for n in self.freq.iter_nodes().chain(self.rec.iter_nodes()) {
+            unsafe {
+                let count = &mut (*n).as_mut().count;
+                *count = count.checked_shr(shift).unwrap_or(0);
+            }
+        }
+        // Carry the remainder, so that aging doesn't drift with commit timing.
+        let rem = elapsed.as_nanos() % halflife.as_nanos();
+        self.last_aged = now - Duration::from_nanos(rem as u64);
+    }
+
+    // The nodes of the resident items, in the order that evict would remove them if
+    // the capacity were reduced one item at a time. Except under LFU, which must sort
+    // by count, the order is walked only as far as it is consumed.
+    fn eviction_order(&self) -> Box<dyn Iterator<Item = *mut LLNode<CacheItemInner<K>>> + '_> {
+        match self.policy {
+            Policy::Lru => Box::new(self.rec.iter_nodes()),
+            Policy::Lfu => {
+                // As in evict_lfu, a stable sort keeps the recency order within a count.
+                let mut nodes: Vec<_> = self.freq.iter_nodes().collect();
+                nodes.sort_by_key(|n| unsafe { (**n).as_ref().count });
+                Box::new(nodes.into_iter())
+            }
+            Policy::Arc => {
+                // Recent items are evicted while rec outweighs p, then frequent items,
+                // then the rest of the recent items.
+                let p = self.p;
+                let outweighs = move |rec_weight: &mut usize, n: &*mut LLNode<_>| {
+                    let over = *rec_weight > p;
+                    if over {
+                        *rec_weight -= unsafe { (**n).weight() };
+                    }
+                    over
+                };
+                let (mut head_weight, mut rest_weight) = (self.rec.weight(), self.rec.weight());
+                let head = self
+                    .rec
+                    .iter_nodes()
+                    .take_while(move |n| outweighs(&mut head_weight, n));
+                let rest = self
+                    .rec
+                    .iter_nodes()
+                    .skip_while(move |n| outweighs(&mut rest_weight, n));
+                Box::new(head.chain(self.freq.iter_nodes()).chain(rest))
+            }
+        }
+    }
+}
+
 impl<
         K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
         V: Clone + Debug + Sync + Send + 'static,
@@ -444,18 +1199,7 @@ impl<
         ex_rw_miss: usize,
         read_cache: bool,
     ) -> Self {
-        let total = isize::try_from(total).unwrap();
-        let threads = isize::try_from(threads).unwrap();
-        let ro_miss = isize::try_from(ex_ro_miss).unwrap();
-        let wr_miss = isize::try_from(ex_rw_miss).unwrap();
-        let ratio = isize::try_from(READ_THREAD_RATIO).unwrap();
-        // I'd like to thank wolfram alpha ... for this magic.
-        let max = -((ratio * ((ro_miss * threads) + wr_miss - total)) / (ratio + threads));
-        let read_max = if read_cache { max / ratio } else { 0 };
-
-        let max = usize::try_from(max).unwrap();
-        let read_max = usize::try_from(read_max).unwrap();
-
+        let (max, read_max) = calc_size(total, threads, ex_ro_miss, ex_rw_miss, read_cache);
         Self::new_size(max, read_max)
     }
 
@@ -466,6 +1210,59 @@ impl<
     /// cache size.
     pub fn new_size(max: usize, read_max: usize) -> Self {
         assert!(max > 0);
+        Self::init(
+            max,
+            read_max,
+            Policy::default(),
+            unit_weight,
+            None,
+            false,
+            None,
+            None,
+            OversizePolicy::default(),
+            false,
+            None,
+            Arc::new(SystemClock),
+        )
+    }
+
+    /// Create a new cache that is partitioned into `shards` independent caches, each
+    /// with an equal portion of `capacity` items. Keys are assigned to a shard by
+    /// their hash, and writes to different shards do not block each other. Reader
+    /// thread local caches are not configured for the shards.
+    ///
+    /// `capacity` must be at least `shards`.
+    pub fn new_sharded(shards: usize, capacity: usize) -> ShardedARCache<K, V> {
+        ShardedARCache::new(shards, capacity, rand::thread_rng())
+    }
+
+    /// As `new_sharded`, but the keys that assign keys to a shard, and those of the
+    /// hashers of each shard, are drawn from `rng` as by `ARCacheBuilder::with_rng`. Two
+    /// caches built from sources of the same seed assign every key to the same shard,
+    /// and make identical eviction choices under the same sequence of operations.
+    pub fn new_sharded_with_rng<R: RngCore>(
+        shards: usize,
+        capacity: usize,
+        rng: R,
+    ) -> ShardedARCache<K, V> {
+        ShardedARCache::new(shards, capacity, rng)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn init(
+        max: usize,
+        read_max: usize,
+        policy: Policy,
+        weigher: fn(&V) -> usize,
+        aging: Option<Duration>,
+        track_access: bool,
+        expired: Option<fn(&V) -> bool>,
+        eviction_budget: Option<usize>,
+        oversize: OversizePolicy,
+        admission: bool,
+        seeds: Option<Seeds>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         let (tx, rx) = unbounded();
         let shared = RwLock::new(ArcShared { max, read_max, tx });
         let inner = Mutex::new(ArcInner {
@@ -477,6 +1274,16 @@ impl<
             haunted: LL::new(),
             rx,
             min_txid: 0,
+            policy,
+            aging,
+            last_aged: clock.now(),
+            eviction_budget: eviction_budget.unwrap_or(usize::MAX),
+            admission: if admission {
+                Some(FrequencySketch::new(max))
+            } else {
+                None
+            },
+            admit_cursor: None,
         });
         let stats = CowCell::new(CacheStats {
             reader_hits: 0,
@@ -492,14 +1299,49 @@ impl<
             p_weight: 0,
             all_seen_keys: 0,
         });
+        let cache = match seeds {
+            Some(seeds) => HashMapBuilder::new().with_seed(seeds.cache).build(),
+            None => HashMap::new(),
+        };
+        let access = if track_access {
+            Some(HashMap::new())
+        } else {
+            None
+        };
         ARCache {
-            cache: HashMap::new(),
+            cache,
+            access,
             shared,
             inner,
             stats,
+            weigher,
+            expired,
+            oversize,
+            local_seeds: seeds.map(|s| s.local),
+            clock,
+            #[cfg(feature = "async")]
+            loads: Mutex::new(Map::new()),
         }
     }
 
+    /// Retrieve the value of a key, loading it with `loader` if it is not in the cache.
+    /// A loaded value is included in the cache before the returned future resolves.
+    /// If `loader` resolves to `None` then nothing is included, and `None` is returned.
+    ///
+    /// Concurrent calls for the same missing key are coalesced, so that only the first
+    /// polls its `loader` and the others wait for its result. If that call is dropped
+    /// before the load completes, a waiting call takes over with its own `loader`.
+    ///
+    /// This is not tied to any executor. Including the value takes a write transaction,
+    /// which blocks the executor thread while another writer is active.
+    #[cfg(feature = "async")]
+    pub fn get_or_load_async<F>(&self, k: K, loader: F) -> GetOrLoad<'_, K, V, F>
+    where
+        F: std::future::Future<Output = Option<V>>,
+    {
+        GetOrLoad::new(self, k, loader)
+    }
+
     /// Begin a read operation on the cache. This reader has a thread-local cache for items
     /// that are localled included via `insert`, and can communicate back to the main cache
     /// to safely include items.
@@ -507,7 +1349,7 @@ impl<
         let rshared = self.shared.read();
         let tlocal = if rshared.read_max > 0 {
             Some(ReadCache {
-                set: Map::new(),
+                set: self.local_map(),
                 read_size: rshared.read_max,
                 tlru: LL::new(),
             })
@@ -517,9 +1359,10 @@ impl<
         ARCacheReadTxn {
             caller: &self,
             cache: self.cache.read(),
+            access: self.access.as_ref().map(|a| a.read()),
             tlocal,
             tx: rshared.tx.clone(),
-            ts: Instant::now(),
+            ts: self.clock.now(),
         }
     }
 
@@ -530,12 +1373,20 @@ impl<
         ARCacheWriteTxn {
             caller: &self,
             cache: self.cache.write(),
-            tlocal: Map::new(),
+            tlocal: self.local_map(),
             hit: UnsafeCell::new(Vec::new()),
             clear: UnsafeCell::new(false),
+            shrink: None,
         }
     }
 
+    fn local_map<T>(&self) -> LocalMap<K, T> {
+        Map::with_hasher(match self.local_seeds {
+            Some([k0, k1, k2, k3]) => ahash::RandomState::with_seeds(k0, k1, k2, k3),
+            None => ahash::RandomState::new(),
+        })
+    }
+
     /// View the statistics for this cache. These values are a snapshot of a point in
     /// time and may not be accurate at "this exact moment".
     pub fn view_stats(&self) -> CowCellReadTxn<CacheStats> {
@@ -546,12 +1397,41 @@ impl<
         self.cache.try_write().map(|cache| ARCacheWriteTxn {
             caller: &self,
             cache,
-            tlocal: Map::new(),
+            tlocal: self.local_map(),
             hit: UnsafeCell::new(Vec::new()),
             clear: UnsafeCell::new(false),
+            shrink: None,
         })
     }
 
+    fn is_expired(&self, v: &V) -> bool {
+        self.expired.map(|f| f(v)).unwrap_or(false)
+    }
+
+    // The weight that a value is included with, or `None` if it is too heavy to be
+    // included. See `OversizePolicy`.
+    fn weigh(&self, v: &V, max: usize) -> Option<usize> {
+        let w = (self.weigher)(v);
+        match self.oversize {
+            _ if w <= max => Some(w),
+            // Accounted as exactly filling the cache, every other item is evicted
+            // before it.
+            OversizePolicy::Admit => Some(max),
+            OversizePolicy::Reject | OversizePolicy::Panic => None,
+        }
+    }
+
+    fn assert_fits(&self, v: &V) {
+        if self.oversize == OversizePolicy::Panic {
+            let max = self.shared.read().max;
+            assert!(
+                (self.weigher)(v) <= max,
+                "Value exceeds the cache capacity of {}",
+                max
+            );
+        }
+    }
+
     fn try_quiesce(&self) {
         if let Some(wr_txn) = self.try_write() {
             wr_txn.commit()
@@ -584,26 +1464,38 @@ impl<
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn drain_tlocal_inc<'a>(
         &'a self,
         cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
         inner: &mut ArcInner<K, V>,
         shared: &ArcShared<K, V>,
         // stats: &mut CacheStats,
-        tlocal: Map<K, ThreadCacheItem<V>>,
+        tlocal: LocalMap<K, ThreadCacheItem<V>>,
         commit_txid: u64,
+        commit_ts: Instant,
     ) {
         // drain tlocal into the main cache.
         tlocal.into_iter().for_each(|(k, tcio)| {
+            // A value too heavy to include, or that the admission filter rejects, is
+            // treated as a remove, so that no previous value of the key remains.
+            let (tcio, w) = match tcio {
+                ThreadCacheItem::Present(tci, clean) => match self.weigh(&tci, shared.max) {
+                    Some(w) if inner.admit(cache, &k, w, shared.max) => {
+                        (ThreadCacheItem::Present(tci, clean), w)
+                    }
+                    _ => (ThreadCacheItem::Removed(clean), 0),
+                },
+                tcio => (tcio, 0),
+            };
             let r = cache.get_mut(&k);
             match (r, tcio) {
                 (None, ThreadCacheItem::Present(tci, clean)) => {
                     assert!(clean);
-                    let llp = inner.rec.append_k(CacheItemInner {
-                        k: k.clone(),
-                        txid: commit_txid,
-                    });
-                    cache.insert(k, CacheItem::Rec(llp, tci));
+                    let ci = inner.include_k(k.clone(), commit_txid, tci, w);
+                    ci.touch(&k, commit_ts, access);
+                    cache.insert(k, ci);
                 }
                 (None, ThreadCacheItem::Removed(clean)) => {
                     assert!(clean);
@@ -611,6 +1503,7 @@ impl<
                     let llp = inner.haunted.append_k(CacheItemInner {
                         k: k.clone(),
                         txid: commit_txid,
+                        count: 0,
                     });
                     cache.insert(k, CacheItem::Haunted(llp));
                 }
@@ -623,6 +1516,7 @@ impl<
                             unsafe { (**llp).as_mut().txid = commit_txid };
                             inner.freq.extract(*llp);
                             inner.haunted.append_n(*llp);
+                            Self::forget_access(access, &k);
                             CacheItem::Haunted(*llp)
                         }
                         CacheItem::Rec(llp, _v) => {
@@ -631,6 +1525,7 @@ impl<
                             unsafe { (**llp).as_mut().txid = commit_txid };
                             inner.rec.extract(*llp);
                             inner.haunted.append_n(*llp);
+                            Self::forget_access(access, &k);
                             CacheItem::Haunted(*llp)
                         }
                         CacheItem::GhostFreq(llp) => {
@@ -667,7 +1562,7 @@ impl<
                             unsafe { (**llp).as_mut().txid = commit_txid };
                             // println!("tlocal {:?} Freq -> Freq", k);
                             // Move the list item to it's head.
-                            inner.freq.touch(*llp);
+                            inner.hit_freq(*llp, w);
                             // Update v.
                             CacheItem::Freq(*llp, (*tci).clone())
                         }
@@ -675,9 +1570,7 @@ impl<
                             // println!("tlocal {:?} Rec -> Freq", k);
                             // Remove the node and put it into freq.
                             unsafe { (**llp).as_mut().txid = commit_txid };
-                            inner.rec.extract(*llp);
-                            inner.freq.append_n(*llp);
-                            CacheItem::Freq(*llp, (*tci).clone())
+                            inner.hit_rec(*llp, (*tci).clone(), w)
                         }
                         CacheItem::GhostFreq(llp) => {
                             // println!("tlocal {:?} GhostFreq -> Freq", k);
@@ -690,6 +1583,7 @@ impl<
                             unsafe { (**llp).as_mut().txid = commit_txid };
                             inner.ghost_freq.extract(*llp);
                             inner.freq.append_n(*llp);
+                            inner.freq.set_weight(*llp, w);
                             CacheItem::Freq(*llp, (*tci).clone())
                         }
                         CacheItem::GhostRec(llp) => {
@@ -704,17 +1598,18 @@ impl<
                             unsafe { (**llp).as_mut().txid = commit_txid };
                             inner.ghost_rec.extract(*llp);
                             inner.rec.append_n(*llp);
+                            inner.rec.set_weight(*llp, w);
                             CacheItem::Rec(*llp, (*tci).clone())
                         }
                         CacheItem::Haunted(llp) => {
                             // println!("tlocal {:?} Haunted -> Rec", k);
                             unsafe { (**llp).as_mut().txid = commit_txid };
                             inner.haunted.extract(*llp);
-                            inner.rec.append_n(*llp);
-                            CacheItem::Rec(*llp, (*tci).clone())
+                            inner.include_n(*llp, (*tci).clone(), w)
                         }
                     };
                     // Now change the state.
+                    next_state.touch(&k, commit_ts, access);
                     mem::swap(*ci, &mut next_state);
                 }
             }
@@ -724,6 +1619,7 @@ impl<
     fn drain_rx<'a>(
         &'a self,
         cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
         inner: &mut ArcInner<K, V>,
         shared: &ArcShared<K, V>,
         stats: &mut CacheStats,
@@ -734,6 +1630,7 @@ impl<
             let t = match ce {
                 // Update if it was hit.
                 CacheEvent::Hit(t, k_hash, is_tlocal) => {
+                    inner.record_access(k_hash);
                     if is_tlocal {
                         stats.reader_tlocal_hits += 1;
                     } else {
@@ -744,14 +1641,12 @@ impl<
                             let mut next_state = match &ci.v {
                                 CacheItem::Freq(llp, v) => {
                                     // println!("rxhit {:?} Freq -> Freq", k);
-                                    inner.freq.touch(*llp);
+                                    inner.hit_freq(*llp, unsafe { (**llp).weight() });
                                     CacheItem::Freq(*llp, v.clone())
                                 }
                                 CacheItem::Rec(llp, v) => {
                                     // println!("rxhit {:?} Rec -> Freq", k);
-                                    inner.rec.extract(*llp);
-                                    inner.freq.append_n(*llp);
-                                    CacheItem::Freq(*llp, v.clone())
+                                    inner.hit_rec(*llp, v.clone(), unsafe { (**llp).weight() })
                                 }
                                 // While we can't add this from nothing, we can
                                 // at least keep it in the ghost sets.
@@ -771,6 +1666,7 @@ impl<
                                     CacheItem::Haunted(*llp)
                                 }
                             };
+                            next_state.touch(&ci.k, t, access);
                             mem::swap(&mut (*ci).v, &mut next_state);
                         } // for each item in the bucket.
                     }
@@ -780,117 +1676,129 @@ impl<
                 // Update if it was inc
                 CacheEvent::Include(t, k, iv, txid) => {
                     stats.reader_includes += 1;
-                    let mut r = cache.get_mut(&k);
-                    match r {
-                        Some(ref mut ci) => {
-                            let mut next_state = match &ci {
-                                CacheItem::Freq(llp, _v) => {
-                                    inner.freq.touch(*llp);
-                                    if unsafe { (**llp).as_ref().txid >= txid }
-                                        || inner.min_txid > txid
-                                    {
-                                        // println!("rxinc {:?} Freq -> Freq (touch only)", k);
-                                        // Our cache already has a newer value, keep it.
-                                        None
-                                    } else {
-                                        // println!("rxinc {:?} Freq -> Freq (update)", k);
-                                        // The value is newer, update.
-                                        unsafe { (**llp).as_mut().txid = txid };
-                                        Some(CacheItem::Freq(*llp, iv))
+                    // A value too heavy to include, or that the admission filter
+                    // rejects, is ignored.
+                    let w = self
+                        .weigh(&iv, shared.max)
+                        .filter(|w| inner.admit(cache, &k, *w, shared.max));
+                    if let Some(w) = w {
+                        let mut r = cache.get_mut(&k);
+                        match r {
+                            Some(ref mut ci) => {
+                                let mut next_state = match &ci {
+                                    CacheItem::Freq(llp, _v) => {
+                                        if unsafe { (**llp).as_ref().txid >= txid }
+                                            || inner.min_txid > txid
+                                        {
+                                            // println!("rxinc {:?} Freq -> Freq (touch only)", k);
+                                            // Our cache already has a newer value, keep it.
+                                            inner.hit_freq(*llp, unsafe { (**llp).weight() });
+                                            None
+                                        } else {
+                                            // println!("rxinc {:?} Freq -> Freq (update)", k);
+                                            // The value is newer, update.
+                                            inner.hit_freq(*llp, w);
+                                            unsafe { (**llp).as_mut().txid = txid };
+                                            Some(CacheItem::Freq(*llp, iv))
+                                        }
                                     }
-                                }
-                                CacheItem::Rec(llp, v) => {
-                                    inner.rec.extract(*llp);
-                                    inner.freq.append_n(*llp);
-                                    if unsafe { (**llp).as_ref().txid >= txid }
-                                        || inner.min_txid > txid
-                                    {
-                                        // println!("rxinc {:?} Rec -> Freq (touch only)", k);
-                                        Some(CacheItem::Freq(*llp, v.clone()))
-                                    } else {
-                                        // println!("rxinc {:?} Rec -> Freq (update)", k);
-                                        unsafe { (**llp).as_mut().txid = txid };
-                                        Some(CacheItem::Freq(*llp, iv))
+                                    CacheItem::Rec(llp, v) => {
+                                        if unsafe { (**llp).as_ref().txid >= txid }
+                                            || inner.min_txid > txid
+                                        {
+                                            // println!("rxinc {:?} Rec -> Freq (touch only)", k);
+                                            Some(inner.hit_rec(*llp, v.clone(), unsafe {
+                                                (**llp).weight()
+                                            }))
+                                        } else {
+                                            // println!("rxinc {:?} Rec -> Freq (update)", k);
+                                            unsafe { (**llp).as_mut().txid = txid };
+                                            Some(inner.hit_rec(*llp, iv, w))
+                                        }
                                     }
-                                }
-                                CacheItem::GhostFreq(llp) => {
-                                    // Adjust p
-                                    Self::calc_p_freq(
-                                        inner.ghost_rec.len(),
-                                        inner.ghost_freq.len(),
-                                        &mut inner.p,
-                                    );
-                                    inner.ghost_freq.extract(*llp);
-                                    if unsafe { (**llp).as_ref().txid > txid }
-                                        || inner.min_txid > txid
-                                    {
-                                        // println!("rxinc {:?} GhostFreq -> GhostFreq", k);
-                                        // The cache version is newer, this is just a hit.
-                                        inner.ghost_freq.append_n(*llp);
-                                        None
-                                    } else {
-                                        // This item is newer, so we can include it.
-                                        // println!("rxinc {:?} GhostFreq -> Rec", k);
-                                        inner.freq.append_n(*llp);
-                                        unsafe { (**llp).as_mut().txid = txid };
-                                        Some(CacheItem::Freq(*llp, iv))
+                                    CacheItem::GhostFreq(llp) => {
+                                        // Adjust p
+                                        Self::calc_p_freq(
+                                            inner.ghost_rec.len(),
+                                            inner.ghost_freq.len(),
+                                            &mut inner.p,
+                                        );
+                                        inner.ghost_freq.extract(*llp);
+                                        if unsafe { (**llp).as_ref().txid > txid }
+                                            || inner.min_txid > txid
+                                        {
+                                            // println!("rxinc {:?} GhostFreq -> GhostFreq", k);
+                                            // The cache version is newer, this is just a hit.
+                                            inner.ghost_freq.append_n(*llp);
+                                            None
+                                        } else {
+                                            // This item is newer, so we can include it.
+                                            // println!("rxinc {:?} GhostFreq -> Rec", k);
+                                            inner.freq.append_n(*llp);
+                                            inner.freq.set_weight(*llp, w);
+                                            unsafe { (**llp).as_mut().txid = txid };
+                                            Some(CacheItem::Freq(*llp, iv))
+                                        }
                                     }
-                                }
-                                CacheItem::GhostRec(llp) => {
-                                    // Adjust p
-                                    Self::calc_p_rec(
-                                        shared.max,
-                                        inner.ghost_rec.len(),
-                                        inner.ghost_freq.len(),
-                                        &mut inner.p,
-                                    );
-                                    if unsafe { (**llp).as_ref().txid > txid }
-                                        || inner.min_txid > txid
-                                    {
-                                        // println!("rxinc {:?} GhostRec -> GhostRec", k);
-                                        inner.ghost_rec.touch(*llp);
-                                        None
-                                    } else {
-                                        // println!("rxinc {:?} GhostRec -> Rec", k);
-                                        inner.ghost_rec.extract(*llp);
-                                        inner.rec.append_n(*llp);
-                                        unsafe { (**llp).as_mut().txid = txid };
-                                        Some(CacheItem::Rec(*llp, iv))
+                                    CacheItem::GhostRec(llp) => {
+                                        // Adjust p
+                                        Self::calc_p_rec(
+                                            shared.max,
+                                            inner.ghost_rec.len(),
+                                            inner.ghost_freq.len(),
+                                            &mut inner.p,
+                                        );
+                                        if unsafe { (**llp).as_ref().txid > txid }
+                                            || inner.min_txid > txid
+                                        {
+                                            // println!("rxinc {:?} GhostRec -> GhostRec", k);
+                                            inner.ghost_rec.touch(*llp);
+                                            None
+                                        } else {
+                                            // println!("rxinc {:?} GhostRec -> Rec", k);
+                                            inner.ghost_rec.extract(*llp);
+                                            inner.rec.append_n(*llp);
+                                            inner.rec.set_weight(*llp, w);
+                                            unsafe { (**llp).as_mut().txid = txid };
+                                            Some(CacheItem::Rec(*llp, iv))
+                                        }
                                     }
-                                }
-                                CacheItem::Haunted(llp) => {
-                                    if unsafe { (**llp).as_ref().txid > txid }
-                                        || inner.min_txid > txid
-                                    {
-                                        // println!("rxinc {:?} Haunted -> Haunted", k);
-                                        None
-                                    } else {
-                                        // println!("rxinc {:?} Haunted -> Rec", k);
-                                        inner.haunted.extract(*llp);
-                                        inner.rec.append_n(*llp);
-                                        unsafe { (**llp).as_mut().txid = txid };
-                                        Some(CacheItem::Rec(*llp, iv))
+                                    CacheItem::Haunted(llp) => {
+                                        if unsafe { (**llp).as_ref().txid > txid }
+                                            || inner.min_txid > txid
+                                        {
+                                            // println!("rxinc {:?} Haunted -> Haunted", k);
+                                            None
+                                        } else {
+                                            // println!("rxinc {:?} Haunted -> Rec", k);
+                                            inner.haunted.extract(*llp);
+                                            unsafe { (**llp).as_mut().txid = txid };
+                                            Some(inner.include_n(*llp, iv, w))
+                                        }
                                     }
+                                };
+                                if let Some(ref mut next_state) = next_state {
+                                    mem::swap(*ci, next_state);
                                 }
-                            };
-                            if let Some(ref mut next_state) = next_state {
-                                mem::swap(*ci, next_state);
+                                ci.touch(&k, t, access);
                             }
-                        }
-                        None => {
-                            // It's not present - include it!
-                            // println!("rxinc {:?} None -> Rec", k);
-                            if txid >= inner.min_txid {
-                                let llp = inner.rec.append_k(CacheItemInner { k: k.clone(), txid });
-                                cache.insert(k, CacheItem::Rec(llp, iv));
+                            None => {
+                                // It's not present - include it!
+                                // println!("rxinc {:?} None -> Rec", k);
+                                if txid >= inner.min_txid {
+                                    let ci = inner.include_k(k.clone(), txid, iv, w);
+                                    ci.touch(&k, t, access);
+                                    cache.insert(k, ci);
+                                }
                             }
-                        }
-                    };
+                        };
+                    }
                     t
                 }
             };
-            // Stop processing the queue, we are up to "now".
-            if t >= commit_ts {
+            // Stop processing the queue, we are past "now". Events of the same time as
+            // the commit are still processed, as a clock may not have advanced.
+            if t > commit_ts {
                 break;
             }
         }
@@ -899,13 +1807,16 @@ impl<
     fn drain_tlocal_hits<'a>(
         &'a self,
         cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
         inner: &mut ArcInner<K, V>,
         // shared: &ArcShared<K, V>,
         // stats: &mut CacheStats,
         commit_txid: u64,
+        commit_ts: Instant,
         hit: Vec<u64>,
     ) {
         hit.into_iter().for_each(|k_hash| {
+            inner.record_access(k_hash);
             // * everything hit must be in main cache now, so bring these
             //   all to the relevant item heads.
             // * Why do this last? Because the write is the "latest" we want all the fresh
@@ -927,7 +1838,7 @@ impl<
                             CacheItem::Freq(llp, v) => {
                                 if unsafe { (**llp).as_ref().txid != commit_txid } {
                                     // println!("hit {:?} Freq -> Freq", k);
-                                    inner.freq.touch(*llp);
+                                    inner.hit_freq(*llp, unsafe { (**llp).weight() });
                                     Some(CacheItem::Freq(*llp, v.clone()))
                                 } else {
                                     None
@@ -936,9 +1847,9 @@ impl<
                             CacheItem::Rec(llp, v) => {
                                 if unsafe { (**llp).as_ref().txid != commit_txid } {
                                     // println!("hit {:?} Rec -> Freq", k);
-                                    inner.rec.extract(*llp);
-                                    inner.freq.append_n(*llp);
-                                    Some(CacheItem::Freq(*llp, v.clone()))
+                                    Some(
+                                        inner.hit_rec(*llp, v.clone(), unsafe { (**llp).weight() }),
+                                    )
                                 } else {
                                     None
                                 }
@@ -950,6 +1861,7 @@ impl<
                         };
                         // Now change the state.
                         if let Some(ref mut next_state) = next_state {
+                            next_state.touch(&ci.k, commit_ts, access);
                             mem::swap(&mut (*ci).v, next_state);
                         }
                     } // for each ci in slots
@@ -962,89 +1874,273 @@ impl<
         });
     }
 
-    #[allow(clippy::cognitive_complexity)]
+    // Forget the access time of k, as its item is no longer resident.
+    fn forget_access(access: &mut Option<HashMapWriteTxn<K, Instant>>, k: &K) {
+        if let Some(access) = access {
+            access.remove(k);
+        }
+    }
+
+    // Remove the expired items from the oldest end of ll, up to budget of them. This
+    // stops at the first item that has not expired, so a commit does not check every
+    // resident item. An expired item further in is still a miss on get, and is removed
+    // once it is the oldest of its set.
+    #[allow(clippy::too_many_arguments)]
+    fn collect_expired_ll<'a>(
+        cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
+        ll: &mut LL<CacheItemInner<K>>,
+        haunted: &mut LL<CacheItemInner<K>>,
+        expired: fn(&V) -> bool,
+        budget: usize,
+        commit_txid: u64,
+    ) -> usize {
+        let mut count = 0;
+        while count < budget {
+            let n = match ll.head_node() {
+                Some(n) => n,
+                None => break,
+            };
+            let is_expired = cache
+                .get(unsafe { &(*n).as_ref().k })
+                .and_then(|ci| ci.to_vref())
+                .map(expired)
+                .unwrap_or(false);
+            if !is_expired {
+                break;
+            }
+            count += 1;
+            ll.extract(n);
+            haunted.append_n(n);
+            unsafe {
+                // Set the item's evict txid.
+                (*n).as_mut().txid = commit_txid;
+            }
+            match cache.get_mut(unsafe { &(*n).as_ref().k }) {
+                Some(ci) => {
+                    *ci = CacheItem::Haunted(n);
+                }
+                None => {
+                    // Impossible state!
+                    unreachable!();
+                }
+            }
+            Self::forget_access(access, unsafe { &(*n).as_ref().k });
+        }
+        count
+    }
+
+    fn collect_expired<'a>(
+        &'a self,
+        cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
+        inner: &mut ArcInner<K, V>,
+        stats: &mut CacheStats,
+        commit_txid: u64,
+    ) {
+        // Expired items are gone from the source, so unlike an eviction they are not
+        // kept in the ghost sets, and don't adjust p.
+        if let Some(expired) = self.expired {
+            stats.freq_evicts += Self::collect_expired_ll(
+                cache,
+                access,
+                &mut inner.freq,
+                &mut inner.haunted,
+                expired,
+                inner.eviction_budget,
+                commit_txid,
+            );
+            stats.recent_evicts += Self::collect_expired_ll(
+                cache,
+                access,
+                &mut inner.rec,
+                &mut inner.haunted,
+                expired,
+                inner.eviction_budget,
+                commit_txid,
+            );
+        }
+    }
+
     fn evict<'a>(
         &'a self,
         cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
         inner: &mut ArcInner<K, V>,
-        shared: &ArcShared<K, V>,
+        max: usize,
+        stats: &mut CacheStats,
+        commit_txid: u64,
+    ) {
+        match inner.policy {
+            Policy::Arc => self.evict_arc(cache, access, inner, max, max, stats, commit_txid),
+            Policy::Lru => self.evict_lru(cache, access, inner, max, stats, commit_txid),
+            Policy::Lfu => self.evict_lfu(cache, access, inner, max, stats, commit_txid),
+        }
+    }
+
+    fn evict_lru<'a>(
+        &'a self,
+        cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
+        inner: &mut ArcInner<K, V>,
+        max: usize,
         stats: &mut CacheStats,
         commit_txid: u64,
     ) {
-        debug_assert!(inner.p <= shared.max);
+        // Everything resident is in rec, ordered by recency. There are no ghost
+        // sets, so we evict directly to haunted.
+        debug_assert!(inner.freq.len() == 0);
+        if inner.rec.weight() > max {
+            let rec_len = inner.rec.len();
+            let mut budget = inner.eviction_budget;
+            evict_to_haunted_len!(
+                cache,
+                inner.rec,
+                &mut inner.haunted,
+                max,
+                &mut budget,
+                access,
+                commit_txid
+            );
+            stats.recent_evicts += rec_len - inner.rec.len();
+        }
+    }
+
+    fn evict_lfu<'a>(
+        &'a self,
+        cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
+        inner: &mut ArcInner<K, V>,
+        max: usize,
+        stats: &mut CacheStats,
+        commit_txid: u64,
+    ) {
+        // Everything resident is in freq, ordered by recency. We evict the lowest
+        // hit counts, and ties are broken by the least recently used.
+        debug_assert!(inner.rec.len() == 0);
+        if inner.freq.weight() > max {
+            let mut victims: Vec<_> = inner.freq.iter_nodes().collect();
+            // This is a stable sort, so the recency order is preserved within a count.
+            victims.sort_by_key(|n| unsafe { (**n).as_ref().count });
+            // Take only as many victims as are needed to fit.
+            let mut delta = inner.freq.weight() - max;
+            let victims = victims.into_iter().take_while(|n| {
+                let take = delta > 0;
+                delta = delta.saturating_sub(unsafe { (**n).weight() });
+                take
+            });
+            let victims = victims.take(inner.eviction_budget);
+
+            for n in victims {
+                stats.freq_evicts += 1;
+                inner.freq.extract(n);
+                inner.haunted.append_n(n);
+                unsafe {
+                    // Set the item's evict txid.
+                    (*n).as_mut().txid = commit_txid;
+                }
+                match cache.get_mut(unsafe { &(*n).as_mut().k }) {
+                    Some(ci) => {
+                        *ci = CacheItem::Haunted(n);
+                    }
+                    None => {
+                        // Impossible state!
+                        unreachable!();
+                    }
+                }
+                Self::forget_access(access, unsafe { &(*n).as_ref().k });
+            }
+        }
+    }
+
+    #[allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
+    fn evict_arc<'a>(
+        &'a self,
+        cache: &mut HashMapWriteTxn<'a, K, CacheItem<K, V>>,
+        access: &mut Option<HashMapWriteTxn<'a, K, Instant>>,
+        inner: &mut ArcInner<K, V>,
+        max: usize,
+        ghost_max: usize,
+        stats: &mut CacheStats,
+        commit_txid: u64,
+    ) {
+        debug_assert!(inner.p <= max);
         // Convince the compiler copying is okay.
         let p = inner.p;
         stats.p_weight = p;
 
-        if inner.rec.len() + inner.freq.len() > shared.max {
+        if inner.rec.weight() + inner.freq.weight() > max {
             // println!("Checking cache evict");
             /*
             println!(
                 "from -> rec {:?}, freq {:?}",
-                inner.rec.len(),
-                inner.freq.len()
+                inner.rec.weight(),
+                inner.freq.weight()
             );
             */
-            let delta = (inner.rec.len() + inner.freq.len()) - shared.max;
+            let delta = (inner.rec.weight() + inner.freq.weight()) - max;
             // We have overflowed by delta. As we are not "evicting as we go" we have to work out
             // what we should have evicted up to now.
             //
             // keep removing from rec until == p OR delta == 0, and if delta remains, then remove from freq.
 
             let rec_to_len = if inner.p == 0 {
-                // println!("p == 0 => {:?}", inner.rec.len());
-                debug_assert!(delta <= inner.rec.len());
-                // We are fully weight to freq, so only remove in rec.
-                inner.rec.len() - delta
-            } else if inner.rec.len() > inner.p {
+                // println!("p == 0 => {:?}", inner.rec.weight());
+                // We are fully weight to freq, so only remove in rec. If a previous
+                // commit exhausted its eviction budget, freq may also be oversized so
+                // the excess is removed from freq.
+                inner.rec.weight().saturating_sub(delta)
+            } else if inner.rec.weight() > inner.p {
                 // There is a partial weighting, how much do we need to move?
-                let rec_delta = inner.rec.len() - inner.p;
+                let rec_delta = inner.rec.weight() - inner.p;
                 if rec_delta > delta {
                     /*
                     println!(
                         "p ({:?}) <= rec ({:?}), rec_delta ({:?}) > delta ({:?})",
                         inner.p,
-                        inner.rec.len(),
+                        inner.rec.weight(),
                         rec_delta,
                         delta
                     );
                     */
                     // We will have removed enough through delta alone in rec.
-                    inner.rec.len() - delta
+                    inner.rec.weight() - delta
                 } else {
                     /*
                     println!(
                         "p ({:?}) <= rec ({:?}), rec_delta ({:?}) <= delta ({:?})",
                         inner.p,
-                        inner.rec.len(),
+                        inner.rec.weight(),
                         rec_delta,
                         delta
                     );
                     */
                     // Remove the full delta, and excess will be removed from freq.
-                    inner.rec.len() - rec_delta
+                    inner.rec.weight() - rec_delta
                 }
             } else {
                 // rec is already below p, therefore we must need to remove in freq, and
                 // we need to consider how much is in rec.
-                // println!("p ({:?}) > rec ({:?})", inner.p, inner.rec.len());
-                inner.rec.len()
+                // println!("p ({:?}) > rec ({:?})", inner.p, inner.rec.weight());
+                inner.rec.weight()
             };
 
             // Now we can get the expected sizes;
-            debug_assert!(shared.max >= rec_to_len);
-            let freq_to_len = shared.max - rec_to_len;
+            debug_assert!(max >= rec_to_len);
+            let freq_to_len = max - rec_to_len;
             // println!("move to -> rec {:?}, freq {:?}", rec_to_len, freq_to_len);
-            debug_assert!(freq_to_len + rec_to_len <= shared.max);
-
-            stats.freq_evicts += inner.freq.len() - freq_to_len;
-            stats.recent_evicts += inner.rec.len() - rec_to_len;
+            debug_assert!(freq_to_len + rec_to_len <= max);
 
+            // Only the eviction of resident items is limited by the budget.
+            let mut budget = inner.eviction_budget;
             evict_to_len!(
                 cache,
                 inner.rec,
                 &mut inner.ghost_rec,
                 rec_to_len,
+                &mut budget,
+                &mut stats.recent_evicts,
+                access,
                 commit_txid
             );
             evict_to_len!(
@@ -1052,28 +2148,39 @@ impl<
                 inner.freq,
                 &mut inner.ghost_freq,
                 freq_to_len,
+                &mut budget,
+                &mut stats.freq_evicts,
+                access,
                 commit_txid
             );
 
             // Finally, do an evict of the ghost sets if they are too long - these are weighted
             // inverse to the above sets. Note the freq to len in ghost rec, and rec to len in
-            // ghost freq!
-            if inner.ghost_rec.len() > (shared.max - p) {
+            // ghost freq! The ghost sets only hold keys, so they are not limited by
+            // the budget. They are bounded by ghost_max, which exceeds max when a shrink
+            // evicts below the max, so that they keep the history that p adapts by.
+            let slack = ghost_max - max;
+            let mut unbounded = usize::MAX;
+            if inner.ghost_rec.weight() > (ghost_max - p) {
                 evict_to_haunted_len!(
                     cache,
                     inner.ghost_rec,
                     &mut inner.haunted,
-                    freq_to_len,
+                    freq_to_len + slack,
+                    &mut unbounded,
+                    access,
                     commit_txid
                 );
             }
 
-            if inner.ghost_freq.len() > p {
+            if inner.ghost_freq.weight() > p + slack {
                 evict_to_haunted_len!(
                     cache,
                     inner.ghost_freq,
                     &mut inner.haunted,
-                    rec_to_len,
+                    rec_to_len + slack,
+                    &mut unbounded,
+                    access,
                     commit_txid
                 );
             }
@@ -1084,12 +2191,13 @@ impl<
     fn commit<'a>(
         &'a self,
         mut cache: HashMapWriteTxn<'a, K, CacheItem<K, V>>,
-        tlocal: Map<K, ThreadCacheItem<V>>,
+        tlocal: LocalMap<K, ThreadCacheItem<V>>,
         hit: Vec<u64>,
         clear: bool,
+        shrink: Option<usize>,
     ) {
         // What is the time?
-        let commit_ts = Instant::now();
+        let commit_ts = self.clock.now();
         let commit_txid = cache.get_txid();
         // Copy p + init cache sizes for adjustment.
         let mut inner = self.inner.lock();
@@ -1097,6 +2205,8 @@ impl<
         let mut stat_guard = self.stats.write();
         let stats = stat_guard.get_mut();
 
+        let mut access = self.access.as_ref().map(|a| a.write());
+
         // Did we request to be cleared? If so, we move everything to a ghost set
         // that was live.
         //
@@ -1109,24 +2219,38 @@ impl<
             // Indicate that we evicted all to ghost/freq
             stats.freq_evicts += inner.freq.len();
             stats.recent_evicts += inner.rec.len();
+            // Nothing remains resident, so no access time is kept.
+            if let Some(access) = access.as_mut() {
+                access.clear();
+            }
 
-            // Move everything active into ghost sets.
-            drain_ll_to_ghost!(
-                &mut cache,
-                inner.freq,
-                inner.ghost_freq,
-                inner.ghost_rec,
-                commit_txid
-            );
-            drain_ll_to_ghost!(
-                &mut cache,
-                inner.rec,
-                inner.ghost_freq,
-                inner.ghost_rec,
-                commit_txid
-            );
+            if inner.policy == Policy::Arc {
+                // Move everything active into ghost sets.
+                drain_ll_to_ghost!(
+                    &mut cache,
+                    inner.freq,
+                    inner.ghost_freq,
+                    inner.ghost_rec,
+                    commit_txid
+                );
+                drain_ll_to_ghost!(
+                    &mut cache,
+                    inner.rec,
+                    inner.ghost_freq,
+                    inner.ghost_rec,
+                    commit_txid
+                );
+            } else {
+                // There are no ghost sets, so everything active is haunted.
+                drain_ll_to_haunted!(&mut cache, inner.freq, inner.haunted, commit_txid);
+                drain_ll_to_haunted!(&mut cache, inner.rec, inner.haunted, commit_txid);
+            }
         }
 
+        // Age the existing hit counts before this commit's hits and inclusions are
+        // applied.
+        inner.age(commit_ts);
+
         // Why is it okay to drain the rx/tlocal and create the cache in a temporary
         // oversize? Because these values in the queue/tlocal are already in memory
         // and we are moving them to the cache, we are not actually using any more
@@ -1140,15 +2264,18 @@ impl<
 
         self.drain_tlocal_inc(
             &mut cache,
+            &mut access,
             inner.deref_mut(),
             shared.deref(),
             tlocal,
             commit_txid,
+            commit_ts,
         );
 
         // drain rx until empty or time >= time.
         self.drain_rx(
             &mut cache,
+            &mut access,
             inner.deref_mut(),
             shared.deref(),
             stats,
@@ -1158,7 +2285,23 @@ impl<
         stats.write_hits += hit.len();
         // drain the tlocal hits into the main cache.
 
-        self.drain_tlocal_hits(&mut cache, inner.deref_mut(), commit_txid, hit);
+        self.drain_tlocal_hits(
+            &mut cache,
+            &mut access,
+            inner.deref_mut(),
+            commit_txid,
+            commit_ts,
+            hit,
+        );
+
+        // Remove expired items before evicting, as they may free enough space.
+        self.collect_expired(
+            &mut cache,
+            &mut access,
+            inner.deref_mut(),
+            stats,
+            commit_txid,
+        );
 
         // now clean the space for each of the primary caches, evicting into the ghost sets.
         // * It's possible that both caches are now over-sized if rx was empty
@@ -1168,12 +2311,46 @@ impl<
         // so we need to do a clean up/balance of all the list lengths.
         self.evict(
             &mut cache,
+            &mut access,
             inner.deref_mut(),
-            shared.deref(),
+            shared.max,
             stats,
             commit_txid,
         );
 
+        // A shrink evicts further, to the target rather than the max. This is not
+        // limited by the eviction budget, and the balance of the policy is restored
+        // afterward, so that the cache may grow back to the max.
+        if let Some(target) = shrink.filter(|t| *t < shared.max) {
+            let (p, budget) = (inner.p, inner.eviction_budget);
+            inner.p = p.min(target);
+            inner.eviction_budget = usize::MAX;
+            if inner.policy == Policy::Arc {
+                // The ghost sets are bounded by the max rather than the target.
+                self.evict_arc(
+                    &mut cache,
+                    &mut access,
+                    inner.deref_mut(),
+                    target,
+                    shared.max,
+                    stats,
+                    commit_txid,
+                );
+            } else {
+                self.evict(
+                    &mut cache,
+                    &mut access,
+                    inner.deref_mut(),
+                    target,
+                    stats,
+                    commit_txid,
+                );
+            }
+            inner.p = p;
+            inner.eviction_budget = budget;
+            stats.p_weight = p;
+        }
+
         stats.shared_max = shared.max;
         stats.freq = inner.freq.len();
         stats.recent = inner.rec.len();
@@ -1181,6 +2358,11 @@ impl<
 
         // Commit the stats
         stat_guard.commit();
+        // Commit the access times before the items, so a reader that sees an item
+        // resident also sees the time of its latest access.
+        if let Some(access) = access {
+            access.commit();
+        }
         // commit on the wr txn.
         cache.commit();
         // done!
@@ -1205,6 +2387,7 @@ impl<
             self.tlocal,
             self.hit.into_inner(),
             self.clear.into_inner(),
+            self.shrink,
         )
     }
 
@@ -1228,6 +2411,17 @@ impl<
         // Inserts are accepted.
     }
 
+    /// Evict items until the weight of the resident items is at most `target`, such as
+    /// in response to memory pressure. The victims are chosen in the order of the
+    /// policy, as reported by `ARCacheReadTxn::eviction_order`, and this is not
+    /// limited by the eviction budget. This does not take effect until you commit.
+    ///
+    /// Unlike a resize, the configured max of the cache is not changed, so later
+    /// commits may include items until it is full again.
+    pub fn shrink_to(&mut self, target: usize) {
+        self.shrink = Some(self.shrink.map_or(target, |t| t.min(target)));
+    }
+
     /// Attempt to retieve a k-v pair from the cache. If it is present in the main cache OR
     /// the thread local cache, a `Some` is returned, else you will recieve a `None`. On a
     /// `None`, you must then consult the external data source that this structure is acting
@@ -1266,6 +2460,8 @@ impl<
                 None
             }
         };
+        // An expired value is a miss.
+        let r = r.filter(|v| !self.caller.is_expired(v));
         // How do we track this was a hit?
         // Remember, we don't track misses - they are *implied* by the fact they'll trigger
         // an inclusion from the external system. Subsequent, any further re-hit on an
@@ -1293,9 +2489,49 @@ impl<
     /// a new value and want it to be submitted for caching. This item is marked as
     /// clean, IE you have synced it to whatever associated store exists.
     pub fn insert(&mut self, k: K, v: V) {
+        self.caller.assert_fits(&v);
         self.tlocal.insert(k, ThreadCacheItem::Present(v, true));
     }
 
+    /// Add many values to the cache in the same manner as `insert`. This is intended
+    /// for pre-warming a cache from a set of precomputed entries.
+    ///
+    /// As with `insert`, the entries are held in this transaction until `commit`, where
+    /// they are included into the cache with a single eviction pass over the whole batch.
+    pub fn insert_batch<I>(&mut self, entries: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let caller = self.caller;
+        self.tlocal.extend(entries.into_iter().map(|(k, v)| {
+            caller.assert_fits(&v);
+            (k, ThreadCacheItem::Present(v, true))
+        }));
+    }
+
+    /// Add a value to the cache in the same manner as `insert`, but reject it if the
+    /// weight of the value alone exceeds the capacity of the cache. On success the
+    /// previous value of this key, as visible to this transaction, is returned. If
+    /// rejected, the key and value are returned to you so that you may handle the
+    /// value outside of the cache.
+    pub fn try_insert(&mut self, k: K, v: V) -> Result<Option<V>, InsertRejected<K, V>> {
+        let max = self.caller.shared.read().max;
+        if (self.caller.weigher)(&v) > max {
+            return Err(InsertRejected { k, v });
+        }
+        let is_cleared = unsafe { *self.clear.get() };
+        let prev = match self
+            .tlocal
+            .insert(k.clone(), ThreadCacheItem::Present(v, true))
+        {
+            Some(ThreadCacheItem::Present(pv, _clean)) => Some(pv),
+            Some(ThreadCacheItem::Removed(_clean)) => None,
+            None if !is_cleared => self.cache.get(&k).and_then(|ci| ci.to_vref().cloned()),
+            None => None,
+        };
+        Ok(prev)
+    }
+
     /// Remove this value from the thread local cache IE mask from from being
     /// returned until this thread performs an insert. This item is marked as clean
     /// IE you have synced it to whatever associated store exists.
@@ -1309,6 +2545,7 @@ impl<
     /// dirty, because you have *not* synced it. You MUST call iter_mut_mark_clean before calling
     /// `commit` on this transaction, or a panic will occur.
     pub fn insert_dirty(&mut self, k: K, v: V) {
+        self.caller.assert_fits(&v);
         self.tlocal.insert(k, ThreadCacheItem::Present(v, false));
     }
 
@@ -1444,19 +2681,24 @@ impl<
             .tlocal
             .as_ref()
             .and_then(|cache| {
-                cache.set.get(k).map(|v| unsafe {
-                    // Indicate a hit on the tlocal cache.
-                    self.tx
-                        .send(CacheEvent::Hit(self.ts, k_hash, true))
-                        .expect("Invalid tx state");
-                    let v = &(**v).as_ref().1 as *const _;
-                    // This discards the lifetime and repins it to &'b.
-                    &(*v)
-                })
+                cache
+                    .set
+                    .get(k)
+                    .filter(|v| !self.caller.is_expired(unsafe { &(***v).as_ref().1 }))
+                    .map(|v| unsafe {
+                        // Indicate a hit on the tlocal cache.
+                        self.tx
+                            .send(CacheEvent::Hit(self.ts, k_hash, true))
+                            .expect("Invalid tx state");
+                        let v = &(**v).as_ref().1 as *const _;
+                        // This discards the lifetime and repins it to &'b.
+                        &(*v)
+                    })
             })
             .or_else(|| {
                 self.cache.get_prehashed(k, k_hash).and_then(|v| {
-                    (*v).to_vref().map(|vin| unsafe {
+                    let vin = (*v).to_vref().filter(|vin| !self.caller.is_expired(vin));
+                    vin.map(|vin| unsafe {
                         // Indicate a hit on the main cache.
                         self.tx
                             .send(CacheEvent::Hit(self.ts, k_hash, false))
@@ -1471,6 +2713,116 @@ impl<
         r
     }
 
+    /// Retrieve the time that this key was last accessed, without this counting as an
+    /// access of the key. This is only available if the cache was built with
+    /// `ARCacheBuilder::set_track_access`, otherwise `None` is returned.
+    ///
+    /// As accesses are recorded during commit, this reflects accesses that were committed
+    /// before this read transaction began, and items only held in this reader's thread
+    /// local cache have no recorded access.
+    pub fn last_access<'b, Q: ?Sized>(&'b self, k: &'b Q) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + Ord,
+    {
+        self.cache.get(k).and_then(|ci| self.access_time(k, ci))
+    }
+
+    // The last access time of the item of k, if it is resident and access is tracked.
+    fn access_time<Q: ?Sized>(&self, k: &Q, ci: &CacheItem<K, V>) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + Ord,
+    {
+        match ci {
+            CacheItem::Freq(..) | CacheItem::Rec(..) => self.access.as_ref()?.get(k).copied(),
+            _ => None,
+        }
+    }
+
+    /// Report the list that this key is tracked by, its hit count, and the time it was
+    /// last accessed, or `None` if the cache has no record of the key. This is for
+    /// understanding the eviction decisions of the cache, and is not an access, so the
+    /// key is not promoted.
+    ///
+    /// The list is that of the start of this read, and the count that of the most
+    /// recent commit. Items only held in this reader's thread local cache are not
+    /// reported.
+    pub fn entry_state<'b, Q: ?Sized>(&'b self, k: &'b Q) -> Option<EntryState>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + Ord,
+    {
+        let ci = self.cache.get(k)?;
+        let (list, llp) = match ci {
+            CacheItem::Rec(llp, _) => (EntryList::Recent, llp),
+            CacheItem::Freq(llp, _) => (EntryList::Frequent, llp),
+            CacheItem::GhostRec(llp) => (EntryList::GhostRecent, llp),
+            CacheItem::GhostFreq(llp) => (EntryList::GhostFrequent, llp),
+            CacheItem::Haunted(llp) => (EntryList::Haunted, llp),
+        };
+        // Counts are updated by commits, so the lists must be held still to read one.
+        let count = {
+            let _inner = self.caller.inner.lock();
+            unsafe { (**llp).as_ref().count }
+        };
+        Some(EntryState {
+            list,
+            count,
+            last_access: self.access_time(k, ci),
+        })
+    }
+
+    /// Summarise the weights of the items resident in the main cache as of the start of
+    /// this read. Items only held in this reader's thread local cache are not included.
+    ///
+    /// The weight of an item is that given by the weigher, even for an oversize item
+    /// accounted as filling the cache by `OversizePolicy::Admit`.
+    ///
+    /// This visits every item of the cache, so is not intended to be called often.
+    pub fn size_histogram(&self) -> SizeHistogram {
+        let weights = self
+            .cache
+            .values()
+            .filter_map(|ci| ci.to_vref().map(self.caller.weigher))
+            .collect();
+        SizeHistogram::from_weights(weights)
+    }
+
+    /// The keys of the items resident in the main cache, in the order that they would
+    /// be evicted. The first key is the next victim, which is the least recently used
+    /// item of the set that the policy evicts from first.
+    ///
+    /// This is a snapshot for testing and debugging. The order is that of the most
+    /// recent commit, which may be newer than this read, and only keys that are
+    /// resident as of this read are included. Any access, or any later commit, may
+    /// change the order.
+    pub fn eviction_order(&self) -> Vec<&K> {
+        self.lru_sample(usize::MAX)
+    }
+
+    /// Up to `n` keys from the front of `eviction_order`, which are the least recently
+    /// used items of the set that the policy evicts from first. This is intended for
+    /// sampling based eviction and admission experiments layered over the cache.
+    ///
+    /// Sampling is not an access, so the recency order of the keys is not changed. The
+    /// order is walked only as far as the `n`th resident key, so a small sample of a
+    /// large cache is cheap, except under `Policy::Lfu` where every item is first
+    /// ordered by its count.
+    pub fn lru_sample(&self, n: usize) -> Vec<&K> {
+        let inner = self.caller.inner.lock();
+        inner
+            .eviction_order()
+            .filter_map(
+                |llp| match self.cache.get_key_value(unsafe { &(*llp).as_ref().k }) {
+                    Some((k, CacheItem::Freq(..))) | Some((k, CacheItem::Rec(..))) => Some(k),
+                    _ => None,
+                },
+            )
+            .take(n)
+            .collect()
+    }
+
     /// Determine if this cache contains the following key.
     pub fn contains_key<'b, Q: ?Sized>(&mut self, k: &'b Q) -> bool
     where
@@ -1491,6 +2843,7 @@ impl<
     /// heed this warning, you may alter the fabric of time and space and have some interesting
     /// distortions in your data over time.
     pub fn insert(&mut self, k: K, mut v: V) {
+        self.caller.assert_fits(&v);
         // Send a copy forward through time and space.
         self.tx
             .send(CacheEvent::Include(
@@ -1525,6 +2878,24 @@ impl<
     }
 }
 
+impl<'a, K, T> ARCacheReadTxn<'a, K, Arc<T>>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    T: Debug + Sync + Send + 'static,
+{
+    /// As `get`, returning a clone of the `Arc` of the value rather than a reference.
+    /// This only increments the count of the `Arc`, and never copies the value, so it
+    /// can be held after this read transaction is dropped, or after the item has been
+    /// evicted from the cache.
+    pub fn get_arc<'b, Q: ?Sized>(&'b self, k: &'b Q) -> Option<Arc<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + Ord,
+    {
+        self.get(k).cloned()
+    }
+}
+
 impl<
         'a,
         K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
@@ -1541,6 +2912,7 @@ mod tests {
     use crate::arcache::ARCache as Arc;
     use crate::arcache::CStat;
     use crate::arcache::CacheState;
+    use crate::arcache::{ARCacheBuilder, OversizePolicy, Policy, SizeHistogram};
 
     #[test]
     fn test_cache_arc_basic() {
@@ -2225,4 +3597,947 @@ mod tests {
         assert!(wr_txn.peek_cache(&3) == CacheState::Rec);
         assert!(wr_txn.peek_cache(&4) == CacheState::Rec);
     }
+
+    // Run 1 as hot, 2 as recently used, then include 3 to force an eviction.
+    fn policy_victim_sequence(policy: Policy) -> Arc<usize, usize> {
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(2, 0)
+            .policy(policy)
+            .build()
+            .expect("Invalid cache parameters");
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, 1);
+        wr_txn.insert(2, 2);
+        wr_txn.commit();
+
+        for _ in 0..2 {
+            let wr_txn = arc.write();
+            assert!(wr_txn.get(&1) == Some(&1));
+            wr_txn.commit();
+        }
+        let wr_txn = arc.write();
+        assert!(wr_txn.get(&2) == Some(&2));
+        wr_txn.commit();
+
+        let mut wr_txn = arc.write();
+        wr_txn.insert(3, 3);
+        wr_txn.commit();
+        arc
+    }
+
+    #[test]
+    fn test_cache_eviction_order() {
+        for (policy, expect) in [
+            (Policy::Arc, [3, 4, 1, 2]),
+            (Policy::Lru, [3, 4, 1, 2]),
+            (Policy::Lfu, [3, 4, 2, 1]),
+        ]
+        .iter()
+        {
+            let arc: Arc<usize, usize> = ARCacheBuilder::new()
+                .set_size(4, 0)
+                .policy(*policy)
+                .build()
+                .expect("Invalid cache parameters");
+            for k in 1..=4 {
+                let mut wr_txn = arc.write();
+                wr_txn.insert(k, k);
+                wr_txn.commit();
+            }
+            for k in [1, 1, 2].iter() {
+                let wr_txn = arc.write();
+                assert!(wr_txn.get(k).is_some());
+                wr_txn.commit();
+            }
+            {
+                let rd_txn = arc.read();
+                let order: Vec<usize> = rd_txn.eviction_order().into_iter().cloned().collect();
+                assert!(order == expect);
+            }
+
+            // A sample is a prefix of the order, and does not change it.
+            {
+                let rd_txn = arc.read();
+                let sample: Vec<usize> = rd_txn.lru_sample(2).into_iter().cloned().collect();
+                assert!(sample[..] == expect[..2]);
+                assert!(rd_txn.lru_sample(0).is_empty());
+                assert!(rd_txn.lru_sample(10).len() == 4);
+                let order: Vec<usize> = rd_txn.eviction_order().into_iter().cloned().collect();
+                assert!(order == expect);
+            }
+
+            // Each new item evicts exactly the reported next victim.
+            for n in 0..3 {
+                let order: Vec<usize> = arc.read().eviction_order().into_iter().cloned().collect();
+                let mut wr_txn = arc.write();
+                wr_txn.insert(100 + n, 0);
+                wr_txn.commit();
+                let wr_txn = arc.write();
+                for (i, k) in order.iter().enumerate() {
+                    let resident =
+                        matches!(wr_txn.peek_cache(k), CacheState::Freq | CacheState::Rec);
+                    assert!(resident == (i != 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cache_shrink_to() {
+        for policy in [Policy::Arc, Policy::Lru, Policy::Lfu].iter() {
+            let arc: Arc<usize, usize> = ARCacheBuilder::new()
+                .set_size(4, 0)
+                .policy(*policy)
+                .set_eviction_budget(1)
+                .build()
+                .expect("Invalid cache parameters");
+            for k in 1..=4 {
+                let mut wr_txn = arc.write();
+                wr_txn.insert(k, k);
+                wr_txn.commit();
+            }
+            for k in [1, 1, 2].iter() {
+                let wr_txn = arc.write();
+                assert!(wr_txn.get(k).is_some());
+                wr_txn.commit();
+            }
+            let order: Vec<usize> = arc.read().eviction_order().into_iter().cloned().collect();
+
+            // The first victims of the order are evicted, regardless of the budget,
+            // and the shrink stops at the target.
+            let mut wr_txn = arc.write();
+            wr_txn.shrink_to(3);
+            wr_txn.shrink_to(2);
+            wr_txn.commit();
+            let after: Vec<usize> = arc.read().eviction_order().into_iter().cloned().collect();
+            assert!(after[..] == order[2..]);
+            let wr_txn = arc.write();
+            for k in order[..2].iter() {
+                assert!(!matches!(
+                    wr_txn.peek_cache(k),
+                    CacheState::Freq | CacheState::Rec
+                ));
+            }
+            drop(wr_txn);
+
+            // The max is not changed, so the cache can fill again.
+            assert!(arc.view_stats().shared_max == 4);
+            for k in 10..12 {
+                let mut wr_txn = arc.write();
+                wr_txn.insert(k, k);
+                wr_txn.commit();
+            }
+            assert!(arc.read().eviction_order().len() == 4);
+
+            // A target above the resident weight evicts nothing.
+            let mut wr_txn = arc.write();
+            wr_txn.shrink_to(8);
+            wr_txn.commit();
+            assert!(arc.read().eviction_order().len() == 4);
+        }
+
+        // Under ARC the ghost sets are bounded by the max rather than the target, so
+        // the oldest history gives way to the victims of the shrink, and rec with its
+        // ghosts still fills the max.
+        let arc: Arc<usize, usize> = Arc::new_size(4, 0);
+        for k in 1..=8 {
+            let mut wr_txn = arc.write();
+            wr_txn.insert(k, k);
+            wr_txn.commit();
+        }
+        assert!(arc.write().peek_stat().ghost_rec == 4);
+        let order: Vec<usize> = arc.read().eviction_order().into_iter().cloned().collect();
+        let mut wr_txn = arc.write();
+        wr_txn.shrink_to(1);
+        wr_txn.commit();
+        let wr_txn = arc.write();
+        assert!(wr_txn.peek_stat().rec == 1 && wr_txn.peek_stat().ghost_rec == 3);
+        for k in order[..3].iter() {
+            assert!(wr_txn.peek_cache(k) == CacheState::GhostRec);
+        }
+    }
+
+    #[test]
+    fn test_cache_policy_builder() {
+        assert!(ARCacheBuilder::<usize, usize>::new().build().is_none());
+        assert!(ARCacheBuilder::<usize, usize>::new()
+            .set_size(0, 0)
+            .build()
+            .is_none());
+        assert!(ARCacheBuilder::<usize, usize>::new()
+            .set_expected_workload(64, 4, 2, 2, true)
+            .build()
+            .is_some());
+    }
+
+    #[test]
+    fn test_cache_policy_arc() {
+        let arc = policy_victim_sequence(Policy::Arc);
+        let wr_txn = arc.write();
+        // 1 and 2 were promoted to freq, so the new item 3 is evicted from rec.
+        assert!(wr_txn.peek_cache(&1) == CacheState::Freq);
+        assert!(wr_txn.peek_cache(&2) == CacheState::Freq);
+        assert!(wr_txn.peek_cache(&3) == CacheState::GhostRec);
+    }
+
+    #[test]
+    fn test_cache_policy_lru() {
+        let arc = policy_victim_sequence(Policy::Lru);
+        let wr_txn = arc.write();
+        // 1 is hot, but least recently used.
+        assert!(wr_txn.peek_cache(&1) == CacheState::Haunted);
+        assert!(wr_txn.peek_cache(&2) == CacheState::Rec);
+        assert!(wr_txn.peek_cache(&3) == CacheState::Rec);
+        let stat = wr_txn.peek_stat();
+        assert!(stat.freq == 0 && stat.ghost_freq == 0 && stat.ghost_rec == 0);
+        drop(wr_txn);
+        assert!(arc.view_stats().recent_evicts == 1);
+    }
+
+    #[test]
+    fn test_cache_policy_lfu() {
+        let arc = policy_victim_sequence(Policy::Lfu);
+        {
+            let wr_txn = arc.write();
+            // 3 has the lowest count of hits, so despite being the most recent
+            // it's the first to go.
+            assert!(wr_txn.peek_cache(&1) == CacheState::Freq);
+            assert!(wr_txn.peek_cache(&2) == CacheState::Freq);
+            assert!(wr_txn.peek_cache(&3) == CacheState::Haunted);
+            let stat = wr_txn.peek_stat();
+            assert!(stat.rec == 0 && stat.ghost_freq == 0 && stat.ghost_rec == 0);
+        }
+        assert!(arc.view_stats().freq_evicts == 1);
+
+        // Now with equal counts, the least recent is evicted.
+        let mut wr_txn = arc.write();
+        wr_txn.insert(4, 4);
+        wr_txn.commit();
+        let mut wr_txn = arc.write();
+        wr_txn.insert(5, 5);
+        wr_txn.commit();
+        let wr_txn = arc.write();
+        assert!(wr_txn.peek_cache(&1) == CacheState::Freq);
+        assert!(wr_txn.peek_cache(&2) == CacheState::Freq);
+        assert!(wr_txn.peek_cache(&4) == CacheState::Haunted);
+        assert!(wr_txn.peek_cache(&5) == CacheState::Haunted);
+    }
+
+    #[test]
+    fn test_cache_policy_clear() {
+        for policy in [Policy::Lru, Policy::Lfu].iter() {
+            let arc: Arc<usize, usize> = ARCacheBuilder::new()
+                .set_size(4, 0)
+                .policy(*policy)
+                .build()
+                .expect("Invalid cache parameters");
+            let mut wr_txn = arc.write();
+            wr_txn.insert(1, 1);
+            wr_txn.insert(2, 2);
+            wr_txn.commit();
+            let mut wr_txn = arc.write();
+            wr_txn.clear();
+            wr_txn.commit();
+            let wr_txn = arc.write();
+            assert!(wr_txn.peek_cache(&1) == CacheState::Haunted);
+            assert!(wr_txn.peek_cache(&2) == CacheState::Haunted);
+        }
+    }
+
+    #[test]
+    fn test_cache_size_histogram() {
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(1000, 0)
+            .set_weigher(|v| *v)
+            .build()
+            .expect("Invalid cache parameters");
+        assert!(arc.read().size_histogram() == SizeHistogram::default());
+
+        let mut wr_txn = arc.write();
+        for w in 1..=10 {
+            wr_txn.insert(w, w);
+        }
+        wr_txn.insert(11, 100);
+        wr_txn.commit();
+
+        let hist = arc.read().size_histogram();
+        assert!(hist.count == 11);
+        assert!(hist.total == 155);
+        assert!(hist.min == 1);
+        assert!(hist.max == 100);
+        assert!((hist.mean - 155.0 / 11.0).abs() < f64::EPSILON);
+        assert!(hist.p50 == 6);
+        assert!(hist.p90 == 10);
+        assert!(hist.p99 == 100);
+
+        // Removed items are no longer resident.
+        let mut wr_txn = arc.write();
+        wr_txn.remove(11);
+        wr_txn.commit();
+        let hist = arc.read().size_histogram();
+        assert!(hist.count == 10 && hist.max == 10 && hist.p99 == 10);
+    }
+
+    #[test]
+    fn test_cache_weighted_evict_stats() {
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(10, 0)
+            .set_weigher(|v| *v)
+            .build()
+            .expect("Invalid cache parameters");
+        let mut wr_txn = arc.write();
+        for k in 0..5 {
+            wr_txn.insert(k, 2);
+        }
+        wr_txn.commit();
+
+        // Two items of weight three displace three items of weight two.
+        let mut wr_txn = arc.write();
+        wr_txn.insert(5, 3);
+        wr_txn.insert(6, 3);
+        wr_txn.commit();
+        let stats = arc.view_stats();
+        assert!(stats.freq_evicts == 0);
+        assert!(stats.recent_evicts == 3);
+        assert!(stats.recent == 4);
+    }
+
+    #[test]
+    fn test_cache_try_insert() {
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(4, 0)
+            .set_weigher(|v| *v)
+            .build()
+            .expect("Invalid cache parameters");
+        let mut wr_txn = arc.write();
+        // A single value heavier than the whole cache is handed back.
+        match wr_txn.try_insert(1, 5) {
+            Err(rej) => assert!(rej.into_inner() == (1, 5)),
+            Ok(_) => panic!("Over capacity value was accepted"),
+        }
+        assert!(wr_txn.get(&1).is_none());
+        assert!(wr_txn.try_insert(1, 2).unwrap().is_none());
+        assert!(wr_txn.try_insert(1, 3).unwrap() == Some(2));
+        wr_txn.commit();
+
+        let mut wr_txn = arc.write();
+        assert!(wr_txn.peek_cache(&1) == CacheState::Rec);
+        // The previous value is found in the main cache.
+        assert!(wr_txn.try_insert(2, 3).unwrap().is_none());
+        assert!(wr_txn.try_insert(1, 3).unwrap() == Some(3));
+        wr_txn.remove(2);
+        assert!(wr_txn.try_insert(2, 3).unwrap().is_none());
+        wr_txn.commit();
+
+        // Both items can't fit by weight, so one must have been evicted.
+        let wr_txn = arc.write();
+        let present = [1, 2]
+            .iter()
+            .filter(|k| matches!(wr_txn.peek_cache(*k), CacheState::Freq | CacheState::Rec))
+            .count();
+        assert!(present == 1);
+    }
+
+    #[test]
+    fn test_cache_with_rng() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let run = |seed: u64| -> Vec<usize> {
+            let arc: Arc<usize, usize> = ARCacheBuilder::new()
+                .set_size(16, 4)
+                .with_admission_filter()
+                .with_rng(StdRng::seed_from_u64(seed))
+                .build()
+                .expect("Invalid cache parameters");
+            // Each commit includes more items than fit, in the order of their hashes.
+            for round in 0..8 {
+                let mut wr_txn = arc.write();
+                wr_txn.insert_batch((0..24).map(|k| (round * 7 + k, k)));
+                wr_txn.commit();
+                let mut rd_txn = arc.read();
+                (0..64).for_each(|k| {
+                    if rd_txn.get(&k).is_none() {
+                        rd_txn.insert(k, k);
+                    }
+                });
+                drop(rd_txn);
+                arc.try_quiesce();
+            }
+            let order = arc.read().eviction_order().into_iter().cloned().collect();
+            order
+        };
+        let a = run(7);
+        assert!(!a.is_empty());
+        assert!(a == run(7));
+    }
+
+    #[test]
+    fn test_cache_on_oversize() {
+        let build = |oversize| -> Arc<usize, usize> {
+            let arc: Arc<usize, usize> = ARCacheBuilder::new()
+                .set_size(4, 0)
+                .set_weigher(|v| *v)
+                .on_oversize(oversize)
+                .build()
+                .expect("Invalid cache parameters");
+            let mut wr_txn = arc.write();
+            wr_txn.insert_batch((1..=3).map(|k| (k, 1)));
+            wr_txn.commit();
+            arc
+        };
+        let resident = |arc: &Arc<usize, usize>| -> Vec<usize> {
+            let mut keys: Vec<usize> = arc.read().eviction_order().into_iter().cloned().collect();
+            keys.sort_unstable();
+            keys
+        };
+
+        // The value is dropped, and replaces any previous value of its key.
+        let arc = build(OversizePolicy::Reject);
+        let mut wr_txn = arc.write();
+        wr_txn.insert(10, 5);
+        wr_txn.insert(3, 5);
+        wr_txn.commit();
+        assert!(resident(&arc) == [1, 2]);
+        let mut rd_txn = arc.read();
+        assert!(rd_txn.get(&3).is_none());
+        rd_txn.insert(11, 5);
+        drop(rd_txn);
+        arc.try_quiesce();
+        assert!(resident(&arc) == [1, 2]);
+
+        // The value is the sole resident until the next include.
+        let arc = build(OversizePolicy::Admit);
+        let mut wr_txn = arc.write();
+        wr_txn.insert(10, 5);
+        wr_txn.commit();
+        assert!(resident(&arc) == [10]);
+        assert!(arc.read().get(&10) == Some(&5));
+        let hist = arc.read().size_histogram();
+        assert!(hist.count == 1 && hist.total == 5 && hist.max == 5);
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, 1);
+        wr_txn.commit();
+        assert!(resident(&arc) == [1]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_cache_on_oversize_panic() {
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(4, 0)
+            .set_weigher(|v| *v)
+            .on_oversize(OversizePolicy::Panic)
+            .build()
+            .expect("Invalid cache parameters");
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, 4);
+        wr_txn.insert(2, 5);
+    }
+
+    #[test]
+    fn test_cache_admission_filter() {
+        // The hits on a hot set that is interleaved with a scan larger than the cache.
+        let hot_hits = |arc: Arc<usize, usize>| -> usize {
+            let mut hits = 0;
+            let mut scan = 1000;
+            for round in 0..40 {
+                let mut wr_txn = arc.write();
+                for k in 0..32 {
+                    if wr_txn.get(&k).is_some() {
+                        hits += (round >= 10) as usize;
+                    } else {
+                        wr_txn.insert(k, k);
+                    }
+                }
+                wr_txn.commit();
+                let mut wr_txn = arc.write();
+                for _ in 0..64 {
+                    wr_txn.insert(scan, scan);
+                    scan += 1;
+                }
+                wr_txn.commit();
+            }
+            hits
+        };
+        let build = |admission: bool| -> Arc<usize, usize> {
+            let builder = ARCacheBuilder::new().set_size(64, 0).policy(Policy::Lru);
+            let builder = if admission {
+                builder.with_admission_filter()
+            } else {
+                builder
+            };
+            builder.build().expect("Invalid cache parameters")
+        };
+
+        // Without the filter every scan evicts the hot set.
+        assert!(hot_hits(build(false)) == 0);
+        assert!(hot_hits(build(true)) == 30 * 32);
+
+        // New items are admitted while there is free space.
+        let arc = build(true);
+        let mut wr_txn = arc.write();
+        wr_txn.insert_batch((0..64).map(|k| (k, k)));
+        wr_txn.commit();
+        assert!(arc.read().eviction_order().len() == 64);
+    }
+
+    #[test]
+    fn test_cache_insert_batch() {
+        let single: Arc<usize, usize> = Arc::new_size(8, 0);
+        let batch: Arc<usize, usize> = Arc::new_size(8, 0);
+
+        let resident = |arc: &Arc<usize, usize>| -> Vec<(usize, CacheState)> {
+            let wr_txn = arc.write();
+            (0..32)
+                .map(|k| (k, wr_txn.peek_cache(&k)))
+                .filter(|(_, s)| matches!(s, CacheState::Freq | CacheState::Rec))
+                .collect()
+        };
+
+        // Batches that fit, then one that overflows and forces eviction. As the
+        // order of inclusion within a commit is not defined, only the shape of
+        // the cache is compared once eviction occurs.
+        for (round, range) in [(0..4), (2..8), (6..32)].iter().cloned().enumerate() {
+            let mut wr_txn = single.write();
+            for k in range.clone() {
+                wr_txn.insert(k, k * 2);
+            }
+            wr_txn.commit();
+
+            let mut wr_txn = batch.write();
+            wr_txn.insert_batch(range.clone().map(|k| (k, k * 2)));
+            wr_txn.commit();
+
+            assert!(single.write().peek_stat() == batch.write().peek_stat());
+            assert!(*single.view_stats() == *batch.view_stats());
+            let (s_res, b_res) = (resident(&single), resident(&batch));
+            if round < 2 {
+                assert!(s_res == b_res);
+            } else {
+                assert!(s_res.len() == b_res.len());
+            }
+        }
+
+        let b_res = resident(&batch);
+        let wr_txn = batch.write();
+        assert!(b_res.iter().all(|(k, _)| wr_txn.get(k) == Some(&(k * 2))));
+    }
+
+    #[test]
+    fn test_cache_last_access() {
+        use crate::arcache::{Clock, ManualClock};
+        use std::time::Duration;
+
+        let clock = ManualClock::new();
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(4, 0)
+            .set_track_access(true)
+            .with_clock(clock.clone())
+            .build()
+            .expect("Invalid cache parameters");
+        let before = clock.now();
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, 1);
+        wr_txn.insert(2, 2);
+        wr_txn.commit();
+
+        let rd_txn = arc.read();
+        let first = rd_txn.last_access(&1).expect("No access recorded");
+        assert!(first == before);
+        assert!(rd_txn.last_access(&3).is_none());
+        // Querying the access time does not count as an access.
+        drop(rd_txn);
+        arc.write().commit();
+        assert!(arc.read().last_access(&1) == Some(first));
+
+        // A promoting get updates the access time once committed.
+        clock.advance(Duration::from_millis(10));
+        {
+            let rd_txn = arc.read();
+            assert!(rd_txn.get(&1) == Some(&1));
+        }
+        arc.write().commit();
+        let rd_txn = arc.read();
+        let second = rd_txn.last_access(&1).expect("No access recorded");
+        assert!(second == first + Duration::from_millis(10));
+        // The untouched key keeps its time.
+        assert!(rd_txn.last_access(&2) == Some(first));
+        drop(rd_txn);
+
+        // An item that is no longer resident has no access time.
+        let mut wr_txn = arc.write();
+        wr_txn.remove(2);
+        wr_txn.commit();
+        assert!(arc.read().last_access(&2).is_none());
+
+        // The times of evicted and cleared items are removed, so the table is bounded
+        // by the resident items.
+        let tracked = |arc: &Arc<usize, usize>| arc.access.as_ref().unwrap().read().len();
+        let mut wr_txn = arc.write();
+        for k in 10..30 {
+            wr_txn.insert(k, k);
+        }
+        wr_txn.commit();
+        assert!(tracked(&arc) <= 4);
+        let mut wr_txn = arc.write();
+        wr_txn.clear();
+        wr_txn.commit();
+        assert!(tracked(&arc) == 0);
+
+        // Without tracking there is nothing recorded, nor any table to record it in.
+        let arc: Arc<usize, usize> = Arc::new_size(4, 0);
+        assert!(arc.access.is_none());
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, 1);
+        wr_txn.commit();
+        assert!(arc.read().last_access(&1).is_none());
+    }
+
+    #[test]
+    fn test_cache_weak_values() {
+        use std::sync::{Arc as SArc, Weak};
+
+        let arc: Arc<usize, Weak<String>> = ARCacheBuilder::new()
+            .set_size(4, 4)
+            .weak_values()
+            .build()
+            .expect("Invalid cache parameters");
+        let a = SArc::new("a".to_string());
+        let b = SArc::new("b".to_string());
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, SArc::downgrade(&a));
+        wr_txn.insert(2, SArc::downgrade(&b));
+        wr_txn.commit();
+
+        let rd_txn = arc.read();
+        assert!(rd_txn.get(&1).and_then(|w| w.upgrade()) == Some(a.clone()));
+        drop(rd_txn);
+
+        // Once the last external Arc is dropped, the key is a miss, even before the
+        // item is collected.
+        drop(a);
+        let mut rd_txn = arc.read();
+        assert!(rd_txn.get(&1).is_none());
+        assert!(!rd_txn.contains_key(&1));
+        assert!(rd_txn.get(&2).is_some());
+        drop(rd_txn);
+
+        let wr_txn = arc.write();
+        assert!(wr_txn.get(&1).is_none());
+        wr_txn.commit();
+
+        // Commit removes the expired item.
+        let mut wr_txn = arc.write();
+        assert!(wr_txn.peek_cache(&1) == CacheState::Haunted);
+        assert!(wr_txn.peek_cache(&2) != CacheState::Haunted);
+        assert!(wr_txn.get(&2).is_some());
+        let (c, d) = (SArc::new("c".to_string()), SArc::new("d".to_string()));
+        wr_txn.insert(3, SArc::downgrade(&c));
+        wr_txn.commit();
+        let mut wr_txn = arc.write();
+        wr_txn.insert(4, SArc::downgrade(&d));
+        wr_txn.commit();
+
+        // An expired item behind one that has not expired is a miss, but is only
+        // removed once it is the oldest.
+        drop(d);
+        arc.write().commit();
+        let mut wr_txn = arc.write();
+        assert!(wr_txn.get(&4).is_none());
+        assert!(wr_txn.peek_cache(&4) != CacheState::Haunted);
+        wr_txn.remove(3);
+        wr_txn.commit();
+        assert!(arc.write().peek_cache(&4) == CacheState::Haunted);
+        drop(c);
+
+        // Without weak_values, expired values are still returned.
+        let arc: Arc<usize, Weak<String>> = Arc::new_size(4, 0);
+        let c = SArc::new("c".to_string());
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, SArc::downgrade(&c));
+        wr_txn.commit();
+        drop(c);
+        assert!(arc.read().get(&1).is_some());
+    }
+
+    #[test]
+    fn test_cache_get_arc() {
+        use std::sync::Arc as SArc;
+
+        // Under LRU, the hit below does not protect the item from eviction.
+        let arc: Arc<usize, SArc<String>> = ARCacheBuilder::new()
+            .set_size(4, 0)
+            .policy(Policy::Lru)
+            .build()
+            .expect("Invalid cache parameters");
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, SArc::new("a".repeat(1024)));
+        wr_txn.commit();
+
+        let rd_txn = arc.read();
+        let a = rd_txn.get_arc(&1).expect("Missing value");
+        assert!(rd_txn.get_arc(&2).is_none());
+        // The cache and the caller share the one value.
+        assert!(SArc::ptr_eq(&a, rd_txn.get(&1).unwrap()));
+        drop(rd_txn);
+        assert!(a.len() == 1024);
+
+        // Evict the item with a run of other keys.
+        for i in 2..32 {
+            let mut wr_txn = arc.write();
+            wr_txn.insert(i, SArc::new(i.to_string()));
+            wr_txn.commit();
+        }
+        assert!(arc.read().get_arc(&1).is_none());
+        assert!(a.len() == 1024 && a.starts_with('a'));
+        assert!(SArc::strong_count(&a) == 1);
+    }
+
+    #[test]
+    fn test_cache_lfu_aging() {
+        use crate::arcache::ManualClock;
+        use std::time::Duration;
+
+        let clock = ManualClock::new();
+        let build = |aging: Option<Duration>| {
+            let b = ARCacheBuilder::new()
+                .set_size(2, 0)
+                .policy(Policy::Lfu)
+                .with_clock(clock.clone());
+            let b = match aging {
+                Some(halflife) => b.set_aging(halflife),
+                None => b,
+            };
+            b.build().expect("Invalid cache parameters")
+        };
+        let aged: Arc<usize, usize> = build(Some(Duration::from_millis(20)));
+        let unaged: Arc<usize, usize> = build(None);
+        assert!(ARCacheBuilder::<usize, usize>::new()
+            .set_size(2, 0)
+            .set_aging(Duration::from_secs(0))
+            .build()
+            .is_none());
+
+        for arc in [&aged, &unaged].iter() {
+            let mut wr_txn = arc.write();
+            wr_txn.insert(1, 1);
+            wr_txn.commit();
+            // 1 becomes hot.
+            for _ in 0..8 {
+                let wr_txn = arc.write();
+                assert!(wr_txn.get(&1).is_some());
+                wr_txn.commit();
+            }
+            let mut wr_txn = arc.write();
+            wr_txn.insert(2, 2);
+            wr_txn.commit();
+            // 2 is the least frequently used.
+            let mut wr_txn = arc.write();
+            wr_txn.insert(3, 3);
+            wr_txn.commit();
+            let wr_txn = arc.write();
+            assert!(wr_txn.peek_cache(&1) == CacheState::Freq);
+            assert!(wr_txn.peek_cache(&2) == CacheState::Haunted);
+            assert!(wr_txn.peek_cache(&3) == CacheState::Freq);
+        }
+
+        // Accesses to 1 stop, and enough time passes for its count to decay.
+        clock.advance(Duration::from_millis(100));
+        for arc in [&aged, &unaged].iter() {
+            let mut wr_txn = arc.write();
+            wr_txn.insert(4, 4);
+            wr_txn.commit();
+        }
+
+        // When aged the cold key is evicted, but otherwise it would remain forever.
+        let wr_txn = aged.write();
+        assert!(wr_txn.peek_cache(&1) == CacheState::Haunted);
+        assert!(wr_txn.peek_cache(&3) == CacheState::Freq);
+        assert!(wr_txn.peek_cache(&4) == CacheState::Freq);
+        drop(wr_txn);
+        let wr_txn = unaged.write();
+        assert!(wr_txn.peek_cache(&1) == CacheState::Freq);
+        assert!(wr_txn.peek_cache(&3) == CacheState::Haunted);
+        assert!(wr_txn.peek_cache(&4) == CacheState::Freq);
+        drop(wr_txn);
+
+        // More halvings than a count has bits leaves every count at zero.
+        clock.advance(Duration::from_millis(20) * 1000);
+        aged.write().commit();
+        let inner = aged.inner.lock();
+        assert!(inner
+            .freq
+            .iter_nodes()
+            .all(|n| unsafe { (*n).as_ref().count } == 0));
+    }
+
+    #[test]
+    fn test_cache_manual_clock() {
+        use crate::arcache::{Clock, ManualClock};
+        use std::time::Duration;
+
+        let clock = ManualClock::new();
+        let halflife = Duration::from_millis(20);
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(4, 0)
+            .policy(Policy::Lfu)
+            .set_aging(halflife)
+            .set_track_access(true)
+            .with_clock(clock.clone())
+            .build()
+            .expect("Invalid cache parameters");
+        let count = |k: usize| {
+            let inner = arc.inner.lock();
+            let n = inner
+                .freq
+                .iter_nodes()
+                .find(|n| unsafe { (**n).as_ref().k == k });
+            n.map(|n| unsafe { (*n).as_ref().count })
+        };
+
+        let start = clock.now();
+        let mut wr_txn = arc.write();
+        wr_txn.insert(1, 1);
+        wr_txn.commit();
+        for _ in 0..8 {
+            let wr_txn = arc.write();
+            assert!(wr_txn.get(&1).is_some());
+            wr_txn.commit();
+        }
+        let hot = count(1).expect("Missing item");
+        assert!(hot >= 8);
+        assert!(arc.read().last_access(&1) == Some(start));
+
+        // The counts are aged exactly once the halflife has passed.
+        clock.advance(halflife - Duration::from_nanos(1));
+        arc.write().commit();
+        assert!(count(1) == Some(hot));
+        clock.advance(Duration::from_nanos(1));
+        arc.write().commit();
+        assert!(count(1) == Some(hot >> 1));
+        clock.advance(halflife * 3);
+        arc.write().commit();
+        assert!(count(1) == Some(hot >> 4));
+
+        // A read hit is stamped with the time of the read, even though the clock has
+        // not moved by the commit.
+        {
+            let rd_txn = arc.read();
+            assert!(rd_txn.get(&1).is_some());
+        }
+        arc.write().commit();
+        assert!(arc.read().last_access(&1) == Some(start + halflife * 4));
+    }
+
+    #[test]
+    fn test_cache_entry_state() {
+        use crate::arcache::EntryList;
+
+        let arc: Arc<usize, usize> = ARCacheBuilder::new()
+            .set_size(4, 0)
+            .set_track_access(true)
+            .build()
+            .expect("Invalid cache parameters");
+        let mut wr_txn = arc.write();
+        (1..=4).for_each(|k| wr_txn.insert(k, k));
+        wr_txn.commit();
+        assert!(arc.read().entry_state(&2).map(|s| s.list) == Some(EntryList::Recent));
+
+        // Inspecting a key is not an access of it.
+        for _ in 0..3 {
+            assert!(arc.read().entry_state(&2).is_some());
+            arc.write().commit();
+        }
+        let state = arc.read().entry_state(&2).expect("Missing item");
+        assert!(state.list == EntryList::Recent && state.count == 1);
+
+        // A frequently accessed key is in the frequent list.
+        for _ in 0..3 {
+            let wr_txn = arc.write();
+            assert!(wr_txn.get(&1).is_some());
+            wr_txn.commit();
+        }
+
+        // Keys of a scan are only seen once, so are evicted from the recent list.
+        for k in 100..108 {
+            let mut wr_txn = arc.write();
+            wr_txn.insert(k, k);
+            wr_txn.commit();
+        }
+        let rd_txn = arc.read();
+        let hot = rd_txn.entry_state(&1).expect("Missing item");
+        assert!(hot.list == EntryList::Frequent);
+        assert!(hot.count >= 3);
+        assert!(hot.last_access.is_some() && hot.last_access == rd_txn.last_access(&1));
+        let scan: Vec<_> = (100..108)
+            .map(|k| rd_txn.entry_state(&k).expect("Missing item").list)
+            .collect();
+        assert!(scan.iter().all(|l| *l == EntryList::Recent
+            || *l == EntryList::GhostRecent
+            || *l == EntryList::Haunted));
+        assert!(scan.contains(&EntryList::Recent));
+        let ghost = rd_txn.entry_state(&104).expect("Missing item");
+        assert!(ghost.list == EntryList::GhostRecent && ghost.last_access.is_none());
+        // The ghost lists are bounded, so the oldest keys of the scan are forgotten.
+        assert!(rd_txn.entry_state(&100).map(|s| s.list) == Some(EntryList::Haunted));
+        assert!(rd_txn.entry_state(&99).is_none());
+        std::mem::drop(rd_txn);
+
+        // A removed key is haunted.
+        let mut wr_txn = arc.write();
+        wr_txn.remove(1);
+        wr_txn.commit();
+        let state = arc.read().entry_state(&1).map(|s| s.list);
+        assert!(state == Some(EntryList::Haunted));
+    }
+
+    #[test]
+    fn test_cache_eviction_budget() {
+        assert!(ARCacheBuilder::<usize, usize>::new()
+            .set_size(16, 0)
+            .set_eviction_budget(0)
+            .build()
+            .is_none());
+
+        for policy in [Policy::Arc, Policy::Lru, Policy::Lfu].iter() {
+            let arc: Arc<usize, usize> = ARCacheBuilder::new()
+                .set_size(16, 0)
+                .policy(*policy)
+                .set_eviction_budget(4)
+                .build()
+                .expect("Invalid cache parameters");
+            let evicts = || {
+                let stats = arc.view_stats();
+                stats.freq_evicts + stats.recent_evicts
+            };
+            let resident = || {
+                let stats = arc.view_stats();
+                stats.freq + stats.recent
+            };
+
+            // A large over-insert only evicts up to the budget, leaving the cache oversized.
+            let mut wr_txn = arc.write();
+            for i in 0..64 {
+                wr_txn.insert(i, i);
+            }
+            wr_txn.commit();
+            assert!(evicts() == 4);
+            assert!(resident() == 60);
+
+            // Each following commit evicts no more than the budget until the size settles.
+            let mut commits = 0;
+            while resident() > 16 {
+                let before = evicts();
+                arc.write().commit();
+                assert!(evicts() - before <= 4);
+                commits += 1;
+            }
+            assert!(commits == 11);
+            assert!(resident() == 16);
+            let before = evicts();
+            arc.write().commit();
+            assert!(evicts() == before);
+        }
+    }
 }
@@ -0,0 +1,305 @@
+//! A sharded ARCache, where keys are partitioned over a number of independent
+//! caches. See `ARCache::new_sharded`.
+
+use super::{ARCache, ARCacheBuilder, ARCacheReadTxn, ARCacheWriteTxn};
+use ahash::AHasher;
+use rand::{Rng, RngCore};
+use std::borrow::Borrow;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+
+/// An `ARCache` that is partitioned into a number of shards by the hash of the key.
+/// Each shard is a complete `ARCache` with its own writer, so writes to keys that
+/// belong to different shards can proceed in parallel.
+///
+/// Each shard adapts to its own workload independently. This means the balance
+/// between recent and frequent items, and the eviction decisions, are made per-shard
+/// rather than over the cache as a whole.
+pub struct ShardedARCache<K, V>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+{
+    shards: Vec<ARCache<K, V>>,
+    key1: u128,
+    key2: u128,
+}
+
+/// A read transaction over all shards of a `ShardedARCache`. See `ARCacheReadTxn`.
+pub struct ShardedARCacheReadTxn<'a, K, V>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+{
+    caller: &'a ShardedARCache<K, V>,
+    shards: Vec<ARCacheReadTxn<'a, K, V>>,
+}
+
+/// A write transaction over a single shard of a `ShardedARCache`. See `ARCacheWriteTxn`.
+///
+/// Only keys that belong to this shard may be used with this transaction, else a
+/// panic will occur.
+pub struct ShardedARCacheWriteTxn<'a, K, V>
+where
+    K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Debug + Sync + Send + 'static,
+{
+    caller: &'a ShardedARCache<K, V>,
+    idx: usize,
+    work: ARCacheWriteTxn<'a, K, V>,
+}
+
+impl<
+        K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+        V: Clone + Debug + Sync + Send + 'static,
+    > ShardedARCache<K, V>
+{
+    pub(crate) fn new<R: RngCore>(shards: usize, capacity: usize, mut rng: R) -> Self {
+        assert!(shards > 0);
+        assert!(capacity >= shards);
+        let base = capacity / shards;
+        let rem = capacity % shards;
+        let key1 = rng.gen::<u128>();
+        let key2 = rng.gen::<u128>();
+        let shards = (0..shards)
+            .map(|i| {
+                ARCacheBuilder::new()
+                    .set_size(if i < rem { base + 1 } else { base }, 0)
+                    .with_rng(&mut rng)
+                    .build()
+                    .expect("Invalid shard parameters")
+            })
+            .collect();
+        ShardedARCache { shards, key1, key2 }
+    }
+
+    fn shard_idx<Q: ?Sized>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash,
+    {
+        let mut hasher = AHasher::new_with_keys(self.key1, self.key2);
+        k.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// The number of shards in this cache.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Access the shard that owns this key.
+    pub fn shard<Q: ?Sized>(&self, k: &Q) -> &ARCache<K, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash,
+    {
+        &self.shards[self.shard_idx(k)]
+    }
+
+    /// Begin a read operation over every shard of the cache.
+    pub fn read(&self) -> ShardedARCacheReadTxn<K, V> {
+        ShardedARCacheReadTxn {
+            caller: self,
+            shards: self.shards.iter().map(|s| s.read()).collect(),
+        }
+    }
+
+    /// Begin a write operation on the shard that owns this key. This only blocks
+    /// other writers of the same shard.
+    pub fn write<Q: ?Sized>(&self, k: &Q) -> ShardedARCacheWriteTxn<K, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash,
+    {
+        let idx = self.shard_idx(k);
+        ShardedARCacheWriteTxn {
+            caller: self,
+            idx,
+            work: self.shards[idx].write(),
+        }
+    }
+}
+
+impl<
+        'a,
+        K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+        V: Clone + Debug + Sync + Send + 'static,
+    > ShardedARCacheReadTxn<'a, K, V>
+{
+    /// Attempt to retrieve a k-v pair from the shard that owns the key. See
+    /// `ARCacheReadTxn::get`.
+    pub fn get<'b, Q: ?Sized>(&'b self, k: &'b Q) -> Option<&'b V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + Ord,
+    {
+        self.shards[self.caller.shard_idx(k)].get(k)
+    }
+
+    /// Determine if the shard that owns this key contains it.
+    pub fn contains_key<Q: ?Sized>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + Ord,
+    {
+        let idx = self.caller.shard_idx(k);
+        self.shards[idx].contains_key(k)
+    }
+
+    /// Add a value to the shard that owns this key. See `ARCacheReadTxn::insert`.
+    pub fn insert(&mut self, k: K, v: V) {
+        let idx = self.caller.shard_idx(&k);
+        self.shards[idx].insert(k, v)
+    }
+}
+
+impl<
+        'a,
+        K: Hash + Eq + Ord + Clone + Debug + Sync + Send + 'static,
+        V: Clone + Debug + Sync + Send + 'static,
+    > ShardedARCacheWriteTxn<'a, K, V>
+{
+    fn assert_owned<Q: ?Sized>(&self, k: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash,
+    {
+        assert!(
+            self.caller.shard_idx(k) == self.idx,
+            "Key does not belong to the shard of this write transaction"
+        );
+    }
+
+    /// Commit the changes of this writer to its shard. See `ARCacheWriteTxn::commit`.
+    pub fn commit(self) {
+        self.work.commit()
+    }
+
+    /// Attempt to retrieve a k-v pair from this shard. See `ARCacheWriteTxn::get`.
+    pub fn get<'b, Q: ?Sized>(&'a self, k: &'b Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + Ord,
+    {
+        self.assert_owned(k);
+        self.work.get(k)
+    }
+
+    /// Add a value to this shard. See `ARCacheWriteTxn::insert`.
+    pub fn insert(&mut self, k: K, v: V) {
+        self.assert_owned(&k);
+        self.work.insert(k, v)
+    }
+
+    /// Remove a value from this shard. See `ARCacheWriteTxn::remove`.
+    pub fn remove(&mut self, k: K) {
+        self.assert_owned(&k);
+        self.work.remove(k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arcache::ARCache;
+
+    #[test]
+    fn test_cache_sharded_basic() {
+        let cache: super::ShardedARCache<usize, usize> = ARCache::new_sharded(4, 128);
+        assert!(cache.shard_count() == 4);
+
+        for i in 0..32 {
+            let mut wr_txn = cache.write(&i);
+            assert!(wr_txn.get(&i).is_none());
+            wr_txn.insert(i, i * 2);
+            wr_txn.commit();
+        }
+
+        // Keys are spread over the shards, and each is only found in its own.
+        let mut per_shard = [0; 4];
+        for i in 0..32 {
+            let idx = cache.shard_idx(&i);
+            per_shard[idx] += 1;
+            for (sidx, shard) in cache.shards.iter().enumerate() {
+                assert!(shard.read().get(&i).is_some() == (sidx == idx));
+            }
+        }
+        assert!(per_shard.iter().filter(|c| **c > 0).count() > 1);
+
+        let rd_txn = cache.read();
+        for i in 0..32 {
+            assert!(rd_txn.get(&i) == Some(&(i * 2)));
+        }
+        assert!(rd_txn.get(&32).is_none());
+
+        // Updates and removes only affect the owning shard.
+        let mut wr_txn = cache.write(&3);
+        wr_txn.remove(3);
+        wr_txn.commit();
+        let rd_txn = cache.read();
+        assert!(rd_txn.get(&3).is_none());
+        assert!(rd_txn.get(&4) == Some(&8));
+    }
+
+    #[test]
+    fn test_cache_sharded_with_rng() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let run = |seed: u64| -> (Vec<usize>, Vec<Vec<usize>>) {
+            let cache: super::ShardedARCache<usize, usize> =
+                ARCache::new_sharded_with_rng(4, 16, StdRng::seed_from_u64(seed));
+            for k in 0..64 {
+                let mut wr_txn = cache.write(&k);
+                wr_txn.insert(k, k);
+                wr_txn.commit();
+            }
+            let idx = (0..64).map(|k| cache.shard_idx(&k)).collect();
+            let resident = cache
+                .shards
+                .iter()
+                .map(|s| s.read().eviction_order().into_iter().cloned().collect())
+                .collect();
+            (idx, resident)
+        };
+        // The same seed assigns keys to the same shards, which evict the same items.
+        let a = run(7);
+        assert!(a == run(7));
+        assert!(a.0 != run(8).0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_cache_sharded_wrong_shard() {
+        let cache: super::ShardedARCache<usize, usize> = ARCache::new_sharded(2, 16);
+        let other = (1..)
+            .find(|k| cache.shard_idx(k) != cache.shard_idx(&0))
+            .unwrap();
+        let mut wr_txn = cache.write(&0);
+        wr_txn.insert(other, other);
+    }
+
+    #[test]
+    fn test_cache_sharded_independent_writers() {
+        let cache: super::ShardedARCache<usize, usize> = ARCache::new_sharded(2, 16);
+        let a = 0;
+        let b = (1..)
+            .find(|k| cache.shard_idx(k) != cache.shard_idx(&a))
+            .unwrap();
+        // Writers of different shards may be open at the same time.
+        let mut wr_a = cache.write(&a);
+        let mut wr_b = cache.write(&b);
+        wr_a.insert(a, 1);
+        wr_b.insert(b, 2);
+        wr_b.commit();
+        {
+            let rd_txn = cache.read();
+            assert!(rd_txn.get(&a).is_none());
+            assert!(rd_txn.get(&b) == Some(&2));
+        }
+        wr_a.commit();
+        let rd_txn = cache.read();
+        assert!(rd_txn.get(&a) == Some(&1));
+        assert!(rd_txn.get(&b) == Some(&2));
+    }
+}
@@ -0,0 +1,93 @@
+//! A bounded estimate of how often each key is accessed, used by the admission filter
+//! of the cache. See `ARCacheBuilder::with_admission_filter`.
+
+// Each key is counted once in each row, and the estimate is the least of its counts,
+// which limits the effect of collisions with other keys.
+const DEPTH: usize = 4;
+const MAX_COUNT: u8 = 15;
+const MAX_WIDTH: usize = 1 << 20;
+// Distinct odd multipliers, selecting an independent counter of each row.
+const SEEDS: [u64; DEPTH] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xbf58_476d_1ce4_e5b9,
+    0x94d0_49bb_1331_11eb,
+    0xd6e8_feb8_6659_fd93,
+];
+
+#[derive(Debug)]
+pub(crate) struct FrequencySketch {
+    counters: Vec<u8>,
+    mask: usize,
+    additions: usize,
+    // Once this many accesses are counted, every count is halved, so that keys that are
+    // no longer accessed lose their history.
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    // A sketch of eight counters per row for each item of the capacity, so that
+    // collisions are rare.
+    pub(crate) fn new(capacity: usize) -> Self {
+        let width = capacity
+            .saturating_mul(8)
+            .next_power_of_two()
+            .clamp(16, MAX_WIDTH);
+        FrequencySketch {
+            counters: vec![0; width * DEPTH],
+            mask: width - 1,
+            additions: 0,
+            sample_size: capacity.saturating_mul(10).max(16),
+        }
+    }
+
+    fn index(&self, hash: u64, row: usize) -> usize {
+        let h = (hash ^ (hash >> 32)).wrapping_mul(SEEDS[row]);
+        (row * (self.mask + 1)) + ((h >> 32) as usize & self.mask)
+    }
+
+    pub(crate) fn increment(&mut self, hash: u64) {
+        for row in 0..DEPTH {
+            let idx = self.index(hash, row);
+            if self.counters[idx] < MAX_COUNT {
+                self.counters[idx] += 1;
+            }
+        }
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.counters.iter_mut().for_each(|c| *c >>= 1);
+            self.additions /= 2;
+        }
+    }
+
+    pub(crate) fn estimate(&self, hash: u64) -> u8 {
+        (0..DEPTH)
+            .map(|row| self.counters[self.index(hash, row)])
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrequencySketch, MAX_COUNT};
+
+    #[test]
+    fn test_cache_sketch_estimate() {
+        let mut sketch = FrequencySketch::new(64);
+        assert!(sketch.estimate(1) == 0);
+        (0..5).for_each(|_| sketch.increment(1));
+        sketch.increment(2);
+        assert!(sketch.estimate(1) >= 5);
+        assert!(sketch.estimate(2) >= 1 && sketch.estimate(2) < 5);
+        (0..100).for_each(|_| sketch.increment(3));
+        assert!(sketch.estimate(3) == MAX_COUNT);
+
+        // Counting a full sample halves the history.
+        let before = sketch.estimate(3);
+        sketch.additions = sketch.sample_size - 1;
+        sketch.increment(4);
+        assert!(sketch.estimate(3) == before / 2);
+        assert!(sketch.additions == sketch.sample_size / 2);
+        assert!(sketch.counters.len() == 64 * 8 * 4);
+    }
+}
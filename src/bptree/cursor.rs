@@ -6,13 +6,17 @@
 
 use super::node::*;
 use std::borrow::Borrow;
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::mem;
+use std::ops::Bound;
+use std::ptr;
 use std::sync::Arc;
 
-use super::iter::{Iter, KeyIter, ValueIter};
+use super::iter::{IntoIter, Iter, KeyIter, RangeIter, RangeScratch, ValueIter};
 use super::states::*;
-use parking_lot::Mutex;
+use super::{DescentStep, StructureEvent};
+use crate::lock::Mutex;
 use std::iter::Extend;
 
 /// The internal root of the tree, with associated garbage lists etc.
@@ -33,6 +37,12 @@ where
     /// This is the SUPERBLOCKCHAIN that let's us pin future
     /// nodes so that we drop IN ORDER.
     pub(crate) pin_next: Mutex<Option<Arc<SuperBlock<K, V>>>>,
+    /// If this tree was forked from another, nodes with a txid at or below this
+    /// are shared with the origin and are not ours to free.
+    fork_base: u64,
+    /// The origin snapshot, pinned so that the shared nodes remain alive for
+    /// as long as any generation of the fork does.
+    fork_pin: Option<Arc<SuperBlock<K, V>>>,
 }
 
 impl<K: Clone + Ord + Debug, V: Clone> SuperBlock<K, V> {
@@ -50,6 +60,50 @@ impl<K: Clone + Ord + Debug, V: Clone> SuperBlock<K, V> {
         // std::mem::drop(new_last_seen);
         // std::mem::drop(active_last_seen);
     }
+
+    pub(crate) fn fork(origin: &Arc<Self>) -> Self {
+        // Share the root of the origin, all nodes of the origin are at or
+        // below its txid, and all nodes we create from here will be above it.
+        SuperBlock {
+            root: origin.root,
+            size: origin.size,
+            txid: origin.txid,
+            last_seen: Mutex::new(None),
+            pin_next: Mutex::new(None),
+            fork_base: origin.txid,
+            fork_pin: Some(origin.clone()),
+        }
+    }
+
+    #[inline(always)]
+    fn owns(&self, n: *mut Node<K, V>) -> bool {
+        unsafe { (*n).get_txid() > self.fork_base }
+    }
+
+    // Consume the tree of this superblock. If nothing else refers to it, the nodes it
+    // owns are ours to move the entries out of, and otherwise every entry is cloned.
+    pub(crate) fn consume(this: Arc<Self>) -> IntoIter<K, V> {
+        match Arc::try_unwrap(this) {
+            Ok(mut sb) => {
+                let iter = IntoIter::new(sb.root, sb.size, sb.fork_base, sb.fork_pin.take());
+                // The owned leaves now belong to the iterator, and the owned branches
+                // are no longer needed.
+                let mut owned = Vec::new();
+                if sb.owns(sb.root) {
+                    owned.push(sb.root);
+                    unsafe { (*sb.root).sblock_collect_after(sb.fork_base, &mut owned) };
+                }
+                owned
+                    .into_iter()
+                    .filter(|n| unsafe { (**n).meta.is_branch() })
+                    .for_each(Node::free);
+                // Nothing is left for the superblock to free.
+                *sb.last_seen.get_mut() = Some(Vec::new());
+                iter
+            }
+            Err(this) => IntoIter::new(this.root, this.size, u64::MAX, Some(this)),
+        }
+    }
 }
 
 impl<K: Clone + Ord + Debug, V: Clone> Default for SuperBlock<K, V> {
@@ -61,6 +115,8 @@ impl<K: Clone + Ord + Debug, V: Clone> Default for SuperBlock<K, V> {
             txid: 1,
             last_seen: Mutex::new(None),
             pin_next: Mutex::new(None),
+            fork_base: 0,
+            fork_pin: None,
         }
     }
 }
@@ -88,6 +144,43 @@ where
     root: *mut Node<K, V>,
     last_seen: Vec<*mut Node<K, V>>,
     first_seen: Vec<*mut Node<K, V>>,
+    fork_base: u64,
+    fork_pin: Option<Arc<SuperBlock<K, V>>>,
+    hook: StructureHook<K>,
+}
+
+// The receiver of the structure events of a write, if any.
+pub(crate) struct StructureHook<K>(Option<Box<dyn FnMut(StructureEvent<K>)>>);
+
+impl<K> StructureHook<K> {
+    // The event is only built when there is a hook to receive it, as a pivot must be
+    // cloned.
+    fn emit<F: FnOnce() -> StructureEvent<K>>(&mut self, f: F) {
+        if let Some(hook) = self.0.as_mut() {
+            hook(f())
+        }
+    }
+
+    fn emit_merge<V: Clone>(&mut self, dnode: *mut Node<K, V>)
+    where
+        K: Ord + Clone + Debug,
+    {
+        self.emit(|| {
+            if self_meta!(dnode).is_leaf() {
+                StructureEvent::LeafMerge
+            } else {
+                StructureEvent::BranchMerge
+            }
+        })
+    }
+}
+
+impl<K> Debug for StructureHook<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("StructureHook")
+            .field(&self.0.is_some())
+            .finish()
+    }
 }
 
 pub(crate) trait CursorReadOps<K: Clone + Ord + Debug, V: Clone> {
@@ -132,19 +225,104 @@ pub(crate) trait CursorReadOps<K: Clone + Ord + Debug, V: Clone> {
         panic!("Tree depth exceeded max limit (65536). This may indicate memory corruption.");
     }
 
+    // The least and greatest entries within the bounds, each found by a descent
+    // from the root. These nodes will not be altered during the lifetime of this txn,
+    // so the references may be bound to self.
+    fn lower_kv(&self, lower: Bound<&K>) -> Option<(&K, &V)> {
+        lower_kv_of(self.get_root(), lower)
+    }
+
+    fn upper_kv(&self, upper: Bound<&K>) -> Option<(&K, &V)> {
+        upper_kv_of(self.get_root(), upper)
+    }
+
+    fn search_kv<'a, 'b, Q: ?Sized>(&'a self, k: &'b Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        let mut node = self.get_root();
+        for _i in 0..65536 {
+            if unsafe { (*node).is_leaf() } {
+                let lref = leaf_ref!(node, K, V);
+                // As in search, these nodes will not be altered during the lifetime of
+                // this txn, so the references may be rebound to 'a.
+                return lref
+                    .get_kv_ref(k)
+                    .map(|(k, v)| unsafe { (&*(k as *const K), &*(v as *const V)) });
+            } else {
+                let bref = branch_ref!(node, K, V);
+                let idx = bref.locate_node(k);
+                node = bref.get_idx_unchecked(idx);
+            }
+        }
+        panic!("Tree depth exceeded max limit (65536). This may indicate memory corruption.");
+    }
+
+    fn descent_path<Q: ?Sized>(&self, k: &Q) -> Vec<DescentStep<K>>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        let mut path = Vec::new();
+        let mut node = self.get_root();
+        while !unsafe { (*node).is_leaf() } {
+            let bref = branch_ref!(node, K, V);
+            let child = bref.locate_node(k);
+            path.push(DescentStep {
+                level: path.len(),
+                pivots: bref.pivots().to_vec(),
+                child,
+            });
+            node = bref.get_idx_unchecked(child);
+        }
+        path
+    }
+
     #[allow(clippy::needless_lifetimes)]
     fn contains_key<'a, 'b, Q: ?Sized>(&'a self, k: &'b Q) -> bool
     where
         K: Borrow<Q>,
         Q: Ord,
     {
-        self.search(k).is_some()
+        // Only the keys of the leaf are searched, so the value is never read.
+        let mut node = self.get_root();
+        for _i in 0..65536 {
+            if unsafe { (*node).is_leaf() } {
+                return leaf_ref!(node, K, V).contains_key(k);
+            } else {
+                let bref = branch_ref!(node, K, V);
+                node = bref.get_idx_unchecked(bref.locate_node(k));
+            }
+        }
+        panic!("Tree depth exceeded max limit (65536). This may indicate memory corruption.");
+    }
+
+    fn node_count(&self) -> usize {
+        self.get_root_ref().node_count()
+    }
+
+    fn height(&self) -> usize {
+        self.get_root_ref().height()
     }
 
     fn kv_iter(&self) -> Iter<K, V> {
         Iter::new(self.get_root(), self.len())
     }
 
+    fn range_iter(&self, lower: Bound<K>, upper: Bound<K>) -> RangeIter<K, V> {
+        RangeIter::new(self.get_root(), self.len(), lower, upper)
+    }
+
+    fn range_iter_in<'a>(
+        &'a self,
+        lower: Bound<K>,
+        upper: Bound<K>,
+        scratch: &'a mut RangeScratch<K, V>,
+    ) -> RangeIter<'a, K, V> {
+        RangeIter::new_in(self.get_root(), self.len(), lower, upper, scratch)
+    }
+
     fn k_iter(&self) -> KeyIter<K, V> {
         KeyIter::new(self.get_root(), self.len())
     }
@@ -155,10 +333,13 @@ pub(crate) trait CursorReadOps<K: Clone + Ord + Debug, V: Clone> {
 
     #[cfg(test)]
     fn verify(&self) -> bool {
-        self.get_root_ref().no_cycles() && self.get_root_ref().verify() && {
-            let (l, _) = self.get_tree_density();
-            l == self.len()
-        }
+        self.get_root_ref().no_cycles()
+            && self.get_root_ref().verify()
+            && self.get_root_ref().verify_pivots()
+            && {
+                let (l, _) = self.get_tree_density();
+                l == self.len()
+            }
     }
 }
 
@@ -180,6 +361,9 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
             root,
             last_seen,
             first_seen,
+            fork_base: sblock.fork_base,
+            fork_pin: sblock.fork_pin.clone(),
+            hook: StructureHook(None),
         }
     }
 
@@ -208,6 +392,9 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
             root,
             last_seen,
             first_seen,
+            fork_base: 0,
+            fork_pin: None,
+            hook: StructureHook(None),
         }
     }
 
@@ -231,12 +418,43 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
             txid: self.txid,
             last_seen: Mutex::new(Some(dummy)),
             pin_next: Mutex::new(None),
+            fork_base: self.fork_base,
+            fork_pin: self.fork_pin.take(),
         }
     }
 
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        // Every node allocated by this txn is tracked in first_seen, so size it for the
+        // leaves needed to hold the new values, and the branches above them.
+        let leaves = additional / L_CAPACITY + 1;
+        let branches = leaves / (BV_CAPACITY - 1) + 1;
+        self.first_seen.reserve(leaves + branches);
+    }
+
+    pub(crate) fn set_hook(&mut self, hook: Box<dyn FnMut(StructureEvent<K>)>) {
+        self.hook = StructureHook(Some(hook));
+    }
+
+    pub(crate) fn alloc_stats(&self) -> (usize, usize) {
+        // Each node this txn creates is tracked in first_seen, and each node it replaces
+        // is tracked in last_seen until it can be freed. A node of this txn that is then
+        // removed, such as by a merge, is in both, and is counted in neither.
+        let released: HashSet<*mut Node<K, V>> = self.last_seen.iter().copied().collect();
+        let transient = self
+            .first_seen
+            .iter()
+            .filter(|n| released.contains(*n))
+            .count();
+        (
+            self.first_seen.len() - transient,
+            self.last_seen.len() - transient,
+        )
+    }
+
     pub(crate) fn clear(&mut self) {
         // Reset the values in this tree.
         // We need to mark everything as disposable, and create a new root!
+        self.last_seen.push(self.root);
         unsafe { (*self.root).sblock_collect(&mut self.last_seen) };
         let nroot: *mut Leaf<K, V> = Node::new_leaf(self.txid);
         let mut nroot = nroot as *mut Node<K, V>;
@@ -247,13 +465,22 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
 
     // Functions as insert_or_update
     pub(crate) fn insert(&mut self, k: K, v: V) -> Option<V> {
+        self.insert_ref(k, v).0
+    }
+
+    // As insert, also returning a reference to the value where it was placed, so that
+    // it can be used without searching for the key again.
+    pub(crate) fn insert_ref(&mut self, k: K, v: V) -> (Option<V>, &mut V) {
+        let mut slot = ptr::null_mut();
         let r = match clone_and_insert(
             self.root,
             self.txid,
             k,
             v,
+            &mut slot,
             &mut self.last_seen,
             &mut self.first_seen,
+            &mut self.hook,
         ) {
             CRInsertState::NoClone(res) => res,
             CRInsertState::Clone(res, mut nnode) => {
@@ -269,6 +496,7 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
                 // root now and put it inplace.
                 let mut nroot = Node::new_branch(self.txid, lnode, rnode) as *mut Node<K, V>;
                 self.first_seen.push(nroot);
+                self.hook.emit(|| StructureEvent::RootGrow);
                 // The root was cloned as part of clone split
                 // This swaps the POINTERS not the content!
                 mem::swap(&mut self.root, &mut nroot);
@@ -284,6 +512,7 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
                 // that we can get it into the branch.
                 let mut nroot = Node::new_branch(self.txid, self.root, rnode) as *mut Node<K, V>;
                 self.first_seen.push(nroot);
+                self.hook.emit(|| StructureEvent::RootGrow);
                 // println!("ls push 2");
                 // self.last_seen.push(self.root);
                 mem::swap(&mut self.root, &mut nroot);
@@ -294,6 +523,7 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
             CRInsertState::RevSplit(lnode) => {
                 let mut nroot = Node::new_branch(self.txid, lnode, self.root) as *mut Node<K, V>;
                 self.first_seen.push(nroot);
+                self.hook.emit(|| StructureEvent::RootGrow);
                 // println!("ls push 3");
                 // self.last_seen.push(self.root);
                 mem::swap(&mut self.root, &mut nroot);
@@ -302,6 +532,7 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
             CRInsertState::CloneRevSplit(rnode, lnode) => {
                 let mut nroot = Node::new_branch(self.txid, lnode, rnode) as *mut Node<K, V>;
                 self.first_seen.push(nroot);
+                self.hook.emit(|| StructureEvent::RootGrow);
                 // root was cloned in the rev split
                 // println!("ls push 4");
                 // self.last_seen.push(self.root);
@@ -313,7 +544,111 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
         if r.is_none() {
             self.length += 1;
         }
-        r
+        (r, unsafe { &mut *slot })
+    }
+
+    fn first_leaf(&self) -> *mut Leaf<K, V> {
+        let mut node = self.root;
+        while !self_meta!(node).is_leaf() {
+            node = branch_ref!(node, K, V).get_idx_unchecked(0);
+        }
+        node as *mut Leaf<K, V>
+    }
+
+    // Only the root may be an empty leaf, so the extreme leaves hold the extreme keys
+    // unless the tree is empty.
+    pub(crate) fn first_key(&self) -> Option<&K> {
+        let lref = unsafe { &*self.first_leaf() };
+        if lref.count() == 0 {
+            None
+        } else {
+            Some(lref.min())
+        }
+    }
+
+    pub(crate) fn last_key(&self) -> Option<&K> {
+        let lref = unsafe { &*self.last_leaf() };
+        if lref.count() == 0 {
+            None
+        } else {
+            Some(lref.max())
+        }
+    }
+
+    fn last_leaf(&self) -> *mut Leaf<K, V> {
+        let mut node = self.root;
+        while !self_meta!(node).is_leaf() {
+            let bref = branch_ref!(node, K, V);
+            node = bref.get_idx_unchecked(bref.count());
+        }
+        node as *mut Leaf<K, V>
+    }
+
+    // Insert a key that is greater than all others in the tree. The rightmost leaf
+    // is cached in tail, so that while it has space the key can be added without
+    // descending from the root. The tail is only valid while no other changes are
+    // made to the tree, and starts as null.
+    pub(crate) fn append(&mut self, tail: &mut *mut Leaf<K, V>, k: K, v: V) {
+        let last = if tail.is_null() {
+            self.last_leaf()
+        } else {
+            *tail
+        };
+        let lref = unsafe { &*last };
+        assert!(
+            lref.count() == 0 || lref.max() < &k,
+            "Appended keys must be greater than all others in the tree"
+        );
+        // Without a tail, the rightmost leaf may still be shared with readers, so it can
+        // only be changed once the insert below clones it into this txn.
+        if !tail.is_null() && lref.count() < L_CAPACITY {
+            let lref = unsafe { &mut **tail };
+            debug_assert!(lref.get_txid() == self.txid);
+            let r = lref.insert_or_update(k, v);
+            debug_assert!(matches!(r, LeafInsertState::Ok(None)));
+            self.length += 1;
+            return;
+        }
+        // Either the tail is unknown, or it is full and must split. Descend as normal,
+        // and then cache the new rightmost leaf which is now part of this txn.
+        let r = self.insert(k, v);
+        debug_assert!(r.is_none());
+        *tail = self.last_leaf();
+    }
+
+    // As append, without a cached tail. The rightmost leaf is found by following the
+    // right edge of the tree rather than a search, and the order of the keys is only
+    // checked in debug builds.
+    pub(crate) fn append_unchecked(&mut self, k: K, v: V) {
+        let lref = unsafe { &mut *self.last_leaf() };
+        debug_assert!(
+            lref.count() == 0 || lref.max() < &k,
+            "Appended keys must be greater than all others in the tree"
+        );
+        // A leaf of this txn is only reachable through branches of this txn, so it can
+        // be changed in place.
+        if lref.get_txid() == self.txid && lref.count() < L_CAPACITY {
+            if let LeafInsertState::Ok(None) = lref.insert_or_update(k, v) {
+                self.length += 1;
+            }
+            return;
+        }
+        self.insert(k, v);
+    }
+
+    pub(crate) fn compact(&mut self) {
+        // The existing nodes may be shared with readers, so the content is copied out
+        // and appended into a new tree, where every leaf but the last is full.
+        let content: Vec<(K, V)> = self
+            .kv_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self.clear();
+        self.reserve(content.len());
+        let mut tail = ptr::null_mut();
+        for (k, v) in content {
+            self.append(&mut tail, k, v);
+        }
     }
 
     pub(crate) fn remove(&mut self, k: &K) -> Option<V> {
@@ -323,6 +658,7 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
             k,
             &mut self.last_seen,
             &mut self.first_seen,
+            &mut self.hook,
         ) {
             CRRemoveState::NoClone(res) => res,
             CRRemoveState::Clone(res, mut nnode) => {
@@ -337,6 +673,7 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
                     // Root is being demoted, get the last branch and
                     // promote it to the root.
                     self.last_seen.push(self.root);
+                    self.hook.emit(|| StructureEvent::RootShrink);
                     let rmut = branch_ref!(self.root, K, V);
                     let mut pnode = rmut.extract_last_node();
                     mem::swap(&mut self.root, &mut pnode);
@@ -351,6 +688,7 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorWrite<K, V> {
                 } else {
                     // Our root is getting demoted here, get the remaining branch
                     self.last_seen.push(nnode);
+                    self.hook.emit(|| StructureEvent::RootShrink);
                     let rmut = branch_ref!(nnode, K, V);
                     let mut pnode = rmut.extract_last_node();
                     // Promote it to the new root
@@ -531,13 +869,17 @@ impl<K: Clone + Ord + Debug, V: Clone> Drop for SuperBlock<K, V> {
         if let Some(ls) = &(*last_seen_guard) {
             // println!("Releasing prev SB LS -> {:?}", ls);
             // Releasing prev txn
-            ls.iter().for_each(|n| Node::free(*n))
+            ls.iter()
+                .filter(|n| self.owns(**n))
+                .for_each(|n| Node::free(*n))
         } else {
             // println!("Releasing active SB LS -> None");
             // We must be the last SB. Drop the tree now.
             let mut first_seen = Vec::with_capacity(16);
-            first_seen.push(self.root);
-            unsafe { (*self.root).sblock_collect(&mut first_seen) };
+            if self.owns(self.root) {
+                first_seen.push(self.root);
+                unsafe { (*self.root).sblock_collect_after(self.fork_base, &mut first_seen) };
+            }
             first_seen.iter().for_each(|n| Node::free(*n));
         }
     }
@@ -598,13 +940,56 @@ impl<K: Clone + Ord + Debug, V: Clone> CursorReadOps<K, V> for CursorWrite<K, V>
     }
 }
 
+// Should the subtree that the bound leads to hold no entry within it, every entry of
+// the next subtree is, so its least entry is taken instead. Only the root may be empty,
+// so this is a single descent, and at most one more.
+fn lower_kv_of<'a, K: Clone + Ord + Debug, V: Clone>(
+    node: *mut Node<K, V>,
+    lower: Bound<&K>,
+) -> Option<(&'a K, &'a V)> {
+    if self_meta!(node).is_leaf() {
+        return leaf_ref!(node, K, V).lower_bound_kv(lower);
+    }
+    let bref = branch_ref!(node, K, V);
+    let idx = match lower {
+        Bound::Unbounded => 0,
+        Bound::Included(k) | Bound::Excluded(k) => bref.locate_node(k),
+    };
+    lower_kv_of(bref.get_idx_unchecked(idx), lower).or_else(|| {
+        bref.get_idx_checked(idx + 1)
+            .and_then(|node| lower_kv_of(node, Bound::Unbounded))
+    })
+}
+
+// As lower_kv_of, falling back to the greatest entry of the previous subtree.
+fn upper_kv_of<'a, K: Clone + Ord + Debug, V: Clone>(
+    node: *mut Node<K, V>,
+    upper: Bound<&K>,
+) -> Option<(&'a K, &'a V)> {
+    if self_meta!(node).is_leaf() {
+        return leaf_ref!(node, K, V).upper_bound_kv(upper);
+    }
+    let bref = branch_ref!(node, K, V);
+    let idx = match upper {
+        Bound::Unbounded => bref.count(),
+        Bound::Included(k) | Bound::Excluded(k) => bref.locate_node(k),
+    };
+    upper_kv_of(bref.get_idx_unchecked(idx), upper).or_else(|| {
+        idx.checked_sub(1)
+            .and_then(|idx| upper_kv_of(bref.get_idx_unchecked(idx), Bound::Unbounded))
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
     node: *mut Node<K, V>,
     txid: u64,
     k: K,
     v: V,
+    slot: &mut *mut V,
     last_seen: &mut Vec<*mut Node<K, V>>,
     first_seen: &mut Vec<*mut Node<K, V>>,
+    hook: &mut StructureHook<K>,
 ) -> CRInsertState<K, V> {
     /*
      * Let's talk about the magic of this function. Come, join
@@ -636,15 +1021,21 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                 // Clone was required.
                 let mref = leaf_ref!(cnode, K, V);
                 // insert to the new node.
-                match mref.insert_or_update(k, v) {
+                match mref.insert_or_update_slot(k, v, slot) {
                     LeafInsertState::Ok(res) => CRInsertState::Clone(res, cnode),
                     LeafInsertState::Split(rnode) => {
                         first_seen.push(rnode as *mut Node<K, V>);
+                        hook.emit(|| StructureEvent::LeafSplit {
+                            pivot: leaf_ref!(rnode, K, V).min().clone(),
+                        });
                         // let rnode = Node::new_leaf_ins(txid, sk, sv);
                         CRInsertState::CloneSplit(cnode, rnode as *mut Node<K, V>)
                     }
                     LeafInsertState::RevSplit(lnode) => {
                         first_seen.push(lnode as *mut Node<K, V>);
+                        hook.emit(|| StructureEvent::LeafSplit {
+                            pivot: leaf_ref!(cnode, K, V).min().clone(),
+                        });
                         CRInsertState::CloneRevSplit(cnode, lnode as *mut Node<K, V>)
                     }
                 }
@@ -653,17 +1044,23 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                 // No clone required.
                 // simply do the insert.
                 let mref = leaf_ref!(node, K, V);
-                match mref.insert_or_update(k, v) {
+                match mref.insert_or_update_slot(k, v, slot) {
                     LeafInsertState::Ok(res) => CRInsertState::NoClone(res),
                     LeafInsertState::Split(rnode) => {
                         // We split, but left is already part of the txn group, so lets
                         // just return what's new.
                         // let rnode = Node::new_leaf_ins(txid, sk, sv);
                         first_seen.push(rnode as *mut Node<K, V>);
+                        hook.emit(|| StructureEvent::LeafSplit {
+                            pivot: leaf_ref!(rnode, K, V).min().clone(),
+                        });
                         CRInsertState::Split(rnode as *mut Node<K, V>)
                     }
                     LeafInsertState::RevSplit(lnode) => {
                         first_seen.push(lnode as *mut Node<K, V>);
+                        hook.emit(|| StructureEvent::LeafSplit {
+                            pivot: leaf_ref!(node, K, V).min().clone(),
+                        });
                         CRInsertState::RevSplit(lnode as *mut Node<K, V>)
                     }
                 }
@@ -690,7 +1087,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                 let anode_idx = nmref.locate_node(&k);
                 let anode = nmref.get_idx_unchecked(anode_idx);
 
-                match clone_and_insert(anode, txid, k, v, last_seen, first_seen) {
+                match clone_and_insert(anode, txid, k, v, slot, last_seen, first_seen, hook) {
                     CRInsertState::Clone(res, lnode) => {
                         nmref.replace_by_idx(anode_idx, lnode);
                         // Pass back up that we cloned.
@@ -708,6 +1105,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                             BranchInsertState::Ok => CRInsertState::Clone(None, cnode),
                             BranchInsertState::Split(clnode, crnode) => {
                                 // Create a new branch to hold these children.
+                                hook.emit(|| StructureEvent::BranchSplit);
                                 let nrnode = Node::new_branch(txid, clnode, crnode);
                                 first_seen.push(nrnode as *mut Node<K, V>);
                                 // Return it
@@ -720,6 +1118,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                         match nmref.add_node_left(lnode, anode_idx) {
                             BranchInsertState::Ok => CRInsertState::Clone(None, cnode),
                             BranchInsertState::Split(clnode, crnode) => {
+                                hook.emit(|| StructureEvent::BranchSplit);
                                 let nrnode = Node::new_branch(txid, clnode, crnode);
                                 first_seen.push(nrnode as *mut Node<K, V>);
                                 CRInsertState::CloneSplit(cnode, nrnode as *mut Node<K, V>)
@@ -745,7 +1144,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                 let anode_idx = nmref.locate_node(&k);
                 let anode = nmref.get_idx_unchecked(anode_idx);
 
-                match clone_and_insert(anode, txid, k, v, last_seen, first_seen) {
+                match clone_and_insert(anode, txid, k, v, slot, last_seen, first_seen, hook) {
                     CRInsertState::Clone(res, lnode) => {
                         nmref.replace_by_idx(anode_idx, lnode);
                         // We did not clone, and no further work needed.
@@ -763,6 +1162,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                             // Or *we* split as well, and need to return a new sibling branch.
                             BranchInsertState::Split(clnode, crnode) => {
                                 // Create a new branch to hold these children.
+                                hook.emit(|| StructureEvent::BranchSplit);
                                 let nrnode = Node::new_branch(txid, clnode, crnode);
                                 first_seen.push(nrnode as *mut Node<K, V>);
                                 // Return it
@@ -783,6 +1183,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                             // Or *we* split as well, and need to return a new sibling branch.
                             BranchInsertState::Split(clnode, crnode) => {
                                 // Create a new branch to hold these children.
+                                hook.emit(|| StructureEvent::BranchSplit);
                                 let nrnode = Node::new_branch(txid, clnode, crnode);
                                 first_seen.push(nrnode as *mut Node<K, V>);
                                 // Return it
@@ -793,6 +1194,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                     CRInsertState::RevSplit(lnode) => match nmref.add_node_left(lnode, anode_idx) {
                         BranchInsertState::Ok => CRInsertState::NoClone(None),
                         BranchInsertState::Split(clnode, crnode) => {
+                            hook.emit(|| StructureEvent::BranchSplit);
                             let nrnode = Node::new_branch(txid, clnode, crnode);
                             first_seen.push(nrnode as *mut Node<K, V>);
                             CRInsertState::Split(nrnode as *mut Node<K, V>)
@@ -803,6 +1205,7 @@ fn clone_and_insert<K: Clone + Ord + Debug, V: Clone>(
                         match nmref.add_node_left(lnode, anode_idx) {
                             BranchInsertState::Ok => CRInsertState::NoClone(None),
                             BranchInsertState::Split(clnode, crnode) => {
+                                hook.emit(|| StructureEvent::BranchSplit);
                                 let nrnode = Node::new_branch(txid, clnode, crnode);
                                 first_seen.push(nrnode as *mut Node<K, V>);
                                 CRInsertState::Split(nrnode as *mut Node<K, V>)
@@ -876,6 +1279,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
     k: &K,
     last_seen: &mut Vec<*mut Node<K, V>>,
     first_seen: &mut Vec<*mut Node<K, V>>,
+    hook: &mut StructureHook<K>,
 ) -> CRRemoveState<K, V> {
     if self_meta!(node).is_leaf() {
         leaf_ref!(node, K, V)
@@ -910,7 +1314,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                 let nmref = branch_ref!(cnode, K, V);
                 let anode_idx = nmref.locate_node(&k);
                 let anode = nmref.get_idx_unchecked(anode_idx);
-                match clone_and_remove(anode, txid, k, last_seen, first_seen) {
+                match clone_and_remove(anode, txid, k, last_seen, first_seen, hook) {
                     CRRemoveState::NoClone(_res) => {
                         unreachable!("Should never occur");
                         // CRRemoveState::NoClone(res)
@@ -943,6 +1347,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                                 // println!("ls push 20 {:?}", dnode);
                                 debug_assert!(!last_seen.contains(&dnode));
                                 last_seen.push(dnode);
+                                hook.emit_merge(dnode);
                                 CRRemoveState::Clone(res, cnode)
                             }
                             BranchShrinkState::Shrink(dnode) => {
@@ -951,6 +1356,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                                 // println!("ls push 21 {:?}", dnode);
                                 debug_assert!(!last_seen.contains(&dnode));
                                 last_seen.push(dnode);
+                                hook.emit_merge(dnode);
                                 CRRemoveState::CloneShrink(res, cnode)
                             }
                         }
@@ -962,7 +1368,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                 let nmref = branch_ref!(node, K, V);
                 let anode_idx = nmref.locate_node(&k);
                 let anode = nmref.get_idx_unchecked(anode_idx);
-                match clone_and_remove(anode, txid, k, last_seen, first_seen) {
+                match clone_and_remove(anode, txid, k, last_seen, first_seen, hook) {
                     CRRemoveState::NoClone(res) => CRRemoveState::NoClone(res),
                     CRRemoveState::Clone(res, lnode) => {
                         nmref.replace_by_idx(anode_idx, lnode);
@@ -988,6 +1394,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                                 // println!("ls push 22 {:?}", dnode);
                                 debug_assert!(!last_seen.contains(&dnode));
                                 last_seen.push(dnode);
+                                hook.emit_merge(dnode);
                                 CRRemoveState::NoClone(res)
                             }
                             BranchShrinkState::Shrink(dnode) => {
@@ -996,6 +1403,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                                 // println!("ls push 23 {:?}", dnode);
                                 debug_assert!(!last_seen.contains(&dnode));
                                 last_seen.push(dnode);
+                                hook.emit_merge(dnode);
                                 CRRemoveState::Shrink(res)
                             }
                         }
@@ -1020,6 +1428,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                                 // println!("ls push 24 {:?}", dnode);
                                 debug_assert!(!last_seen.contains(&dnode));
                                 last_seen.push(dnode);
+                                hook.emit_merge(dnode);
                                 CRRemoveState::NoClone(res)
                             }
                             BranchShrinkState::Shrink(dnode) => {
@@ -1028,6 +1437,7 @@ fn clone_and_remove<K: Clone + Ord + Debug, V: Clone>(
                                 // println!("ls push 25 {:?}", dnode);
                                 debug_assert!(!last_seen.contains(&dnode));
                                 last_seen.push(dnode);
+                                hook.emit_merge(dnode);
                                 CRRemoveState::Shrink(res)
                             }
                         }
@@ -1233,7 +1643,7 @@ mod tests {
     use super::super::node::*;
     use super::super::states::*;
     use super::SuperBlock;
-    use super::{CursorReadOps, CursorWrite};
+    use super::{CursorReadOps, CursorWrite, DescentStep};
     use rand::seq::SliceRandom;
     use std::mem;
 
@@ -1677,6 +2087,49 @@ mod tests {
         assert_released();
     }
 
+    #[test]
+    fn test_bptree2_cursor_descent_path() {
+        // A root over two full branches, each over leaves starting at multiples of 10.
+        let base = BV_CAPACITY * 10;
+        let lbranch = create_branch_node_full(0);
+        let rbranch = create_branch_node_full(base);
+        let root = Node::new_branch(0, lbranch, rbranch) as *mut Node<usize, usize>;
+        let wcurs = CursorWrite::new_test(1, root);
+
+        let leaf_pivots =
+            |vbase: usize| -> Vec<usize> { (1..BV_CAPACITY).map(|i| vbase + (10 * i)).collect() };
+        let path = wcurs.descent_path(&(base + 25));
+        assert!(
+            path == vec![
+                DescentStep {
+                    level: 0,
+                    pivots: vec![base],
+                    child: 1,
+                },
+                DescentStep {
+                    level: 1,
+                    pivots: leaf_pivots(base),
+                    child: 2,
+                },
+            ]
+        );
+        // A key equal to a pivot descends to the right of it.
+        let path = wcurs.descent_path(&10);
+        assert!(path[0].child == 0);
+        assert!(path[1].pivots == leaf_pivots(0));
+        assert!(path[1].child == 1);
+        // Keys beyond either end go to the outer children.
+        assert!(wcurs.descent_path(&0).iter().all(|s| s.child == 0));
+        assert!(wcurs
+            .descent_path(&usize::MAX)
+            .iter()
+            .all(|s| s.child == s.pivots.len()));
+        assert!(wcurs.verify());
+
+        mem::drop(wcurs);
+        assert_released();
+    }
+
     #[test]
     fn test_bptree2_cursor_length_1() {
         // Check the length is consistent on operations.
@@ -2211,6 +2664,47 @@ mod tests {
         new_sblock
     }
 
+    #[test]
+    fn test_bptree2_cursor_remove_collapse() {
+        // Removing from a tall tree must collapse it as it empties. As every branch
+        // has at least two children, and no leaf is empty, a tree of height h holds
+        // at least 2^(h-1) keys. A chain of single child branches would break this.
+        let count = L_CAPACITY * L_CAPACITY * L_CAPACITY * 4;
+        let mut rng = rand::thread_rng();
+        let mut scattered: Vec<usize> = (0..count).collect();
+        scattered.shuffle(&mut rng);
+        let orders: Vec<Vec<usize>> =
+            vec![(0..count).collect(), (0..count).rev().collect(), scattered];
+
+        for order in orders {
+            let sblock = SuperBlock::default();
+            let mut wcurs = CursorWrite::new(&sblock);
+            for v in 0..count {
+                wcurs.insert(v, v);
+            }
+            let mut height = wcurs.height();
+            assert!(height >= 4);
+
+            for k in order {
+                assert!(wcurs.remove(&k) == Some(k));
+                assert!(wcurs.remove(&k).is_none());
+                // The tree never grows during removal.
+                assert!(wcurs.height() <= height);
+                height = wcurs.height();
+                assert!(wcurs.len() >= 1 << (height - 1) || height == 1);
+                assert!(wcurs.verify());
+            }
+            assert!(wcurs.len() == 0);
+            assert!(wcurs.height() == 1);
+
+            let new_sblock = wcurs.finalise();
+            new_sblock.commit_prep(&sblock);
+            std::mem::drop(sblock);
+            std::mem::drop(new_sblock);
+            assert_released();
+        }
+    }
+
     #[test]
     fn test_bptree2_cursor_remove_stress_1() {
         // Insert ascending - we want to ensure the tree is a few levels deep
@@ -2280,6 +2774,37 @@ mod tests {
         assert_released();
     }
 
+    #[test]
+    fn test_bptree2_cursor_branch_pivots() {
+        // Build a tree at least three levels deep, and check the pivots of every
+        // branch after each split and each merge.
+        let mut rng = rand::thread_rng();
+        let mut ins: Vec<usize> = (1..(L_CAPACITY << 6)).collect();
+        ins.shuffle(&mut rng);
+
+        let sblock = SuperBlock::default();
+        let mut wcurs = CursorWrite::new(&sblock);
+        for v in ins.iter() {
+            assert!(wcurs.insert(*v, *v).is_none());
+            assert!(wcurs.get_root_ref().verify_pivots());
+        }
+        assert!(wcurs.get_root_ref().height() >= 3);
+        assert!(wcurs.verify());
+
+        ins.shuffle(&mut rng);
+        for v in ins.iter() {
+            assert!(wcurs.remove(v) == Some(*v));
+            assert!(wcurs.get_root_ref().verify_pivots());
+        }
+        assert!(wcurs.verify());
+
+        let new_sblock = wcurs.finalise();
+        new_sblock.commit_prep(&sblock);
+        std::mem::drop(sblock);
+        std::mem::drop(new_sblock);
+        assert_released();
+    }
+
     // Add transaction-ised versions.
     #[test]
     fn test_bptree2_cursor_remove_stress_4() {
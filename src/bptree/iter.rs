@@ -1,10 +1,16 @@
 //! Iterators for the map.
 
 // Iterators for the bptree
+use super::cursor::{CursorWrite, SuperBlock};
 use super::node::{Branch, Leaf, Meta, Node};
-use std::collections::VecDeque;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
 use std::fmt::Debug;
+use std::iter::FusedIterator;
 use std::marker::PhantomData;
+use std::mem;
+use std::ops::Bound;
+use std::sync::Arc;
 
 pub(crate) struct LeafIter<'a, K, V>
 where
@@ -48,6 +54,43 @@ impl<'a, K: Clone + Ord + Debug, V: Clone> LeafIter<'a, K, V> {
         }
     }
 
+    // Position the stack at the leaf that may hold the lower bound, rather than at the
+    // first leaf of the tree.
+    pub(crate) fn new_seek(root: *mut Node<K, V>, lower: Bound<&K>) -> Self {
+        Self::new_seek_in(root, lower, VecDeque::new())
+    }
+
+    // As new_seek, building the stack in an existing, empty buffer.
+    fn new_seek_in(
+        root: *mut Node<K, V>,
+        lower: Bound<&K>,
+        mut stack: VecDeque<(*mut Node<K, V>, usize)>,
+    ) -> Self {
+        debug_assert!(stack.is_empty());
+        let mut work_node = root;
+        let mut work_idx = 0;
+        loop {
+            stack.push_back((work_node, work_idx));
+            if self_meta!(work_node).is_leaf() {
+                break;
+            } else {
+                let branch = branch_ref!(work_node, K, V);
+                work_idx = match lower {
+                    Bound::Included(k) | Bound::Excluded(k) => branch.locate_node(k),
+                    Bound::Unbounded => 0,
+                };
+                work_node = branch.get_idx_unchecked(work_idx);
+            }
+        }
+
+        LeafIter {
+            length: None,
+            stack,
+            phantom_k: PhantomData,
+            phantom_v: PhantomData,
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn new_base() -> Self {
         LeafIter {
@@ -157,6 +200,7 @@ impl<'a, K: Clone + Ord + Debug, V: Clone> Iterator for Iter<'a, K, V> {
         if let Some(leaf) = self.curleaf {
             if let Some(r) = leaf.get_kv_idx_checked(self.idx) {
                 self.idx += 1;
+                self.length = self.length.saturating_sub(1);
                 Some(r)
             } else {
                 self.curleaf = self.leafiter.next();
@@ -174,6 +218,402 @@ impl<'a, K: Clone + Ord + Debug, V: Clone> Iterator for Iter<'a, K, V> {
     }
 }
 
+// The length of the map is known, and reduced as each item is yielded.
+impl<'a, K: Clone + Ord + Debug, V: Clone> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> FusedIterator for Iter<'a, K, V> {}
+
+/// A buffer for the position of a range query, which may be reused by later queries so
+/// that they do not allocate. See `BptreeMapReadTxn::range_into`.
+pub struct RangeScratch<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    stack: VecDeque<(*mut Node<K, V>, usize)>,
+}
+
+// The buffer only holds pointers to nodes while it is borrowed by a RangeIter, and is
+// emptied once it is returned.
+unsafe impl<K: Ord + Clone + Debug + Send, V: Clone + Send> Send for RangeScratch<K, V> {}
+unsafe impl<K: Ord + Clone + Debug + Sync, V: Clone + Sync> Sync for RangeScratch<K, V> {}
+
+impl<K: Clone + Ord + Debug, V: Clone> RangeScratch<K, V> {
+    /// Create a new, empty buffer. This does not allocate until it is first used.
+    pub fn new() -> Self {
+        RangeScratch {
+            stack: VecDeque::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn buffer(&self) -> (usize, usize) {
+        (self.stack.len(), self.stack.capacity())
+    }
+}
+
+impl<K: Clone + Ord + Debug, V: Clone> Default for RangeScratch<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over references to the Key Value pairs of a range of the map, in
+/// ascending key order.
+pub struct RangeIter<'a, K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    iter: Iter<'a, K, V>,
+    lower: Bound<K>,
+    upper: Bound<K>,
+    started: bool,
+    done: bool,
+    scratch: Option<&'a mut RangeScratch<K, V>>,
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> RangeIter<'a, K, V> {
+    pub(crate) fn new(
+        root: *mut Node<K, V>,
+        length: usize,
+        lower: Bound<K>,
+        upper: Bound<K>,
+    ) -> Self {
+        let liter = LeafIter::new_seek(root, lower.as_ref());
+        Self::from_leafiter(liter, length, lower, upper, None)
+    }
+
+    // As new, with the position held in the buffer of the scratch, which is returned to
+    // it once this is dropped.
+    pub(crate) fn new_in(
+        root: *mut Node<K, V>,
+        length: usize,
+        lower: Bound<K>,
+        upper: Bound<K>,
+        scratch: &'a mut RangeScratch<K, V>,
+    ) -> Self {
+        let stack = mem::take(&mut scratch.stack);
+        let liter = LeafIter::new_seek_in(root, lower.as_ref(), stack);
+        Self::from_leafiter(liter, length, lower, upper, Some(scratch))
+    }
+
+    fn from_leafiter(
+        mut liter: LeafIter<'a, K, V>,
+        length: usize,
+        lower: Bound<K>,
+        upper: Bound<K>,
+        scratch: Option<&'a mut RangeScratch<K, V>>,
+    ) -> Self {
+        let leaf = liter.next();
+        RangeIter {
+            iter: Iter {
+                length,
+                idx: 0,
+                curleaf: leaf,
+                leafiter: liter,
+            },
+            lower,
+            upper,
+            started: false,
+            done: false,
+            scratch,
+        }
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> Drop for RangeIter<'a, K, V> {
+    fn drop(&mut self) {
+        if let Some(scratch) = self.scratch.take() {
+            let mut stack = mem::take(&mut self.iter.leafiter.stack);
+            stack.clear();
+            scratch.stack = stack;
+        }
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> Iterator for RangeIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    /// Yield the next key value reference, or `None` if exhausted.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let (k, v) = match self.iter.next() {
+                Some(r) => r,
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            };
+            // Only the first leaf may hold keys below the lower bound.
+            if !self.started {
+                let below = match &self.lower {
+                    Bound::Included(l) => k < l,
+                    Bound::Excluded(l) => k <= l,
+                    Bound::Unbounded => false,
+                };
+                if below {
+                    continue;
+                }
+                self.started = true;
+            }
+            let within = match &self.upper {
+                Bound::Included(u) => k <= u,
+                Bound::Excluded(u) => k < u,
+                Bound::Unbounded => true,
+            };
+            if within {
+                return Some((k, v));
+            }
+            self.done = true;
+            return None;
+        }
+    }
+
+    /// Provide a hint as to the number of items this iterator will yield.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            (0, Some(0))
+        } else {
+            (0, Some(self.iter.length))
+        }
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> FusedIterator for RangeIter<'a, K, V> {}
+
+/// A difference between two snapshots of a map. See `BptreeMapReadTxn::diff`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DiffEntry<'a, K, V> {
+    /// The key is only present in the newer snapshot.
+    Added(&'a K, &'a V),
+    /// The key is only present in the older snapshot.
+    Removed(&'a K, &'a V),
+    /// The key is present in both snapshots, with the value of the older and then of
+    /// the newer snapshot.
+    Changed(&'a K, &'a V, &'a V),
+}
+
+// One snapshot of a diff: the nodes that are yet to be visited, with the leftmost at
+// the end, and the leaf that is being visited.
+struct DiffSide<'a, K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    pending: Vec<*mut Node<K, V>>,
+    leaf: Option<(&'a Leaf<K, V>, usize)>,
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> DiffSide<'a, K, V> {
+    fn new(root: *mut Node<K, V>) -> Self {
+        // Only the root may be an empty leaf, and it has no entries to visit.
+        let empty = self_meta!(root).is_leaf() && leaf_ref!(root, K, V).count() == 0;
+        DiffSide {
+            pending: if empty { Vec::new() } else { vec![root] },
+            leaf: None,
+        }
+    }
+
+    fn peek(&self) -> Option<(&'a K, &'a V)> {
+        self.leaf
+            .and_then(|(leaf, idx)| leaf.get_kv_idx_checked(idx))
+    }
+
+    fn advance(&mut self) {
+        if let Some((_, idx)) = &mut self.leaf {
+            *idx += 1;
+        }
+    }
+
+    fn front(&self) -> Option<*mut Node<K, V>> {
+        self.pending.last().cloned()
+    }
+
+    // Replace the next pending node with its children, or if it is a leaf, begin to
+    // visit it. Returns true if a leaf was reached.
+    fn expand(&mut self) -> bool {
+        let node = match self.pending.pop() {
+            Some(node) => node,
+            None => return false,
+        };
+        if self_meta!(node).is_leaf() {
+            self.leaf = Some((leaf_ref!(node, K, V), 0));
+            true
+        } else {
+            let branch = branch_ref!(node, K, V);
+            let children = (0..=branch.count())
+                .rev()
+                .map(|idx| branch.get_idx_unchecked(idx));
+            self.pending.extend(children);
+            false
+        }
+    }
+}
+
+/// Iterator over the differences between two snapshots of a map, in ascending key
+/// order. See `BptreeMapReadTxn::diff`.
+pub struct DiffIter<'a, K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    old: DiffSide<'a, K, V>,
+    new: DiffSide<'a, K, V>,
+    skipped: usize,
+    visited: usize,
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> DiffIter<'a, K, V> {
+    pub(crate) fn new(old: *mut Node<K, V>, new: *mut Node<K, V>) -> Self {
+        DiffIter {
+            old: DiffSide::new(old),
+            new: DiffSide::new(new),
+            skipped: 0,
+            visited: 0,
+        }
+    }
+
+    // The number of shared nodes that were skipped, and of leaves that were visited.
+    #[cfg(test)]
+    pub(crate) fn stats(&self) -> (usize, usize) {
+        (self.skipped, self.visited)
+    }
+
+    fn expand_old(&mut self) {
+        self.visited += self.old.expand() as usize;
+    }
+
+    fn expand_new(&mut self) {
+        self.visited += self.new.expand() as usize;
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone + PartialEq> Iterator for DiffIter<'a, K, V> {
+    type Item = DiffEntry<'a, K, V>;
+
+    /// Yield the next difference, or `None` if exhausted.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match (self.old.peek(), self.new.peek()) {
+                (Some((ok, ov)), Some((nk, nv))) => match ok.cmp(nk) {
+                    Ordering::Less => {
+                        self.old.advance();
+                        return Some(DiffEntry::Removed(ok, ov));
+                    }
+                    Ordering::Greater => {
+                        self.new.advance();
+                        return Some(DiffEntry::Added(nk, nv));
+                    }
+                    Ordering::Equal => {
+                        self.old.advance();
+                        self.new.advance();
+                        if ov != nv {
+                            return Some(DiffEntry::Changed(ok, ov, nv));
+                        }
+                    }
+                },
+                // Only one side is within a leaf. The next node of the other is visited
+                // only once it may hold the key, so that the sides stay aligned on the
+                // boundaries of nodes that may be shared.
+                (Some((ok, ov)), None) => match self.new.front() {
+                    Some(n) if unsafe { (*n).min() } <= ok => self.expand_new(),
+                    _ => {
+                        self.old.advance();
+                        return Some(DiffEntry::Removed(ok, ov));
+                    }
+                },
+                (None, Some((nk, nv))) => match self.old.front() {
+                    Some(o) if unsafe { (*o).min() } <= nk => self.expand_old(),
+                    _ => {
+                        self.new.advance();
+                        return Some(DiffEntry::Added(nk, nv));
+                    }
+                },
+                (None, None) => match (self.old.front(), self.new.front()) {
+                    (None, None) => return None,
+                    // Nodes are never changed once committed, so a node that is in both
+                    // snapshots holds the same entries in each.
+                    (Some(o), Some(n)) if o == n => {
+                        self.old.pending.pop();
+                        self.new.pending.pop();
+                        self.skipped += 1;
+                    }
+                    (Some(o), Some(n)) => {
+                        // Expand the taller node, as the other may be shared with one
+                        // of its descendants.
+                        let (oh, nh) = unsafe { ((*o).height(), (*n).height()) };
+                        if oh >= nh {
+                            self.expand_old();
+                        }
+                        if nh >= oh {
+                            self.expand_new();
+                        }
+                    }
+                    (Some(_), None) => self.expand_old(),
+                    (None, Some(_)) => self.expand_new(),
+                },
+            }
+        }
+    }
+}
+
+/// Iterator over the merged content of several maps, in ascending key order. See
+/// `merge_iter`.
+pub struct MergeIter<'a, K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    layers: Vec<Iter<'a, K, V>>,
+    // The value at the front of each layer.
+    fronts: Vec<Option<&'a V>>,
+    // The key at the front of each layer, least first, and ties by layer priority.
+    heap: BinaryHeap<Reverse<(&'a K, usize)>>,
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> MergeIter<'a, K, V> {
+    pub(crate) fn new(layers: Vec<Iter<'a, K, V>>) -> Self {
+        let mut iter = MergeIter {
+            fronts: vec![None; layers.len()],
+            heap: BinaryHeap::with_capacity(layers.len()),
+            layers,
+        };
+        (0..iter.layers.len()).for_each(|idx| iter.advance(idx));
+        iter
+    }
+
+    fn advance(&mut self, idx: usize) {
+        self.fronts[idx] = self.layers[idx].next().map(|(k, v)| {
+            self.heap.push(Reverse((k, idx)));
+            v
+        });
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> Iterator for MergeIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    /// Yield the next key value reference, or `None` if exhausted.
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((k, idx)) = self.heap.pop()?;
+        let v = self.fronts[idx].expect("Layer front is missing");
+        self.advance(idx);
+        // Skip the shadowed values of the same key in lower priority layers.
+        while let Some(Reverse((nk, nidx))) = self.heap.peek().cloned() {
+            if nk != k {
+                break;
+            }
+            self.heap.pop();
+            self.advance(nidx);
+        }
+        Some((k, v))
+    }
+}
+
 /// Iterater over references to Keys stored in the map.
 pub struct KeyIter<'a, K, V>
 where
@@ -204,6 +644,10 @@ impl<'a, K: Clone + Ord + Debug, V: Clone> Iterator for KeyIter<'a, K, V> {
     }
 }
 
+impl<'a, K: Clone + Ord + Debug, V: Clone> ExactSizeIterator for KeyIter<'a, K, V> {}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> FusedIterator for KeyIter<'a, K, V> {}
+
 /// Iterater over references to Values stored in the map.
 pub struct ValueIter<'a, K, V>
 where
@@ -234,6 +678,134 @@ impl<'a, K: Clone + Ord + Debug, V: Clone> Iterator for ValueIter<'a, K, V> {
     }
 }
 
+impl<'a, K: Clone + Ord + Debug, V: Clone> ExactSizeIterator for ValueIter<'a, K, V> {}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> FusedIterator for ValueIter<'a, K, V> {}
+
+/// Removes and yields the entries of a range from a write transaction. See
+/// `BptreeMapWriteTxn::drain_range`.
+pub struct DrainRange<'a, K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    work: &'a mut CursorWrite<K, V>,
+    keys: std::vec::IntoIter<K>,
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> DrainRange<'a, K, V> {
+    pub(crate) fn new(work: &'a mut CursorWrite<K, V>, keys: Vec<K>) -> Self {
+        DrainRange {
+            work,
+            keys: keys.into_iter(),
+        }
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> Iterator for DrainRange<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let k = self.keys.next()?;
+        let v = self
+            .work
+            .remove(&k)
+            .expect("drained key missing from the tree");
+        Some((k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug, V: Clone> Drop for DrainRange<'a, K, V> {
+    fn drop(&mut self) {
+        // Entries that were not yet yielded are still removed.
+        for k in self.keys.by_ref() {
+            self.work.remove(&k);
+        }
+    }
+}
+
+/// Consumes a map, yielding its entries in order. See `BptreeMap::into_iter`.
+pub struct IntoIter<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    length: usize,
+    // Leaves with a txid above this are ours alone, so their entries are moved out
+    // rather than cloned, and they are freed as we go.
+    shared: u64,
+    leaves: std::vec::IntoIter<*mut Leaf<K, V>>,
+    entries: std::vec::IntoIter<(K, V)>,
+    // Keeps the leaves that are not ours alive until we are done with them.
+    _pin: Option<Arc<SuperBlock<K, V>>>,
+}
+
+unsafe impl<K: Ord + Clone + Debug + Send + Sync, V: Clone + Send + Sync> Send for IntoIter<K, V> {}
+
+impl<K: Clone + Ord + Debug, V: Clone> IntoIter<K, V> {
+    pub(crate) fn new(
+        root: *mut Node<K, V>,
+        length: usize,
+        shared: u64,
+        pin: Option<Arc<SuperBlock<K, V>>>,
+    ) -> Self {
+        let leaves: Vec<_> = LeafIter::new(root, false)
+            .map(|l| l as *const Leaf<K, V> as *mut Leaf<K, V>)
+            .collect();
+        IntoIter {
+            length,
+            shared,
+            leaves: leaves.into_iter(),
+            entries: Vec::new().into_iter(),
+            _pin: pin,
+        }
+    }
+}
+
+impl<K: Clone + Ord + Debug, V: Clone> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.entries.next() {
+                self.length -= 1;
+                return Some(e);
+            }
+            let leaf = self.leaves.next()?;
+            let mut entries = Vec::new();
+            if unsafe { (*leaf).get_txid() } > self.shared {
+                Leaf::take_all(leaf, &mut entries);
+            } else {
+                unsafe { (*leaf).clone_all(&mut entries) };
+            }
+            self.entries = entries.into_iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length, Some(self.length))
+    }
+}
+
+impl<K: Clone + Ord + Debug, V: Clone> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K: Clone + Ord + Debug, V: Clone> FusedIterator for IntoIter<K, V> {}
+
+impl<K: Clone + Ord + Debug, V: Clone> Drop for IntoIter<K, V> {
+    fn drop(&mut self) {
+        // The leaves of ours that were not reached are freed with their entries.
+        for leaf in self.leaves.by_ref() {
+            if unsafe { (*leaf).get_txid() } > self.shared {
+                Node::free(leaf as *mut Node<K, V>);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::cursor::CursorWrite;
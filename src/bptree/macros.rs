@@ -37,3 +37,29 @@ macro_rules! key_search {
         slice_search_linear(inited, $k)
     }};
 }
+
+macro_rules! branch_key_search {
+    ($self:expr, $k:expr) => {{
+        let (left, _) = $self.key.split_at($self.count());
+        let inited: &[K] = unsafe { slice::from_raw_parts(left.as_ptr() as *const K, left.len()) };
+        slice_search_binary(inited, $k)
+    }};
+}
+
+// Record the time taken by an operation of the map, if profiling is enabled.
+#[cfg(feature = "profiling")]
+macro_rules! timed {
+    ($caller:expr, $op:ident, $e:expr) => {{
+        let start = std::time::Instant::now();
+        let r = $e;
+        $caller.latencies.$op.record(start.elapsed());
+        r
+    }};
+}
+
+#[cfg(not(feature = "profiling"))]
+macro_rules! timed {
+    ($caller:expr, $op:ident, $e:expr) => {{
+        $e
+    }};
+}
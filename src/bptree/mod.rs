@@ -4,19 +4,41 @@ mod macros;
 mod cursor;
 pub mod iter;
 mod node;
+#[cfg(feature = "profiling")]
+mod profile;
+pub mod raw;
+pub mod set;
 mod states;
 
 use self::cursor::CursorReadOps;
 use self::cursor::{CursorRead, CursorWrite, SuperBlock};
-use self::iter::{Iter, KeyIter, ValueIter};
+use self::iter::{
+    DiffEntry, DiffIter, DrainRange, IntoIter, Iter, KeyIter, MergeIter, RangeIter, RangeScratch,
+    ValueIter,
+};
+use self::node::{Leaf, BV_CAPACITY, L_CAPACITY};
+#[cfg(feature = "profiling")]
+use self::profile::Latencies;
+#[cfg(feature = "profiling")]
+pub use self::profile::{LatencySummary, OpLatency, LATENCY_BUCKETS};
+use crate::{CommitSummary, Versioned};
 // use self::node::{Leaf, Node};
-use parking_lot::{Mutex, MutexGuard};
+use crate::lock::{Mutex, MutexGuard};
 use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::iter::FromIterator;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
 // use std::marker::PhantomData;
+use std::ptr;
 use std::sync::Arc;
 
+// The number of entries a cancellable operation processes between each poll of its
+// cancellation check.
+const CANCEL_INTERVAL: usize = 64;
+
 /// A concurrently readable map based on a modified B+Tree structure.
 ///
 /// This structure can be used in locations where you would otherwise us
@@ -35,26 +57,58 @@ use std::sync::Arc;
 /// later. This may cause memory usage to increase in exchange for a gain
 /// in concurrent behaviour.
 ///
+/// Cloning a `BptreeMap` creates another handle to the same map, in the manner of an
+/// `Arc`. Commits through either handle are visible to readers of both. To create an
+/// independent copy that can diverge, use `fork` on a read transaction instead.
+///
 /// Transactions can be rolled-back (aborted) without penalty by dropping
 /// the `BptreeMapWriteTxn` without calling `commit()`.
+///
+/// When a leaf is copied during a write, every key and value in it is cloned.
+/// If your keys are large, wrap them in a type that implements `CheapClone`
+/// such as `Arc<str>`, so that these copies share the key rather than
+/// duplicating it.
 pub struct BptreeMap<K, V>
 where
     K: Ord + Clone + Debug + Sync + Send + 'static,
     V: Clone + Sync + Send + 'static,
 {
-    write: Mutex<()>,
-    active: Mutex<Arc<SuperBlock<K, V>>>,
+    write: Arc<Mutex<()>>,
+    active: Arc<Mutex<Arc<SuperBlock<K, V>>>>,
+    #[cfg(feature = "profiling")]
+    latencies: Arc<Latencies>,
+    limit: Option<(usize, EvictSide)>,
+    net_summary: bool,
 }
 
+#[cfg(not(feature = "single_threaded"))]
 unsafe impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Send
     for BptreeMap<K, V>
 {
 }
+#[cfg(not(feature = "single_threaded"))]
 unsafe impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Sync
     for BptreeMap<K, V>
 {
 }
 
+/// A marker for types where `Clone` is inexpensive and shares the underlying
+/// data, rather than performing a deep copy, such as `Arc<T>`.
+///
+/// Keys are cloned each time a write copies a leaf of a `BptreeMap`. Using a
+/// `CheapClone` key means that each copy is only a reference count increment,
+/// and all versions of the tree share a single allocation of each key. You may
+/// implement this for your own types if they have this property.
+///
+/// The trait does not change how a leaf is copied. Each copy calls `K::clone`,
+/// which for a `CheapClone` key already shares it, and without specialization the
+/// copy could not take a different path by the trait in any case. It documents the
+/// property, and lets code that builds large trees require it with a bound on `K`.
+pub trait CheapClone: Clone {}
+
+impl<T: ?Sized> CheapClone for Arc<T> {}
+impl<T: ?Sized> CheapClone for &'static T {}
+
 /// An active read transaction over a `BptreeMap`. The data in this tree
 /// is guaranteed to not change and will remain consistent for the life
 /// of this transaction.
@@ -71,8 +125,12 @@ where
 /// An active write transaction for a `BptreeMap`. The data in this tree
 /// may be modified exclusively through this transaction without affecting
 /// readers. The write may be rolledback/aborted by dropping this guard
-/// without calling `commit()`. Once `commit()` is called, readers will be
-/// able to access and percieve changes in new transactions.
+/// without calling `commit()`, or by calling `discard()`. Once `commit()` is
+/// called, readers will be able to access and percieve changes in new
+/// transactions.
+///
+/// A panic while the transaction is held discards it without poisoning the lock, see
+/// [the crate documentation](crate#panics-in-write-transactions).
 pub struct BptreeMapWriteTxn<'a, K, V>
 where
     K: Ord + Clone + Debug + Sync + Send + 'static,
@@ -81,6 +139,68 @@ where
     work: CursorWrite<K, V>,
     caller: &'a BptreeMap<K, V>,
     _guard: MutexGuard<'a, ()>,
+    changes: Changes<K>,
+}
+
+// The changes that a write transaction has made, for the `CommitSummary` of its commit.
+// Each operation is counted from its result as it is made. A map built with
+// `net_commit_summary` also keeps each key that the transaction changes, with whether
+// it was present before the first change, and the summary is found from these instead.
+struct Changes<K> {
+    counts: CommitSummary,
+    keys: Option<BTreeMap<K, bool>>,
+}
+
+impl<K: Ord + Clone> Changes<K> {
+    fn new(net: bool) -> Self {
+        Changes {
+            counts: CommitSummary::default(),
+            keys: if net { Some(BTreeMap::new()) } else { None },
+        }
+    }
+
+    // Keep k, which was `present` before the change, if the keys are kept at all.
+    fn track(&mut self, k: &K, present: bool) {
+        if let Some(keys) = self.keys.as_mut() {
+            if !keys.contains_key(k) {
+                keys.insert(k.clone(), present);
+            }
+        }
+    }
+
+    // For an insert that consumes its key, the copy of the key to give to `inserted`
+    // once the insert is made, if the key is kept and has not already been changed.
+    fn unseen(&self, k: &K) -> Option<K> {
+        match self.keys.as_ref() {
+            Some(keys) if !keys.contains_key(k) => Some(k.clone()),
+            _ => None,
+        }
+    }
+
+    fn inserted(&mut self, unseen: Option<K>, replaced: bool) {
+        self.counts.record_insert(replaced);
+        if let (Some(keys), Some(k)) = (self.keys.as_mut(), unseen) {
+            keys.insert(k, replaced);
+        }
+    }
+
+    // A write of k, which was `present` before it, as an insert or an update.
+    fn wrote(&mut self, k: &K, present: bool) {
+        self.counts.record_insert(present);
+        self.track(k, present);
+    }
+
+    fn removed(&mut self, k: &K) {
+        self.counts.record_removed(1);
+        self.track(k, true);
+    }
+
+    fn summary<F: Fn(&K) -> bool>(&self, present: F) -> CommitSummary {
+        match self.keys.as_ref() {
+            Some(keys) => CommitSummary::from_changes(keys.iter(), present),
+            None => self.counts,
+        }
+    }
 }
 
 enum SnapshotType<'a, K, V>
@@ -108,6 +228,190 @@ where
     work: SnapshotType<'a, K, V>,
 }
 
+/// A branch visited during a search of the tree. See `BptreeMapReadTxn::descent_path`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DescentStep<K> {
+    /// The depth of this branch, where the root is at level 0.
+    pub level: usize,
+    /// The pivot keys of this branch. Child `n` holds the keys that are at least pivot
+    /// `n - 1`, and less than pivot `n`.
+    pub pivots: Vec<K>,
+    /// The index of the child that the search descended to.
+    pub child: usize,
+}
+
+/// Counts of the nodes allocated and released by a write transaction. See
+/// `BptreeMapWriteTxn::alloc_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// The number of nodes allocated by this transaction.
+    pub allocated: u64,
+    /// The number of existing nodes this transaction has replaced or removed. These are
+    /// freed once the transaction commits and no reader can observe them, or are kept
+    /// if the transaction is dropped.
+    pub freed: u64,
+}
+
+/// A change to the shape of the tree made by a write transaction. See
+/// `BptreeMapWriteTxn::set_structure_hook`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StructureEvent<K> {
+    /// A full leaf was split in two. The keys that are at least `pivot` are in the new
+    /// right leaf.
+    LeafSplit {
+        /// The least key of the right leaf.
+        pivot: K,
+    },
+    /// An emptied leaf was removed, and its parent rebalanced with a sibling.
+    LeafMerge,
+    /// A full branch was split in two.
+    BranchSplit,
+    /// A branch was merged into its sibling and removed.
+    BranchMerge,
+    /// The root was split, and a new root branch now holds both halves, so the tree is
+    /// one level taller.
+    RootGrow,
+    /// The root branch was left with a single child, which is now the root, so the tree
+    /// is one level shorter.
+    RootShrink,
+}
+
+/// Which key a `BptreeMap` with a `max_entries` limit evicts to stay within it. See
+/// `BptreeMapBuilder::max_entries`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictSide {
+    /// Evict the least key, so the map keeps the greatest keys.
+    Min,
+    /// Evict the greatest key, so the map keeps the least keys.
+    Max,
+}
+
+/// A builder of a `BptreeMap` with options that `BptreeMap::new` does not set.
+///
+/// # Examples
+/// ```
+/// use concread::bptree::{BptreeMap, BptreeMapBuilder, EvictSide};
+///
+/// let map: BptreeMap<usize, usize> = BptreeMapBuilder::new()
+///     .max_entries(2, EvictSide::Min)
+///     .build();
+/// let mut wr = map.write();
+/// wr.extend(vec![(1, 1), (2, 2), (3, 3)]);
+/// assert_eq!(wr.len(), 2);
+/// assert!(!wr.contains_key(&1));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BptreeMapBuilder {
+    limit: Option<(usize, EvictSide)>,
+    net_summary: bool,
+}
+
+impl BptreeMapBuilder {
+    /// Create a new builder. By default the map has no limit on its entries.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the map to at most `max` entries. An insert that takes the map past the
+    /// limit evicts the least or greatest key, as chosen by `side`, which may be the
+    /// key just inserted. This is not an LRU: the evicted key is chosen by the order of
+    /// the keys alone, and how recently a key was inserted or read has no effect.
+    ///
+    /// Evictions are made within the write transaction, and are counted as removes by
+    /// its `CommitSummary`. `max` must be greater than zero, else this panics.
+    pub fn max_entries(self, max: usize, side: EvictSide) -> Self {
+        assert!(max > 0, "The entry limit must be greater than zero");
+        BptreeMapBuilder {
+            limit: Some((max, side)),
+            ..self
+        }
+    }
+
+    /// Report the net change of each key in the `CommitSummary` of each write
+    /// transaction, rather than a count of each operation. See `CommitSummary` for the
+    /// difference. To find the net changes, a transaction keeps a copy of each key
+    /// that it changes until it commits, so this makes each write more expensive.
+    pub fn net_commit_summary(self) -> Self {
+        BptreeMapBuilder {
+            net_summary: true,
+            ..self
+        }
+    }
+
+    /// Build the `BptreeMap`.
+    pub fn build<K, V>(self) -> BptreeMap<K, V>
+    where
+        K: Ord + Clone + Debug + Sync + Send + 'static,
+        V: Clone + Sync + Send + 'static,
+    {
+        BptreeMap {
+            limit: self.limit,
+            net_summary: self.net_summary,
+            ..BptreeMap::new()
+        }
+    }
+}
+
+/// A cursor that appends keys in ascending order to a `BptreeMapWriteTxn`. See
+/// `BptreeMapWriteTxn::append_cursor`.
+pub struct AppendCursor<'w, 'a, K, V>
+where
+    K: Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    txn: &'w mut BptreeMapWriteTxn<'a, K, V>,
+    tail: *mut Leaf<K, V>,
+}
+
+/// A writer that commits every fixed number of inserts, for importing more entries than
+/// should be held by a single write transaction. See `BptreeMap::batch_writer`.
+pub struct BatchWriter<'a, K, V>
+where
+    K: Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    map: &'a BptreeMap<K, V>,
+    txn: Option<BptreeMapWriteTxn<'a, K, V>>,
+    batch: usize,
+    pending: usize,
+}
+
+/// An entry of a `BptreeMapWriteTxn`, which may be occupied or vacant. See
+/// `BptreeMapWriteTxn::entry`.
+pub enum Entry<'w, 'a, K, V>
+where
+    K: Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    /// The key is present in the tree.
+    Occupied(OccupiedEntry<'w, 'a, K, V>),
+    /// The key is not present in the tree.
+    Vacant(VacantEntry<'w, 'a, K, V>),
+}
+
+/// An entry of a key that is present in the tree.
+pub struct OccupiedEntry<'w, 'a, K, V>
+where
+    K: Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    txn: &'w mut BptreeMapWriteTxn<'a, K, V>,
+    k: K,
+    // The value found by the search for the entry. The entry holds the transaction, so
+    // the tree can not change while this is held.
+    value: *const V,
+}
+
+/// An entry of a key that is not present in the tree.
+pub struct VacantEntry<'w, 'a, K, V>
+where
+    K: Ord + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    txn: &'w mut BptreeMapWriteTxn<'a, K, V>,
+    k: K,
+}
+
 impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Default
     for BptreeMap<K, V>
 {
@@ -116,17 +420,92 @@ impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 's
     }
 }
 
+impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Clone
+    for BptreeMap<K, V>
+{
+    fn clone(&self) -> Self {
+        BptreeMap {
+            write: self.write.clone(),
+            active: self.active.clone(),
+            #[cfg(feature = "profiling")]
+            latencies: self.latencies.clone(),
+            limit: self.limit,
+            net_summary: self.net_summary,
+        }
+    }
+}
+
 impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
     BptreeMap<K, V>
 {
     /// Construct a new concurrent tree
     pub fn new() -> Self {
         BptreeMap {
-            write: Mutex::new(()),
-            active: Mutex::new(Arc::new(SuperBlock::default())),
+            write: Arc::new(Mutex::new(())),
+            active: Arc::new(Mutex::new(Arc::new(SuperBlock::default()))),
+            #[cfg(feature = "profiling")]
+            latencies: Arc::new(Latencies::default()),
+            limit: None,
+            net_summary: false,
+        }
+    }
+
+    /// Build a tree from pairs in ascending key order, where consecutive pairs may have
+    /// equal keys. Of each run of equal keys only the last pair is kept. The tree is
+    /// built by appending to the rightmost leaf, so every leaf but the last is full.
+    ///
+    /// This panics if a key is less than the key before it.
+    pub fn from_sorted_iter_dedup<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let temp_sb = SuperBlock::default();
+        let mut cursor = CursorWrite::new(&temp_sb);
+        let mut tail = ptr::null_mut();
+        let mut iter = iter.into_iter();
+        if let Some(mut pending) = iter.next() {
+            for (k, v) in iter {
+                match pending.0.cmp(&k) {
+                    Ordering::Less => {
+                        let (pk, pv) = mem::replace(&mut pending, (k, v));
+                        cursor.append(&mut tail, pk, pv);
+                    }
+                    // The later of the equal pairs wins.
+                    Ordering::Equal => pending = (k, v),
+                    Ordering::Greater => panic!(
+                        "Keys are not in ascending order, {:?} follows {:?}",
+                        k, pending.0
+                    ),
+                }
+            }
+            cursor.append(&mut tail, pending.0, pending.1);
+        }
+
+        let new_sblock = cursor.finalise();
+        new_sblock.commit_prep(&temp_sb);
+
+        BptreeMap {
+            write: Arc::new(Mutex::new(())),
+            active: Arc::new(Mutex::new(Arc::new(new_sblock))),
+            #[cfg(feature = "profiling")]
+            latencies: Arc::new(Latencies::default()),
+            limit: None,
+            net_summary: false,
         }
     }
 
+    /// The maximum number of k:v pairs stored in a single leaf of the tree. This
+    /// is fixed at compile time, and is reduced by the `skinny` feature.
+    pub fn leaf_capacity() -> usize {
+        L_CAPACITY
+    }
+
+    /// The maximum number of children of a single branch of the tree, IE the
+    /// fan-out of the tree. This is fixed at compile time.
+    pub fn branch_capacity() -> usize {
+        BV_CAPACITY
+    }
+
     /// Initiate a read transaction for the tree, concurrent to any
     /// other readers or writers.
     pub fn read(&self) -> BptreeMapReadTxn<K, V> {
@@ -162,6 +541,7 @@ impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 's
             work: cursor,
             caller: self,
             _guard: mguard,
+            changes: Changes::new(self.net_summary),
         }
         /* rguard dropped here */
     }
@@ -177,11 +557,31 @@ impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 's
                 work: cursor,
                 caller: self,
                 _guard: mguard,
+                changes: Changes::new(self.net_summary),
             }
         })
     }
 
-    fn commit(&self, newdata: SuperBlock<K, V>) {
+    /// Create a writer that inserts into this tree, committing after every `batch`
+    /// inserts. A batch of zero is treated as one.
+    ///
+    /// Each commit is a consistent snapshot that new readers observe, so an import
+    /// shows its progress, and the nodes copied by a batch can be released once its
+    /// readers finish, rather than being held until the whole import commits. A write
+    /// transaction is only held while a batch is pending, so other writers may commit
+    /// between the batches. The final partial batch is committed when the writer is
+    /// dropped, unless it is dropped by a panic, in which case it is discarded in the
+    /// same way as an uncommitted write transaction.
+    pub fn batch_writer(&self, batch: usize) -> BatchWriter<K, V> {
+        BatchWriter {
+            map: self,
+            txn: None,
+            batch: batch.max(1),
+            pending: 0,
+        }
+    }
+
+    fn commit(&self, newdata: SuperBlock<K, V>) -> Arc<SuperBlock<K, V>> {
         // println!("commit wr");
         let mut rwguard = self.active.lock();
         // Now we need to setup the sb pointers properly.
@@ -197,7 +597,8 @@ impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 's
         }
 
         // Now push the new SB.
-        *rwguard = arc_newdata;
+        *rwguard = arc_newdata.clone();
+        arc_newdata
     }
 }
 
@@ -213,17 +614,68 @@ impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 's
         new_sblock.commit_prep(&temp_sb);
 
         BptreeMap {
-            write: Mutex::new(()),
-            active: Mutex::new(Arc::new(new_sblock)),
+            write: Arc::new(Mutex::new(())),
+            active: Arc::new(Mutex::new(Arc::new(new_sblock))),
+            #[cfg(feature = "profiling")]
+            latencies: Arc::new(Latencies::default()),
+            limit: None,
+            net_summary: false,
         }
     }
 }
 
+impl<K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> IntoIterator
+    for BptreeMap<K, V>
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Consume this handle of the map, yielding every entry in order of keys.
+    ///
+    /// If no other handle or fork shares the current tree, the entries are moved out,
+    /// and each leaf is freed as soon as it is exhausted. Otherwise, the entries are
+    /// cloned and the map is unchanged for the others. The tree of a fork is moved out
+    /// of unless it is still shared with its origin, which stays unchanged.
+    fn into_iter(self) -> IntoIter<K, V> {
+        let sblock = match Arc::try_unwrap(self.active) {
+            Ok(active) => active.into_inner(),
+            Err(active) => active.lock().clone(),
+        };
+        SuperBlock::consume(sblock)
+    }
+}
+
 impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
     Extend<(K, V)> for BptreeMapWriteTxn<'a, K, V>
 {
     fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
-        self.work.extend(iter);
+        if self.caller.limit.is_some() {
+            // Each insert is evicted for in turn, so the tree never exceeds its limit.
+            iter.into_iter().for_each(|(k, v)| {
+                self.insert(k, v);
+            });
+            return;
+        }
+        if self.changes.keys.is_none() {
+            let before = self.work.len();
+            let mut count = 0;
+            self.work.extend(iter.into_iter().inspect(|_| count += 1));
+            self.changes
+                .counts
+                .record_extend(count, before, self.work.len());
+            return;
+        }
+        let items: Vec<(K, V)> = iter.into_iter().collect();
+        for (k, _) in items.iter() {
+            let present = self.work.contains_key(k);
+            self.changes.track(k, present);
+        }
+        let before = self.work.len();
+        let count = items.len();
+        self.work.extend(items);
+        self.changes
+            .counts
+            .record_extend(count, before, self.work.len());
     }
 }
 
@@ -239,7 +691,7 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
         K: Borrow<Q>,
         Q: Ord,
     {
-        self.work.search(k)
+        timed!(self.caller, get, self.work.search(k))
     }
 
     /// Assert if a key exists in the tree.
@@ -292,19 +744,250 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
     /// Reset this tree to an empty state. As this is within the transaction this
     /// change only takes effect once commited.
     pub fn clear(&mut self) {
+        self.changes.counts.record_removed(self.work.len());
+        if self.changes.keys.is_some() {
+            for k in self.work.k_iter() {
+                self.changes.track(k, true);
+            }
+        }
         self.work.clear()
     }
 
     /// Insert or update a value by key. If the value previously existed it is returned
     /// as `Some(V)`. If the value did not previously exist this returns `None`.
     pub fn insert(&mut self, k: K, v: V) -> Option<V> {
-        self.work.insert(k, v)
+        let unseen = self.changes.unseen(&k);
+        let prev = timed!(self.caller, insert, self.work.insert(k, v));
+        self.changes.inserted(unseen, prev.is_some());
+        self.evict_to_limit(0);
+        prev
+    }
+
+    // Evict the keys of the limit side of the map until `reserved` entries can be
+    // added within the limit, returning whether anything was evicted.
+    fn evict_to_limit(&mut self, reserved: usize) -> bool {
+        let (max, side) = match self.caller.limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        let mut evicted = false;
+        while self.work.len() + reserved > max {
+            match side {
+                EvictSide::Min => self.pop_first(),
+                EvictSide::Max => self.pop_last(),
+            };
+            evicted = true;
+        }
+        evicted
+    }
+
+    /// Remove and return the entry of the least key, if the tree is not empty.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let k = self.work.first_key()?.clone();
+        self.remove(&k).map(|v| (k, v))
+    }
+
+    /// Remove and return the entry of the greatest key, if the tree is not empty.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let k = self.work.last_key()?.clone();
+        self.remove(&k).map(|v| (k, v))
+    }
+
+    /// Reserve capacity for at least `additional` further inserts in the list this
+    /// transaction keeps of the nodes it allocates, so that a large bulk insert does not
+    /// reallocate it as it grows. This only sizes that bookkeeping: the nodes themselves
+    /// are not preallocated, and are still allocated one at a time as the tree needs
+    /// them. The content of the tree is not changed.
+    pub fn reserve(&mut self, additional: usize) {
+        self.work.reserve(additional)
+    }
+
+    /// Rebuild the tree from its current content, so that every leaf is filled to
+    /// capacity. A pattern of inserts and removes can leave many leaves partially empty,
+    /// and this restores the tree to the fewest nodes and the least height that can
+    /// hold its content.
+    ///
+    /// Every key and value is cloned into the new tree, as the existing nodes may still
+    /// be in use by readers.
+    pub fn compact(&mut self) {
+        self.work.compact()
+    }
+
+    /// Report the number of nodes this transaction has allocated and released so far.
+    /// As nodes are copied on write, each changed node is counted once as allocated
+    /// for the copy, and once as freed for the original. A node that this transaction
+    /// both allocates and releases, such as a copy that later merges away, is counted
+    /// in neither. Nodes are not pooled, so a released node is never reused by a later
+    /// allocation.
+    pub fn alloc_stats(&self) -> AllocStats {
+        let (allocated, freed) = self.work.alloc_stats();
+        AllocStats {
+            allocated: allocated as u64,
+            freed: freed as u64,
+        }
+    }
+
+    /// Call `hook` with each change to the shape of the tree that this transaction makes
+    /// from now on, as the change is made. This allows a structure that mirrors the
+    /// boundaries of the nodes to be kept in step with the tree. Setting a new hook
+    /// replaces the previous one.
+    ///
+    /// Events are reported for inserts and removes, including those made by other
+    /// operations of this transaction such as `extend`, `retain`, `drain_range` or
+    /// `split_off_lt` or `extend_from_read`. `clear` replaces the whole tree with an
+    /// empty leaf, and reports no events. `compact` also replaces the whole tree
+    /// without an event, and then reports the splits of filling the new tree, but not
+    /// the removal of the nodes it replaced. Events of a transaction that is dropped
+    /// without a commit are still reported.
+    pub fn set_structure_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(StructureEvent<K>) + 'static,
+    {
+        self.work.set_hook(Box::new(hook))
+    }
+
+    /// Retrieve the value of a key, inserting the result of `f` if the key is not
+    /// present. `f` is only called when the key is absent. Unlike a `get_mut`, finding
+    /// an existing value does not clone any nodes into this transaction.
+    ///
+    /// If the map has a `max_entries` limit, an inserted key is always kept, and an
+    /// entry of another key is evicted instead.
+    pub fn get_or_insert_with<F>(&mut self, k: K, f: F) -> &V
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(v) = self.work.search(&k) {
+            // The borrow checker can not see that the borrow ends here when the key is
+            // absent, so rebind the reference to return it.
+            let v = v as *const V;
+            return unsafe { &*v };
+        }
+        self.evict_to_limit(1);
+        self.changes.wrote(&k, false);
+        self.work.insert_ref(k, f()).1
+    }
+
+    /// Find the entry of a key, to inspect or change its value, or to insert a value
+    /// if it is vacant. As with `get_or_insert_with`, finding an occupied entry does
+    /// not clone any nodes into this transaction until the value is accessed mutably.
+    ///
+    /// The tree is searched once to find the entry. Inserting into a vacant entry then
+    /// descends once more to place the value, and accessing an occupied value mutably
+    /// descends once more to clone its path into this transaction.
+    pub fn entry(&mut self, k: K) -> Entry<'_, 'a, K, V> {
+        match self.work.search(&k).map(|v| v as *const V) {
+            Some(value) => Entry::Occupied(OccupiedEntry {
+                txn: self,
+                k,
+                value,
+            }),
+            None => Entry::Vacant(VacantEntry { txn: self, k }),
+        }
+    }
+
+    /// Create a cursor for appending keys in ascending order, such as for a log or a
+    /// time series. Each `AppendCursor::push` must be given a key that is greater than
+    /// every key already in the tree.
+    ///
+    /// The cursor remembers the rightmost leaf of the tree, so that while it has space
+    /// a push does not need to descend from the root. This makes appending a sorted
+    /// sequence `O(1)` amortised per key, rather than the `O(log n)` of `insert`.
+    pub fn append_cursor(&mut self) -> AppendCursor<'_, 'a, K, V> {
+        AppendCursor {
+            txn: self,
+            tail: ptr::null_mut(),
+        }
+    }
+
+    /// Insert a key that is greater than every key in the tree, without searching for
+    /// its position. This is the incremental counterpart of `reserve` and `extend` for
+    /// streaming sorted input that is known to hold no duplicates, and it follows the
+    /// right edge of the tree to its last leaf, adding the key there while it has space.
+    ///
+    /// Unlike `AppendCursor::push`, the order of the keys is only checked in debug
+    /// builds, where a key that is not greater panics. In a release build such a key
+    /// is not undefined behaviour, but it leaves the tree out of order, after which
+    /// searches, iteration and the length of the tree may all be wrong. A key equal to
+    /// the last key of the tree replaces its value, and is counted as an update by the
+    /// `CommitSummary`.
+    pub fn insert_unique_unchecked(&mut self, k: K, v: V) {
+        let unseen = self.changes.unseen(&k);
+        let before = self.work.len();
+        timed!(self.caller, insert, self.work.append_unchecked(k, v));
+        self.changes.inserted(unseen, self.work.len() == before);
+        self.evict_to_limit(0);
     }
 
     /// Remove a key if it exists in the tree. If the value exists, we return it as `Some(V)`,
     /// and if it did not exist, we return `None`
     pub fn remove(&mut self, k: &K) -> Option<V> {
-        self.work.remove(k)
+        let prev = timed!(self.caller, remove, self.work.remove(k));
+        if prev.is_some() {
+            self.changes.removed(k);
+        }
+        prev
+    }
+
+    /// Remove all entries whose key is within `range`, returning an iterator that
+    /// yields the removed entries by value in ascending key order. Each entry is
+    /// removed from the tree as it is yielded, and the tree is rebalanced as this
+    /// proceeds.
+    ///
+    /// As with `Vec::drain`, if the iterator is dropped before it is exhausted, the
+    /// remaining entries of the range are still removed.
+    pub fn drain_range<R: RangeBounds<K>>(&mut self, range: R) -> DrainRange<K, V> {
+        // The keys are found from the leaf that holds the start of the range, rather
+        // than the first leaf of the tree.
+        let keys: Vec<K> = self
+            .work
+            .range_iter(range.start_bound().cloned(), range.end_bound().cloned())
+            .map(|(k, _)| k.clone())
+            .collect();
+        // Every key of the range is removed, even if the iterator is not exhausted.
+        for k in keys.iter() {
+            self.changes.removed(k);
+        }
+        DrainRange::new(&mut self.work, keys)
+    }
+
+    /// Remove every entry for which `f` returns `false`, keeping only those for which
+    /// it returns `true`.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.retain_cancellable(f, || false);
+    }
+
+    /// As `retain`, but `cancel` is polled periodically while this proceeds, and if it
+    /// returns `true` the operation stops early. Returns `true` if every entry was
+    /// visited, and `false` if it was cancelled.
+    ///
+    /// A cancelled retain may have removed only some of the entries, so the transaction
+    /// should then be dropped rather than committed. As with any uncommitted change, the
+    /// committed tree is unaffected.
+    pub fn retain_cancellable<F, C>(&mut self, mut f: F, cancel: C) -> bool
+    where
+        F: FnMut(&K, &V) -> bool,
+        C: Fn() -> bool,
+    {
+        let mut remove = Vec::new();
+        for (i, (k, v)) in self.work.kv_iter().enumerate() {
+            if i % CANCEL_INTERVAL == 0 && cancel() {
+                return false;
+            }
+            if !f(k, v) {
+                remove.push(k.clone());
+            }
+        }
+        for (i, k) in remove.iter().enumerate() {
+            if i % CANCEL_INTERVAL == 0 && cancel() {
+                return false;
+            }
+            self.work.remove(k);
+            self.changes.removed(k);
+        }
+        true
     }
 
     // split_off
@@ -316,7 +999,68 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
 
     /// Remove all values less than (but not including) key from the map.
     pub fn split_off_lt(&mut self, key: &K) {
-        self.work.split_off_lt(key)
+        if self.changes.keys.is_some() {
+            for k in self.work.k_iter().take_while(|k| *k < key) {
+                self.changes.track(k, true);
+            }
+        }
+        let before = self.work.len();
+        self.work.split_off_lt(key);
+        self.changes.counts.record_removed(before - self.work.len());
+    }
+
+    /// Merge all the entries of another read transaction into this tree. If a key
+    /// exists in both, the value from `other` replaces the value in this tree.
+    ///
+    /// As both trees are ordered, the entries of `other` that differ from this tree are
+    /// found by a single sorted merge of the two, rather than a lookup per key. A value
+    /// of a node that both trees share, such as when `other` is a snapshot this tree
+    /// has not yet changed, is known to be equal and is skipped. Only the differing
+    /// entries are cloned, and they are merged in place, so only the leaves that they
+    /// change are copied. Keys past the end of this tree are appended along its right
+    /// edge, as by `AppendCursor::push`.
+    ///
+    /// The `CommitSummary` counts each differing key of `other` as written, as by an
+    /// `insert`.
+    pub fn extend_from_read(&mut self, other: &BptreeMapReadTxn<K, V>) {
+        // Each differing entry of other, with whether its key is present in this tree,
+        // and the index from which the keys are past the end of this tree.
+        let mut changed: Vec<(K, V, bool)> = Vec::new();
+        let mut past_end = None;
+        {
+            let mut ours = self.work.kv_iter().peekable();
+            for (tk, tv) in other.iter() {
+                while let Some(&(ok, _)) = ours.peek() {
+                    if ok >= tk {
+                        break;
+                    }
+                    ours.next();
+                }
+                let ov = match ours.peek() {
+                    Some(&(ok, ov)) if ok == tk => Some(ov),
+                    Some(_) => None,
+                    None => {
+                        past_end.get_or_insert(changed.len());
+                        None
+                    }
+                };
+                match ov {
+                    Some(ov) if ptr::eq(ov, tv) => {}
+                    _ => changed.push((tk.clone(), tv.clone(), ov.is_some())),
+                }
+            }
+        }
+        let appended = changed.split_off(past_end.unwrap_or(changed.len()));
+        for (k, v, present) in changed {
+            self.changes.wrote(&k, present);
+            self.work.insert(k, v);
+        }
+        let mut tail = ptr::null_mut();
+        for (k, v, _) in appended {
+            self.changes.wrote(&k, false);
+            self.work.append(&mut tail, k, v);
+        }
+        self.evict_to_limit(0);
     }
 
     // ADVANCED
@@ -326,7 +1070,35 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
     /// safely cloned before you attempt to mutate the value, isolating it from
     /// other transactions.
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        self.work.get_mut_ref(key)
+        let v = self.work.get_mut_ref(key)?;
+        self.changes.wrote(key, true);
+        Some(v)
+    }
+
+    /// Get mutable references to the values of several distinct keys at once. This
+    /// returns `None` if any key is not present in the tree, or if any key is
+    /// requested more than once, as that would alias the same value.
+    ///
+    /// As with `get_mut`, each value is cloned into this transaction before you
+    /// are able to mutate it.
+    pub fn get_many_mut<const N: usize>(&mut self, keys: [&K; N]) -> Option<[&mut V; N]> {
+        // Validate everything up front, so that we never hand out aliased
+        // references or clone paths for a request that can't be satisfied.
+        for (idx, k) in keys.iter().enumerate() {
+            if keys[..idx].contains(k) || !self.work.contains_key(*k) {
+                return None;
+            }
+        }
+
+        let mut vptrs: [*mut V; N] = [ptr::null_mut(); N];
+        for (vptr, k) in vptrs.iter_mut().zip(keys.iter()) {
+            // Once a leaf is cloned into this txn it is never cloned again by
+            // a later path clone, so the earlier pointers remain valid.
+            *vptr = self.work.get_mut_ref(*k)? as *mut V;
+            self.changes.wrote(*k, true);
+        }
+        // The keys are distinct, so these can not alias.
+        Some(vptrs.map(|vptr| unsafe { &mut *vptr }))
     }
 
     // range_mut
@@ -411,11 +1183,51 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
     }
 
     /// Commit the changes from this write transaction. Readers after this point
-    /// will be able to percieve these changes.
+    /// will be able to percieve these changes. Returns the counts of the inserts,
+    /// updates and removes this transaction made, as described by `CommitSummary`.
     ///
     /// To abort (unstage changes), just do not call this function.
-    pub fn commit(self) {
-        self.caller.commit(self.work.finalise())
+    pub fn commit(self) -> CommitSummary {
+        let caller = self.caller;
+        let changes = self.changes.summary(|k| self.work.contains_key(k));
+        timed!(caller, commit, caller.commit(self.work.finalise()));
+        changes
+    }
+
+    /// Abandon the changes of this write transaction. This is equivalent to dropping
+    /// it without calling `commit()`: the committed tree and its version are unchanged.
+    pub fn discard(self) {
+        drop(self)
+    }
+
+    /// Commit the changes from this write transaction, and begin a read transaction
+    /// of exactly the version that was committed. Unlike a `commit` followed by a
+    /// `read`, another writer can not commit in between, so the reader is guaranteed
+    /// to observe the changes of this transaction.
+    pub fn commit_and_read(self) -> BptreeMapReadTxn<'a, K, V> {
+        let caller = self.caller;
+        let pin = timed!(caller, commit, caller.commit(self.work.finalise()));
+        let work = CursorRead::new(pin.as_ref());
+        BptreeMapReadTxn {
+            _caller: caller,
+            _pin: pin,
+            work,
+        }
+    }
+
+    /// Commit the changes from this write transaction only if `validate` accepts
+    /// them. The validator is given a snapshot of the tree as it would be once
+    /// committed. If it returns an error, the changes are discarded, the previously
+    /// committed state remains, and the error is returned. Otherwise the summary of
+    /// `commit` is returned.
+    pub fn commit_checked<E, F>(self, validate: F) -> Result<CommitSummary, E>
+    where
+        F: FnOnce(&BptreeMapReadSnapshot<K, V>) -> Result<(), E>,
+    {
+        validate(&BptreeMapReadSnapshot {
+            work: SnapshotType::W(&self.work),
+        })?;
+        Ok(self.commit())
     }
 }
 
@@ -429,10 +1241,21 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
         K: Borrow<Q>,
         Q: Ord,
     {
-        self.work.search(k)
+        timed!(self._caller, get, self.work.search(k))
     }
 
-    /// Assert if a key exists in the tree.
+    /// Retrieve the key as it is stored in the tree, and its value. This allows an
+    /// equal key to be exchanged for the stored instance, such as when interning.
+    pub fn get_key_value<Q: ?Sized>(&'a self, k: &Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        self.work.search_kv(k)
+    }
+
+    /// Assert if a key exists in the tree. Unlike `get`, this only searches the keys
+    /// of the leaf, and never reads the value.
     pub fn contains_key<'b, Q: ?Sized>(&'a self, k: &'b Q) -> bool
     where
         K: Borrow<Q>,
@@ -441,6 +1264,14 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
         self.work.contains_key(k)
     }
 
+    /// Determine if every key of `keys` is present in the tree. Each key is searched
+    /// for from the root, so a few keys are checked without walking the tree. This
+    /// stops at the first key that is not found. An empty set of keys is always
+    /// present.
+    pub fn contains_all(&self, keys: &[K]) -> bool {
+        keys.iter().all(|k| self.work.contains_key(k))
+    }
+
     /// Returns the current number of k:v pairs in the tree
     pub fn len(&self) -> usize {
         self.work.len()
@@ -457,176 +1288,2416 @@ impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send
         self.work.get_txid()
     }
 
-    /// Iterator over `(&K, &V)` of the set
-    pub fn iter(&self) -> Iter<K, V> {
-        self.work.kv_iter()
+    /// The number of nodes (both branches and leaves) in this tree.
+    pub fn node_count(&self) -> usize {
+        self.work.node_count()
     }
 
-    /// Iterator over &K
-    pub fn values(&self) -> ValueIter<K, V> {
-        self.work.v_iter()
+    /// The height of this tree. A tree that consists of a single leaf has a height
+    /// of 1.
+    pub fn height(&self) -> usize {
+        self.work.height()
     }
 
-    /// Iterator over &V
-    pub fn keys(&self) -> KeyIter<K, V> {
-        self.work.k_iter()
+    /// The latencies of the operations of this map that have been recorded so far.
+    /// Unlike the content of the tree, this is not a snapshot, and includes operations
+    /// that began after this read.
+    #[cfg(feature = "profiling")]
+    pub fn op_latencies(&self) -> LatencySummary {
+        self._caller.latencies.summary()
     }
 
-    /// Create a read-snapshot of the current tree.
-    /// As this is the read variant, it IS safe, and guaranteed the tree will not change.
-    pub fn to_snapshot(&'a self) -> BptreeMapReadSnapshot<K, V> {
-        BptreeMapReadSnapshot {
-            work: SnapshotType::R(&self.work), // pin: PhantomData,
-        }
+    /// Clone the content of this snapshot into a vector of pairs, in ascending key
+    /// order. After this the read transaction can be dropped, releasing the snapshot,
+    /// in exchange for holding a full copy of the data.
+    pub fn to_owned_vec(&self) -> Vec<(K, V)> {
+        let mut v = Vec::with_capacity(self.len());
+        v.extend(self.iter().map(|(k, v)| (k.clone(), v.clone())));
+        v
     }
 
-    #[cfg(test)]
-    #[allow(dead_code)]
-    pub(crate) fn verify(&self) -> bool {
+    /// Fold every pair of this snapshot into an accumulator, in ascending key order.
+    /// This consumes the read transaction, so the snapshot is released as soon as the
+    /// fold completes, and only the result outlives it.
+    pub fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, &K, &V) -> B,
+    {
+        self.iter().fold(init, |acc, (k, v)| f(acc, k, v))
+    }
+
+    /// The branches visited while searching for `k`, from the root down to the parent
+    /// of the leaf that may hold it. This is only for debugging and introspection, and
+    /// for a tree that is a single leaf the path is empty.
+    pub fn descent_path<Q: ?Sized>(&self, k: &Q) -> Vec<DescentStep<K>>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        self.work.descent_path(k)
+    }
+
+    /// Iterator over `(&K, &V)` of the set
+    ///
+    /// The iterator borrows this read transaction's snapshot, so it is never invalidated
+    /// by writers. Concurrent inserts, removes and commits are not visible to it, and it
+    /// yields exactly the content of the tree as at the time the read began.
+    pub fn iter(&self) -> Iter<K, V> {
+        self.work.kv_iter()
+    }
+
+    /// Iterator over &K
+    pub fn values(&self) -> ValueIter<K, V> {
+        self.work.v_iter()
+    }
+
+    /// Iterator over &V
+    pub fn keys(&self) -> KeyIter<K, V> {
+        self.work.k_iter()
+    }
+
+    /// Iterator over the `(&K, &V)` of the keys within `range`, in ascending key order.
+    /// The search begins at the leaf that holds the start of the range, rather than
+    /// the first leaf of the tree.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> RangeIter<K, V> {
+        self.work
+            .range_iter(range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    /// Serialize the entries of the keys within `range` as a map, in ascending key
+    /// order, such as for an incremental export. The entries are streamed from this
+    /// snapshot to the serializer, so they are never collected in memory.
+    #[cfg(feature = "serde")]
+    pub fn serialize_range<R, S>(&self, range: R, ser: S) -> Result<S::Ok, S::Error>
+    where
+        R: RangeBounds<K>,
+        S: serde::Serializer,
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        ser.collect_map(self.range(range))
+    }
+
+    /// The entry of the least key within `range`, such as to find the next entry after
+    /// a key. Unlike taking the first of `range`, this is a single descent of the tree,
+    /// and no iterator is built.
+    pub fn range_first<R: RangeBounds<K>>(&self, range: R) -> Option<(&K, &V)> {
+        self.work
+            .lower_kv(range.start_bound())
+            .filter(|(k, _)| range.contains(*k))
+    }
+
+    /// The entry of the greatest key within `range`. As with `range_first`, this is a
+    /// single descent of the tree.
+    pub fn range_last<R: RangeBounds<K>>(&self, range: R) -> Option<(&K, &V)> {
+        self.work
+            .upper_kv(range.end_bound())
+            .filter(|(k, _)| range.contains(*k))
+    }
+
+    /// As `range`, with the bounds given as owned values. This suits bounds that are
+    /// computed at runtime, such as from a parsed query.
+    pub fn range_bounds(&self, lo: Bound<K>, hi: Bound<K>) -> RangeIter<K, V> {
+        self.work.range_iter(lo, hi)
+    }
+
+    /// Iterator over the differences from this snapshot to a `newer` snapshot of the
+    /// same map, in ascending key order. Keys only in `newer` are `DiffEntry::Added`,
+    /// keys only in this snapshot are `DiffEntry::Removed`, and keys in both with
+    /// values that are not equal are `DiffEntry::Changed`.
+    ///
+    /// As nodes are copied on write, the two snapshots share every node that was not
+    /// altered between them, and these shared subtrees are skipped without visiting
+    /// their entries. The cost of the diff is then related to the number of changes,
+    /// rather than to the size of the map. Snapshots of unrelated maps are compared
+    /// correctly, but share no nodes, so every entry of each is visited.
+    pub fn diff<'d>(&'d self, newer: &'d BptreeMapReadTxn<K, V>) -> DiffIter<'d, K, V>
+    where
+        V: PartialEq,
+    {
+        DiffIter::new(self.work.get_root(), newer.work.get_root())
+    }
+
+    /// As `range`, with the position of the search held in `scratch`. The buffer is
+    /// returned to `scratch` once the iterator is dropped, so that a query that is
+    /// repeated reuses it rather than allocating a new one each time. Once the buffer
+    /// has grown to the height of the tree, the query does not allocate.
+    pub fn range_into<'s, R: RangeBounds<K>>(
+        &'s self,
+        range: R,
+        scratch: &'s mut RangeScratch<K, V>,
+    ) -> RangeIter<'s, K, V> {
+        self.work.range_iter_in(
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            scratch,
+        )
+    }
+
+    /// Iterator over the `(&K, &V)` of the keys strictly greater than `last`, in
+    /// ascending key order. This suits keyset pagination, where the last key of a page
+    /// is kept to resume from in a later, possibly newer, snapshot. `last` need not be
+    /// present in this snapshot, so a page still resumes correctly if it was removed.
+    pub fn resume_after(&self, last: &K) -> RangeIter<K, V> {
+        self.work
+            .range_iter(Bound::Excluded(last.clone()), Bound::Unbounded)
+    }
+
+    /// Create a new, independent map with the content of this snapshot. The new map
+    /// shares all of the nodes of this snapshot, so no copy occurs until either map is
+    /// written to, and then only the altered nodes are copied.
+    ///
+    /// The fork holds this snapshot alive, so memory of the origin map that would
+    /// otherwise be released may be retained for as long as the fork exists.
+    pub fn fork(&self) -> BptreeMap<K, V> {
+        BptreeMap {
+            write: Arc::new(Mutex::new(())),
+            active: Arc::new(Mutex::new(Arc::new(SuperBlock::fork(&self._pin)))),
+            #[cfg(feature = "profiling")]
+            latencies: Arc::new(Latencies::default()),
+            limit: self._caller.limit,
+            net_summary: self._caller.net_summary,
+        }
+    }
+
+    /// Replace the content of `dst` with that of this snapshot, keeping the handle of
+    /// `dst` and its options. A `max_entries` limit of `dst` still applies, so entries
+    /// beyond it are evicted as they are copied. This suits a double buffer, where one
+    /// map is repeatedly refreshed from another. Returns the nodes that were allocated
+    /// and released to do so.
+    ///
+    /// Nodes are not pooled, so rather than an allocation of a new tree, the nodes of
+    /// `dst` are kept wherever they already hold the same entries, and the change is
+    /// committed by a write of `dst` that only copies the leaves that differ, and the
+    /// branches above them. Every entry of both maps is compared, unless they share
+    /// nodes. Readers of `dst` keep their snapshot, and this waits for any writer of
+    /// `dst` to finish.
+    ///
+    /// Unlike `fork`, this never holds the snapshot alive, so two maps that are
+    /// refreshed from each other in turn do not retain each other's past versions.
+    pub fn clone_into(&self, dst: &mut BptreeMap<K, V>) -> AllocStats
+    where
+        V: PartialEq,
+    {
+        let mut wr = dst.write();
+        let prev = dst.read();
+        for change in prev.diff(self) {
+            match change {
+                DiffEntry::Removed(k, _) => {
+                    wr.remove(k);
+                }
+                DiffEntry::Added(k, v) | DiffEntry::Changed(k, _, v) => {
+                    wr.insert(k.clone(), v.clone());
+                }
+            }
+        }
+        let stats = wr.alloc_stats();
+        wr.commit();
+        stats
+    }
+
+    /// Create a read-snapshot of the current tree.
+    /// As this is the read variant, it IS safe, and guaranteed the tree will not change.
+    pub fn to_snapshot(&'a self) -> BptreeMapReadSnapshot<K, V> {
+        BptreeMapReadSnapshot {
+            work: SnapshotType::R(&self.work), // pin: PhantomData,
+        }
+    }
+
+    #[cfg(test)]
+    #[allow(dead_code)]
+    pub(crate) fn verify(&self) -> bool {
         self.work.verify()
     }
-}
+}
+
+impl<'w, 'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
+    AppendCursor<'w, 'a, K, V>
+{
+    /// Insert a key that is greater than every key in the tree, splitting the rightmost
+    /// leaf if it is full. If `k` is not greater, this panics.
+    pub fn push(&mut self, k: K, v: V) {
+        self.txn.changes.wrote(&k, false);
+        self.txn.work.append(&mut self.tail, k, v);
+        if self.txn.evict_to_limit(0) {
+            // An eviction may have changed the rightmost leaf.
+            self.tail = ptr::null_mut();
+        }
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
+    BatchWriter<'a, K, V>
+{
+    /// Insert a key and value, committing if this completes a batch.
+    pub fn push(&mut self, k: K, v: V) {
+        let map = self.map;
+        self.txn.get_or_insert_with(|| map.write()).insert(k, v);
+        self.pending += 1;
+        if self.pending >= self.batch {
+            self.flush();
+        }
+    }
+
+    /// Commit the inserts of the current batch now, even if it is not complete.
+    pub fn flush(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            txn.commit();
+        }
+        self.pending = 0;
+    }
+
+    /// The number of inserts that are not yet committed.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Drop
+    for BatchWriter<'a, K, V>
+{
+    fn drop(&mut self) {
+        // Don't publish a batch that a panic may have left half applied.
+        if !std::thread::panicking() {
+            self.flush();
+        }
+    }
+}
+
+impl<'w, 'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
+    Entry<'w, 'a, K, V>
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+
+    /// Access the value of this entry, inserting `v` if it is vacant.
+    pub fn or_insert(self, v: V) -> &'w mut V {
+        self.or_insert_with(|| v)
+    }
+
+    /// Access the value of this entry, inserting the result of `f` if it is vacant.
+    /// `f` is only called when the entry is vacant.
+    pub fn or_insert_with<F>(self, f: F) -> &'w mut V
+    where
+        F: FnOnce() -> V,
+    {
+        self.or_insert_with_key(|_| f())
+    }
+
+    /// As `or_insert_with`, where `f` is given the key that is being inserted, so
+    /// that the value can be computed from it. `f` is only called when the entry is
+    /// vacant.
+    pub fn or_insert_with_key<F>(self, f: F) -> &'w mut V
+    where
+        F: FnOnce(&K) -> V,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let v = f(e.key());
+                e.insert(v)
+            }
+        }
+    }
+}
+
+impl<'w, 'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
+    OccupiedEntry<'w, 'a, K, V>
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.k
+    }
+
+    /// Access the value of this entry. This does not clone any nodes into the
+    /// transaction.
+    pub fn get(&self) -> &V {
+        unsafe { &*self.value }
+    }
+
+    /// Access the value of this entry mutably. As with `BptreeMapWriteTxn::get_mut`,
+    /// the value is cloned into this transaction before it is returned.
+    pub fn into_mut(self) -> &'w mut V {
+        self.txn.changes.wrote(&self.k, true);
+        self.txn
+            .work
+            .get_mut_ref(&self.k)
+            .expect("Occupied entry is missing from the tree")
+    }
+}
+
+impl<'w, 'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
+    VacantEntry<'w, 'a, K, V>
+{
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.k
+    }
+
+    /// Insert a value into this entry. If the map has a `max_entries` limit, the
+    /// inserted key is always kept, and an entry of another key is evicted instead.
+    pub fn insert(self, v: V) -> &'w mut V {
+        let VacantEntry { txn, k } = self;
+        txn.evict_to_limit(1);
+        txn.changes.wrote(&k, false);
+        txn.work.insert_ref(k, v).1
+    }
+}
+
+impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Versioned
+    for BptreeMapReadTxn<'a, K, V>
+{
+    /// The transaction id of the tree this transaction observes. This increases with
+    /// each commit.
+    fn version(&self) -> u64 {
+        self.work.get_txid()
+    }
+}
+
+/// Iterator over the merged content of several read transactions, in ascending key
+/// order, where `layers` are ordered from the highest priority to the lowest. Each key
+/// is yielded once, with the value of the highest priority layer that holds it, so
+/// that newer layers shadow older ones, as in a log structured merge tree.
+///
+/// This walks every layer once, keeping only the front entry of each in a heap, so the
+/// merged content is never copied.
+///
+/// # Examples
+/// ```
+/// use concread::bptree::{merge_iter, BptreeMap};
+///
+/// let newer: BptreeMap<usize, &str> = vec![(2, "new")].into_iter().collect();
+/// let older: BptreeMap<usize, &str> = vec![(1, "old"), (2, "old")].into_iter().collect();
+/// let (newer, older) = (newer.read(), older.read());
+/// let merged: Vec<_> = merge_iter(&[&newer, &older]).collect();
+/// assert_eq!(merged, vec![(&1, &"old"), (&2, &"new")]);
+/// ```
+pub fn merge_iter<'a, 'b, K, V>(layers: &[&'a BptreeMapReadTxn<'b, K, V>]) -> MergeIter<'a, K, V>
+where
+    K: Clone + Ord + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    MergeIter::new(layers.iter().map(|l| l.iter()).collect())
+}
+
+impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
+    BptreeMapReadSnapshot<'a, K, V>
+{
+    /// Retrieve a value from the tree. If the value exists, a reference is returned
+    /// as `Some(&V)`, otherwise if not present `None` is returned.
+    pub fn get<Q: ?Sized>(&'a self, k: &'a Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        match self.work {
+            SnapshotType::R(work) => work.search(k),
+            SnapshotType::W(work) => work.search(k),
+        }
+    }
+
+    /// Assert if a key exists in the tree.
+    pub fn contains_key<'b, Q: ?Sized>(&'a self, k: &'b Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        match self.work {
+            SnapshotType::R(work) => work.contains_key(k),
+            SnapshotType::W(work) => work.contains_key(k),
+        }
+    }
+
+    /// Returns the current number of k:v pairs in the tree
+    pub fn len(&self) -> usize {
+        match self.work {
+            SnapshotType::R(work) => work.len(),
+            SnapshotType::W(work) => work.len(),
+        }
+    }
+
+    /// Determine if the set is currently empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // (adv) range
+
+    /// Iterator over `(&K, &V)` of the set
+    pub fn iter(&self) -> Iter<K, V> {
+        match self.work {
+            SnapshotType::R(work) => work.kv_iter(),
+            SnapshotType::W(work) => work.kv_iter(),
+        }
+    }
+
+    /// Iterator over &K
+    pub fn values(&self) -> ValueIter<K, V> {
+        match self.work {
+            SnapshotType::R(work) => work.v_iter(),
+            SnapshotType::W(work) => work.v_iter(),
+        }
+    }
+
+    /// Iterator over &V
+    pub fn keys(&self) -> KeyIter<K, V> {
+        match self.work {
+            SnapshotType::R(work) => work.k_iter(),
+            SnapshotType::W(work) => work.k_iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cursor::CursorReadOps;
+    use super::iter::{DiffEntry, RangeScratch};
+    use super::node::{alloc_count, assert_released, BV_CAPACITY, L_CAPACITY};
+    use super::{
+        merge_iter, AllocStats, BptreeMap, BptreeMapBuilder, BptreeMapReadSnapshot,
+        BptreeMapReadTxn, BptreeMapWriteTxn, Entry, EvictSide, StructureEvent,
+    };
+    use crate::{CommitSummary, Versioned};
+    // use rand::prelude::*;
+    use rand::seq::SliceRandom;
+    use std::iter::FromIterator;
+    use std::ops::Bound;
+
+    #[test]
+    fn test_bptree2_map_basic_write() {
+        let bptree: BptreeMap<usize, usize> = BptreeMap::new();
+        {
+            let mut bpwrite = bptree.write();
+            // We should be able to insert.
+            bpwrite.insert(0, 0);
+            bpwrite.insert(1, 1);
+            assert!(bpwrite.get(&0) == Some(&0));
+            assert!(bpwrite.get(&1) == Some(&1));
+            bpwrite.insert(2, 2);
+            bpwrite.commit();
+            // println!("commit");
+        }
+        {
+            // Do a clear, but roll it back.
+            let mut bpwrite = bptree.write();
+            bpwrite.clear();
+            // DO NOT commit, this triggers the rollback.
+            // println!("post clear");
+        }
+        {
+            let bpwrite = bptree.write();
+            assert!(bpwrite.get(&0) == Some(&0));
+            assert!(bpwrite.get(&1) == Some(&1));
+            // println!("fin write");
+        }
+        std::mem::drop(bptree);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_clear_commit() {
+        let bptree: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..(L_CAPACITY << 4)).map(|v| (v, v)));
+        let rd = bptree.read();
+        {
+            let mut bpwrite = bptree.write();
+            bpwrite.clear();
+            bpwrite.insert(0, 1);
+            bpwrite.commit();
+        }
+        assert!(rd.len() == (L_CAPACITY << 4));
+        assert!(bptree.read().len() == 1);
+        std::mem::drop(rd);
+        std::mem::drop(bptree);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_cursed_get_mut() {
+        let bptree: BptreeMap<usize, usize> = BptreeMap::new();
+        {
+            let mut w = bptree.write();
+            w.insert(0, 0);
+            w.commit();
+        }
+        let r1 = bptree.read();
+        {
+            let mut w = bptree.write();
+            let cursed_zone = w.get_mut(&0).unwrap();
+            *cursed_zone = 1;
+            // Correctly fails to work as it's a second borrow, which isn't
+            // possible once w.remove occurs
+            // w.remove(&0);
+            // *cursed_zone = 2;
+            w.commit();
+        }
+        let r2 = bptree.read();
+        assert!(r1.get(&0) == Some(&0));
+        assert!(r2.get(&0) == Some(&1));
+
+        /*
+        // Correctly fails to compile. PHEW!
+        let fail = {
+            let mut w = bptree.write();
+            w.get_mut(&0).unwrap()
+        };
+        */
+        std::mem::drop(r1);
+        std::mem::drop(r2);
+        std::mem::drop(bptree);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_get_many_mut() {
+        let bptree: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..(L_CAPACITY << 2)).map(|v| (v, v)));
+        let r1 = bptree.read();
+        {
+            let mut w = bptree.write();
+            // Swap two values that live in different leaves.
+            {
+                let [a, b] = w.get_many_mut([&0, &((L_CAPACITY << 2) - 1)]).unwrap();
+                std::mem::swap(a, b);
+            }
+            assert!(w.get(&0) == Some(&((L_CAPACITY << 2) - 1)));
+            assert!(w.get(&((L_CAPACITY << 2) - 1)) == Some(&0));
+            // Duplicate and missing keys are rejected.
+            assert!(w.get_many_mut([&1, &2, &1]).is_none());
+            assert!(w.get_many_mut([&1, &(L_CAPACITY << 2)]).is_none());
+            assert!(w.verify());
+            w.commit();
+        }
+        let r2 = bptree.read();
+        assert!(r1.get(&0) == Some(&0));
+        assert!(r2.get(&0) == Some(&((L_CAPACITY << 2) - 1)));
+
+        std::mem::drop(r1);
+        std::mem::drop(r2);
+        std::mem::drop(bptree);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_cheap_clone_keys() {
+        use super::CheapClone;
+        use std::sync::Arc;
+
+        fn is_cheap<K: CheapClone>(_k: &K) {}
+
+        let keys: Vec<Arc<str>> = (0..(L_CAPACITY << 2))
+            .map(|v| Arc::from(format!("key-{:08}", v).as_str()))
+            .collect();
+        is_cheap(&keys[0]);
+        let map = BptreeMap::from_iter(keys.iter().cloned().map(|k| (k, 0)));
+        let rd = map.read();
+        // Touch every leaf so that the write has to copy them from the snapshot.
+        {
+            let mut wr = map.write();
+            for k in keys.iter() {
+                *wr.get_mut(k).unwrap() += 1;
+            }
+            wr.commit();
+        }
+        let rd2 = map.read();
+        assert!(rd2.iter().all(|(_, v)| *v == 1));
+        // Both versions share the same key allocation, and the copy never made a
+        // deep clone.
+        assert!(rd
+            .keys()
+            .zip(rd2.keys())
+            .zip(keys.iter())
+            .all(|((a, b), k)| Arc::ptr_eq(a, b) && Arc::ptr_eq(a, k)));
+        std::mem::drop(rd);
+        std::mem::drop(rd2);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_introspection() {
+        type Map = BptreeMap<usize, usize>;
+        assert!(Map::leaf_capacity() == L_CAPACITY);
+        assert!(Map::branch_capacity() == L_CAPACITY + 1);
+
+        let map: Map = BptreeMap::new();
+        {
+            let rd = map.read();
+            assert!(rd.height() == 1);
+            assert!(rd.node_count() == 1);
+        }
+
+        let mut last_height = 1;
+        for size in [
+            L_CAPACITY,
+            L_CAPACITY << 2,
+            L_CAPACITY << 6,
+            L_CAPACITY << 10,
+        ]
+        .iter()
+        {
+            let mut wr = map.write();
+            wr.extend((0..*size).map(|v| (v, v)));
+            wr.commit();
+            let rd = map.read();
+            let height = rd.height();
+            // Compare to a walk of every path in the tree.
+            assert!(rd.work.get_root_ref().depth_bounds() == (height, height));
+            assert!(height >= last_height);
+            // A full tree of this height must be able to hold all the items.
+            assert!(Map::branch_capacity().pow(height as u32 - 1) * Map::leaf_capacity() >= *size);
+            assert!(rd.node_count() >= rd.work.get_root_ref().leaf_count() + height - 1);
+            last_height = height;
+        }
+        assert!(last_height > 2);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_extend_from_read() {
+        // Interleaved and overlapping keys, with different values in each map.
+        let primary: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..(L_CAPACITY << 4)).map(|v| (v * 2, v)));
+        let staging: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..(L_CAPACITY << 3)).map(|v| (v * 3, v + 1_000)));
+
+        let mut expect: std::collections::BTreeMap<usize, usize> =
+            primary.read().iter().map(|(k, v)| (*k, *v)).collect();
+        expect.extend(staging.read().iter().map(|(k, v)| (*k, *v)));
+
+        let prev = primary.read();
+        {
+            let srd = staging.read();
+            let mut wr = primary.write();
+            wr.extend_from_read(&srd);
+            assert!(wr.verify());
+            assert!(wr.len() == expect.len());
+            assert!(wr
+                .iter()
+                .map(|(k, v)| (*k, *v))
+                .eq(expect.clone().into_iter()));
+            wr.commit();
+        }
+        let rd = primary.read();
+        assert!(rd.verify());
+        assert!(rd.iter().map(|(k, v)| (*k, *v)).eq(expect.into_iter()));
+        // The staging map and earlier readers are unaffected.
+        assert!(prev.len() == (L_CAPACITY << 4));
+        assert!(prev.iter().all(|(k, v)| *k == *v * 2));
+        assert!(staging.read().len() == (L_CAPACITY << 3));
+
+        // Merging an empty snapshot, or the snapshot this tree began from, is a no-op.
+        let empty: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = primary.write();
+        wr.extend_from_read(&empty.read());
+        wr.extend_from_read(&rd);
+        assert!(wr.len() == rd.len());
+        assert!(wr.alloc_stats().allocated == 0);
+        std::mem::drop(wr);
+
+        // Only the leaves that a merge changes are copied, with the branches above
+        // them, rather than the whole tree.
+        let few: BptreeMap<usize, usize> = BptreeMap::from_iter(vec![(1, 1), (usize::MAX, 0)]);
+        let mut wr = primary.write();
+        wr.extend_from_read(&few.read());
+        assert!(wr.get(&1) == Some(&1) && wr.get(&usize::MAX) == Some(&0));
+        assert!(wr.verify());
+        // A tree of this size has at least len / L_CAPACITY leaves.
+        assert!(wr.alloc_stats().freed < (wr.len() / L_CAPACITY / 2) as u64);
+
+        std::mem::drop(wr);
+        std::mem::drop(rd);
+        std::mem::drop(prev);
+        std::mem::drop(primary);
+        std::mem::drop(staging);
+        std::mem::drop(empty);
+        std::mem::drop(few);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_drain_range() {
+        let size = L_CAPACITY << 4;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..size).map(|v| (v, v)));
+        let lower = L_CAPACITY * 3;
+        let upper = size - (L_CAPACITY * 3);
+
+        let prev = map.read();
+        {
+            let mut wr = map.write();
+            let drained: Vec<(usize, usize)> = wr.drain_range(lower..upper).collect();
+            assert!(drained.iter().map(|(k, _)| *k).eq(lower..upper));
+            assert!(drained.iter().all(|(k, v)| k == v));
+            assert!(wr.verify());
+            assert!(wr.len() == size - (upper - lower));
+            assert!(wr.keys().copied().eq((0..lower).chain(upper..size)));
+            // An empty range removes nothing.
+            assert!(wr.drain_range(lower..upper).next().is_none());
+            wr.commit();
+        }
+        // Readers prior to the drain are unaffected.
+        assert!(prev.len() == size);
+        std::mem::drop(prev);
+
+        // Dropping the iterator early still removes the rest of the range.
+        {
+            let mut wr = map.write();
+            {
+                let mut drain = wr.drain_range(..=L_CAPACITY);
+                assert!(drain.next() == Some((0, 0)));
+            }
+            assert!(wr.verify());
+            assert!(wr.get(&L_CAPACITY).is_none());
+            assert!(wr.keys().next() == Some(&(L_CAPACITY + 1)));
+            wr.commit();
+        }
+
+        // The bounds of the range are honoured however they are given.
+        {
+            let mut wr = map.write();
+            let drained: Vec<usize> = wr
+                .drain_range((Bound::Excluded(upper), Bound::Unbounded))
+                .map(|(k, _)| k)
+                .collect();
+            assert!(drained.into_iter().eq((upper + 1)..size));
+            assert!(wr.verify());
+            assert!(wr.keys().last() == Some(&upper));
+        }
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_commit_checked() {
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..10).map(|v| (v, v)));
+        // Values must be unique.
+        let unique = |snap: &BptreeMapReadSnapshot<usize, usize>| {
+            let mut seen = std::collections::BTreeSet::new();
+            match snap.values().find(|v| !seen.insert(**v)) {
+                Some(v) => Err(*v),
+                None => Ok(()),
+            }
+        };
+
+        let mut wr = map.write();
+        wr.insert(10, 3);
+        wr.remove(&0);
+        assert!(wr.commit_checked(unique) == Err(3));
+
+        let rd = map.read();
+        assert!(rd.len() == 10);
+        assert!(rd.get(&0) == Some(&0));
+        assert!(rd.get(&10).is_none());
+        std::mem::drop(rd);
+
+        let mut wr = map.write();
+        wr.insert(10, 10);
+        assert!(wr.commit_checked(unique).is_ok());
+        assert!(map.read().get(&10) == Some(&10));
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_reserve() {
+        let size = L_CAPACITY << 6;
+        let mut ins: Vec<usize> = (0..size).collect();
+        ins.shuffle(&mut rand::thread_rng());
+
+        let plain: BptreeMap<usize, usize> = BptreeMap::new();
+        let reserved: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = plain.write();
+        wr.extend(ins.iter().map(|v| (*v, *v)));
+        wr.commit();
+        let mut wr = reserved.write();
+        wr.reserve(size);
+        assert!(wr.is_empty());
+        wr.extend(ins.iter().map(|v| (*v, *v)));
+        assert!(wr.verify());
+        wr.commit();
+
+        assert!(plain.read().iter().eq(reserved.read().iter()));
+        std::mem::drop(plain);
+        std::mem::drop(reserved);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_get_or_insert_with() {
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..L_CAPACITY).map(|v| (v, v)));
+        let mut calls = 0;
+        let mut wr = map.write();
+        // Present keys do not call f.
+        assert!(
+            *wr.get_or_insert_with(1, || {
+                calls += 1;
+                100
+            }) == 1
+        );
+        assert!(calls == 0);
+        // Absent keys call f exactly once, and the value is inserted.
+        for k in L_CAPACITY..(L_CAPACITY << 4) {
+            assert!(
+                *wr.get_or_insert_with(k, || {
+                    calls += 1;
+                    k * 2
+                }) == k * 2
+            );
+        }
+        assert!(calls == (L_CAPACITY << 4) - L_CAPACITY);
+        assert!(*wr.get_or_insert_with(L_CAPACITY, || 0) == L_CAPACITY * 2);
+        assert!(wr.len() == L_CAPACITY << 4);
+        assert!(wr.verify());
+        wr.commit();
+
+        // The value is returned from where the insert placed it, including when a leaf
+        // splits within its range or to the left, rather than by a second search.
+        let base = L_CAPACITY << 3;
+        let split: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..(L_CAPACITY << 2)).map(|v| (base + v * 2, v)));
+        let mut wr = split.write();
+        let within = (0..(L_CAPACITY << 2)).map(|v| base + v * 2 + 1);
+        for k in within.chain((0..base).rev()) {
+            let v = wr.get_or_insert_with(k, || k) as *const usize;
+            assert!(unsafe { *v } == k);
+            assert!(std::ptr::eq(v, wr.get(&k).unwrap()));
+        }
+        assert!(wr.len() == base + (L_CAPACITY << 3));
+        assert!(wr.verify());
+        std::mem::drop(wr);
+        std::mem::drop(split);
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_clone_handle() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let handle = map.clone();
+        let rd = map.read();
+
+        let mut wr = handle.write();
+        wr.insert(1, 1);
+        wr.commit();
+        // The clone shares the map, so the commit is visible through the original.
+        assert!(map.read().get(&1) == Some(&1));
+        assert!(rd.get(&1).is_none());
+
+        let mut wr = map.write();
+        wr.insert(2, 2);
+        wr.commit();
+        assert!(handle.read().get(&2) == Some(&2));
+
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert!(handle.read().len() == 2);
+        std::mem::drop(handle);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_commit_and_read() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.insert(1, 1);
+        let rd = wr.commit_and_read();
+
+        // A competing writer commits after, but the reader stays on our version.
+        let mut wr = map.write();
+        wr.insert(1, 2);
+        wr.insert(2, 2);
+        wr.commit();
+
+        assert!(rd.get(&1) == Some(&1));
+        assert!(rd.get(&2).is_none());
+        assert!(rd.len() == 1);
+        assert!(map.read().get(&1) == Some(&2));
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_remove_collapse() {
+        let count = L_CAPACITY << 6;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..count).map(|v| (v, v)));
+        assert!(map.read().height() > 2);
+
+        let mut wr = map.write();
+        for k in 1..count {
+            assert!(wr.remove(&k) == Some(k));
+        }
+        assert!(wr.remove(&1).is_none());
+        wr.commit();
+
+        // Only one key is left, so the tree is a single leaf again.
+        let rd = map.read();
+        assert!(rd.len() == 1);
+        assert!(rd.height() == 1);
+        assert!(rd.verify());
+        std::mem::drop(rd);
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_contains_key() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.extend(
+            (0..(L_CAPACITY * 16))
+                .filter(|k| k % 3 != 0)
+                .map(|k| (k, k)),
+        );
+        wr.commit();
+
+        let rd = map.read();
+        for k in 0..(L_CAPACITY * 16 + 2) {
+            assert!(rd.contains_key(&k) == rd.get(&k).is_some());
+            assert!(rd.contains_key(&k) == (k % 3 != 0 && k < L_CAPACITY * 16));
+        }
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_contains_all() {
+        let count = L_CAPACITY << 4;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..count).map(|v| (v * 2, v)));
+        let rd = map.read();
+        let sets: [&[usize]; 6] = [
+            &[],
+            &[4, 0, 2, 4],
+            &[(count - 1) * 2, 0],
+            &[0, 2, 3],
+            &[1, 3, 5],
+            &[count * 2],
+        ];
+        for keys in sets.iter() {
+            let expect = keys.iter().all(|k| k % 2 == 0 && *k < count * 2);
+            assert!(rd.contains_all(keys) == expect);
+        }
+        std::mem::drop(rd);
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_to_owned_vec() {
+        let count = L_CAPACITY << 4;
+        let map: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..count).rev().map(|v| (v, v * 2)));
+        let rd = map.read();
+        let owned = rd.to_owned_vec();
+        assert!(owned.len() == rd.len());
+        assert!(owned.iter().zip(rd.iter()).all(|(a, b)| (&a.0, &a.1) == b));
+        std::mem::drop(rd);
+
+        // Changes after the snapshot do not affect the owned copy.
+        let mut wr = map.write();
+        wr.remove(&0);
+        wr.commit();
+        assert!(owned[0] == (0, 0));
+        assert!(owned.windows(2).all(|w| w[0].0 < w[1].0));
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_bptree2_map_serialize_range() {
+        use std::collections::BTreeMap;
+
+        let count = L_CAPACITY << 4;
+        let map: BptreeMap<usize, String> =
+            BptreeMap::from_iter((0..count).map(|v| (v, v.to_string())));
+        let rd = map.read();
+        let mut out = Vec::new();
+        rd.serialize_range(10..=40, &mut serde_json::Serializer::new(&mut out))
+            .expect("Failed to serialize");
+        let back: BTreeMap<usize, String> =
+            serde_json::from_slice(&out).expect("Failed to deserialize");
+        assert!(back.into_iter().eq((10..=40).map(|v| (v, v.to_string()))));
+
+        // An empty range is an empty map.
+        let mut out = Vec::new();
+        rd.serialize_range(count.., &mut serde_json::Serializer::new(&mut out))
+            .expect("Failed to serialize");
+        assert!(out == b"{}");
+        drop(rd);
+
+        drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_range_first_last() {
+        // Only the even keys, so that odd bounds fall between entries, across many
+        // leaves and branches.
+        let count = L_CAPACITY * BV_CAPACITY * 4;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..count).map(|v| (v * 2, v)));
+        let rd = map.read();
+        assert!(rd.height() > 1);
+        for lo in (0..count * 2).step_by(7) {
+            for hi in [lo, lo + 1, lo + 2, lo + 99].iter().cloned() {
+                assert!(rd.range_first(lo..hi) == rd.range(lo..hi).next());
+                assert!(rd.range_last(lo..hi) == rd.range(lo..hi).last());
+                assert!(rd.range_first(lo..=hi) == rd.range(lo..=hi).next());
+                assert!(rd.range_last(lo..=hi) == rd.range(lo..=hi).last());
+            }
+            assert!(
+                rd.range_first((Bound::Excluded(lo), Bound::Unbounded))
+                    == rd.range((Bound::Excluded(lo), Bound::Unbounded)).next()
+            );
+            assert!(rd.range_last(..lo) == rd.range(..lo).last());
+        }
+        assert!(rd.range_first(3..) == Some((&4, &2)));
+        assert!(rd.range_first((Bound::Excluded(4), Bound::Unbounded)) == Some((&6, &3)));
+        assert!(rd.range_last(..=7) == Some((&6, &3)));
+        assert!(rd.range_last(..6) == Some((&4, &2)));
+        assert!(rd.range_first(..) == Some((&0, &0)));
+        assert!(rd.range_last(..) == Some((&((count - 1) * 2), &(count - 1))));
+
+        // Empty ranges, and ranges beyond the extremes.
+        assert!(rd.range_first(3..4).is_none());
+        assert!(rd.range_last(3..4).is_none());
+        assert!(rd.range_first(count * 2..).is_none());
+        assert!(rd.range_last(..0).is_none());
+        assert!(rd
+            .range_first((Bound::Excluded(4), Bound::Excluded(6)))
+            .is_none());
+        drop(rd);
+
+        let empty: BptreeMap<usize, usize> = BptreeMap::new();
+        assert!(empty.read().range_first(..).is_none());
+        assert!(empty.read().range_last(..).is_none());
+
+        drop(empty);
+        drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_fold() {
+        let count = L_CAPACITY << 4;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..count).map(|v| (v, v * 2)));
+        let sum = map.read().fold(0, |acc, _, v| acc + v);
+        assert!(sum == (0..count).map(|v| v * 2).sum::<usize>());
+        let keys = map.read().fold(Vec::new(), |mut acc, k, _| {
+            acc.push(*k);
+            acc
+        });
+        assert!(keys.into_iter().eq(0..count));
+        // Neither fold holds the snapshot any longer.
+        assert!(std::sync::Arc::strong_count(&*map.active.lock()) == 1);
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_into_iter() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq)]
+        struct Counted(usize);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Counted(self.0)
+            }
+        }
+
+        let count = L_CAPACITY << 4;
+        let fill = || BptreeMap::from_iter((0..count).map(|v| (v, Counted(v))));
+
+        // A map that is not shared is moved out of.
+        let map: BptreeMap<usize, Counted> = fill();
+        let before = CLONES.load(Ordering::Relaxed);
+        let iter = map.into_iter();
+        assert!(iter.len() == count);
+        assert!(iter.eq((0..count).map(|v| (v, Counted(v)))));
+        assert!(CLONES.load(Ordering::Relaxed) == before);
+
+        // Another handle keeps the tree, so it is cloned from.
+        let map: BptreeMap<usize, Counted> = fill();
+        let other = map.clone();
+        let before = CLONES.load(Ordering::Relaxed);
+        assert!(other.into_iter().eq((0..count).map(|v| (v, Counted(v)))));
+        assert!(CLONES.load(Ordering::Relaxed) == before + count);
+        assert!(map.read().len() == count);
+
+        // A fork only clones the leaves it shares with its origin.
+        let fork = map.read().fork();
+        {
+            let mut wr = fork.write();
+            wr.insert(count, Counted(count));
+            wr.commit();
+        }
+        let before = CLONES.load(Ordering::Relaxed);
+        assert!(fork.into_iter().eq((0..=count).map(|v| (v, Counted(v)))));
+        let cloned = CLONES.load(Ordering::Relaxed) - before;
+        assert!(cloned > 0 && cloned < count);
+        assert!(map.read().len() == count);
+
+        // Dropping a partially consumed iterator releases the rest of the tree.
+        let mut iter = map.into_iter();
+        assert!(iter.next() == Some((0, Counted(0))));
+        assert!(iter.nth(L_CAPACITY) == Some((L_CAPACITY + 1, Counted(L_CAPACITY + 1))));
+        std::mem::drop(iter);
+        let empty: BptreeMap<usize, Counted> = BptreeMap::new();
+        assert!(empty.into_iter().next().is_none());
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_batch_writer() {
+        let count = L_CAPACITY << 6;
+        let batch = L_CAPACITY * 3;
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        {
+            let mut wr = map.batch_writer(batch);
+            let mut prev = map.read();
+            for i in 0..count {
+                wr.push(i, i);
+                if wr.pending() == 0 {
+                    // Each complete batch is observed by new readers, while earlier
+                    // readers keep their snapshot.
+                    let rd = map.read();
+                    assert!(rd.len() == i + 1);
+                    assert!(prev.len() + batch == rd.len());
+                    assert!(prev.iter().eq(rd.iter().take(prev.len())));
+                    prev = rd;
+                } else {
+                    assert!(map.read().len() == prev.len());
+                }
+            }
+            assert!(wr.pending() == count % batch);
+            assert!(wr.pending() > 0);
+        }
+        // The final partial batch is committed on drop.
+        assert!(map.read().len() == count);
+        assert!(map
+            .read()
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..count).map(|i| (i, i))));
+
+        // A batch of zero commits every insert, and a flush commits an incomplete batch.
+        let mut wr = map.batch_writer(0);
+        wr.push(count, count);
+        assert!(wr.pending() == 0);
+        assert!(map.read().len() == count + 1);
+        std::mem::drop(wr);
+        let mut wr = map.batch_writer(batch);
+        wr.push(count + 1, count + 1);
+        wr.flush();
+        assert!(wr.pending() == 0);
+        assert!(map.read().len() == count + 2);
+        std::mem::drop(wr);
+
+        // A panic discards the partial batch, but keeps the batches already committed.
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut wr = map.batch_writer(batch);
+            for i in 0..(batch + 1) {
+                wr.push(count + 2 + i, i);
+            }
+            assert!(wr.pending() == 1);
+            panic!("Panic during an import");
+        }));
+        assert!(r.is_err());
+        let rd = map.read();
+        assert!(rd.len() == count + 2 + batch);
+        assert!(rd.get(&(count + 2 + batch)).is_none());
+        std::mem::drop(rd);
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_append_cursor() {
+        let count = L_CAPACITY << 6;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..L_CAPACITY).map(|v| (v, v)));
+        let expect: BptreeMap<usize, usize> = BptreeMap::from_iter((0..L_CAPACITY).map(|v| (v, v)));
+        {
+            // An open reader means the rightmost leaf must be cloned before appending.
+            let rd = map.read();
+            let mut wr = map.write();
+            let mut cursor = wr.append_cursor();
+            for v in L_CAPACITY..count {
+                cursor.push(v, v);
+            }
+            assert!(wr.verify());
+            assert!(wr.len() == count);
+            wr.commit();
+            assert!(rd.len() == L_CAPACITY);
+            assert!(rd.verify());
+        }
+        let mut wr = expect.write();
+        for v in L_CAPACITY..count {
+            wr.insert(v, v);
+        }
+        wr.commit();
+
+        let rd = map.read();
+        let rd_expect = expect.read();
+        assert!(rd.verify());
+        assert!(rd.iter().eq(rd_expect.iter()));
+        std::mem::drop(rd);
+        std::mem::drop(rd_expect);
+
+        // A new cursor in a later txn continues from the existing tree.
+        let mut wr = map.write();
+        wr.append_cursor().push(count, count);
+        wr.append_cursor().push(count + 1, count + 1);
+        assert!(wr.verify());
+        assert!(wr.get(&(count + 1)) == Some(&(count + 1)));
+        wr.commit();
+
+        std::mem::drop(map);
+        std::mem::drop(expect);
+        assert_released();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bptree2_map_append_cursor_unordered() {
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..4).map(|v| (v, v)));
+        let mut wr = map.write();
+        let mut cursor = wr.append_cursor();
+        cursor.push(4, 4);
+        cursor.push(4, 4);
+    }
+
+    #[test]
+    fn test_bptree2_map_write_panic() {
+        let count = L_CAPACITY << 2;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..count).map(|v| (v, v)));
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut wr = map.write();
+            for v in 0..count {
+                wr.insert(v, v + 1);
+            }
+            wr.insert(count, count);
+            panic!("Panic during a write");
+        }));
+        assert!(r.is_err());
+        // The uncommitted changes were discarded, and the lock was released.
+        let mut wr = map.try_write().expect("The write lock was not released");
+        assert!(wr.len() == count);
+        assert!(wr.iter().all(|(k, v)| k == v));
+        assert!(wr.verify());
+        wr.insert(count, count);
+        wr.commit();
+        assert!(map.read().len() == count + 1);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_descent_path() {
+        let empty: BptreeMap<usize, usize> = BptreeMap::new();
+        assert!(empty.read().descent_path(&0).is_empty());
+        std::mem::drop(empty);
+        let map: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..(L_CAPACITY << 6)).map(|v| (v, v)));
+        let rd = map.read();
+        for k in [0, L_CAPACITY, L_CAPACITY << 5, usize::MAX].iter() {
+            let path = rd.descent_path(k);
+            assert!(!path.is_empty());
+            for (level, step) in path.iter().enumerate() {
+                assert!(step.level == level);
+                // The chosen child is the one whose pivots bound the key.
+                assert!(step.child == 0 || step.pivots[step.child - 1] <= *k);
+                assert!(step.child == step.pivots.len() || *k < step.pivots[step.child]);
+            }
+        }
+        assert!(rd.verify());
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_compact() {
+        let count = L_CAPACITY << 6;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..count).map(|v| (v, v)));
+        // Leave a single key in each leaf.
+        let mut wr = map.write();
+        for v in (0..count).filter(|v| v % L_CAPACITY != 0) {
+            wr.remove(&v);
+        }
+        wr.commit();
+
+        let rd = map.read();
+        let mut wr = map.write();
+        let (populated, sparse) = wr.tree_density();
+        assert!(populated == count / L_CAPACITY);
+        wr.compact();
+        assert!(wr.verify());
+        let (populated, compact) = wr.tree_density();
+        assert!(populated == count / L_CAPACITY);
+        // Every leaf is full, except possibly the last.
+        assert!(compact < sparse);
+        assert!(compact - populated < L_CAPACITY);
+        assert!(wr
+            .iter()
+            .map(|(k, _)| *k)
+            .eq((0..count).step_by(L_CAPACITY)));
+        wr.commit();
+
+        // The reader still observes the sparse tree, which remains valid.
+        assert!(rd.verify());
+        assert!(rd.work.get_tree_density().1 == sparse);
+        let rd_compact = map.read();
+        assert!(rd_compact.node_count() < rd.node_count());
+        assert!(rd_compact.height() <= rd.height());
+        std::mem::drop(rd);
+        std::mem::drop(rd_compact);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_from_sorted_iter_dedup() {
+        let count = L_CAPACITY * 16;
+        // Every third key appears twice, and the second value should win.
+        let input = (0..count).flat_map(|k| {
+            let first = Some((k, k));
+            let dup = if k % 3 == 0 { Some((k, k + 1)) } else { None };
+            first.into_iter().chain(dup)
+        });
+        let map = BptreeMap::from_sorted_iter_dedup(input);
+        let rd = map.read();
+        assert!(rd.verify());
+        assert!(rd.len() == count);
+        for k in 0..count {
+            let expect = if k % 3 == 0 { k + 1 } else { k };
+            assert!(rd.get(&k) == Some(&expect));
+        }
+        // Every leaf is full, except possibly the last.
+        let (populated, capacity) = rd.work.get_tree_density();
+        assert!(populated == count && capacity - populated < L_CAPACITY);
+        std::mem::drop(rd);
+        std::mem::drop(map);
+
+        let empty: BptreeMap<usize, usize> = BptreeMap::from_sorted_iter_dedup(Vec::new());
+        assert!(empty.read().is_empty());
+        std::mem::drop(empty);
+        assert_released();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bptree2_map_from_sorted_iter_dedup_unordered() {
+        let _map = BptreeMap::from_sorted_iter_dedup(vec![(1, 1), (3, 3), (2, 2)]);
+    }
+
+    #[test]
+    fn test_bptree2_map_get_key_value() {
+        let map: BptreeMap<String, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        let mut stored = Vec::new();
+        for i in 0..(L_CAPACITY << 2) {
+            let k = format!("key{:04}", i);
+            // Moving the key does not move its buffer.
+            stored.push(k.as_ptr());
+            wr.insert(k, i);
+        }
+        wr.commit();
+
+        let rd = map.read();
+        for (i, ptr) in stored.iter().enumerate() {
+            let k = format!("key{:04}", i);
+            let (sk, v) = rd.get_key_value(k.as_str()).unwrap();
+            assert!(*sk == k && *v == i);
+            // The stored key is returned, rather than the one that was searched for.
+            assert!(sk.as_ptr() == *ptr);
+            assert!(sk.as_ptr() != k.as_ptr());
+        }
+        assert!(rd.get_key_value("missing").is_none());
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_retain_cancellable() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.extend((0..1024).map(|k| (k, k)));
+        wr.commit();
+
+        // Cancel part way through, so only some of the entries are removed.
+        let polls = std::cell::Cell::new(0);
+        let mut wr = map.write();
+        let done = wr.retain_cancellable(
+            |k, _| k % 2 == 0,
+            || {
+                polls.set(polls.get() + 1);
+                polls.get() > 20
+            },
+        );
+        assert!(!done);
+        assert!(wr.len() < 1024 && wr.len() > 512);
+        assert!(wr.verify());
+        std::mem::drop(wr);
+
+        // The committed tree is untouched.
+        let rd = map.read();
+        assert!(rd.len() == 1024);
+        assert!(rd.verify());
+        assert!((0..1024).all(|k| rd.get(&k) == Some(&k)));
+        std::mem::drop(rd);
+
+        // Without a cancel, every odd key is removed.
+        let mut wr = map.write();
+        assert!(wr.retain_cancellable(|k, _| k % 2 == 0, || false));
+        wr.commit();
+        let rd = map.read();
+        assert!(rd.len() == 512);
+        assert!(rd.iter().all(|(k, _)| k % 2 == 0));
+        std::mem::drop(rd);
+
+        let mut wr = map.write();
+        wr.retain(|_, v| *v < 100);
+        wr.commit();
+        assert!(map.read().len() == 50);
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_alloc_stats() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        assert!(wr.alloc_stats() == AllocStats::default());
+        // The first change copies the root leaf, releasing the original. Further
+        // changes to the copy do not allocate.
+        wr.insert(1, 1);
+        wr.insert(2, 2);
+        assert!(wr.remove(&1) == Some(1));
+        let stats = wr.alloc_stats();
+        assert!(stats.allocated == 1 && stats.freed == 1);
+        wr.commit();
+
+        // A larger insert and remove cycle allocates and releases in step.
+        let mut wr = map.write();
+        wr.extend((0..(L_CAPACITY * 8)).map(|k| (k, k)));
+        let grown = wr.alloc_stats();
+        assert!(grown.allocated > grown.freed);
+        wr.commit();
+        // No allocation is lost, so the tree holds its single prior leaf, and the
+        // surplus of this transaction.
+        let count = map.read().node_count() as u64;
+        assert!(1 + grown.allocated - grown.freed == count);
+
+        let mut wr = map.write();
+        wr.clear();
+        let stats = wr.alloc_stats();
+        // Every node of the tree is released, and only the new root is allocated.
+        assert!(stats.freed == count && stats.allocated == 1);
+        wr.commit();
+
+        // Nodes that are copied and then merge away in the same transaction are counted
+        // in neither, so the counts still account for the change in the tree.
+        let merged: BptreeMap<usize, usize> =
+            BptreeMap::from_iter((0..(L_CAPACITY * 8)).map(|k| (k, k)));
+        let count = merged.read().node_count() as u64;
+        let mut wr = merged.write();
+        for k in 0..(L_CAPACITY * 6) {
+            wr.remove(&k);
+        }
+        let stats = wr.alloc_stats();
+        assert!(stats.freed <= count);
+        wr.commit();
+        assert!(count + stats.allocated - stats.freed == merged.read().node_count() as u64);
+        std::mem::drop(merged);
+
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_structure_hook() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut wr = map.write();
+        let sink = events.clone();
+        wr.set_structure_hook(move |e| sink.borrow_mut().push(e));
+
+        // Filling the root leaf changes nothing, and the next key splits it.
+        wr.extend((0..L_CAPACITY).map(|k| (k, k)));
+        assert!(events.borrow().is_empty());
+        wr.insert(L_CAPACITY, L_CAPACITY);
+        assert!(
+            *events.borrow()
+                == vec![
+                    StructureEvent::LeafSplit { pivot: L_CAPACITY },
+                    StructureEvent::RootGrow
+                ]
+        );
+
+        // Ascending keys split each leaf as it fills, at the key that overflowed it.
+        events.borrow_mut().clear();
+        let count = L_CAPACITY * BV_CAPACITY * 2;
+        wr.extend(((L_CAPACITY + 1)..count).map(|k| (k, k)));
+        let pivots: Vec<usize> = events
+            .borrow()
+            .iter()
+            .filter_map(|e| match e {
+                StructureEvent::LeafSplit { pivot } => Some(*pivot),
+                _ => None,
+            })
+            .collect();
+        let expect: Vec<usize> = (2..(count / L_CAPACITY)).map(|i| i * L_CAPACITY).collect();
+        assert!(pivots == expect);
+        assert!(events.borrow().contains(&StructureEvent::BranchSplit));
+        // Including the first split, the root grew once for each added level.
+        let grows = 1 + events
+            .borrow()
+            .iter()
+            .filter(|e| **e == StructureEvent::RootGrow)
+            .count();
+        assert!(grows >= 2);
+        wr.commit();
+
+        // A key below a full leaf splits it to the left, so the pivot is the least key
+        // of the original leaf.
+        let rev: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut rwr = rev.write();
+        let sink = events.clone();
+        rwr.set_structure_hook(move |e| sink.borrow_mut().push(e));
+        events.borrow_mut().clear();
+        rwr.extend((1..=(L_CAPACITY + 1)).rev().map(|k| (k, k)));
+        assert!(
+            *events.borrow()
+                == vec![
+                    StructureEvent::LeafSplit { pivot: 2 },
+                    StructureEvent::RootGrow
+                ]
+        );
+        rwr.commit();
+
+        let mut wr = map.write();
+        let sink = events.clone();
+        wr.set_structure_hook(move |e| sink.borrow_mut().push(e));
+        events.borrow_mut().clear();
+        for k in 0..count {
+            wr.remove(&k);
+        }
+        // Every leaf but the last merges away, and the tree shrinks back to a leaf.
+        let leaf_merges = events
+            .borrow()
+            .iter()
+            .filter(|e| **e == StructureEvent::LeafMerge)
+            .count();
+        assert!(leaf_merges == count / L_CAPACITY - 1);
+        assert!(events.borrow().contains(&StructureEvent::BranchMerge));
+        let shrinks = events
+            .borrow()
+            .iter()
+            .filter(|e| **e == StructureEvent::RootShrink)
+            .count();
+        assert!(shrinks == grows);
+        assert!(events.borrow().last() == Some(&StructureEvent::RootShrink));
+        wr.commit();
+        assert!(map.read().node_count() == 1);
+
+        // split_off_lt removes the keys below the split one at a time, so each leaf it
+        // empties is reported as it merges away.
+        let split: BptreeMap<usize, usize> = BptreeMap::from_iter((0..count).map(|k| (k, k)));
+        let mut swr = split.write();
+        let sink = events.clone();
+        swr.set_structure_hook(move |e| sink.borrow_mut().push(e));
+        events.borrow_mut().clear();
+        swr.split_off_lt(&(count - L_CAPACITY));
+        let leaf_merges = events
+            .borrow()
+            .iter()
+            .filter(|e| **e == StructureEvent::LeafMerge)
+            .count();
+        assert!(leaf_merges == count / L_CAPACITY - 1);
+        assert!(events.borrow().last() == Some(&StructureEvent::RootShrink));
+        assert!(swr.verify());
+        assert!(swr.len() == L_CAPACITY);
+
+        // A merge is made in place, and reports the splits of the keys it adds.
+        events.borrow_mut().clear();
+        let rev_rd = rev.read();
+        swr.extend_from_read(&rev_rd);
+        assert!(!events.borrow().contains(&StructureEvent::LeafMerge));
+        assert!(matches!(
+            events.borrow().first(),
+            Some(StructureEvent::LeafSplit { .. })
+        ));
+        assert!(swr.verify());
+        std::mem::drop(swr);
+        std::mem::drop(rev_rd);
+        std::mem::drop(split);
+
+        std::mem::drop(rev);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_insert_unique_unchecked() {
+        let count = L_CAPACITY * BV_CAPACITY * 3;
+        let expect: BptreeMap<usize, usize> = BptreeMap::new();
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut ewr = expect.write();
+        let mut wr = map.write();
+        wr.reserve(count / 2);
+        for k in 0..count / 2 {
+            ewr.insert(k, k);
+            wr.insert_unique_unchecked(k, k);
+        }
+        ewr.commit();
+        assert!(wr.commit().inserted == (count / 2) as u64);
+
+        // Appending past a reader copies the shared right edge before changing it.
+        let rd = map.read();
+        let mut ewr = expect.write();
+        let mut wr = map.write();
+        for k in count / 2..count {
+            ewr.insert(k, k);
+            wr.insert_unique_unchecked(k, k);
+        }
+        assert!(wr.verify());
+        ewr.commit();
+        wr.commit();
+        assert!(rd.len() == count / 2);
+        assert!(rd.iter().map(|(k, _)| *k).eq(0..count / 2));
+        drop(rd);
+
+        let (erd, rd) = (expect.read(), map.read());
+        assert!(rd.len() == count);
+        assert!(rd.iter().eq(erd.iter()));
+        assert!(rd.get(&(count - 1)) == Some(&(count - 1)));
+        drop(erd);
+        drop(rd);
+        drop(expect);
+        drop(map);
+        assert_released();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_bptree2_map_insert_unique_unchecked_order() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.insert_unique_unchecked(2, 2);
+        wr.insert_unique_unchecked(1, 1);
+    }
+
+    #[test]
+    fn test_bptree2_map_entry() {
+        let map: BptreeMap<usize, String> =
+            BptreeMap::from_iter((0..8).map(|k| (k, k.to_string())));
+        let mut wr = map.write();
+
+        // The key is passed through on vacancy, and f is never run otherwise.
+        let v = wr.entry(10).or_insert_with_key(|k| format!("key {}", k));
+        assert!(v == "key 10");
+        let v = wr
+            .entry(3)
+            .or_insert_with_key(|_| panic!("Called on an occupied entry"));
+        assert!(v == "3");
+        v.push('!');
+        match wr.entry(3) {
+            Entry::Occupied(e) => assert!(e.key() == &3 && e.get() == "3!"),
+            Entry::Vacant(_) => panic!("Expected an occupied entry"),
+        }
+        assert!(wr.entry(11).key() == &11);
+        match wr.entry(11) {
+            Entry::Vacant(e) => e.insert("eleven".to_string()).push('?'),
+            Entry::Occupied(_) => panic!("Expected a vacant entry"),
+        }
+        assert!(wr.entry(11).or_insert_with(String::new) == "eleven?");
+        assert!(wr.verify());
+        let summary = wr.commit();
+        assert!(summary.inserted == 2);
+
+        let rd = map.read();
+        assert!(rd.len() == 10);
+        assert!(rd.get(&3).map(String::as_str) == Some("3!"));
+        assert!(rd.get(&10).map(String::as_str) == Some("key 10"));
+        drop(rd);
+
+        // An entry keeps its key under a limit, evicting another instead.
+        let limited: BptreeMap<usize, usize> = BptreeMapBuilder::new()
+            .max_entries(2, EvictSide::Min)
+            .build();
+        let mut wr = limited.write();
+        wr.entry(5).or_insert(5);
+        wr.entry(6).or_insert(6);
+        wr.entry(1).or_insert(1);
+        assert!(wr.iter().map(|(k, _)| *k).eq([1, 6].iter().cloned()));
+        wr.commit();
+
+        // Vacant entries are filled as their leaves split, and occupied entries read
+        // the value that the search for them found.
+        let split: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = split.write();
+        for k in (0..(L_CAPACITY << 3)).rev() {
+            let v = wr.entry(k).or_insert(k) as *const usize;
+            assert!(std::ptr::eq(v, wr.get(&k).unwrap()));
+        }
+        for k in 0..(L_CAPACITY << 3) {
+            match wr.entry(k) {
+                Entry::Occupied(e) => assert!(*e.get() == k),
+                Entry::Vacant(_) => panic!("Expected an occupied entry"),
+            }
+        }
+        assert!(wr.verify());
+        drop(wr);
+        drop(split);
+
+        drop(limited);
+        drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_max_entries() {
+        let map: BptreeMap<usize, usize> = BptreeMapBuilder::new()
+            .max_entries(L_CAPACITY * 2, EvictSide::Min)
+            .build();
+        let mut wr = map.write();
+        for k in 0..(L_CAPACITY * 8) {
+            wr.insert(k, k);
+            assert!(wr.len() <= L_CAPACITY * 2);
+        }
+        // The greatest keys are kept.
+        let keys: Vec<usize> = wr.keys().cloned().collect();
+        assert!(keys == ((L_CAPACITY * 6)..(L_CAPACITY * 8)).collect::<Vec<_>>());
+        // Updating a present key evicts nothing.
+        assert!(wr.insert(L_CAPACITY * 6, 0) == Some(L_CAPACITY * 6));
+        assert!(wr.len() == L_CAPACITY * 2);
+        // A key below every other is itself the one evicted.
+        wr.insert(0, 0);
+        assert!(!wr.contains_key(&0) && wr.len() == L_CAPACITY * 2);
+        // An inserted key is kept by get_or_insert_with.
+        assert!(*wr.get_or_insert_with(1, || 1) == 1);
+        assert!(wr.len() == L_CAPACITY * 2 && !wr.contains_key(&(L_CAPACITY * 6)));
+        let summary = wr.commit();
+        assert!(summary.inserted == summary.removed + (L_CAPACITY * 2) as u64);
+        assert!(map.read().verify());
+
+        std::mem::drop(map);
+
+        // The other side keeps the least keys, including for bulk inserts.
+        let map: BptreeMap<usize, usize> = BptreeMapBuilder::new()
+            .max_entries(3, EvictSide::Max)
+            .build();
+        let mut wr = map.write();
+        wr.extend((0..100).rev().map(|k| (k, k)));
+        assert!(wr.keys().cloned().collect::<Vec<_>>() == vec![0, 1, 2]);
+        {
+            let mut append = wr.append_cursor();
+            append.push(100, 100);
+        }
+        assert!(wr.len() == 3 && !wr.contains_key(&100));
+        assert!(wr.pop_first() == Some((0, 0)) && wr.pop_last() == Some((2, 2)));
+        assert!(wr.len() == 1);
+        wr.commit();
+
+        // Handles and forks share the limit.
+        let fork = map.read().fork();
+        let mut wr = fork.write();
+        wr.extend((10..20).map(|k| (k, k)));
+        assert!(wr.keys().cloned().collect::<Vec<_>>() == vec![1, 10, 11]);
+        wr.commit();
+
+        std::mem::drop(fork);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_range_bounds() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        use std::ops::RangeBounds;
+
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        // Only even keys, so that bounds fall both on and between keys.
+        wr.extend((0..(L_CAPACITY * 16)).map(|k| (k * 2, k)));
+        wr.commit();
+        let max = L_CAPACITY * 32;
+
+        let rd = map.read();
+        let bounds = [
+            Included(0),
+            Included(7),
+            Included(40),
+            Excluded(0),
+            Excluded(7),
+            Excluded(40),
+            Included(max),
+            Unbounded,
+        ];
+        for lo in bounds.iter() {
+            for hi in bounds.iter() {
+                let expect: Vec<_> = rd.iter().filter(|(k, _)| (*lo, *hi).contains(*k)).collect();
+                let got: Vec<_> = rd.range_bounds(*lo, *hi).collect();
+                assert!(got == expect);
+                // The owned bounds share the walk of the RangeBounds version.
+                assert!(rd.range((*lo, *hi)).eq(got.into_iter()));
+            }
+        }
+        assert!(rd
+            .range(10..=14)
+            .map(|(k, _)| *k)
+            .eq([10, 12, 14].iter().cloned()));
+        assert!(rd.range(..).count() == rd.len());
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
+    }
 
-impl<'a, K: Clone + Ord + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
-    BptreeMapReadSnapshot<'a, K, V>
-{
-    /// Retrieve a value from the tree. If the value exists, a reference is returned
-    /// as `Some(&V)`, otherwise if not present `None` is returned.
-    pub fn get<Q: ?Sized>(&'a self, k: &'a Q) -> Option<&'a V>
-    where
-        K: Borrow<Q>,
-        Q: Ord,
-    {
-        match self.work {
-            SnapshotType::R(work) => work.search(k),
-            SnapshotType::W(work) => work.search(k),
+    #[test]
+    fn test_bptree2_map_merge_iter() {
+        // Each layer holds multiples of its stride, tagged with the layer.
+        let layer = |stride: usize, tag: usize| -> BptreeMap<usize, usize> {
+            (0..(L_CAPACITY * 4)).map(|k| (k * stride, tag)).collect()
+        };
+        let (newest, middle, oldest) = (layer(3, 0), layer(2, 1), layer(1, 2));
+        let (r0, r1, r2) = (newest.read(), middle.read(), oldest.read());
+
+        let merged: Vec<(usize, usize)> = merge_iter(&[&r0, &r1, &r2])
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        let max = L_CAPACITY * 4;
+        let expect: Vec<(usize, usize)> = (0..(L_CAPACITY * 12))
+            .filter_map(|k| {
+                if k % 3 == 0 && k / 3 < max {
+                    Some((k, 0))
+                } else if k % 2 == 0 && k / 2 < max {
+                    Some((k, 1))
+                } else if k < max {
+                    Some((k, 2))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        assert!(merged == expect);
+
+        // Reordering the layers changes which value shadows the others.
+        assert!(merge_iter(&[&r2, &r1, &r0]).all(|(k, v)| *v == 2 || *k >= max));
+        assert!(merge_iter(&[&r1]).eq(r1.iter()));
+        assert!(merge_iter::<usize, usize>(&[]).next().is_none());
+        std::mem::drop((r0, r1, r2));
+        std::mem::drop((newest, middle, oldest));
+        assert_released();
+    }
+
+    // Compare every entry of both snapshots.
+    fn naive_diff<'a>(
+        old: &'a BptreeMapReadTxn<usize, usize>,
+        new: &'a BptreeMapReadTxn<usize, usize>,
+    ) -> Vec<DiffEntry<'a, usize, usize>> {
+        let mut diff = Vec::new();
+        for (k, ov) in old.iter() {
+            match new.get(k) {
+                None => diff.push(DiffEntry::Removed(k, ov)),
+                Some(nv) if nv != ov => diff.push(DiffEntry::Changed(k, ov, nv)),
+                Some(_) => {}
+            }
         }
+        diff.extend(
+            new.iter()
+                .filter(|(k, _)| !old.contains_key(*k))
+                .map(|(k, v)| DiffEntry::Added(k, v)),
+        );
+        diff.sort_by_key(|d| match d {
+            DiffEntry::Added(k, _) | DiffEntry::Removed(k, _) | DiffEntry::Changed(k, _, _) => **k,
+        });
+        diff
     }
 
-    /// Assert if a key exists in the tree.
-    pub fn contains_key<'b, Q: ?Sized>(&'a self, k: &'b Q) -> bool
-    where
-        K: Borrow<Q>,
-        Q: Ord,
-    {
-        match self.work {
-            SnapshotType::R(work) => work.contains_key(k),
-            SnapshotType::W(work) => work.contains_key(k),
+    #[test]
+    fn test_bptree2_map_diff() {
+        let count = L_CAPACITY * 256;
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let empty = map.read();
+        let mut wr = map.write();
+        wr.extend((0..count).map(|k| (k * 2, k)));
+        wr.commit();
+
+        let old = map.read();
+        assert!(empty.diff(&old).count() == count);
+        assert!(old.diff(&old).next().is_none());
+
+        // A single change visits only the leaf that holds it.
+        let mut wr = map.write();
+        wr.insert(count, 0);
+        wr.commit();
+        {
+            let new = map.read();
+            let mut diff = old.diff(&new);
+            assert!(diff.next() == Some(DiffEntry::Changed(&count, &(count / 2), &0)));
+            assert!(diff.next().is_none());
+            let (skipped, visited) = diff.stats();
+            assert!(skipped > 0 && visited == 2);
+        }
+
+        // Scattered inserts, removes and updates match a comparison of every entry.
+        let mut wr = map.write();
+        for k in (0..count).step_by(37) {
+            wr.insert(k * 2 + 1, k);
         }
+        for k in (0..count).step_by(53) {
+            wr.remove(&(k * 2));
+        }
+        for k in (0..count).step_by(101) {
+            if let Some(v) = wr.get_mut(&(k * 2)) {
+                *v += 1;
+            }
+        }
+        wr.extend((count..(count + L_CAPACITY * 4)).map(|k| (k * 2, k)));
+        wr.commit();
+        let new = map.read();
+        let diff: Vec<_> = old.diff(&new).collect();
+        assert!(diff == naive_diff(&old, &new));
+        let mut reverse: Vec<_> = new
+            .diff(&old)
+            .map(|d| match d {
+                DiffEntry::Added(k, v) => DiffEntry::Removed(k, v),
+                DiffEntry::Removed(k, v) => DiffEntry::Added(k, v),
+                DiffEntry::Changed(k, o, n) => DiffEntry::Changed(k, n, o),
+            })
+            .collect();
+        reverse.sort_by_key(|d| match d {
+            DiffEntry::Added(k, _) | DiffEntry::Removed(k, _) | DiffEntry::Changed(k, _, _) => **k,
+        });
+        assert!(reverse == diff);
+        assert!(new.diff(&empty).count() == new.len());
+
+        std::mem::drop((empty, old, new));
+        std::mem::drop(map);
+        assert_released();
     }
 
-    /// Returns the current number of k:v pairs in the tree
-    pub fn len(&self) -> usize {
-        match self.work {
-            SnapshotType::R(work) => work.len(),
-            SnapshotType::W(work) => work.len(),
+    #[test]
+    fn test_bptree2_map_iter_exact_size() {
+        let count = L_CAPACITY * 8 + 3;
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.extend((0..count).map(|k| (k, k)));
+        wr.commit();
+
+        let rd = map.read();
+        let mut iter = rd.iter();
+        for remaining in (0..=count).rev() {
+            assert!(iter.len() == remaining);
+            assert!(iter.size_hint() == (remaining, Some(remaining)));
+            if remaining > 0 {
+                assert!(iter.next().is_some());
+            }
         }
+        assert!(iter.next().is_none() && iter.next().is_none());
+        assert!(rd.keys().len() == count && rd.values().len() == count);
+        assert!(rd.keys().zip(rd.values()).all(|(k, v)| k == v));
+
+        let mut range = rd.range(5..10);
+        assert!(range.by_ref().count() == 5);
+        assert!(range.next().is_none() && range.next().is_none());
+        std::mem::drop(range);
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
     }
 
-    /// Determine if the set is currently empty
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    #[test]
+    fn test_bptree2_map_range_into() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.extend((0..(L_CAPACITY * 64)).map(|k| (k, k)));
+        wr.commit();
+
+        let rd = map.read();
+        let mut scratch = RangeScratch::new();
+        let ranges = [
+            (10, 20),
+            (0, L_CAPACITY * 64),
+            (L_CAPACITY * 3, L_CAPACITY * 9),
+            (5, 5),
+        ];
+        for (lo, hi) in ranges.iter().cloned() {
+            let a: Vec<_> = rd.range(lo..hi).collect();
+            let b: Vec<_> = rd.range_into(lo..hi, &mut scratch).collect();
+            assert!(a == b);
+        }
+        // The buffer is returned empty, and reused by the next query.
+        let (len, cap) = scratch.buffer();
+        assert!(len == 0 && cap > 0);
+        let partial: Vec<_> = rd.range_into(.., &mut scratch).take(3).collect();
+        assert!(partial == [(&0, &0), (&1, &1), (&2, &2)]);
+        assert!(scratch.buffer() == (0, cap));
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
     }
 
-    // (adv) range
+    #[test]
+    fn test_bptree2_map_resume_after() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.extend((0..(L_CAPACITY * 8)).map(|k| (k, k)));
+        wr.commit();
 
-    /// Iterator over `(&K, &V)` of the set
-    pub fn iter(&self) -> Iter<K, V> {
-        match self.work {
-            SnapshotType::R(work) => work.kv_iter(),
-            SnapshotType::W(work) => work.kv_iter(),
+        // Take the first page, then remove its last key before the next page.
+        let last = {
+            let rd = map.read();
+            let page: Vec<usize> = rd.iter().take(10).map(|(k, _)| *k).collect();
+            *page.last().unwrap()
+        };
+        let mut wr = map.write();
+        wr.remove(&last);
+        wr.remove(&(last + 1));
+        wr.commit();
+
+        let rd = map.read();
+        let page: Vec<usize> = rd.resume_after(&last).take(3).map(|(k, _)| *k).collect();
+        assert!(page == [last + 2, last + 3, last + 4]);
+        assert!(rd.resume_after(&(L_CAPACITY * 8)).next().is_none());
+        assert!(rd.resume_after(&0).count() == rd.len() - 1);
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_map_commit_summary() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let mut wr = map.write();
+        wr.extend((0..10).map(|k| (k, k)));
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 10,
+                    updated: 0,
+                    removed: 0,
+                }
+        );
+
+        let mut wr = map.write();
+        // Updates and removes of present keys are distinguished from no-ops.
+        wr.insert(0, 1);
+        wr.insert(10, 10);
+        assert!(wr.remove(&1).is_some());
+        assert!(wr.remove(&100).is_none());
+        wr.get_or_insert_with(2, || 0);
+        wr.get_or_insert_with(11, || 11);
+        wr.extend(vec![(3, 0), (12, 12)]);
+        wr.drain_range(4..6);
+        wr.retain(|k, _| *k != 6);
+        {
+            let mut append = wr.append_cursor();
+            append.push(13, 13);
         }
+        let summary = wr.commit_checked(|_| Ok::<(), ()>(())).unwrap();
+        assert!(
+            summary
+                == CommitSummary {
+                    inserted: 4,
+                    updated: 2,
+                    removed: 4,
+                }
+        );
+
+        // Each operation is counted from its result, however often the key changed. A
+        // value reached through get_mut is an update.
+        let changes = |wr: &mut BptreeMapWriteTxn<usize, usize>| {
+            wr.insert(100, 100);
+            wr.remove(&100);
+            wr.remove(&7);
+            wr.insert(7, 70);
+            for i in 0..4 {
+                wr.insert(8, i);
+                *wr.get_mut(&9).unwrap() += 1;
+            }
+            *wr.entry(10).or_insert(0) += 1;
+            wr.split_off_lt(&2);
+        };
+        let net_map: BptreeMap<usize, usize> = BptreeMapBuilder::new().net_commit_summary().build();
+        let mut wr = net_map.write();
+        wr.extend(map.read().iter().map(|(k, v)| (*k, *v)));
+        wr.commit();
+
+        let mut wr = map.write();
+        changes(&mut wr);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 2,
+                    updated: 9,
+                    removed: 3,
+                }
+        );
+
+        // With net counts, an insert that is removed again cancels, a removed key that
+        // is inserted again is an update, and a key changed many times is counted once.
+        let mut wr = net_map.write();
+        changes(&mut wr);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 0,
+                    updated: 4,
+                    removed: 1,
+                }
+        );
+
+        // Merging the snapshot that the transaction began from changes nothing, as the
+        // values are those of the nodes the two share.
+        let rd = map.read();
+        let mut wr = map.write();
+        wr.extend_from_read(&rd);
+        assert!(wr.commit() == CommitSummary::default());
+        std::mem::drop(rd);
+
+        // A dropped transaction reports nothing, and clear counts every entry.
+        let mut wr = map.write();
+        wr.insert(20, 20);
+        std::mem::drop(wr);
+        let mut wr = map.write();
+        let len = wr.len() as u64;
+        wr.clear();
+        wr.insert(2, 2);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 1,
+                    updated: 0,
+                    removed: len,
+                }
+        );
+        let mut wr = net_map.write();
+        let len = wr.len() as u64;
+        wr.clear();
+        wr.insert(2, 2);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 0,
+                    updated: 1,
+                    removed: len - 1,
+                }
+        );
+        std::mem::drop(map);
+        std::mem::drop(net_map);
+        assert_released();
     }
 
-    /// Iterator over &K
-    pub fn values(&self) -> ValueIter<K, V> {
-        match self.work {
-            SnapshotType::R(work) => work.v_iter(),
-            SnapshotType::W(work) => work.v_iter(),
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_bptree2_map_op_latencies() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        assert!(map.read().op_latencies() == Default::default());
+
+        let mut wr = map.write();
+        for k in 0..100 {
+            wr.insert(k, k);
+        }
+        for k in 0..10 {
+            assert!(wr.get(&k) == Some(&k));
+        }
+        for k in 0..20 {
+            wr.remove(&k);
+        }
+        wr.commit();
+        let rd = map.write().commit_and_read();
+        for k in 0..30 {
+            rd.get(&k);
         }
+
+        let lat = rd.op_latencies();
+        assert!(lat.insert.count == 100);
+        assert!(lat.remove.count == 20);
+        assert!(lat.get.count == 40);
+        assert!(lat.commit.count == 2);
+        assert!(lat.insert.buckets.iter().sum::<u64>() == 100);
+        assert!(lat.insert.quantile(0.5).is_some());
+        // Handles of the same map share the record, but a fork begins its own.
+        assert!(map.clone().read().op_latencies() == lat);
+        assert!(rd.fork().read().op_latencies() == Default::default());
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
     }
 
-    /// Iterator over &V
-    pub fn keys(&self) -> KeyIter<K, V> {
-        match self.work {
-            SnapshotType::R(work) => work.k_iter(),
-            SnapshotType::W(work) => work.k_iter(),
+    #[test]
+    fn test_bptree2_map_version() {
+        let map: BptreeMap<usize, usize> = BptreeMap::new();
+        let other: BptreeMap<usize, usize> = BptreeMap::new();
+        let rd = map.read();
+        let mut prev = rd.version();
+        for i in 0..4 {
+            let mut wr = map.write();
+            wr.insert(i, i);
+            wr.commit();
+            let version = map.read().version();
+            assert!(version > prev);
+            prev = version;
         }
+        // Existing readers, and other maps, are unaffected.
+        assert!(rd.version() < prev);
+        assert!(other.read().version() == rd.version());
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        std::mem::drop(other);
+        assert_released();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::node::{assert_released, L_CAPACITY};
-    use super::BptreeMap;
-    // use rand::prelude::*;
-    use rand::seq::SliceRandom;
-    use std::iter::FromIterator;
+    #[test]
+    fn test_bptree2_map_discard() {
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..L_CAPACITY).map(|v| (v, v)));
+        let version = map.read().version();
+
+        let mut wr = map.write();
+        wr.extend((L_CAPACITY..L_CAPACITY << 2).map(|v| (v, v)));
+        wr.remove(&0);
+        drop(wr);
+        let rd = map.read();
+        assert!(rd.len() == L_CAPACITY && rd.get(&0) == Some(&0));
+        assert!(rd.version() == version);
+        drop(rd);
+
+        let mut wr = map.write();
+        wr.extend((L_CAPACITY..L_CAPACITY << 2).map(|v| (v, v)));
+        wr.remove(&0);
+        wr.discard();
+        let rd = map.read();
+        assert!(rd.len() == L_CAPACITY && rd.get(&0) == Some(&0));
+        assert!(rd.version() == version);
+        assert!(rd.verify());
+        drop(rd);
+        drop(map);
+        assert_released();
+    }
 
     #[test]
-    fn test_bptree2_map_basic_write() {
-        let bptree: BptreeMap<usize, usize> = BptreeMap::new();
-        {
-            let mut bpwrite = bptree.write();
-            // We should be able to insert.
-            bpwrite.insert(0, 0);
-            bpwrite.insert(1, 1);
-            assert!(bpwrite.get(&0) == Some(&0));
-            assert!(bpwrite.get(&1) == Some(&1));
-            bpwrite.insert(2, 2);
-            bpwrite.commit();
-            // println!("commit");
-        }
-        {
-            // Do a clear, but roll it back.
-            let mut bpwrite = bptree.write();
-            bpwrite.clear();
-            // DO NOT commit, this triggers the rollback.
-            // println!("post clear");
+    fn test_bptree2_map_clone_into() {
+        let size = L_CAPACITY << 4;
+        let mut ping: BptreeMap<usize, usize> = BptreeMap::from_iter((0..size).map(|v| (v, v)));
+        let mut pong: BptreeMap<usize, usize> = BptreeMap::from_iter((0..size).map(|v| (v, v + 1)));
+        // Every value differs, so every node of the destination is replaced.
+        let stats = ping.read().clone_into(&mut pong);
+        assert!(pong.read().iter().eq(ping.read().iter()));
+        assert!(stats.allocated as usize == pong.read().node_count());
+        assert!(stats.freed == stats.allocated);
+
+        // Each refresh of a double buffer only copies the path to what changed, and
+        // neither map retains the other's past versions.
+        let before = alloc_count();
+        for i in 0..64 {
+            {
+                let mut wr = ping.write();
+                wr.insert((i * 3) % size, size + i);
+                wr.commit();
+            }
+            let stats = ping.read().clone_into(&mut pong);
+            assert!(stats.allocated as usize == pong.read().height());
+            assert!(pong.read().iter().eq(ping.read().iter()));
+            std::mem::swap(&mut ping, &mut pong);
         }
+        assert!(alloc_count() == before);
+
+        // Removed and added keys are copied too, and readers of the destination keep
+        // their snapshot.
         {
-            let bpwrite = bptree.write();
-            assert!(bpwrite.get(&0) == Some(&0));
-            assert!(bpwrite.get(&1) == Some(&1));
-            // println!("fin write");
+            let mut wr = ping.write();
+            wr.remove(&0);
+            wr.insert(size, size);
+            wr.commit();
         }
-        std::mem::drop(bptree);
+        let other = pong.clone();
+        let rd = other.read();
+        ping.read().clone_into(&mut pong);
+        assert!(pong.read().iter().eq(ping.read().iter()));
+        assert!(pong.read().len() == size);
+        assert!(rd.contains_key(&0) && !rd.contains_key(&size));
+        std::mem::drop(rd);
+        std::mem::drop(other);
+
+        std::mem::drop(ping);
+        std::mem::drop(pong);
         assert_released();
     }
 
     #[test]
-    fn test_bptree2_map_cursed_get_mut() {
-        let bptree: BptreeMap<usize, usize> = BptreeMap::new();
+    fn test_bptree2_map_fork() {
+        let size = L_CAPACITY << 4;
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..size).map(|v| (v, v)));
+        let before = alloc_count();
+        let fork = map.read().fork();
+        // No nodes are allocated by the fork itself.
+        assert!(alloc_count() == before);
+        assert!(fork.read().len() == size);
+
+        // Mutating the fork doesn't alter the origin, and only copies the path
+        // to the altered leaf.
         {
-            let mut w = bptree.write();
-            w.insert(0, 0);
-            w.commit();
+            let mut wr = fork.write();
+            wr.insert(0, 100);
+            wr.remove(&1);
+            wr.insert(size, size);
+            assert!(wr.verify());
+            wr.commit();
         }
-        let r1 = bptree.read();
+        assert!(alloc_count() - before < fork.read().node_count());
+        assert!(map.read().get(&0) == Some(&0));
+        assert!(map.read().get(&1) == Some(&1));
+        assert!(map.read().get(&size).is_none());
+        assert!(fork.read().get(&0) == Some(&100));
+        assert!(fork.read().get(&1).is_none());
+
+        // And the reverse.
         {
-            let mut w = bptree.write();
-            let cursed_zone = w.get_mut(&0).unwrap();
-            *cursed_zone = 1;
-            // Correctly fails to work as it's a second borrow, which isn't
-            // possible once w.remove occurs
-            // w.remove(&0);
-            // *cursed_zone = 2;
-            w.commit();
+            let mut wr = map.write();
+            wr.insert(2, 200);
+            wr.commit();
         }
-        let r2 = bptree.read();
-        assert!(r1.get(&0) == Some(&0));
-        assert!(r2.get(&0) == Some(&1));
+        assert!(fork.read().get(&2) == Some(&2));
 
-        /*
-        // Correctly fails to compile. PHEW!
-        let fail = {
-            let mut w = bptree.write();
-            w.get_mut(&0).unwrap()
-        };
-        */
-        std::mem::drop(r1);
-        std::mem::drop(r2);
-        std::mem::drop(bptree);
+        // A fork of a fork, outliving its parent and the origin.
+        let fork2 = fork.read().fork();
+        {
+            let mut wr = fork2.write();
+            wr.clear();
+            wr.insert(3, 300);
+            wr.commit();
+        }
+        std::mem::drop(map);
+        assert!(fork.read().get(&3) == Some(&3));
+        std::mem::drop(fork);
+        assert!(fork2.read().len() == 1);
+        std::mem::drop(fork2);
         assert_released();
     }
 
@@ -799,6 +3870,49 @@ mod tests {
         bptree_map_basic_concurrency(10_000, 20_000)
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_bptree2_map_iter_stable_under_writes() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let size = L_CAPACITY << 6;
+
+        let map: BptreeMap<usize, usize> = BptreeMap::from_iter((0..size).map(|v| (v, v)));
+        let rd = map.read();
+        let mut seen = Vec::with_capacity(size);
+
+        // Between every step of the iterator, commit a writer that inserts, updates
+        // and removes keys both inside and outside the range of the snapshot.
+        for (k, v) in rd.iter() {
+            assert!(k == v);
+            seen.push(*k);
+            for _ in 0..8 {
+                let mut wr = map.write();
+                for _ in 0..L_CAPACITY {
+                    let k = rng.gen_range(0..(size << 1));
+                    if rng.gen::<bool>() {
+                        wr.insert(k, k + 1);
+                    } else {
+                        wr.remove(&k);
+                    }
+                }
+                wr.commit();
+            }
+            // A fresh iterator over the same snapshot is equally unaffected.
+            if *k % L_CAPACITY == 0 {
+                assert!(rd.iter().map(|(k, _)| *k).eq(0..size));
+            }
+        }
+
+        // No keys were skipped or duplicated.
+        assert!(seen.into_iter().eq(0..size));
+        assert!(rd.len() == size);
+        assert!(rd.verify());
+        std::mem::drop(rd);
+        std::mem::drop(map);
+        assert_released();
+    }
+
     /*
     #[test]
     fn test_bptree2_map_write_compact() {
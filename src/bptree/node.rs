@@ -6,6 +6,7 @@ use std::borrow::Borrow;
 use std::fmt::{self, Debug, Error};
 use std::marker::PhantomData;
 use std::mem::MaybeUninit;
+use std::ops::Bound;
 use std::ptr;
 use std::slice;
 
@@ -77,6 +78,18 @@ pub(crate) fn assert_released() {
     }
 }
 
+#[cfg(test)]
+pub(crate) fn alloc_count() -> usize {
+    #[cfg(not(miri))]
+    {
+        ALLOC_LIST.with(|llist| llist.lock().unwrap().len())
+    }
+    #[cfg(miri)]
+    {
+        0
+    }
+}
+
 #[repr(C)]
 pub(crate) struct Meta(u64);
 
@@ -251,7 +264,6 @@ impl<K: Clone + Ord + Debug, V: Clone> Node<K, V> {
     }
 
     #[inline(always)]
-    #[cfg(test)]
     pub(crate) fn get_txid(&self) -> u64 {
         self.meta.get_txid()
     }
@@ -306,6 +318,53 @@ impl<K: Clone + Ord + Debug, V: Clone> Node<K, V> {
         }
     }
 
+    pub(crate) fn node_count(&self) -> usize {
+        match self.meta.0 & FLAG_MASK {
+            FLAG_LEAF => 1,
+            FLAG_BRANCH => {
+                let bref = unsafe { &*(self as *const _ as *const Branch<K, V>) };
+                let mut ncount = 1; // this branch
+                for idx in 0..(bref.count() + 1) {
+                    let n = bref.nodes[idx];
+                    ncount += unsafe { (*n).node_count() };
+                }
+                ncount
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        // The tree is balanced, so following the left most path is sufficient.
+        let mut height = 1;
+        let mut node = self as *const Self;
+        while unsafe { (*node).is_branch() } {
+            let bref = unsafe { &*(node as *const Branch<K, V>) };
+            node = bref.nodes[0];
+            height += 1;
+        }
+        height
+    }
+
+    #[cfg(test)]
+    pub(crate) fn depth_bounds(&self) -> (usize, usize) {
+        // Walk every path, returning the shortest and longest depth to a leaf.
+        match self.meta.0 & FLAG_MASK {
+            FLAG_LEAF => (1, 1),
+            FLAG_BRANCH => {
+                let bref = unsafe { &*(self as *const _ as *const Branch<K, V>) };
+                let mut bounds = (usize::MAX, 0);
+                for idx in 0..(bref.count() + 1) {
+                    let n = bref.nodes[idx];
+                    let (min, max) = unsafe { (*n).depth_bounds() };
+                    bounds = (bounds.0.min(min + 1), bounds.1.max(max + 1));
+                }
+                bounds
+            }
+            _ => unreachable!(),
+        }
+    }
+
     #[cfg(test)]
     #[inline(always)]
     pub(crate) fn get_ref<Q: ?Sized>(&self, k: &Q) -> Option<&V>
@@ -374,6 +433,18 @@ impl<K: Clone + Ord + Debug, V: Clone> Node<K, V> {
         }
     }
 
+    #[cfg(test)]
+    pub(crate) fn verify_pivots(&self) -> bool {
+        match self.meta.0 & FLAG_MASK {
+            FLAG_LEAF => true,
+            FLAG_BRANCH => {
+                let bref = unsafe { &*(self as *const _ as *const Branch<K, V>) };
+                bref.verify_pivots()
+            }
+            _ => unreachable!(),
+        }
+    }
+
     #[cfg(test)]
     fn no_cycles_inner(&self, track: &mut BTreeSet<*const Self>) -> bool {
         match self.meta.0 & FLAG_MASK {
@@ -427,6 +498,21 @@ impl<K: Clone + Ord + Debug, V: Clone> Node<K, V> {
         }
     }
 
+    // As sblock_collect, but only for nodes newer than txid. As a node can only
+    // refer to nodes at or older than itself, older subtrees are skipped entirely.
+    pub(crate) fn sblock_collect_after(&mut self, txid: u64, alloc: &mut Vec<*mut Node<K, V>>) {
+        if (self.meta.0 & FLAG_MASK) == FLAG_BRANCH {
+            let bref = unsafe { &*(self as *const _ as *const Branch<K, V>) };
+            for idx in 0..(bref.count() + 1) {
+                let n = bref.nodes[idx];
+                if unsafe { (*n).get_txid() } > txid {
+                    alloc.push(n);
+                    unsafe { (*n).sblock_collect_after(txid, alloc) };
+                }
+            }
+        }
+    }
+
     pub(crate) fn free(node: *mut Node<K, V>) {
         let self_meta = self_meta!(node);
         match self_meta.0 & FLAG_MASK {
@@ -530,6 +616,26 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
             .map(|idx| unsafe { &*self.values[idx].as_ptr() })
     }
 
+    pub(crate) fn contains_key<Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        debug_assert_leaf!(self);
+        key_search!(self, k).is_ok()
+    }
+
+    pub(crate) fn get_kv_ref<Q: ?Sized>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        debug_assert_leaf!(self);
+        key_search!(self, k)
+            .ok()
+            .map(|idx| unsafe { (&*self.key[idx].as_ptr(), &*self.values[idx].as_ptr()) })
+    }
+
     pub(crate) fn get_mut_ref<Q: ?Sized>(&mut self, k: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
@@ -553,6 +659,29 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
         }
     }
 
+    // The least entry of this leaf that is not below the bound.
+    pub(crate) fn lower_bound_kv(&self, lower: Bound<&K>) -> Option<(&K, &V)> {
+        debug_assert_leaf!(self);
+        let idx = match lower {
+            Bound::Unbounded => 0,
+            Bound::Included(k) => key_search!(self, k).unwrap_or_else(|idx| idx),
+            Bound::Excluded(k) => key_search!(self, k).map_or_else(|idx| idx, |idx| idx + 1),
+        };
+        self.get_kv_idx_checked(idx)
+    }
+
+    // The greatest entry of this leaf that is not above the bound.
+    pub(crate) fn upper_bound_kv(&self, upper: Bound<&K>) -> Option<(&K, &V)> {
+        debug_assert_leaf!(self);
+        let idx = match upper {
+            Bound::Unbounded => self.count(),
+            Bound::Included(k) => key_search!(self, k).map_or_else(|idx| idx, |idx| idx + 1),
+            Bound::Excluded(k) => key_search!(self, k).unwrap_or_else(|idx| idx),
+        };
+        idx.checked_sub(1)
+            .and_then(|idx| self.get_kv_idx_checked(idx))
+    }
+
     pub(crate) fn min(&self) -> &K {
         debug_assert!(self.count() > 0);
         unsafe { &*self.key[0].as_ptr() }
@@ -583,7 +712,8 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
                 nid: alloc_nid(),
             }));
 
-            // Copy in the values to the correct location.
+            // Copy in the values to the correct location. For CheapClone keys this
+            // is a reference count increment, and the copy shares the key.
             for idx in 0..self.count() {
                 unsafe {
                     let lkey = (*self.key[idx].as_ptr()).clone();
@@ -598,6 +728,19 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
     }
 
     pub(crate) fn insert_or_update(&mut self, k: K, v: V) -> LeafInsertState<K, V> {
+        let mut slot = ptr::null_mut();
+        self.insert_or_update_slot(k, v, &mut slot)
+    }
+
+    // As insert_or_update, where slot is set to the location of the value in this or
+    // the new leaf. Leaves never move their values once the insert is complete, so this
+    // remains valid until the leaf is next changed.
+    pub(crate) fn insert_or_update_slot(
+        &mut self,
+        k: K,
+        v: V,
+        slot: &mut *mut V,
+    ) -> LeafInsertState<K, V> {
         debug_assert_leaf!(self);
         // Find the location we need to update
         let r = key_search!(self, &k);
@@ -605,6 +748,7 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
             Ok(idx) => {
                 // It exists at idx, replace
                 let prev = unsafe { self.values[idx].as_mut_ptr().replace(v) };
+                *slot = self.values[idx].as_mut_ptr();
                 // Prev now contains the original value, return it!
                 LeafInsertState::Ok(Some(prev))
             }
@@ -614,11 +758,13 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
                     if idx >= self.count() {
                         // Greate than all else, split right
                         let rnode = Node::new_leaf_ins(self.meta.0, k, v);
+                        *slot = unsafe { (*rnode).values[0].as_mut_ptr() };
                         LeafInsertState::Split(rnode)
                     } else if idx == 0 {
                         // Lower than all else, split left.
                         // let lnode = ...;
                         let lnode = Node::new_leaf_ins(self.meta.0, k, v);
+                        *slot = unsafe { (*lnode).values[0].as_mut_ptr() };
                         LeafInsertState::RevSplit(lnode)
                     } else {
                         // Within our range, pop max, insert, and split
@@ -631,6 +777,7 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
                             slice_insert(&mut self.key, MaybeUninit::new(k), idx);
                             slice_insert(&mut self.values, MaybeUninit::new(v), idx);
                         }
+                        *slot = self.values[idx].as_mut_ptr();
 
                         let rnode = Node::new_leaf_ins(self.meta.0, pk, pv);
                         LeafInsertState::Split(rnode)
@@ -641,6 +788,7 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
                         slice_insert(&mut self.key, MaybeUninit::new(k), idx);
                         slice_insert(&mut self.values, MaybeUninit::new(v), idx);
                     }
+                    *slot = self.values[idx].as_mut_ptr();
                     self.inc_count();
                     LeafInsertState::Ok(None)
                 }
@@ -759,6 +907,38 @@ impl<K: Ord + Clone + Debug, V: Clone> Leaf<K, V> {
         right.meta.set_count(0);
     }
 
+    // Move every entry out of this leaf in order, and free it. The leaf must not be
+    // reachable from any other tree.
+    pub(crate) fn take_all(node: *mut Self, out: &mut Vec<(K, V)>) {
+        let leaf = unsafe { &mut *node };
+        debug_assert_leaf!(leaf);
+        out.reserve(leaf.count());
+        for idx in 0..leaf.count() {
+            out.push(unsafe {
+                (
+                    ptr::read(leaf.key[idx].as_ptr()),
+                    ptr::read(leaf.values[idx].as_ptr()),
+                )
+            });
+        }
+        // The entries were moved out, so they must not be dropped with the leaf.
+        leaf.meta.set_count(0);
+        Self::free(node)
+    }
+
+    pub(crate) fn clone_all(&self, out: &mut Vec<(K, V)>) {
+        debug_assert_leaf!(self);
+        out.reserve(self.count());
+        for idx in 0..self.count() {
+            out.push(unsafe {
+                (
+                    (*self.key[idx].as_ptr()).clone(),
+                    (*self.values[idx].as_ptr()).clone(),
+                )
+            });
+        }
+    }
+
     pub(crate) fn verify(&self) -> bool {
         debug_assert_leaf!(self);
         // println!("verify leaf -> {:?}", self);
@@ -906,12 +1086,18 @@ impl<K: Ord + Clone + Debug, V: Clone> Branch<K, V> {
         Q: Ord,
     {
         debug_assert_branch!(self);
-        match key_search!(self, k) {
+        match branch_key_search!(self, k) {
             Err(idx) => idx,
             Ok(idx) => idx + 1,
         }
     }
 
+    pub(crate) fn pivots(&self) -> &[K] {
+        debug_assert_branch!(self);
+        let (left, _) = self.key.split_at(self.count());
+        unsafe { slice::from_raw_parts(left.as_ptr() as *const K, left.len()) }
+    }
+
     #[inline(always)]
     pub(crate) fn get_idx_unchecked(&self, idx: usize) -> *mut Node<K, V> {
         debug_assert_branch!(self);
@@ -962,7 +1148,7 @@ impl<K: Ord + Clone + Debug, V: Clone> Branch<K, V> {
             // 3 * The inserted node is a low/middle value, causing max and max -1 to be returned.
             //
             let kr = unsafe { (*node).min() };
-            let r = key_search!(self, kr);
+            let r = branch_key_search!(self, kr);
             let ins_idx = r.unwrap_err();
             // Everything will pop max.
             let max = unsafe { *(self.nodes.get_unchecked(BV_CAPACITY - 1)) };
@@ -1016,7 +1202,7 @@ impl<K: Ord + Clone + Debug, V: Clone> Branch<K, V> {
             // Get the nodes min-key - we clone it because we'll certainly be inserting it!
             let k: K = unsafe { (*node).min().clone() };
             // bst and find when min-key < key[idx]
-            let r = key_search!(self, &k);
+            let r = branch_key_search!(self, &k);
             // if r is ever found, I think this is a bug, because we should never be able to
             // add a node with an existing min.
             //
@@ -1623,7 +1809,7 @@ impl<K: Ord + Clone + Debug, V: Clone> Branch<K, V> {
         // [  0,  4,  8,  12  ]
         // [n1, n2, n3, n4, n5]
         //
-        let r = key_search!(self, k);
+        let r = branch_key_search!(self, k);
 
         let sc = self.count();
 
@@ -1760,17 +1946,12 @@ impl<K: Ord + Clone + Debug, V: Clone> Branch<K, V> {
             lk = rk;
         }
         // Recursively call verify
-        for work_idx in 0..self.count() {
-            let node = unsafe { &*self.nodes[work_idx] };
-            if !node.verify() {
-                for work_idx in 0..(self.count() + 1) {
-                    let nref = unsafe { &*self.nodes[work_idx] };
-                    if !nref.verify() {
-                        // println!("Failed children");
-                        debug_assert!(false);
-                        return false;
-                    }
-                }
+        for work_idx in 0..(self.count() + 1) {
+            let nref = unsafe { &*self.nodes[work_idx] };
+            if !nref.verify() {
+                // println!("Failed children");
+                debug_assert!(false);
+                return false;
             }
         }
         // Check descendants are validly ordered.
@@ -1798,6 +1979,40 @@ impl<K: Ord + Clone + Debug, V: Clone> Branch<K, V> {
         true
     }
 
+    #[cfg(test)]
+    pub(crate) fn verify_pivots(&self) -> bool {
+        debug_assert_branch!(self);
+        // Pivots must be strictly ascending.
+        for work_idx in 1..self.count() {
+            let lk: &K = unsafe { &*self.key[work_idx - 1].as_ptr() };
+            let rk: &K = unsafe { &*self.key[work_idx].as_ptr() };
+            if lk >= rk {
+                return false;
+            }
+        }
+        // Every child must sit within the bounds of the pivots either side of it,
+        // such that child n holds keys where key[n - 1] <= k < key[n].
+        for work_idx in 0..(self.count() + 1) {
+            let nref = unsafe { &*self.nodes[work_idx] };
+            if work_idx > 0 {
+                let lower: &K = unsafe { &*self.key[work_idx - 1].as_ptr() };
+                if nref.min() < lower {
+                    return false;
+                }
+            }
+            if work_idx < self.count() {
+                let upper: &K = unsafe { &*self.key[work_idx].as_ptr() };
+                if nref.max() >= upper {
+                    return false;
+                }
+            }
+            if !nref.verify_pivots() {
+                return false;
+            }
+        }
+        true
+    }
+
     fn free(node: *mut Self) {
         unsafe {
             let mut _x: Box<CachePadded<Branch<K, V>>> =
@@ -2272,6 +2487,34 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_bptree2_node_branch_locate() {
+        test_3_leaf!(|a, b, c| {
+            let branch: *mut Branch<usize, usize> = Node::new_branch(
+                1,
+                a as *mut Node<usize, usize>,
+                b as *mut Node<usize, usize>,
+            );
+            let branch_ref = unsafe { &mut *branch };
+            let r = branch_ref.add_node(c as *mut Node<usize, usize>);
+            match r {
+                BranchInsertState::Ok => {}
+                _ => debug_assert!(false),
+            };
+            assert!(branch_ref.verify());
+            assert!(branch_ref.verify_pivots());
+            // Pivots are [20, 30], so keys below 20 go left, keys equal to a
+            // pivot go to the right of it.
+            assert!(branch_ref.locate_node(&0) == 0);
+            assert!(branch_ref.locate_node(&19) == 0);
+            assert!(branch_ref.locate_node(&20) == 1);
+            assert!(branch_ref.locate_node(&29) == 1);
+            assert!(branch_ref.locate_node(&30) == 2);
+            assert!(branch_ref.locate_node(&usize::MAX) == 2);
+            Branch::free(branch as *mut _);
+        })
+    }
+
     #[test]
     fn test_bptree2_node_branch_add_mid() {
         test_3_leaf!(|a, b, c| {
@@ -0,0 +1,121 @@
+//! Recording of the latency of map operations, enabled by the `profiling` feature.
+//! See `BptreeMapReadTxn::op_latencies`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The number of buckets of each histogram. Bucket `n` counts the operations that
+/// took less than `2^n` nanoseconds, and at least `2^(n - 1)`. The last bucket also
+/// counts any operation that took longer.
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// A histogram of the latency of one kind of operation. See `LatencySummary`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpLatency {
+    /// The number of operations recorded.
+    pub count: u64,
+    /// The number of operations recorded in each bucket, as described by
+    /// `LATENCY_BUCKETS`.
+    pub buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl OpLatency {
+    /// The upper bound of the bucket that holds the `q` quantile of recorded latencies,
+    /// where `q` is between 0 and 1. Returns `None` if nothing has been recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let idx = self
+            .buckets
+            .iter()
+            .position(|c| {
+                seen += c;
+                seen >= rank
+            })
+            .unwrap_or(LATENCY_BUCKETS - 1);
+        Some(Duration::from_nanos(1 << idx))
+    }
+}
+
+/// The latencies of the operations of a `BptreeMap`, recorded since it was created.
+/// This is shared by every handle of a map, and a fork begins with its own.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// Inserts through a write transaction.
+    pub insert: OpLatency,
+    /// Removes through a write transaction.
+    pub remove: OpLatency,
+    /// Gets through either a read or a write transaction.
+    pub get: OpLatency,
+    /// Commits of a write transaction.
+    pub commit: OpLatency,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl Histogram {
+    pub(crate) fn record(&self, d: Duration) {
+        let nanos = d.as_nanos().min(u64::MAX as u128) as u64;
+        // The number of bits needed to hold the value selects the bucket.
+        let idx = (64 - nanos.leading_zeros() as usize).min(LATENCY_BUCKETS - 1);
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> OpLatency {
+        let mut buckets = [0; LATENCY_BUCKETS];
+        for (b, c) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *b = c.load(Ordering::Relaxed);
+        }
+        OpLatency {
+            count: buckets.iter().sum(),
+            buckets,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Latencies {
+    pub(crate) insert: Histogram,
+    pub(crate) remove: Histogram,
+    pub(crate) get: Histogram,
+    pub(crate) commit: Histogram,
+}
+
+impl Latencies {
+    pub(crate) fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            insert: self.insert.summary(),
+            remove: self.remove.summary(),
+            get: self.get.summary(),
+            commit: self.commit.summary(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Histogram, LATENCY_BUCKETS};
+    use std::time::Duration;
+
+    #[test]
+    fn test_bptree2_profile_histogram() {
+        let h = Histogram::default();
+        assert!(h.summary().quantile(0.5).is_none());
+        h.record(Duration::from_nanos(0));
+        h.record(Duration::from_nanos(3));
+        h.record(Duration::from_nanos(1000));
+        h.record(Duration::from_secs(100));
+        let s = h.summary();
+        assert!(s.count == 4);
+        assert!(s.buckets[0] == 1 && s.buckets[2] == 1 && s.buckets[10] == 1);
+        assert!(s.buckets[LATENCY_BUCKETS - 1] == 1);
+        assert!(s.quantile(0.5) == Some(Duration::from_nanos(4)));
+        assert!(s.quantile(0.75) == Some(Duration::from_nanos(1024)));
+    }
+}
@@ -0,0 +1,281 @@
+//! Low level access to the leaves and branches that a `BptreeMap` is built from.
+//!
+//! This allows the leaf split and merge decisions of the tree to be reused to build
+//! custom ordered structures. These nodes are not transactional, and are not shared
+//! with any `BptreeMap`. This API is unstable, and may change in any release.
+//!
+//! # Examples
+//! ```
+//! use concread::bptree::raw::{RawBranch, RawInsert, RawLeaf};
+//!
+//! // Fill a leaf until it overflows into a new right leaf.
+//! let mut left = RawLeaf::new();
+//! let mut k = 0;
+//! let right = loop {
+//!     match left.insert(k, k * 10) {
+//!         RawInsert::Ok(_) => k += 1,
+//!         RawInsert::Split(right) => break right,
+//!         RawInsert::RevSplit(_) => unreachable!(),
+//!     }
+//! };
+//! assert_eq!(right.len(), 1);
+//!
+//! // Join the two leaves under a branch, and query through it.
+//! let branch = RawBranch::new(left, right);
+//! assert_eq!(branch.get(&0), Some(&0));
+//! assert_eq!(branch.get(&k), Some(&(k * 10)));
+//! assert_eq!(branch.get(&(k + 1)), None);
+//! ```
+
+use super::node::{Branch, Leaf, Node};
+use super::states::{LeafInsertState, LeafRemoveState};
+use std::borrow::Borrow;
+use std::fmt::Debug;
+use std::mem;
+
+/// A single leaf node, holding up to its capacity of sorted key-value pairs.
+pub struct RawLeaf<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    node: *mut Leaf<K, V>,
+}
+
+/// The result of inserting into a `RawLeaf`.
+pub enum RawInsert<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    /// The pair was inserted, and the previous value of the key if any is returned.
+    Ok(Option<V>),
+    /// The leaf was full. The returned leaf must be placed to the right of this leaf, and
+    /// holds either the inserted pair, or the previous maximum of this leaf.
+    Split(RawLeaf<K, V>),
+    /// The leaf was full, and the key was less than all others. The returned leaf holds
+    /// only the inserted pair, and must be placed to the left of this leaf.
+    RevSplit(RawLeaf<K, V>),
+}
+
+/// The result of removing from a `RawLeaf`.
+#[derive(Debug)]
+pub enum RawRemove<V> {
+    /// The leaf still holds other keys. The removed value if any is returned.
+    Ok(Option<V>),
+    /// The leaf is now empty, and should be removed from its parent. The removed value
+    /// if any is returned.
+    Shrink(Option<V>),
+}
+
+impl<K, V> RawLeaf<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    /// Create a new, empty leaf.
+    pub fn new() -> Self {
+        RawLeaf {
+            node: Node::new_leaf(0),
+        }
+    }
+
+    fn inner(&self) -> &Leaf<K, V> {
+        unsafe { &*self.node }
+    }
+
+    /// Insert a key-value pair. If the leaf is full, it splits as it would within a
+    /// `BptreeMap`, and the new sibling is returned.
+    pub fn insert(&mut self, k: K, v: V) -> RawInsert<K, V> {
+        match unsafe { (*self.node).insert_or_update(k, v) } {
+            LeafInsertState::Ok(prev) => RawInsert::Ok(prev),
+            LeafInsertState::Split(node) => RawInsert::Split(RawLeaf { node }),
+            LeafInsertState::RevSplit(node) => RawInsert::RevSplit(RawLeaf { node }),
+        }
+    }
+
+    /// Remove a key, returning its value if it was present.
+    pub fn remove<Q>(&mut self, k: &Q) -> RawRemove<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match unsafe { (*self.node).remove(k) } {
+            LeafRemoveState::Ok(v) => RawRemove::Ok(v),
+            LeafRemoveState::Shrink(v) => RawRemove::Shrink(v),
+        }
+    }
+
+    /// Retrieve the value of a key.
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.inner().get_ref(k)
+    }
+
+    /// The number of pairs in this leaf.
+    pub fn len(&self) -> usize {
+        self.inner().count()
+    }
+
+    /// Determine if this leaf is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The smallest key in this leaf, if any.
+    pub fn min(&self) -> Option<&K> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.inner().min())
+        }
+    }
+
+    /// The largest key in this leaf, if any.
+    pub fn max(&self) -> Option<&K> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.inner().max())
+        }
+    }
+
+    fn into_node(self) -> *mut Node<K, V> {
+        let node = self.node as *mut Node<K, V>;
+        mem::forget(self);
+        node
+    }
+}
+
+impl<K, V> Default for RawLeaf<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Drop for RawLeaf<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    fn drop(&mut self) {
+        Node::free(self.node as *mut Node<K, V>)
+    }
+}
+
+/// A branch that owns two leaves, directing each key to the leaf that may hold it.
+pub struct RawBranch<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    node: *mut Branch<K, V>,
+}
+
+impl<K, V> RawBranch<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    /// Create a branch over two leaves. Both leaves must hold at least one key, and
+    /// every key of `left` must be less than every key of `right`, else this panics.
+    pub fn new(left: RawLeaf<K, V>, right: RawLeaf<K, V>) -> Self {
+        match (left.max(), right.min()) {
+            (Some(l), Some(r)) => assert!(l < r, "Leaves are not ordered"),
+            _ => panic!("Leaves must not be empty"),
+        }
+        RawBranch {
+            node: Node::new_branch(0, left.into_node(), right.into_node()),
+        }
+    }
+
+    /// Retrieve the value of a key from whichever leaf holds it.
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // Only leaves are held by a raw branch.
+        let branch = unsafe { &*self.node };
+        let node = branch.get_idx_unchecked(branch.locate_node(k)) as *mut Leaf<K, V>;
+        unsafe { (*node).get_ref(k) }
+    }
+}
+
+impl<K, V> Drop for RawBranch<K, V>
+where
+    K: Ord + Clone + Debug,
+    V: Clone,
+{
+    fn drop(&mut self) {
+        let branch = unsafe { &*self.node };
+        for idx in 0..(branch.count() + 1) {
+            Node::free(branch.get_idx_unchecked(idx));
+        }
+        Node::free(self.node as *mut Node<K, V>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::node::{assert_released, L_CAPACITY};
+    use super::{RawBranch, RawInsert, RawLeaf, RawRemove};
+
+    #[test]
+    fn test_bptree2_raw_assemble() {
+        let mut left: RawLeaf<usize, usize> = RawLeaf::new();
+        assert!(left.min().is_none());
+        for k in 1..=L_CAPACITY {
+            assert!(matches!(left.insert(k, k), RawInsert::Ok(None)));
+        }
+        assert!(matches!(left.insert(1, 10), RawInsert::Ok(Some(1))));
+        assert!(left.len() == L_CAPACITY);
+
+        // Each direction of split hands back the new sibling.
+        match left.insert(0, 0) {
+            RawInsert::RevSplit(rev) => {
+                assert!(rev.len() == 1 && rev.get(&0) == Some(&0));
+            }
+            _ => panic!("Expected a reverse split"),
+        }
+        let right = match left.insert(L_CAPACITY + 1, L_CAPACITY + 1) {
+            RawInsert::Split(right) => right,
+            _ => panic!("Expected a split"),
+        };
+        assert!(right.min() == Some(&(L_CAPACITY + 1)));
+
+        // Removing the last key of a leaf signals it should shrink.
+        let mut single: RawLeaf<usize, usize> = RawLeaf::new();
+        single.insert(1, 1);
+        assert!(matches!(single.remove(&2), RawRemove::Ok(None)));
+        assert!(matches!(single.remove(&1), RawRemove::Shrink(Some(1))));
+        drop(single);
+
+        let branch = RawBranch::new(left, right);
+        assert!(branch.get(&1) == Some(&10));
+        for k in 2..=(L_CAPACITY + 1) {
+            assert!(branch.get(&k) == Some(&k));
+        }
+        assert!(branch.get(&0).is_none());
+        assert!(branch.get(&(L_CAPACITY + 2)).is_none());
+        drop(branch);
+        assert_released();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_bptree2_raw_branch_unordered() {
+        let mut left: RawLeaf<usize, usize> = RawLeaf::new();
+        let mut right: RawLeaf<usize, usize> = RawLeaf::new();
+        left.insert(2, 2);
+        right.insert(1, 1);
+        let _branch = RawBranch::new(left, right);
+    }
+}
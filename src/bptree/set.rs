@@ -0,0 +1,460 @@
+//! A concurrently readable ordered set, built on `BptreeMap`.
+//!
+//! This is a `BptreeMap` with a unit value, so it shares the same transactional
+//! behaviour. Readers see a consistent snapshot of the set as at the time the read
+//! began, and a single writer is applied atomically on commit.
+//!
+//! # Examples
+//! ```
+//! use concread::bptree::set::BptreeSet;
+//!
+//! let a: BptreeSet<usize> = (0..6).collect();
+//! let b: BptreeSet<usize> = (3..9).collect();
+//! let (ra, rb) = (a.read(), b.read());
+//! assert!(ra.intersection(&rb).cloned().collect::<Vec<_>>() == vec![3, 4, 5]);
+//! assert!(ra.difference(&rb).cloned().collect::<Vec<_>>() == vec![0, 1, 2]);
+//! assert!(ra.union(&rb).count() == 9);
+//! ```
+
+use super::iter::{KeyIter, RangeIter};
+use super::{BptreeMap, BptreeMapReadTxn, BptreeMapWriteTxn};
+use crate::CommitSummary;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::iter::{FromIterator, FusedIterator, Peekable};
+use std::ops::RangeBounds;
+
+/// A concurrently readable ordered set. See the module documentation.
+pub struct BptreeSet<T>
+where
+    T: Ord + Clone + Debug + Sync + Send + 'static,
+{
+    map: BptreeMap<T, ()>,
+}
+
+/// A point-in-time snapshot of the set. The content is not changed by concurrent
+/// writers.
+pub struct BptreeSetReadTxn<'a, T>
+where
+    T: Ord + Clone + Debug + Sync + Send + 'static,
+{
+    inner: BptreeMapReadTxn<'a, T, ()>,
+}
+
+/// An exclusive write transaction of the set. Changes are only visible to new readers
+/// once this is committed, and are discarded if it is dropped without a commit.
+pub struct BptreeSetWriteTxn<'a, T>
+where
+    T: Ord + Clone + Debug + Sync + Send + 'static,
+{
+    inner: BptreeMapWriteTxn<'a, T, ()>,
+}
+
+/// Iterator over the members of a set, in ascending order.
+pub struct Iter<'a, T>
+where
+    T: Ord + Clone + Debug,
+{
+    iter: KeyIter<'a, T, ()>,
+}
+
+/// Iterator over the members of a set within a range, in ascending order. See
+/// `BptreeSetReadTxn::range`.
+pub struct Range<'a, T>
+where
+    T: Ord + Clone + Debug,
+{
+    iter: RangeIter<'a, T, ()>,
+}
+
+/// Iterator over the members that are in either of two sets, in ascending order. See
+/// `BptreeSetReadTxn::union`.
+pub struct Union<'a, T>
+where
+    T: Ord + Clone + Debug,
+{
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Iter<'a, T>>,
+}
+
+/// Iterator over the members that are in both of two sets, in ascending order. See
+/// `BptreeSetReadTxn::intersection`.
+pub struct Intersection<'a, T>
+where
+    T: Ord + Clone + Debug,
+{
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Iter<'a, T>>,
+}
+
+/// Iterator over the members of one set that are not in another, in ascending order.
+/// See `BptreeSetReadTxn::difference`.
+pub struct Difference<'a, T>
+where
+    T: Ord + Clone + Debug,
+{
+    a: Peekable<Iter<'a, T>>,
+    b: Peekable<Iter<'a, T>>,
+}
+
+impl<T: Ord + Clone + Debug + Sync + Send + 'static> BptreeSet<T> {
+    /// Construct a new, empty set.
+    pub fn new() -> Self {
+        BptreeSet {
+            map: BptreeMap::new(),
+        }
+    }
+
+    /// Initiate a read transaction for the set, concurrent to any other readers or
+    /// writers.
+    pub fn read(&self) -> BptreeSetReadTxn<T> {
+        BptreeSetReadTxn {
+            inner: self.map.read(),
+        }
+    }
+
+    /// Initiate a write transaction for the set, exclusive to this writer, and
+    /// concurrently to all existing reads.
+    pub fn write(&self) -> BptreeSetWriteTxn<T> {
+        BptreeSetWriteTxn {
+            inner: self.map.write(),
+        }
+    }
+
+    /// Attempt to create a new write, returns None if another writer already exists.
+    pub fn try_write(&self) -> Option<BptreeSetWriteTxn<T>> {
+        self.map
+            .try_write()
+            .map(|inner| BptreeSetWriteTxn { inner })
+    }
+}
+
+impl<T: Ord + Clone + Debug + Sync + Send + 'static> Default for BptreeSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord + Clone + Debug + Sync + Send + 'static> Clone for BptreeSet<T> {
+    /// Another handle to the same set, as with `BptreeMap::clone`.
+    fn clone(&self) -> Self {
+        BptreeSet {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<T: Ord + Clone + Debug + Sync + Send + 'static> FromIterator<T> for BptreeSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        BptreeSet {
+            map: iter.into_iter().map(|t| (t, ())).collect(),
+        }
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug + Sync + Send + 'static> BptreeSetReadTxn<'a, T> {
+    /// Determine if `t` is a member of the set.
+    pub fn contains<Q>(&self, t: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.inner.contains_key(t)
+    }
+
+    /// The number of members of the set.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Determine if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Iterator over the members of the set, in ascending order.
+    pub fn iter(&self) -> Iter<T> {
+        Iter {
+            iter: self.inner.keys(),
+        }
+    }
+
+    /// Iterator over the members of the set within `range`, in ascending order.
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> Range<T> {
+        Range {
+            iter: self.inner.range(range),
+        }
+    }
+
+    /// Iterator over the members that are in this set, `other`, or both, in
+    /// ascending order. Each member is yielded once.
+    pub fn union<'b>(&'b self, other: &'b BptreeSetReadTxn<T>) -> Union<'b, T> {
+        Union {
+            a: self.iter().peekable(),
+            b: other.iter().peekable(),
+        }
+    }
+
+    /// Iterator over the members that are in both this set and `other`, in ascending
+    /// order.
+    pub fn intersection<'b>(&'b self, other: &'b BptreeSetReadTxn<T>) -> Intersection<'b, T> {
+        Intersection {
+            a: self.iter().peekable(),
+            b: other.iter().peekable(),
+        }
+    }
+
+    /// Iterator over the members of this set that are not in `other`, in ascending
+    /// order.
+    pub fn difference<'b>(&'b self, other: &'b BptreeSetReadTxn<T>) -> Difference<'b, T> {
+        Difference {
+            a: self.iter().peekable(),
+            b: other.iter().peekable(),
+        }
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug + Sync + Send + 'static> BptreeSetWriteTxn<'a, T> {
+    /// Determine if `t` is a member of the set, including the changes of this
+    /// transaction.
+    pub fn contains<Q>(&self, t: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.inner.contains_key(t)
+    }
+
+    /// The number of members of the set.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Determine if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Iterator over the members of the set, in ascending order.
+    pub fn iter(&self) -> Iter<T> {
+        Iter {
+            iter: self.inner.keys(),
+        }
+    }
+
+    /// Add `t` to the set. Returns `true` if it was not already a member.
+    pub fn insert(&mut self, t: T) -> bool {
+        self.inner.insert(t, ()).is_none()
+    }
+
+    /// Remove `t` from the set. Returns `true` if it was a member.
+    pub fn remove(&mut self, t: &T) -> bool {
+        self.inner.remove(t).is_some()
+    }
+
+    /// Remove every member of the set.
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    /// Commit the changes of this transaction, making them visible to new readers.
+    pub fn commit(self) -> CommitSummary {
+        self.inner.commit()
+    }
+
+    /// Abandon the changes of this transaction. This is equivalent to dropping it.
+    pub fn discard(self) {
+        self.inner.discard()
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug + Sync + Send + 'static> Extend<T> for BptreeSetWriteTxn<'a, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.inner.extend(iter.into_iter().map(|t| (t, ())));
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T: Ord + Clone + Debug> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T: Ord + Clone + Debug> Iterator for Range<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(t, _)| t)
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug> Iterator for Union<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ord = match (self.a.peek(), self.b.peek()) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+        match ord {
+            Ordering::Less => self.a.next(),
+            Ordering::Greater => self.b.next(),
+            Ordering::Equal => {
+                self.b.next();
+                self.a.next()
+            }
+        }
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug> Iterator for Intersection<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ord = match (self.a.peek(), self.b.peek()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                _ => return None,
+            };
+            match ord {
+                Ordering::Less => {
+                    self.a.next();
+                }
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.b.next();
+                    return self.a.next();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: Ord + Clone + Debug> Iterator for Difference<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ord = match (self.a.peek(), self.b.peek()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                (Some(_), None) => Ordering::Less,
+                (None, _) => return None,
+            };
+            match ord {
+                Ordering::Less => return self.a.next(),
+                Ordering::Greater => {
+                    self.b.next();
+                }
+                Ordering::Equal => {
+                    self.a.next();
+                    self.b.next();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::node::assert_released;
+    use super::BptreeSet;
+
+    #[test]
+    fn test_bptree2_set_basic() {
+        let set: BptreeSet<usize> = BptreeSet::new();
+        let mut wr = set.write();
+        assert!(wr.is_empty());
+        assert!(wr.insert(1));
+        assert!(wr.insert(2));
+        assert!(!wr.insert(1));
+        wr.extend(3..100);
+        assert!(wr.len() == 99);
+        assert!(wr.contains(&50));
+        assert!(wr.remove(&50));
+        assert!(!wr.remove(&50));
+        assert!(!wr.contains(&50));
+        wr.commit();
+
+        let rd = set.read();
+        assert!(rd.len() == 98);
+        assert!(rd.contains(&1) && !rd.contains(&0) && !rd.contains(&50));
+        assert!(rd.iter().cloned().eq((1..50).chain(51..100)));
+        assert!(rd.range(48..52).cloned().collect::<Vec<_>>() == vec![48, 49, 51]);
+
+        // A reader keeps its snapshot while a writer clears and commits.
+        let mut wr = set.write();
+        wr.clear();
+        assert!(wr.insert(1000));
+        wr.commit();
+        assert!(rd.len() == 98 && rd.contains(&1) && !rd.contains(&1000));
+        assert!(rd.iter().count() == 98);
+        drop(rd);
+        {
+            let rd = set.read();
+            assert!(rd.iter().cloned().collect::<Vec<_>>() == vec![1000]);
+        }
+        drop(set);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_set_discard() {
+        let set: BptreeSet<usize> = (0..10).collect();
+        let mut wr = set.write();
+        wr.extend(10..100);
+        wr.discard();
+        let rd = set.read();
+        assert!(rd.len() == 10 && !rd.contains(&10));
+        drop(rd);
+        drop(set);
+        assert_released();
+    }
+
+    #[test]
+    fn test_bptree2_set_algebra() {
+        let a: BptreeSet<usize> = (0..300).filter(|t| t % 2 == 0).collect();
+        let b: BptreeSet<usize> = (0..300).filter(|t| t % 3 == 0).collect();
+        {
+            let (ra, rb) = (a.read(), b.read());
+            assert!(ra
+                .union(&rb)
+                .cloned()
+                .eq((0..300).filter(|t| t % 2 == 0 || t % 3 == 0)));
+            assert!(ra.intersection(&rb).cloned().eq((0..300).step_by(6)));
+            assert!(ra
+                .difference(&rb)
+                .cloned()
+                .eq((0..300).filter(|t| t % 2 == 0 && t % 3 != 0)));
+            assert!(rb
+                .difference(&ra)
+                .cloned()
+                .eq((0..300).filter(|t| t % 3 == 0 && t % 2 != 0)));
+
+            // Either side being empty.
+            let empty: BptreeSet<usize> = BptreeSet::new();
+            let re = empty.read();
+            assert!(ra.union(&re).count() == ra.len());
+            assert!(ra.intersection(&re).next().is_none());
+            assert!(re.difference(&ra).next().is_none());
+            assert!(ra.difference(&re).count() == ra.len());
+        }
+        drop(a);
+        drop(b);
+        assert_released();
+    }
+}
@@ -0,0 +1,409 @@
+//! ConcReadVec - A concurrently readable append-mostly sequence
+//!
+//! A `ConcReadVec` is an ordered sequence of values, that is optimised for appending
+//! to the end, such as a log. Readers are guaranteed that the length and content
+//! of the sequence will not change during the lifetime of the read, while writers
+//! are able to append new values in parallel. Writers are serialised.
+//!
+//! Internally the values are stored in fixed size chunks which are shared between
+//! generations by `Arc`. A write only needs to copy the trailing, partially filled
+//! chunk, so taking a read snapshot or committing a batch of appends does not
+//! require the whole sequence to be cloned.
+
+use parking_lot::{Mutex, MutexGuard};
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+#[cfg(not(feature = "skinny"))]
+const CHUNK_CAPACITY: usize = 64;
+#[cfg(feature = "skinny")]
+const CHUNK_CAPACITY: usize = 8;
+
+#[derive(Debug)]
+struct ConcReadVecInner<T> {
+    chunks: Vec<Arc<Vec<T>>>,
+    length: usize,
+}
+
+/// A concurrently readable append-mostly sequence.
+///
+/// This structure behaves in a similar manner to a `RwLock<Vec<T>>`. However unlike
+/// a `RwLock`, writes and parallel reads can be performed at the same time. Readers
+/// see a stable length and content for the duration of their transaction, even as
+/// writers append and commit new values.
+///
+/// A write transaction batches its appends, and they are made visible to new
+/// readers atomically when `commit()` is called.
+///
+/// # Examples
+/// ```
+/// use concread::covec::ConcReadVec;
+///
+/// let log: ConcReadVec<usize> = ConcReadVec::new();
+///
+/// // Begin a read transaction
+/// let read_txn = log.read();
+/// assert_eq!(read_txn.len(), 0);
+/// {
+///     // Now create a write, and commit it.
+///     let mut write_txn = log.write();
+///     write_txn.push(1);
+///     write_txn.push(2);
+///     // Commit the change
+///     write_txn.commit();
+/// }
+/// // Show the previous generation is still empty
+/// assert_eq!(read_txn.len(), 0);
+/// let new_read_txn = log.read();
+/// // And a new read transaction has the appended values
+/// assert_eq!(new_read_txn.len(), 2);
+/// assert_eq!(new_read_txn.get(1), Some(&2));
+/// ```
+#[derive(Debug)]
+pub struct ConcReadVec<T> {
+    write: Mutex<()>,
+    active: Mutex<Arc<ConcReadVecInner<T>>>,
+}
+
+/// A `ConcReadVec` Write Transaction handle.
+///
+/// This allows appending to the `ConcReadVec` without blocking or affecting
+/// current readers.
+///
+/// Changes are only stored in this structure until you call commit. To abort/
+/// rollback a change, don't call commit and allow the write transaction to
+/// be dropped. This causes the `ConcReadVec` to unlock allowing the next writer
+/// to proceed.
+pub struct ConcReadVecWriteTxn<'a, T: 'a> {
+    chunks: Vec<Arc<Vec<T>>>,
+    length: usize,
+    caller: &'a ConcReadVec<T>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+/// A `ConcReadVec` Read Transaction handle.
+///
+/// This allows safe reading of the values within the `ConcReadVec`, that allows
+/// no mutation of the sequence, and without blocking writers.
+#[derive(Debug)]
+pub struct ConcReadVecReadTxn<T>(Arc<ConcReadVecInner<T>>);
+
+impl<T> Clone for ConcReadVecReadTxn<T> {
+    fn clone(&self) -> Self {
+        ConcReadVecReadTxn(self.0.clone())
+    }
+}
+
+impl<T> ConcReadVecInner<T> {
+    #[inline]
+    fn get(&self, idx: usize) -> Option<&T> {
+        if idx < self.length {
+            self.chunks[idx / CHUNK_CAPACITY].get(idx % CHUNK_CAPACITY)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> ConcReadVec<T>
+where
+    T: Clone,
+{
+    /// Create a new, empty `ConcReadVec` for storing type `T`. `T` must implement
+    /// `Clone` to enable clone-on-write of partially filled chunks.
+    pub fn new() -> Self {
+        ConcReadVec {
+            write: Mutex::new(()),
+            active: Mutex::new(Arc::new(ConcReadVecInner {
+                chunks: Vec::new(),
+                length: 0,
+            })),
+        }
+    }
+
+    /// Begin a read transaction, returning a read guard. The length and content
+    /// of the read guard are guaranteed to be consistent for the life time of the
+    /// read - even if writers commit during.
+    pub fn read(&self) -> ConcReadVecReadTxn<T> {
+        let rwguard = self.active.lock();
+        ConcReadVecReadTxn(rwguard.clone())
+    }
+
+    /// Begin a write transaction, returning a write guard. Values appended in
+    /// the write are only visible to this thread, and are not visible to any
+    /// reader until `commit()` is called.
+    pub fn write(&self) -> ConcReadVecWriteTxn<T> {
+        let mguard = self.write.lock();
+        self.prepare_write(mguard)
+    }
+
+    /// Attempt to create a write transaction. If it fails, `None` is returned.
+    /// On success the `Some(guard)` is returned. See also `write(&self)`
+    pub fn try_write(&self) -> Option<ConcReadVecWriteTxn<T>> {
+        self.write
+            .try_lock()
+            .map(|mguard| self.prepare_write(mguard))
+    }
+
+    fn prepare_write<'a>(&'a self, mguard: MutexGuard<'a, ()>) -> ConcReadVecWriteTxn<'a, T> {
+        // This only clones the chunk pointers, not the values within.
+        let (chunks, length) = {
+            let rwguard = self.active.lock();
+            (rwguard.chunks.clone(), rwguard.length)
+        };
+        ConcReadVecWriteTxn {
+            chunks,
+            length,
+            caller: self,
+            _guard: mguard,
+        }
+    }
+
+    fn commit(&self, chunks: Vec<Arc<Vec<T>>>, length: usize) {
+        let mut rwguard = self.active.lock();
+        *rwguard = Arc::new(ConcReadVecInner { chunks, length });
+    }
+}
+
+impl<T> Default for ConcReadVec<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        ConcReadVec::new()
+    }
+}
+
+impl<T> FromIterator<T> for ConcReadVec<T>
+where
+    T: Clone,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let covec = ConcReadVec::new();
+        {
+            let mut wr = covec.write();
+            wr.extend(iter);
+            wr.commit();
+        }
+        covec
+    }
+}
+
+impl<T> ConcReadVecReadTxn<T> {
+    /// Retrieve a reference to the value at `idx`, or `None` if `idx` is out
+    /// of bounds for this read.
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.0.get(idx)
+    }
+
+    /// Returns the number of values visible to this read.
+    pub fn len(&self) -> usize {
+        self.0.length
+    }
+
+    /// Determine if the sequence was empty at the time this read began.
+    pub fn is_empty(&self) -> bool {
+        self.0.length == 0
+    }
+
+    /// Iterate over the values visible to this read, in the order they were appended.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.0.chunks.iter().flat_map(|c| c.iter())
+    }
+}
+
+impl<'a, T> ConcReadVecWriteTxn<'a, T>
+where
+    T: Clone,
+{
+    /// Append a value to the end of the sequence. This value is not visible to
+    /// readers until `commit()` is called.
+    pub fn push(&mut self, value: T) {
+        let need_chunk = match self.chunks.last() {
+            Some(c) => c.len() >= CHUNK_CAPACITY,
+            None => true,
+        };
+        if need_chunk {
+            self.chunks
+                .push(Arc::new(Vec::with_capacity(CHUNK_CAPACITY)));
+        }
+        // If this chunk is shared with a previous generation, this clones
+        // it once, and then all further pushes to it are in place.
+        let tail = self.chunks.last_mut().expect("can not fail");
+        Arc::make_mut(tail).push(value);
+        self.length += 1;
+    }
+
+    /// Retrieve a reference to the value at `idx`, including values appended
+    /// by this transaction.
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx < self.length {
+            self.chunks[idx / CHUNK_CAPACITY].get(idx % CHUNK_CAPACITY)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of values in the sequence, including values appended
+    /// by this transaction.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Determine if the sequence is empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Iterate over the values in the sequence, including values appended by
+    /// this transaction.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flat_map(|c| c.iter())
+    }
+
+    /// Commit the values appended in this write transaction to the `ConcReadVec`.
+    /// This will consume the transaction so no further changes can be made
+    /// after this is called. Not calling this in a block, is equivalent to
+    /// an abort/rollback of the transaction.
+    pub fn commit(self) {
+        self.caller.commit(self.chunks, self.length);
+    }
+}
+
+impl<'a, T> Extend<T> for ConcReadVecWriteTxn<'a, T>
+where
+    T: Clone,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|v| self.push(v));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConcReadVec, CHUNK_CAPACITY};
+    use crossbeam_utils::thread::scope;
+
+    #[test]
+    fn test_covec_simple_create() {
+        let cv: ConcReadVec<usize> = ConcReadVec::new();
+
+        let cv_rotxn_a = cv.read();
+        assert!(cv_rotxn_a.is_empty());
+
+        {
+            let mut cv_wrtxn = cv.write();
+            cv_wrtxn.push(1);
+            cv_wrtxn.push(2);
+            assert!(cv_wrtxn.len() == 2);
+            assert!(cv_wrtxn.get(1) == Some(&2));
+            assert!(cv_rotxn_a.is_empty());
+
+            let cv_rotxn_b = cv.read();
+            assert!(cv_rotxn_b.is_empty());
+            cv_wrtxn.commit();
+        }
+
+        let cv_rotxn_c = cv.read();
+        assert!(cv_rotxn_c.len() == 2);
+        assert!(cv_rotxn_c.get(0) == Some(&1));
+        assert!(cv_rotxn_c.get(1) == Some(&2));
+        assert!(cv_rotxn_c.get(2).is_none());
+        assert!(cv_rotxn_a.is_empty());
+    }
+
+    #[test]
+    fn test_covec_rollback() {
+        let cv: ConcReadVec<usize> = (0..4).collect();
+        {
+            let mut cv_wrtxn = cv.write();
+            cv_wrtxn.push(4);
+            assert!(cv_wrtxn.len() == 5);
+            // Dropped without commit.
+        }
+        let cv_rotxn = cv.read();
+        assert!(cv_rotxn.len() == 4);
+        assert!(cv_rotxn.get(4).is_none());
+    }
+
+    #[test]
+    fn test_covec_try_write() {
+        let cv: ConcReadVec<usize> = ConcReadVec::new();
+        let cv_wrtxn_a = cv.try_write();
+        assert!(cv_wrtxn_a.is_some());
+        // Because we already hold the write, the second is guaranteed to fail.
+        let cv_wrtxn_b = cv.try_write();
+        assert!(cv_wrtxn_b.is_none());
+    }
+
+    #[test]
+    fn test_covec_chunk_boundary() {
+        let cv: ConcReadVec<usize> = ConcReadVec::new();
+        let count = CHUNK_CAPACITY * 3 + 1;
+        // Commit across a number of txns, so that partial chunks are shared
+        // with older readers.
+        let mut readers = Vec::new();
+        for i in 0..count {
+            let mut cv_wrtxn = cv.write();
+            cv_wrtxn.push(i);
+            cv_wrtxn.commit();
+            readers.push(cv.read());
+        }
+        for (i, rotxn) in readers.iter().enumerate() {
+            assert!(rotxn.len() == i + 1);
+            assert!(rotxn.iter().count() == i + 1);
+            assert!(rotxn.iter().cloned().eq(0..(i + 1)));
+            assert!(rotxn.get(i) == Some(&i));
+            assert!(rotxn.get(i + 1).is_none());
+        }
+    }
+
+    const MAX_TARGET: usize = 2000;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_covec_multithread_append() {
+        let cv: ConcReadVec<usize> = ConcReadVec::new();
+
+        assert!(scope(|scope| {
+            let cv_ref = &cv;
+
+            let _readers: Vec<_> = (0..7)
+                .map(|_| {
+                    scope.spawn(move |_| {
+                        let mut last_len: usize = 0;
+                        while last_len < MAX_TARGET {
+                            let cv_rotxn = cv_ref.read();
+                            let len = cv_rotxn.len();
+                            assert!(len >= last_len);
+                            // The read must remain stable, even as writers commit.
+                            assert!(cv_rotxn.iter().cloned().eq(0..len));
+                            assert!(cv_rotxn.len() == len);
+                            last_len = len;
+                        }
+                    })
+                })
+                .collect();
+
+            let _writers: Vec<_> = (0..3)
+                .map(|_| {
+                    scope.spawn(move |_| {
+                        let mut last_len: usize = 0;
+                        while last_len < MAX_TARGET {
+                            let mut cv_wrtxn = cv_ref.write();
+                            let len = cv_wrtxn.len();
+                            assert!(len >= last_len);
+                            // Batch a few appends to commit atomically.
+                            cv_wrtxn.extend(len..(len + 3));
+                            last_len = cv_wrtxn.len();
+                            cv_wrtxn.commit();
+                        }
+                    })
+                })
+                .collect();
+        })
+        .is_ok());
+
+        assert!(cv.read().len() >= MAX_TARGET);
+    }
+}
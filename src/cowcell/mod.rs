@@ -9,8 +9,15 @@
 //! but has better behaviour with very long running read operations, and more
 //! accurate memory reclaim behaviour.
 
-use parking_lot::{Mutex, MutexGuard};
+use crate::lock::{Condvar, Mutex, MutexGuard};
+use crate::utils::next_version;
+use crate::Versioned;
+use crossbeam::utils::Backoff;
+use std::any::Any;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 /// A conncurrently readable cell.
@@ -23,6 +30,10 @@ use std::sync::Arc;
 /// used. As a write transaction begins, we clone the existing data to a new
 /// location that is capable of being mutated.
 ///
+/// Cloning a `CowCell` creates another handle to the same cell, in the manner of an
+/// `Arc`, so a commit through one handle is visible through all of them. This does
+/// not clone the data `T`.
+///
 /// Readers are guaranteed that the content of the `CowCell` will live as long
 /// as the read transaction is open, and will be consistent for the duration
 /// of the transaction. There can be an "unlimited" number of readers in parallel
@@ -54,10 +65,84 @@ use std::sync::Arc;
 /// // And a new read transaction has '1'
 /// assert_eq!(*new_read_txn, 1);
 /// ```
-#[derive(Debug)]
 pub struct CowCell<T> {
+    inner: Arc<CowCellInner<T>>,
+}
+
+// The state of a cell, shared by each of its handles.
+struct CowCellInner<T> {
+    // Identifies the cell in the tokens of its commits. Unlike the address of this
+    // state, an id is never reused by a later cell once this one is dropped.
+    id: u64,
     write: Mutex<()>,
-    active: Mutex<Arc<T>>,
+    // The read transaction of the current value, shared with each new reader.
+    active: Mutex<CowCellReadTxn<T>>,
+    // Deltas given to `merge` while another writer held the lock, which are applied
+    // by the next commit.
+    merges: Mutex<Vec<Merge<T>>>,
+    // Notified with the active lock held, as each new version is committed.
+    committed: Condvar,
+}
+
+type Merge<T> = Box<dyn Deltas<T>>;
+
+// A run of deltas of the same type queued by `merge`, each with the function that
+// applies it. Consecutive merges of the same kind are pushed to the same run, so the
+// queue does not allocate for each delta.
+trait Deltas<T>: Send {
+    fn len(&self) -> usize;
+    fn apply(self: Box<Self>, value: &mut T);
+    fn as_any(&mut self) -> &mut dyn Any;
+}
+
+impl<T, D, F> Deltas<T> for Vec<(D, F)>
+where
+    D: Send + 'static,
+    F: FnOnce(&mut T, D) + Send + 'static,
+{
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn apply(self: Box<Self>, value: &mut T) {
+        for (delta, apply) in *self {
+            apply(value, delta);
+        }
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+static NEXT_CELL_ID: AtomicU64 = AtomicU64::new(0);
+
+impl<T> Clone for CowCell<T> {
+    fn clone(&self) -> Self {
+        CowCell {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CowCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CowCell")
+            .field("write", &self.inner.write)
+            .field("active", &self.inner.active)
+            .field(
+                "merges",
+                &self
+                    .inner
+                    .merges
+                    .lock()
+                    .iter()
+                    .map(|m| m.len())
+                    .sum::<usize>(),
+            )
+            .field("committed", &self.inner.committed)
+            .finish()
+    }
 }
 
 /// A `CowCell` Write Transaction handle.
@@ -67,8 +152,11 @@ pub struct CowCell<T> {
 ///
 /// Changes are only stored in this structure until you call commit. To abort/
 /// rollback a change, don't call commit and allow the write transaction to
-/// be dropped. This causes the `CowCell` to unlock allowing the next writer
-/// to proceed.
+/// be dropped, or call `discard` to make the intent explicit. This causes the
+/// `CowCell` to unlock allowing the next writer to proceed.
+///
+/// A panic while the transaction is held discards it without poisoning the lock, see
+/// [the crate documentation](crate#panics-in-write-transactions).
 pub struct CowCellWriteTxn<'a, T: 'a> {
     // Hold open the guard, and initiate the copy to here.
     work: Option<T>,
@@ -82,12 +170,59 @@ pub struct CowCellWriteTxn<'a, T: 'a> {
 ///
 /// This allows safe reading of the value within the `CowCell`, that allows
 /// no mutation of the value, and without blocking writers.
+///
+/// Equality and hashing are by the current value of the snapshot, not by its
+/// version, so snapshots may be used as the keys of a collection.
 #[derive(Debug)]
-pub struct CowCellReadTxn<T>(Arc<T>);
+pub struct CowCellReadTxn<T> {
+    data: Arc<T>,
+    version: u64,
+    deps: Option<Arc<[CommitToken]>>,
+}
 
 impl<T> Clone for CowCellReadTxn<T> {
     fn clone(&self) -> Self {
-        CowCellReadTxn(self.0.clone())
+        CowCellReadTxn {
+            data: self.data.clone(),
+            version: self.version,
+            deps: self.deps.clone(),
+        }
+    }
+}
+
+/// The version of a `CowCell` that a commit produced. See `CowCellWriteTxn::commit`.
+///
+/// A token may be given as a dependency to the commit of another cell, so that a
+/// reader of that cell can find the versions of other cells that it was written after,
+/// and with `CowCell::read_at_least` read them at those versions or later. This gives
+/// causal consistency across independent cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CommitToken {
+    // The id of the cell, which identifies it across handles.
+    cell: u64,
+    version: u64,
+}
+
+impl CommitToken {
+    /// The version of the cell that was committed.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl<T> CowCellReadTxn<T> {
+    /// The commits of other cells that the commit of this version was made after. See
+    /// `CowCellWriteTxn::commit_after`.
+    pub fn dependencies(&self) -> &[CommitToken] {
+        self.deps.as_deref().unwrap_or(&[])
+    }
+}
+
+impl<T> Versioned for CowCellReadTxn<T> {
+    /// The version of the value this transaction observes. This starts at zero, and
+    /// increases by one for each commit that changes the value.
+    fn version(&self) -> u64 {
+        self.version
     }
 }
 
@@ -99,8 +234,17 @@ where
     /// to enable clone-on-write.
     pub fn new(data: T) -> Self {
         CowCell {
-            write: Mutex::new(()),
-            active: Mutex::new(Arc::new(data)),
+            inner: Arc::new(CowCellInner {
+                id: NEXT_CELL_ID.fetch_add(1, Ordering::Relaxed),
+                write: Mutex::new(()),
+                active: Mutex::new(CowCellReadTxn {
+                    data: Arc::new(data),
+                    version: 0,
+                    deps: None,
+                }),
+                merges: Mutex::new(Vec::new()),
+                committed: Condvar::new(),
+            }),
         }
     }
 
@@ -108,21 +252,80 @@ where
     /// the read guard is guaranteed to be consistent for the life time of the
     /// read - even if writers commit during.
     pub fn read(&self) -> CowCellReadTxn<T> {
-        let rwguard = self.active.lock();
-        CowCellReadTxn(rwguard.clone())
+        let rwguard = self.inner.active.lock();
+        rwguard.clone()
         // rwguard ends here
     }
 
+    /// Run `f` on a snapshot of the value, and return its result. The snapshot is
+    /// released as soon as `f` returns, rather than when a read guard would be dropped.
+    pub fn with_read<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let rtxn = self.read();
+        f(&rtxn)
+    }
+
+    /// Begin a read transaction of at least the version of `token`, waiting for it to be
+    /// committed if it is not yet. Together with the dependencies of a read transaction
+    /// of another cell, this reads this cell as it was when the other was written, or
+    /// later.
+    ///
+    /// `token` must have been returned by a commit of this cell, or a clone of its
+    /// handle, else this panics.
+    ///
+    /// # Examples
+    /// ```
+    /// use concread::cowcell::CowCell;
+    ///
+    /// let a = CowCell::new(0);
+    /// let b = CowCell::new(0);
+    /// let mut wr = a.write();
+    /// *wr = 1;
+    /// let token = wr.commit();
+    /// let mut wr = b.write();
+    /// *wr = 1;
+    /// wr.commit_after(&[token]);
+    ///
+    /// // A reader of the new value of b also reads the value of a that preceded it.
+    /// let rb = b.read();
+    /// for dep in rb.dependencies() {
+    ///     assert_eq!(*a.read_at_least(dep), 1);
+    /// }
+    /// ```
+    pub fn read_at_least(&self, token: &CommitToken) -> CowCellReadTxn<T> {
+        assert!(
+            token.cell == self.inner.id,
+            "The token was not committed by this cell"
+        );
+        let mut rwguard = self.inner.active.lock();
+        while rwguard.version < token.version {
+            self.inner.committed.wait(&mut rwguard);
+        }
+        rwguard.clone()
+    }
+
+    /// Attempt to begin a read transaction without waiting. `read` holds an internal
+    /// lock for the moment needed to take a reference to the current value, and so may
+    /// briefly wait on a concurrent `read` or `commit`. This never waits, and instead
+    /// returns `None` if that lock is held at the time of the call.
+    ///
+    /// On success the read transaction is identical to one returned by `read`.
+    pub fn try_read(&self) -> Option<CowCellReadTxn<T>> {
+        self.inner.active.try_lock().map(|rwguard| rwguard.clone())
+    }
+
     /// Begin a write transaction, returning a write guard. The content of the
     /// write is only visible to this thread, and is not visible to any reader
     /// until `commit()` is called.
     pub fn write(&self) -> CowCellWriteTxn<T> {
         /* Take the exclusive write lock first */
-        let mguard = self.write.lock();
+        let mguard = self.inner.write.lock();
         // We delay copying until the first get_mut.
         let read = {
-            let rwguard = self.active.lock();
-            rwguard.clone()
+            let rwguard = self.inner.active.lock();
+            rwguard.data.clone()
         };
         /* Now build the write struct */
         CowCellWriteTxn {
@@ -133,16 +336,141 @@ where
         }
     }
 
+    /// Begin write transactions on many cells at once, returning the write guards in
+    /// the same order as `cells`. The cells are always locked in a single global order,
+    /// so callers that lock overlapping sets of cells with this function can not
+    /// deadlock each other, regardless of the order that they list the cells in.
+    ///
+    /// Each guard is committed or dropped independently. Listing the same cell (or a
+    /// clone of its handle) more than once is invalid, and will panic.
+    pub fn write_many<'a>(cells: &[&'a CowCell<T>]) -> Vec<CowCellWriteTxn<'a, T>> {
+        // Clones of a handle share the same lock, so order by the id of the cell.
+        let id = |i: &usize| cells[*i].inner.id;
+        let mut order: Vec<usize> = (0..cells.len()).collect();
+        order.sort_by_key(id);
+        assert!(
+            order.windows(2).all(|w| id(&w[0]) != id(&w[1])),
+            "The same CowCell was listed more than once"
+        );
+
+        let mut txns: Vec<Option<CowCellWriteTxn<'a, T>>> =
+            (0..cells.len()).map(|_| None).collect();
+        for i in order {
+            txns[i] = Some(cells[i].write());
+        }
+        txns.into_iter().map(|t| t.unwrap()).collect()
+    }
+
+    /// Apply `f` to the value in a write transaction, commit it, and return the result
+    /// of `f`. This waits for and excludes other writers in the same manner as `write`,
+    /// so the change is committed atomically, and readers only observe the value from
+    /// before or after `f`.
+    pub fn update<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut txn = self.write();
+        let r = f(txn.get_mut());
+        txn.commit();
+        r
+    }
+
+    /// As `update`, without waiting for another writer. If the writer is held, this
+    /// retries up to `spins` more times, spinning between attempts with an exponential
+    /// backoff, and then gives up and returns `None` without calling `f`. The thread is
+    /// never parked or yielded, so this bounds the latency of a writer under light
+    /// contention, rather than its chance of success.
+    pub fn update_spin<R, F>(&self, spins: u32, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let backoff = Backoff::new();
+        let mut remaining = spins;
+        loop {
+            if let Some(mut txn) = self.try_write() {
+                let r = f(txn.get_mut());
+                txn.commit();
+                return Some(r);
+            }
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            backoff.spin();
+        }
+    }
+
+    /// Apply `delta` to the value with `apply`, coalescing with other writers. If no
+    /// writer holds the cell, this commits the change immediately. Otherwise this does
+    /// not wait, and instead queues the delta so that the holder applies it to its
+    /// working copy as it commits, combining both changes into a single version. Under
+    /// contention, many merges are then committed together.
+    ///
+    /// A delta is applied after the changes of the transaction that commits it. If that
+    /// transaction is dropped without committing, the delta remains queued until the
+    /// next commit or merge.
+    ///
+    /// # Examples
+    /// ```
+    /// use concread::cowcell::CowCell;
+    ///
+    /// let cell = CowCell::new(0);
+    /// let wrtxn = cell.write();
+    /// // The writer is held, so these are applied when it commits.
+    /// cell.merge(1, |v, d| *v += d);
+    /// cell.merge(2, |v, d| *v += d);
+    /// assert_eq!(*cell.read(), 0);
+    /// wrtxn.commit();
+    /// assert_eq!(*cell.read(), 3);
+    /// ```
+    pub fn merge<D, F>(&self, delta: D, apply: F)
+    where
+        D: Send + 'static,
+        F: FnOnce(&mut T, D) + Send + 'static,
+    {
+        // Without a writer there is nothing to coalesce with, so the delta is applied
+        // directly rather than queued.
+        if let Some(mut txn) = self.try_write() {
+            // Deltas queued before this one are still applied first.
+            txn.apply_merges();
+            apply(txn.get_mut(), delta);
+            txn.install(None);
+        } else {
+            let mut merges = self.inner.merges.lock();
+            match merges
+                .last_mut()
+                .and_then(|run| run.as_any().downcast_mut::<Vec<(D, F)>>())
+            {
+                Some(run) => run.push((delta, apply)),
+                None => merges.push(Box::new(vec![(delta, apply)])),
+            }
+        }
+        self.flush_merges();
+    }
+
+    // A delta may be queued after the holder of the lock applied the queue, but before
+    // it released the lock, so the queue is checked again once it is released.
+    fn flush_merges(&self) {
+        while !self.inner.merges.lock().is_empty() {
+            match self.try_write() {
+                Some(txn) => {
+                    txn.install(None);
+                }
+                None => return,
+            }
+        }
+    }
+
     /// Attempt to create a write transaction. If it fails, and err
     /// is returned. On success the `Ok(guard)` is returned. See also
     /// `write(&self)`
     pub fn try_write(&self) -> Option<CowCellWriteTxn<T>> {
         /* Take the exclusive write lock first */
-        self.write.try_lock().map(|mguard| {
+        self.inner.write.try_lock().map(|mguard| {
             // We delay copying until the first get_mut.
             let read = {
-                let rwguard = self.active.lock();
-                rwguard.clone()
+                let rwguard = self.inner.active.lock();
+                rwguard.data.clone()
             };
             /* Now build the write struct */
             CowCellWriteTxn {
@@ -154,15 +482,76 @@ where
         })
     }
 
-    fn commit(&self, newdata: Option<T>) {
+    fn commit(&self, newdata: Option<T>, deps: Option<Arc<[CommitToken]>>) -> CommitToken {
+        let mut rwguard = self.inner.active.lock();
         if let Some(nd) = newdata {
-            let mut rwguard = self.active.lock();
-            let new_inner = Arc::new(nd);
+            let new_inner = CowCellReadTxn {
+                data: Arc::new(nd),
+                version: next_version(rwguard.version),
+                deps,
+            };
             // now over-write the last value in the mutex.
             *rwguard = new_inner;
+            self.inner.committed.notify_all();
         }
         // If not some, we do nothing.
-        // Done
+        CommitToken {
+            cell: self.inner.id,
+            version: rwguard.version,
+        }
+    }
+}
+
+/// A `CowCell` that holds an `Arc` may be used for values that are unsized, such as
+/// trait objects. Rather than cloning and mutating the value, a writer replaces the
+/// `Arc` as a whole, so a write never copies the value it replaces.
+///
+/// # Examples
+/// ```
+/// use concread::cowcell::CowCell;
+/// use std::fmt::Display;
+/// use std::sync::Arc;
+///
+/// let cell: CowCell<Arc<dyn Display + Send + Sync>> = CowCell::new(Arc::new(1));
+/// let prev = cell.replace(Arc::new("two"));
+/// assert_eq!(prev.to_string(), "1");
+/// assert_eq!(cell.load().to_string(), "two");
+/// ```
+impl<T> CowCell<Arc<T>>
+where
+    T: ?Sized,
+{
+    /// Retrieve the current value. This is only a clone of the `Arc`, and the value
+    /// remains alive as long as it is held, in the same manner as a read transaction.
+    pub fn load(&self) -> Arc<T> {
+        (*self.inner.active.lock().data).clone()
+    }
+
+    /// Install `value` as the current value, returning the value that it replaced.
+    /// This waits for and excludes other writers in the same manner as `write`.
+    pub fn replace(&self, value: Arc<T>) -> Arc<T> {
+        let mut txn = self.write();
+        let prev = std::mem::replace(txn.get_mut(), value);
+        txn.commit();
+        prev
+    }
+}
+
+impl<T> Default for CowCell<T>
+where
+    T: Clone + Default,
+{
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for CowCell<T>
+where
+    T: Clone,
+{
+    fn from(data: T) -> Self {
+        Self::new(data)
     }
 }
 
@@ -171,7 +560,21 @@ impl<T> Deref for CowCellReadTxn<T> {
 
     #[inline]
     fn deref(&self) -> &T {
-        &self.0
+        &self.data
+    }
+}
+
+impl<T: PartialEq> PartialEq for CowCellReadTxn<T> {
+    fn eq(&self, other: &Self) -> bool {
+        *self.data == *other.data
+    }
+}
+
+impl<T: Eq> Eq for CowCellReadTxn<T> {}
+
+impl<T: Hash> Hash for CowCellReadTxn<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state)
     }
 }
 
@@ -193,13 +596,80 @@ where
         self.work.as_mut().expect("can not fail")
     }
 
+    /// Access the value as it would be committed by this transaction, including any
+    /// changes made so far. This is the same as dereferencing the write transaction,
+    /// and never causes the value to be copied. Readers do not observe these changes
+    /// until `commit()` is called.
+    #[inline(always)]
+    pub fn read_uncommitted(&self) -> &T {
+        self
+    }
+
     /// Commit the changes made in this write transactions to the `CowCell`.
     /// This will consume the transaction so no further changes can be made
     /// after this is called. Not calling this in a block, is equivalent to
     /// an abort/rollback of the transaction.
-    pub fn commit(self) {
-        /* Write our data back to the CowCell */
-        self.caller.commit(self.work);
+    ///
+    /// Returns a token of the version that this committed, or of the current version if
+    /// there was no change. See `CommitToken`.
+    pub fn commit(self) -> CommitToken {
+        self.commit_after(&[])
+    }
+
+    /// Abandon the changes made in this write transaction. This is equivalent to
+    /// dropping the transaction without calling `commit`: the committed value and
+    /// version of the `CowCell` are unchanged, and the next writer may proceed.
+    pub fn discard(self) {
+        drop(self)
+    }
+
+    /// Commit the changes made in this write transaction, recording that they were made
+    /// after the commits of `deps`, which are usually of other cells. Readers of the
+    /// new version find these with `CowCellReadTxn::dependencies`. If there was no
+    /// change, no version is committed, and `deps` are not recorded.
+    pub fn commit_after(self, deps: &[CommitToken]) -> CommitToken {
+        let caller = self.caller;
+        let deps = if deps.is_empty() {
+            None
+        } else {
+            Some(Arc::from(deps))
+        };
+        let token = self.install(deps);
+        caller.flush_merges();
+        token
+    }
+
+    // Apply any queued merges, and write our data back to the CowCell.
+    fn install(mut self, deps: Option<Arc<[CommitToken]>>) -> CommitToken {
+        self.apply_merges();
+        self.caller.commit(self.work, deps)
+    }
+
+    fn apply_merges(&mut self) {
+        let merges = std::mem::take(&mut *self.caller.inner.merges.lock());
+        for run in merges {
+            run.apply(self.get_mut());
+        }
+    }
+
+    /// Commit the changes made in this write transaction only if the value now differs
+    /// from the committed value, returning `true` if a new version was committed. An
+    /// idempotent update then creates no new version, so readers keep sharing the
+    /// existing value and its version is unchanged.
+    pub fn commit_if_changed(mut self) -> bool
+    where
+        T: PartialEq,
+    {
+        let caller = self.caller;
+        self.apply_merges();
+        let changed = matches!(self.work, Some(ref work) if *work != *self.read);
+        if changed {
+            self.install(None);
+        } else {
+            drop(self);
+        }
+        caller.flush_merges();
+        changed
     }
 }
 
@@ -231,7 +701,12 @@ where
 #[cfg(test)]
 mod tests {
     use super::CowCell;
+    use crate::Versioned;
     use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
 
     use crossbeam_utils::thread::scope;
 
@@ -249,6 +724,282 @@ mod tests {
         assert_eq!(*cc_rotxn, 1);
     }
 
+    #[test]
+    fn test_default_from() {
+        let cc: CowCell<i64> = CowCell::default();
+        assert_eq!(*cc.read(), 0);
+        let cc = CowCell::from(5);
+        assert_eq!(*cc.read(), 5);
+        let cc: CowCell<String> = String::from("cell").into();
+        assert_eq!(cc.read().as_str(), "cell");
+    }
+
+    #[test]
+    fn test_clone_handle() {
+        let cc = CowCell::new(0);
+        let handle = cc.clone();
+        std::thread::spawn(move || {
+            let mut wrtxn = handle.write();
+            *wrtxn = 1;
+            wrtxn.commit();
+        })
+        .join()
+        .unwrap();
+        // The commit through the cloned handle is visible through the original.
+        assert!(*cc.read() == 1);
+        // A cloned handle shares the write lock.
+        let handle = cc.clone();
+        let _wrtxn = cc.write();
+        assert!(handle.try_write().is_none());
+    }
+
+    #[test]
+    fn test_write_panic() {
+        let cc = CowCell::new(0);
+        let handle = cc.clone();
+        let r = std::thread::spawn(move || {
+            let mut wrtxn = handle.write();
+            *wrtxn = 1;
+            panic!("Panic during a write");
+        })
+        .join();
+        assert!(r.is_err());
+        // The uncommitted change was discarded, and the lock was released.
+        assert!(*cc.read() == 0);
+        let mut wrtxn = cc.try_write().expect("The write lock was not released");
+        assert!(*wrtxn == 0);
+        *wrtxn = 2;
+        wrtxn.commit();
+        assert!(*cc.read() == 2);
+    }
+
+    #[test]
+    fn test_version_exhausted() {
+        let cc = CowCell::new(0);
+        cc.inner.active.lock().version = u64::MAX - 2;
+        let old = cc.write().commit();
+        {
+            let mut wr = cc.write();
+            *wr = 1;
+            assert!(wr.commit().version() == u64::MAX - 1);
+        }
+        let last = {
+            let mut wr = cc.write();
+            *wr = 2;
+            wr.commit()
+        };
+        assert!(last.version() == u64::MAX);
+        assert!(cc.read_at_least(&old).version() == u64::MAX);
+        assert!(cc.read_at_least(&last).version() == u64::MAX);
+        // Commits without a change do not need a new version.
+        assert!(!cc.write().commit_if_changed());
+        assert!(cc.write().commit().version() == u64::MAX);
+
+        // A commit past the last version fails, rather than repeating an earlier one.
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut wr = cc.write();
+            *wr = 3;
+            wr.commit();
+        }));
+        assert!(r.is_err());
+        let rd = cc.read();
+        assert!(*rd == 2 && rd.version() == u64::MAX);
+        assert!(cc.read_at_least(&last).version() == u64::MAX);
+    }
+
+    #[test]
+    fn test_version() {
+        let cc = CowCell::new(0);
+        let other = CowCell::new(0);
+        let rotxn = cc.read();
+        assert!(rotxn.version() == 0);
+        // A commit that did not change the value is not a new version.
+        cc.write().commit();
+        assert!(cc.read().version() == 0);
+        for i in 1..4 {
+            let mut wrtxn = cc.write();
+            *wrtxn = i;
+            wrtxn.commit();
+            assert!(cc.read().version() == i);
+        }
+        // Existing readers, and other cells, are unaffected.
+        assert!(rotxn.version() == 0);
+        assert!(other.read().version() == 0);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_write_many() {
+        let cells: Vec<CowCell<usize>> = (0..4).map(CowCell::new).collect();
+        let (a, b, c, d) = (&cells[0], &cells[1], &cells[2], &cells[3]);
+
+        // Guards are returned in the order given.
+        let txns = CowCell::write_many(&[c, a]);
+        assert!(*txns[0] == 2 && *txns[1] == 0);
+        drop(txns);
+
+        // Overlapping sets in opposite orders can not deadlock.
+        let sets = [[a, b, c, d], [d, c, b, a], [b, d, a, c]];
+        scope(|scope| {
+            for set in sets.iter() {
+                scope.spawn(move |_| {
+                    for _ in 0..1000 {
+                        for mut txn in CowCell::write_many(set) {
+                            *txn += 1;
+                            txn.commit();
+                        }
+                    }
+                });
+            }
+        })
+        .unwrap();
+        for (i, cell) in cells.iter().enumerate() {
+            assert!(*cell.read() == i + 3000);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_write_many_duplicate() {
+        let cc = CowCell::new(0);
+        let handle = cc.clone();
+        let _txns = CowCell::write_many(&[&cc, &handle]);
+    }
+
+    #[test]
+    fn test_try_read_uncommitted() {
+        let cc = CowCell::new(0);
+        assert_eq!(*cc.try_read().expect("Lock contended"), 0);
+
+        let mut cc_wrtxn = cc.write();
+        assert_eq!(*cc_wrtxn.read_uncommitted(), 0);
+        *cc_wrtxn.get_mut() = 1;
+        // The writer sees the pending change, but readers do not.
+        assert_eq!(*cc_wrtxn.read_uncommitted(), 1);
+        assert_eq!(*cc.read(), 0);
+        assert_eq!(*cc.try_read().expect("Lock contended"), 0);
+        cc_wrtxn.commit();
+        assert_eq!(*cc.read(), 1);
+
+        // try_read never waits on the active lock.
+        let guard = cc.inner.active.lock();
+        assert!(cc.try_read().is_none());
+        drop(guard);
+        assert_eq!(*cc.try_read().expect("Lock contended"), 1);
+    }
+
+    #[test]
+    fn test_update() {
+        let cc = CowCell::new(0usize);
+        assert!(
+            cc.update(|v| {
+                *v += 1;
+                *v
+            }) == 1
+        );
+        assert!(*cc.read() == 1);
+
+        // Each update is a whole read-modify-write, so none are lost to a concurrent
+        // writer interleaving with it.
+        scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|_| {
+                    for _ in 0..1000 {
+                        cc.update(|v| {
+                            let prev = *v;
+                            std::thread::yield_now();
+                            *v = prev + 1;
+                        });
+                    }
+                });
+            }
+        })
+        .unwrap();
+        assert!(*cc.read() == 4001);
+    }
+
+    #[test]
+    fn test_update_spin() {
+        let cc = CowCell::new(0usize);
+        // Without contention the first attempt always succeeds.
+        for i in 0..100 {
+            assert!(
+                cc.update_spin(0, |v| {
+                    *v += 1;
+                    *v
+                }) == Some(i + 1)
+            );
+        }
+        assert!(*cc.read() == 100);
+
+        // While the writer is held, every attempt fails and f is never called.
+        let held = cc.write();
+        assert!(cc
+            .update_spin(1000, |_| panic!("Called while the writer is held"))
+            .is_none());
+        assert!(cc.update_spin(0, |v| *v).is_none());
+        drop(held);
+        assert!(cc.update_spin(0, |v| *v) == Some(100));
+    }
+
+    #[test]
+    fn test_with_read() {
+        let cc = CowCell::new(0usize);
+        let seen = cc.with_read(|v| {
+            // The snapshot is held only while the closure runs.
+            assert!(Arc::strong_count(&cc.inner.active.lock().data) == 2);
+            // A commit during the closure does not change the snapshot.
+            cc.update(|w| *w = 1);
+            *v
+        });
+        assert!(seen == 0);
+        assert!(Arc::strong_count(&cc.inner.active.lock().data) == 1);
+        assert!(cc.with_read(|v| *v) == 1);
+    }
+
+    #[test]
+    fn test_arc_trait_object() {
+        trait Greeting {
+            fn greet(&self) -> &'static str;
+        }
+        struct Hello;
+        struct Goodbye;
+        impl Greeting for Hello {
+            fn greet(&self) -> &'static str {
+                "hello"
+            }
+        }
+        impl Greeting for Goodbye {
+            fn greet(&self) -> &'static str {
+                "goodbye"
+            }
+        }
+
+        let cc: CowCell<Arc<dyn Greeting + Send + Sync>> = CowCell::new(Arc::new(Hello));
+        let held = cc.load();
+        let seen = AtomicUsize::new(0);
+        scope(|scope| {
+            scope.spawn(|_| {
+                // The reader observes each value whole, and eventually the new one.
+                loop {
+                    match cc.load().greet() {
+                        "hello" => continue,
+                        "goodbye" => break,
+                        _ => unreachable!(),
+                    }
+                }
+                seen.store(1, Ordering::Release);
+            });
+            let prev = cc.replace(Arc::new(Goodbye));
+            assert!(prev.greet() == "hello");
+        })
+        .unwrap();
+        assert!(seen.load(Ordering::Acquire) == 1);
+        // A value that was loaded before the replace is unaffected.
+        assert!(held.greet() == "hello");
+        assert!(cc.read().greet() == "goodbye");
+    }
+
     #[test]
     fn test_try_write() {
         let data: i64 = 0;
@@ -395,4 +1146,221 @@ mod tests {
 
         assert!(GC_COUNT.load(Ordering::Acquire) >= 50);
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_reader_writer_fairness() {
+        let cc = CowCell::new(0usize);
+        crate::utils::assert_no_starvation(
+            || cc.read(),
+            || cc.write(),
+            || {
+                let mut wrtxn = cc.write();
+                *wrtxn += 1;
+                wrtxn.commit();
+            },
+        );
+        assert!(*cc.read() == 200);
+    }
+
+    #[test]
+    fn test_read_txn_eq_hash() {
+        fn hash_of<T: Hash>(t: &T) -> u64 {
+            let mut h = DefaultHasher::new();
+            t.hash(&mut h);
+            h.finish()
+        }
+
+        let a = CowCell::new(String::from("value"));
+        let b = CowCell::new(String::from("other"));
+        let b_first = b.read();
+        assert!(a.read() != b_first);
+        {
+            let mut wr = b.write();
+            *wr = String::from("value");
+            wr.commit();
+        }
+        // Different versions of equal values are equal.
+        let (a_read, b_read) = (a.read(), b.read());
+        assert!(a_read.version() != b_read.version());
+        assert!(a_read == b_read);
+        assert!(hash_of(&a_read) == hash_of(&b_read));
+        assert!(hash_of(&a_read) == hash_of(&String::from("value")));
+
+        let mut set = std::collections::HashSet::new();
+        assert!(set.insert(a_read));
+        assert!(!set.insert(b_read));
+        assert!(set.insert(b_first));
+    }
+
+    #[test]
+    fn test_commit_if_changed() {
+        let cc = CowCell::new(String::from("value"));
+        let rd = cc.read();
+        // Unchanged values, whether copied or not, do not commit.
+        assert!(!cc.write().commit_if_changed());
+        let mut wr = cc.write();
+        wr.get_mut().push_str("");
+        assert!(!wr.commit_if_changed());
+        let current = cc.read();
+        assert!(current.version() == 0);
+        assert!(Arc::ptr_eq(&current.data, &rd.data));
+
+        let mut wr = cc.write();
+        wr.get_mut().push('s');
+        assert!(wr.commit_if_changed());
+        assert!(cc.read().version() == 1);
+        assert!(cc.read().as_str() == "values");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_merge() {
+        const N: usize = 64;
+        let cc = CowCell::new(0);
+
+        // While a writer is held, merges are queued and committed with it.
+        let mut wrtxn = cc.write();
+        *wrtxn += 100;
+        scope(|scope| {
+            for _ in 0..N {
+                scope.spawn(|_| cc.merge(1, |v, d| *v += d));
+            }
+        })
+        .unwrap();
+        assert!(*cc.read() == 0);
+        wrtxn.commit();
+        assert!(*cc.read() == 100 + N);
+        assert!(cc.read().version() == 1);
+
+        // A dropped writer leaves its queued merges for the next commit.
+        let wrtxn = cc.write();
+        cc.merge(1, |v, d| *v += d);
+        drop(wrtxn);
+        assert!(*cc.read() == 100 + N);
+        cc.merge(1, |v, d| *v += d);
+        assert!(*cc.read() == 102 + N);
+
+        // Without a writer, each merge commits a version of its own.
+        let cc = CowCell::new(0);
+        for i in 1..=N {
+            cc.merge(1, |v, d| *v += d);
+            assert!(*cc.read() == i && cc.read().version() == i as u64);
+        }
+
+        // Merges of many threads under one writer are never lost, and are committed
+        // together as a single version.
+        let wrtxn = cc.write();
+        scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|_| {
+                    for _ in 0..N {
+                        cc.merge(1, |v, d| *v += d);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        wrtxn.commit();
+        assert!(*cc.read() == 5 * N);
+        assert!(cc.read().version() == N as u64 + 1);
+
+        // Deltas of different kinds are applied in the order that they were merged.
+        let cc = CowCell::new(Vec::new());
+        let wrtxn = cc.write();
+        cc.merge(1, |v, d| v.push(d));
+        cc.merge(2, |v, d| v.push(d));
+        cc.merge((), |v, _| v.reverse());
+        // Each delta keeps its own function, even where they are of the same type.
+        for offset in 3..5 {
+            cc.merge(0, move |v, d| v.push(d + offset));
+        }
+        wrtxn.commit();
+        assert!(*cc.read() == vec![2, 1, 3, 4]);
+        assert!(cc.read().version() == 1);
+    }
+
+    #[test]
+    fn test_discard() {
+        let data = CowCell::new(0);
+        let version = data.read().version();
+
+        let mut wr = data.write();
+        *wr = 1;
+        drop(wr);
+        assert!(*data.read() == 0 && data.read().version() == version);
+
+        let mut wr = data.write();
+        *wr = 2;
+        wr.discard();
+        assert!(*data.read() == 0 && data.read().version() == version);
+
+        let mut wr = data.write();
+        *wr = 3;
+        wr.commit();
+        assert!(*data.read() == 3 && data.read().version() == version + 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_commit_token() {
+        let a = CowCell::new(0);
+        let b = CowCell::new(0);
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        scope(|scope| {
+            let (a, b) = (&a, &b);
+            scope.spawn(move |_| {
+                for i in 1..=100 {
+                    let mut wr = a.write();
+                    *wr = i;
+                    let token = wr.commit();
+                    assert!(token.version() == i as u64);
+                    let mut wr = b.write();
+                    *wr = i;
+                    tx.send(wr.commit_after(&[token])).unwrap();
+                }
+            });
+            scope.spawn(move |_| {
+                for token in rx.iter() {
+                    let rb = b.read_at_least(&token);
+                    assert!(rb.version() >= token.version());
+                    assert!(rb.dependencies().len() == 1);
+                    // Every version of b was written after the same version of a.
+                    for dep in rb.dependencies() {
+                        assert!(*a.read_at_least(dep) >= *rb);
+                    }
+                }
+            });
+        })
+        .unwrap();
+
+        // An unchanged commit is a token of the current version, with no dependencies.
+        let token = b.write().commit();
+        assert!(token.version() == 100 && b.read().dependencies().len() == 1);
+        let mut wr = b.write();
+        *wr = 0;
+        wr.commit();
+        assert!(b.read().dependencies().is_empty());
+        assert!(b.clone().read_at_least(&token).version() == 101);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_commit_token_other_cell() {
+        let a = CowCell::new(0);
+        let b = CowCell::new(0);
+        let token = a.write().commit();
+        let _ = b.read_at_least(&token);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_commit_token_dropped_cell() {
+        // A new cell may be allocated where a dropped one was, and must still reject
+        // the tokens of the dropped cell.
+        let token = CowCell::new(0).write().commit();
+        let b = CowCell::new(0);
+        let _ = b.read_at_least(&token);
+    }
 }
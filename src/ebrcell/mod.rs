@@ -19,10 +19,16 @@ use crossbeam_epoch as epoch;
 use crossbeam_epoch::{Atomic, Guard, Owned};
 use std::sync::atomic::Ordering::{Acquire, Release};
 
-use parking_lot::{Mutex, MutexGuard};
+use crate::utils::next_version;
+use crate::Versioned;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use std::collections::{BTreeMap, VecDeque};
 use std::marker::Send;
 use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 /// An `EbrCell` Write Transaction handle.
 ///
@@ -31,8 +37,11 @@ use std::ops::{Deref, DerefMut};
 ///
 /// Changes are only stored in the structure until you call commit: to
 /// abort a change, don't call commit and allow the write transaction to
-/// go out of scope. This causes the `EbrCell` to unlock allowing other
-/// writes to proceed.
+/// go out of scope, or call `discard`. This causes the `EbrCell` to unlock
+/// allowing other writes to proceed.
+///
+/// A panic while the transaction is held discards it without poisoning the lock, see
+/// [the crate documentation](crate#panics-in-write-transactions).
 pub struct EbrCellWriteTxn<'a, T: 'static + Clone + Send + Sync> {
     data: Option<T>,
     // This way we know who to contact for updating our data ....
@@ -62,6 +71,30 @@ where
         mem::swap(&mut element, &mut self.data);
         self.caller.commit(element);
     }
+
+    /// Abandon the changes in this write transaction. This is equivalent to
+    /// dropping it without calling `commit`, so the `EbrCell` is unchanged.
+    pub fn discard(self) {
+        drop(self)
+    }
+
+    /// Commit the changes in this write transaction only if the value now differs
+    /// from the committed value, returning `true` if a new version was committed. An
+    /// idempotent update then creates no new version, and retires no previous one.
+    pub fn commit_if_changed(self) -> bool
+    where
+        T: PartialEq,
+    {
+        let changed = {
+            let guard = epoch::pin();
+            let cur = self.caller.active.load(Acquire, &guard);
+            self.data.as_ref() != Some(unsafe { &cur.deref().data })
+        };
+        if changed {
+            self.commit();
+        }
+        changed
+    }
 }
 
 impl<'a, T> Deref for EbrCellWriteTxn<'a, T>
@@ -85,6 +118,51 @@ where
     }
 }
 
+/// An `EbrCell` batch write handle, which stages several versions and commits only
+/// the last of them. See `EbrCell::write_batch`.
+///
+/// As with `EbrCellWriteTxn`, this holds the write lock until it is dropped, and
+/// nothing that is staged is visible until `commit` is called.
+pub struct EbrCellBatchTxn<'a, T: 'static + Clone + Send + Sync> {
+    staged: Vec<T>,
+    caller: &'a EbrCell<T>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl<'a, T> EbrCellBatchTxn<'a, T>
+where
+    T: Clone + Sync + Send + 'static,
+{
+    /// Queue a version of the value. Only the last version staged is committed.
+    pub fn stage(&mut self, data: T) {
+        self.staged.push(data);
+    }
+
+    /// The last version that was staged, if any.
+    pub fn last(&self) -> Option<&T> {
+        self.staged.last()
+    }
+
+    /// The number of versions that have been staged.
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+
+    /// Determine if no version has been staged.
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Commit the last staged version to the `EbrCell`, in a single epoch operation.
+    /// The earlier versions were never visible to a reader, so they are dropped at
+    /// once rather than retired. If nothing was staged, the cell is unchanged.
+    pub fn commit(mut self) {
+        if let Some(last) = self.staged.pop() {
+            self.caller.commit(Some(last));
+        }
+    }
+}
+
 /// A concurrently readable cell.
 ///
 /// This structure behaves in a similar manner to a `RwLock<Box<T>>`. However
@@ -132,7 +210,100 @@ where
 #[derive(Debug)]
 pub struct EbrCell<T: Clone + Sync + Send + 'static> {
     write: Mutex<()>,
-    active: Atomic<T>,
+    active: Atomic<EbrCellInner<T>>,
+    // Previously committed versions, oldest first.
+    history: Mutex<VecDeque<Owned<EbrCellInner<T>>>>,
+    history_max: usize,
+    // Versions that have been superseded but not yet reclaimed, by retirement order.
+    retired: Arc<Mutex<Retired>>,
+}
+
+// A committed value, and the version it was committed as.
+#[derive(Debug)]
+struct EbrCellInner<T> {
+    version: u64,
+    data: T,
+}
+
+#[derive(Debug, Default)]
+struct Retired {
+    next: u64,
+    pending: BTreeMap<u64, Instant>,
+    // The number of running reclaimers. While any run, retired versions are handed to
+    // the global queue as they are retired, so that the reclaimer is able to free them.
+    reclaimers: usize,
+}
+
+/// A background thread that reclaims the superseded versions of an `EbrCell`. See
+/// `EbrCell::spawn_reclaimer`. Dropping this stops the thread, and waits for it to
+/// exit.
+#[derive(Debug)]
+pub struct ReclaimerHandle {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    retired: Arc<Mutex<Retired>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ReclaimerHandle {
+    fn drop(&mut self) {
+        {
+            let (lock, cvar) = &*self.stop;
+            *lock.lock() = true;
+            cvar.notify_one();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.retired.lock().reclaimers -= 1;
+    }
+}
+
+/// A builder for configuring an `EbrCell`.
+///
+/// # Examples
+/// ```
+/// use concread::ebrcell::EbrCellBuilder;
+///
+/// let ebrcell = EbrCellBuilder::new().history(2).build(0);
+/// {
+///     let mut write_txn = ebrcell.write();
+///     *write_txn = 1;
+///     write_txn.commit();
+/// }
+/// assert!(ebrcell.rollback());
+/// assert_eq!(*ebrcell.read(), 0);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EbrCellBuilder {
+    history: usize,
+}
+
+impl EbrCellBuilder {
+    /// Create a new builder. By default no history is retained.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retain up to `n` previously committed versions, allowing them to be restored
+    /// with `EbrCell::rollback`. Each retained version is kept in memory until it
+    /// falls out of this window, at which point it is reclaimed.
+    pub fn history(self, n: usize) -> Self {
+        EbrCellBuilder { history: n }
+    }
+
+    /// Build the `EbrCell` containing `data`.
+    pub fn build<T>(self, data: T) -> EbrCell<T>
+    where
+        T: Clone + Sync + Send + 'static,
+    {
+        EbrCell {
+            write: Mutex::new(()),
+            active: Atomic::new(EbrCellInner { version: 0, data }),
+            history: Mutex::new(VecDeque::with_capacity(self.history)),
+            history_max: self.history,
+            retired: Arc::new(Mutex::new(Retired::default())),
+        }
+    }
 }
 
 impl<T> EbrCell<T>
@@ -141,10 +312,7 @@ where
 {
     /// Create a new `EbrCell` storing type `T`. `T` must implement `Clone`.
     pub fn new(data: T) -> Self {
-        EbrCell {
-            write: Mutex::new(()),
-            active: Atomic::new(data),
-        }
+        EbrCellBuilder::new().build(data)
     }
 
     /// Begin a write transaction, returning a write guard.
@@ -157,12 +325,28 @@ where
         /* Now build the write struct, we'll discard the pin shortly! */
         EbrCellWriteTxn {
             /* This is the 'copy' of the copy on write! */
-            data: Some(unsafe { cur_shared.deref().clone() }),
+            data: Some(unsafe { cur_shared.deref().data.clone() }),
             caller: self,
             _guard: mguard,
         }
     }
 
+    /// Begin a batch write, returning a handle that stages several versions of the
+    /// value and commits only the last. This suits a writer with a burst of small
+    /// changes, as each commit pins the epoch and retires the version it replaces,
+    /// while a batch does so only once. `count_hint` is the number of versions
+    /// expected to be staged.
+    ///
+    /// Unlike `write`, the current value is not cloned, so each staged version must be
+    /// built by the caller.
+    pub fn write_batch(&self, count_hint: usize) -> EbrCellBatchTxn<T> {
+        EbrCellBatchTxn {
+            staged: Vec::with_capacity(count_hint),
+            caller: self,
+            _guard: self.write.lock(),
+        }
+    }
+
     /// Attempt to begin a write transaction. If it's already held,
     /// `None` is returned.
     pub fn try_write(&self) -> Option<EbrCellWriteTxn<T>> {
@@ -172,7 +356,7 @@ where
             /* Now build the write struct, we'll discard the pin shortly! */
             EbrCellWriteTxn {
                 /* This is the 'copy' of the copy on write! */
-                data: Some(unsafe { cur_shared.deref().clone() }),
+                data: Some(unsafe { cur_shared.deref().data.clone() }),
                 caller: self,
                 _guard: mguard,
             }
@@ -193,18 +377,151 @@ where
         // Load the previous data ready for unlinking
         let prev_data = self.active.load(Acquire, &guard);
         // Make the data Owned, and set it in the active.
-        let owned_data: Owned<T> = Owned::new(element.unwrap());
+        let owned_data: Owned<EbrCellInner<T>> = Owned::new(EbrCellInner {
+            version: next_version(unsafe { prev_data.deref().version }),
+            data: element.unwrap(),
+        });
         let _shared_data = self
             .active
             .compare_and_set(prev_data, owned_data, Release, &guard);
-        // Finally, set our previous data for cleanup.
-        unsafe { guard.defer_destroy(prev_data) };
+        if self.history_max > 0 {
+            // Retain the previous data, and clean up whatever falls out of the window.
+            let mut history = self.history.lock();
+            history.push_back(unsafe { prev_data.into_owned() });
+            if history.len() > self.history_max {
+                if let Some(oldest) = history.pop_front() {
+                    self.retire(oldest, &guard);
+                }
+            }
+        } else {
+            // Finally, set our previous data for cleanup.
+            self.retire(unsafe { prev_data.into_owned() }, &guard);
+        }
         // Then return the current data with a readtxn. Do we need a new guard scope?
     }
 
+    /// Reinstall the most recent retained version as the current value. Returns
+    /// `false` if there is no retained history to restore. See
+    /// `EbrCellBuilder::history`.
+    ///
+    /// This does not rewind the cell. The restored value becomes a new commit, so
+    /// existing readers are unaffected and new readers observe the restored value.
+    /// The restored value is copied, as readers may still observe the retained one.
+    /// The replaced value is discarded rather than retained, so repeated calls step
+    /// further back through the history.
+    pub fn rollback(&self) -> bool {
+        let _mguard = self.write.lock();
+        let guard = epoch::pin();
+        // The restored value is a new commit, so it needs a new version. This is taken
+        // first, so that the history is unchanged if the versions are exhausted.
+        let version = next_version(unsafe { self.active.load(Acquire, &guard).deref().version });
+        let prev = match self.history.lock().pop_back() {
+            Some(prev) => prev,
+            None => return false,
+        };
+        // Readers of the retained version may still observe it, so it can not be
+        // changed in place, and is copied instead.
+        let restored = Owned::new(EbrCellInner {
+            version,
+            data: prev.data.clone(),
+        });
+        self.retire(prev, &guard);
+        let cur_data = self.active.swap(restored, Release, &guard);
+        self.retire(unsafe { cur_data.into_owned() }, &guard);
+        true
+    }
+
+    /// Defer the destruction of a superseded version until no reader can observe it,
+    /// recording when it was superseded.
+    fn retire(&self, data: Owned<EbrCellInner<T>>, guard: &Guard) {
+        let (id, flush) = {
+            let mut retired = self.retired.lock();
+            let id = retired.next;
+            retired.next += 1;
+            retired.pending.insert(id, Instant::now());
+            (id, retired.reclaimers > 0)
+        };
+        let retired = self.retired.clone();
+        guard.defer(move || {
+            mem::drop(data);
+            retired.lock().pending.remove(&id);
+        });
+        if flush {
+            // Deferred destruction is otherwise held by this thread until it pins
+            // again, and no other thread may run it.
+            guard.flush();
+        }
+    }
+
+    /// Start a background thread that attempts to reclaim the superseded versions of
+    /// this cell every `interval`, so that they are freed promptly once their readers
+    /// drop, even if no further writes occur. The thread runs until the returned
+    /// handle is dropped.
+    ///
+    /// The thread only pins the epoch for as long as each attempt takes, so it never
+    /// itself delays reclamation. While a reclaimer runs, each commit hands the
+    /// version it supersedes to the shared collector, which is slightly more
+    /// expensive than leaving it with the committing thread.
+    pub fn spawn_reclaimer(&self, interval: Duration) -> ReclaimerHandle {
+        self.retired.lock().reclaimers += 1;
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = {
+            let stop = stop.clone();
+            let retired = self.retired.clone();
+            thread::spawn(move || {
+                let (lock, cvar) = &*stop;
+                let mut stopped = lock.lock();
+                loop {
+                    cvar.wait_for(&mut stopped, interval);
+                    if *stopped {
+                        break;
+                    }
+                    MutexGuard::unlocked(&mut stopped, || {
+                        // A version is only freed once the epoch has advanced twice
+                        // after it was retired, and each attempt advances it by at
+                        // most one.
+                        for _ in 0..3 {
+                            if retired.lock().pending.is_empty() {
+                                break;
+                            }
+                            epoch::pin().flush();
+                        }
+                    });
+                }
+            })
+        };
+        ReclaimerHandle {
+            stop,
+            retired: self.retired.clone(),
+            thread: Some(thread),
+        }
+    }
+
+    /// The time since the oldest version that has not yet been reclaimed was
+    /// superseded, or `None` if every superseded version has been reclaimed.
+    /// Retained history (see `EbrCellBuilder::history`) is not included.
+    ///
+    /// A version can only be reclaimed once every reader that may observe it has
+    /// been dropped, so a growing age indicates a reader holding a snapshot for a
+    /// long time. Reclamation is also deferred until the epoch advances, so a
+    /// short age may be reported even when no readers remain.
+    pub fn oldest_pinned_age(&self) -> Option<Duration> {
+        self.retired
+            .lock()
+            .pending
+            .values()
+            .next()
+            .map(|t| t.elapsed())
+    }
+
     /// Begin a read transaction. The returned [`EbrCellReadTxn'] guarantees
     /// the data lives long enough via crossbeam's Epoch type. When this is
     /// dropped the data *may* be freed at some point in the future.
+    ///
+    /// Each read pins the epoch, which requires a full memory fence. In a tight loop
+    /// of reads this dominates the cost of the read, and `read_pinned` should be used
+    /// instead. Calling `PinnedReader::refresh` before each access still observes the
+    /// latest value, at a fraction of the cost of a new read transaction.
     pub fn read(&self) -> EbrCellReadTxn<T> {
         let guard = epoch::pin();
 
@@ -221,6 +538,80 @@ where
             data: cur,
         }
     }
+
+    /// Run `f` on a snapshot of the value, and return its result. The epoch is only
+    /// pinned while `f` runs, so the snapshot does not delay reclamation once this
+    /// returns.
+    pub fn with_read<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let rtxn = self.read();
+        f(&rtxn)
+    }
+
+    /// Begin a pinned reader. This holds a single epoch guard that is reused
+    /// for every call to `current()`, avoiding the cost of pinning the epoch for
+    /// each read. All calls to `current()` observe the same snapshot until
+    /// `refresh()` is called.
+    ///
+    /// The epoch is pinned for the whole lifetime of the `PinnedReader`, which
+    /// delays the reclamation of any data replaced after it was created. Avoid
+    /// holding this for long periods without calling `refresh()`.
+    pub fn read_pinned(&self) -> PinnedReader<'_, T> {
+        let guard = epoch::pin();
+        let data = self.active.load(Acquire, &guard).as_raw();
+        PinnedReader {
+            caller: self,
+            guard,
+            data,
+        }
+    }
+
+    /// Begin a local reader. Like `read_pinned`, this holds a single epoch guard
+    /// for all of its reads, but `LocalReader::refresh` only repins the epoch when
+    /// the value has changed, so a refresh of an unchanged cell is a single atomic
+    /// load. This is the cheapest way to observe new values in a hot loop on one
+    /// thread. As it holds an epoch guard, the reader can not be sent to another
+    /// thread.
+    ///
+    /// The reader observes exactly one snapshot between refreshes, even if writers
+    /// commit. A refresh observes every commit or rollback that happens before it,
+    /// such as one that returned earlier on the same thread, or on another thread
+    /// that has since signalled this one. A commit that is still in progress on
+    /// another thread may be missed, and is then observed by a later refresh.
+    ///
+    /// As a refresh of an unchanged cell does not repin the epoch, a reader of a
+    /// cell that is rarely written holds the epoch pinned until it is dropped. This
+    /// delays the reclamation of data replaced in this cell, and in any other
+    /// structure, after the reader last changed.
+    pub fn local_reader(&self) -> LocalReader<'_, T> {
+        let guard = epoch::pin();
+        let data = self.active.load(Acquire, &guard).as_raw();
+        LocalReader {
+            caller: self,
+            guard,
+            data,
+        }
+    }
+}
+
+impl<T> Default for EbrCell<T>
+where
+    T: Clone + Sync + Send + Default + 'static,
+{
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for EbrCell<T>
+where
+    T: Clone + Sync + Send + 'static,
+{
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
 }
 
 impl<T> Drop for EbrCell<T>
@@ -235,6 +626,10 @@ where
 
         let prev_data = self.active.load(Acquire, &guard);
         unsafe { guard.defer_destroy(prev_data) };
+        // Readers may still reference retained versions.
+        for old in self.history.get_mut().drain(..) {
+            unsafe { guard.defer_destroy(old.into_shared(&guard)) };
+        }
     }
 }
 
@@ -243,7 +638,7 @@ where
 // #[derive(Debug)]
 pub struct EbrCellReadTxn<T> {
     _guard: Guard,
-    data: *const T,
+    data: *const EbrCellInner<T>,
 }
 
 impl<T> Deref for EbrCellReadTxn<T> {
@@ -251,7 +646,87 @@ impl<T> Deref for EbrCellReadTxn<T> {
 
     /// Derference and access the value within the read transaction.
     fn deref(&self) -> &T {
-        unsafe { &(*self.data) }
+        unsafe { &(*self.data).data }
+    }
+}
+
+impl<T> Versioned for EbrCellReadTxn<T> {
+    /// The version of the value this transaction observes. This starts at zero, and
+    /// increases by one for each commit or rollback.
+    fn version(&self) -> u64 {
+        unsafe { (*self.data).version }
+    }
+}
+
+/// A reader that holds the epoch pinned across many reads. See
+/// `EbrCell::read_pinned`.
+pub struct PinnedReader<'a, T: Clone + Sync + Send + 'static> {
+    caller: &'a EbrCell<T>,
+    guard: Guard,
+    data: *const EbrCellInner<T>,
+}
+
+impl<'a, T> PinnedReader<'a, T>
+where
+    T: Clone + Sync + Send + 'static,
+{
+    /// Access the snapshot this reader currently observes. This is stable
+    /// between calls, even if writers commit.
+    #[inline]
+    pub fn current(&self) -> &T {
+        unsafe { &(*self.data).data }
+    }
+
+    /// Update this reader to observe the latest committed value. This repins
+    /// the epoch, allowing data that is no longer referenced to be reclaimed.
+    pub fn refresh(&mut self) {
+        self.guard.repin();
+        self.data = self.caller.active.load(Acquire, &self.guard).as_raw();
+    }
+}
+
+/// A reader that holds the epoch pinned across many reads, and only repins it
+/// when the value has changed. See `EbrCell::local_reader`.
+pub struct LocalReader<'a, T: Clone + Sync + Send + 'static> {
+    caller: &'a EbrCell<T>,
+    guard: Guard,
+    data: *const EbrCellInner<T>,
+}
+
+impl<'a, T> LocalReader<'a, T>
+where
+    T: Clone + Sync + Send + 'static,
+{
+    /// Access the snapshot this reader currently observes. This is stable
+    /// between calls, even if writers commit.
+    #[inline]
+    pub fn current(&self) -> &T {
+        unsafe { &(*self.data).data }
+    }
+
+    /// Update this reader to observe the latest committed value, returning `true`
+    /// if it changed. If it has not, this only loads the current value, and does
+    /// not repin the epoch.
+    #[inline]
+    pub fn refresh(&mut self) -> bool {
+        // Our snapshot is protected by the guard, so its address can not be reused
+        // by a later commit, and a matching address is always the same value.
+        if self.caller.active.load(Acquire, &self.guard).as_raw() == self.data {
+            return false;
+        }
+        self.guard.repin();
+        self.data = self.caller.active.load(Acquire, &self.guard).as_raw();
+        true
+    }
+}
+
+impl<'a, T> Versioned for LocalReader<'a, T>
+where
+    T: Clone + Sync + Send + 'static,
+{
+    /// The version of the snapshot this reader currently observes.
+    fn version(&self) -> u64 {
+        unsafe { (*self.data).version }
     }
 }
 
@@ -259,7 +734,8 @@ impl<T> Deref for EbrCellReadTxn<T> {
 mod tests {
     use std::sync::atomic::{AtomicUsize, Ordering};
 
-    use super::EbrCell;
+    use super::{EbrCell, EbrCellBuilder};
+    use crate::Versioned;
     use crossbeam_utils::thread::scope;
 
     #[test]
@@ -276,6 +752,174 @@ mod tests {
         assert_eq!(*cc_rotxn, 1);
     }
 
+    #[test]
+    fn test_discard() {
+        let cc = EbrCell::new(0);
+        let version = cc.read().version();
+
+        let mut cc_wrtxn = cc.write();
+        *cc_wrtxn = 1;
+        drop(cc_wrtxn);
+        assert!(*cc.read() == 0 && cc.read().version() == version);
+
+        let mut cc_wrtxn = cc.write();
+        *cc_wrtxn = 2;
+        cc_wrtxn.discard();
+        assert!(*cc.read() == 0 && cc.read().version() == version);
+    }
+
+    #[test]
+    fn test_default_from() {
+        let cc: EbrCell<i64> = EbrCell::default();
+        assert_eq!(*cc.read(), 0);
+        let cc = EbrCell::from(5);
+        assert_eq!(*cc.read(), 5);
+        let cc: EbrCell<String> = String::from("cell").into();
+        assert_eq!(cc.read().as_str(), "cell");
+    }
+
+    #[test]
+    fn test_oldest_pinned_age() {
+        use std::thread;
+        use std::time::Duration;
+
+        let cc = EbrCell::new(0);
+        let rd = cc.read();
+        {
+            let mut wr = cc.write();
+            *wr = 1;
+            wr.commit();
+        }
+        // The reader pins the superseded version, so the age grows while it is held.
+        let first = cc.oldest_pinned_age().expect("No pinned version");
+        thread::sleep(Duration::from_millis(20));
+        let second = cc.oldest_pinned_age().expect("No pinned version");
+        assert!(second >= first + Duration::from_millis(20));
+        assert_eq!(*rd, 0);
+
+        // Once the reader drops, the version is reclaimed as the epoch advances.
+        drop(rd);
+        let mut reclaimed = false;
+        for _ in 0..10_000 {
+            crossbeam_epoch::pin().flush();
+            if cc.oldest_pinned_age().is_none() {
+                reclaimed = true;
+                break;
+            }
+            thread::yield_now();
+        }
+        assert!(reclaimed);
+    }
+
+    #[test]
+    fn test_commit_if_changed() {
+        let cc = EbrCell::new(1);
+        let retired = |cc: &EbrCell<i32>| cc.retired.lock().next;
+        assert!(!cc.write().commit_if_changed());
+        let mut wr = cc.write();
+        *wr = 1;
+        assert!(!wr.commit_if_changed());
+        assert!(cc.read().version() == 0);
+        assert!(retired(&cc) == 0);
+
+        let mut wr = cc.write();
+        *wr = 2;
+        assert!(wr.commit_if_changed());
+        assert!(cc.read().version() == 1);
+        assert!(*cc.read() == 2);
+        assert!(retired(&cc) == 1);
+    }
+
+    #[test]
+    fn test_write_batch() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        let marker = Arc::new(());
+        let cc = EbrCell::new((0, marker.clone()));
+        let done = AtomicBool::new(false);
+        scope(|scope| {
+            let reader = scope.spawn(|_| {
+                let mut seen = Vec::new();
+                while !done.load(Ordering::Acquire) {
+                    seen.push(cc.read().0);
+                }
+                seen.push(cc.read().0);
+                seen
+            });
+            let mut batch = cc.write_batch(100);
+            for i in 1..=100 {
+                batch.stage((i, marker.clone()));
+            }
+            assert!(batch.len() == 100 && batch.last().map(|v| v.0) == Some(100));
+            batch.commit();
+            done.store(true, Ordering::Release);
+            let seen = reader.join().unwrap();
+            assert!(seen.iter().all(|v| *v == 0 || *v == 100));
+            assert!(seen.last() == Some(&100));
+        })
+        .unwrap();
+
+        // Only a single version was committed, and the intermediates were dropped.
+        assert!(cc.read().version() == 1);
+        assert!(Arc::strong_count(&marker) <= 3);
+        let rd = cc.read();
+        assert!(rd.0 == 100);
+        drop(rd);
+
+        // An empty batch commits nothing.
+        cc.write_batch(0).commit();
+        assert!(cc.read().version() == 1);
+    }
+
+    #[test]
+    fn test_spawn_reclaimer() {
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let interval = Duration::from_millis(20);
+        let cc = EbrCell::new(0);
+        let reclaimer = cc.spawn_reclaimer(interval);
+        let rd = cc.read();
+        {
+            let mut wr = cc.write();
+            *wr = 1;
+            wr.commit();
+        }
+        // The reader holds the superseded version across several attempts.
+        thread::sleep(interval * 3);
+        assert!(cc.oldest_pinned_age().is_some());
+        assert_eq!(*rd, 0);
+
+        // Once it drops, the reclaimer frees the version without further writes. Other
+        // tests may briefly hold the epoch, so allow a generous bound.
+        drop(rd);
+        let start = Instant::now();
+        while cc.oldest_pinned_age().is_some() {
+            assert!(start.elapsed() < interval * 100);
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(reclaimer);
+        assert!(cc.retired.lock().reclaimers == 0);
+    }
+
+    #[test]
+    fn test_with_read() {
+        let cc = EbrCell::new(0);
+        let seen = cc.with_read(|v| {
+            assert!(crossbeam_epoch::is_pinned());
+            // A commit during the closure does not change the snapshot.
+            let mut wr = cc.write();
+            *wr = 1;
+            wr.commit();
+            *v
+        });
+        assert!(seen == 0);
+        // The guard was dropped on return.
+        assert!(!crossbeam_epoch::is_pinned());
+        assert!(cc.with_read(|v| *v) == 1);
+    }
+
     #[test]
     fn test_try_write() {
         let data: i64 = 0;
@@ -288,6 +932,179 @@ mod tests {
         assert!(cc_wrtxn_a.is_none());
     }
 
+    #[test]
+    fn test_read_pinned() {
+        let data: i64 = 0;
+        let cc = EbrCell::new(data);
+
+        let mut pinned = cc.read_pinned();
+        assert_eq!(*pinned.current(), 0);
+        {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = 1;
+            cc_wrtxn.commit();
+        }
+        // The pinned reader still observes the same snapshot.
+        assert_eq!(*pinned.current(), 0);
+        assert_eq!(*pinned.current(), 0);
+        assert_eq!(*cc.read(), 1);
+
+        pinned.refresh();
+        assert_eq!(*pinned.current(), 1);
+    }
+
+    #[test]
+    fn test_local_reader() {
+        let cc = EbrCellBuilder::new().history(1).build(0);
+
+        let mut local = cc.local_reader();
+        assert_eq!(*local.current(), 0);
+        assert!(!local.refresh());
+        {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = 1;
+            cc_wrtxn.commit();
+        }
+        // The reader observes the same snapshot until it is refreshed.
+        assert_eq!(*local.current(), 0);
+        assert_eq!(local.version(), 0);
+        assert!(local.refresh());
+        assert_eq!(*local.current(), 1);
+        assert_eq!(local.version(), 1);
+        assert!(!local.refresh());
+
+        // A rollback is a change too, even though the value was seen before.
+        assert!(cc.rollback());
+        assert!(local.refresh());
+        assert_eq!(*local.current(), 0);
+        assert_eq!(local.version(), 2);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let cc = EbrCellBuilder::new().history(3).build(0);
+        // Nothing to roll back to yet.
+        assert!(!cc.rollback());
+        for i in 1..=4 {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = i;
+            cc_wrtxn.commit();
+        }
+        let cc_rotxn = cc.read();
+        assert_eq!(*cc_rotxn, 4);
+
+        assert!(cc.rollback());
+        assert_eq!(*cc.read(), 3);
+        assert!(cc.rollback());
+        assert_eq!(*cc.read(), 2);
+        // Existing readers are unaffected.
+        assert_eq!(*cc_rotxn, 4);
+
+        // A new commit after rollback is retained too.
+        {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = 5;
+            cc_wrtxn.commit();
+        }
+        assert!(cc.rollback());
+        assert_eq!(*cc.read(), 2);
+        // Only 3 versions were retained, so 0 fell out of the window.
+        assert!(cc.rollback());
+        assert_eq!(*cc.read(), 1);
+        assert!(!cc.rollback());
+        assert_eq!(*cc.read(), 1);
+
+        let cc = EbrCell::new(0);
+        {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = 1;
+            cc_wrtxn.commit();
+        }
+        assert!(!cc.rollback());
+        assert_eq!(*cc.read(), 1);
+    }
+
+    #[test]
+    fn test_write_panic() {
+        let cc = EbrCell::new(0);
+        scope(|scope| {
+            let r = scope
+                .spawn(|_| {
+                    let mut cc_wrtxn = cc.write();
+                    *cc_wrtxn = 1;
+                    panic!("Panic during a write");
+                })
+                .join();
+            assert!(r.is_err());
+        })
+        .unwrap();
+        // The uncommitted change was discarded, and the lock was released.
+        assert_eq!(*cc.read(), 0);
+        let mut cc_wrtxn = cc.try_write().expect("The write lock was not released");
+        assert_eq!(*cc_wrtxn, 0);
+        *cc_wrtxn = 2;
+        cc_wrtxn.commit();
+        assert_eq!(*cc.read(), 2);
+    }
+
+    #[test]
+    fn test_version_exhausted() {
+        use super::EbrCellInner;
+        use crossbeam_epoch as epoch;
+        use std::sync::atomic::Ordering::Acquire;
+
+        let cc = EbrCellBuilder::new().history(1).build(0);
+        {
+            // Nothing else can observe the cell yet, so its version may be set.
+            let guard = epoch::pin();
+            let cur = cc.active.load(Acquire, &guard).as_raw() as *mut EbrCellInner<i32>;
+            unsafe { (*cur).version = u64::MAX - 1 };
+        }
+        let mut local = cc.local_reader();
+        {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = 1;
+            cc_wrtxn.commit();
+        }
+        assert!(cc.read().version() == u64::MAX);
+        assert!(local.refresh() && local.version() == u64::MAX);
+
+        // Neither a commit nor a rollback may repeat an earlier version.
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = 2;
+            cc_wrtxn.commit();
+        }));
+        assert!(r.is_err());
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cc.rollback()));
+        assert!(r.is_err());
+        assert!(*cc.read() == 1 && cc.read().version() == u64::MAX);
+        assert!(!local.refresh());
+        // The retained version is still available.
+        assert!(cc.history.lock().len() == 1);
+    }
+
+    #[test]
+    fn test_version() {
+        let cc = EbrCellBuilder::new().history(1).build(0);
+        let other = EbrCell::new(0);
+        let cc_rotxn = cc.read();
+        assert!(cc_rotxn.version() == 0);
+        {
+            let mut cc_wrtxn = cc.write();
+            *cc_wrtxn = 1;
+            cc_wrtxn.commit();
+        }
+        assert!(cc.read().version() == 1);
+        // Restoring an older value is still a new version.
+        assert!(cc.rollback());
+        assert_eq!(*cc.read(), 0);
+        assert!(cc.read().version() == 2);
+        // Existing readers, and other cells, are unaffected.
+        assert!(cc_rotxn.version() == 0);
+        assert!(other.read().version() == 0);
+    }
+
     #[test]
     fn test_simple_create() {
         let data: i64 = 0;
@@ -422,6 +1239,22 @@ mod tests {
 
         assert!(GC_COUNT.load(Ordering::Acquire) >= 50);
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_reader_writer_fairness() {
+        let cc = EbrCell::new(0usize);
+        crate::utils::assert_no_starvation(
+            || cc.read(),
+            || cc.write(),
+            || {
+                let mut wrtxn = cc.write();
+                *wrtxn += 1;
+                wrtxn.commit();
+            },
+        );
+        assert!(*cc.read() == 200);
+    }
 }
 
 #[cfg(test)]
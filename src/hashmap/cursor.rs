@@ -9,9 +9,10 @@ use std::borrow::Borrow;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::mem;
+use std::ptr;
 use std::sync::Arc;
 
-use super::iter::{Iter, KeyIter, ValueIter};
+use super::iter::{IntoIter, Iter, KeyIter, ValueIter, ValueIterMut};
 use super::states::*;
 use parking_lot::Mutex;
 // use std::iter::Extend;
@@ -26,6 +27,8 @@ where
     root: *mut Node<K, V>,
     size: usize,
     txid: u64,
+    /// The keys of the hasher that the nodes of this tree are placed by.
+    keys: (u128, u128),
     /// Last seen has a mutex to allow descendant transactions to push back
     /// last_seen into older transactions. Because this is arced, we know
     ///  it will exist, and the mutex is ONLY locked/dropped when the
@@ -37,6 +40,44 @@ where
 }
 
 impl<K: Hash + Eq + Clone + Debug, V: Clone> SuperBlock<K, V> {
+    pub(crate) fn new(keys: (u128, u128)) -> Self {
+        let leaf: *mut Leaf<K, V> = Node::new_leaf(1);
+        SuperBlock {
+            root: leaf as *mut Node<K, V>,
+            size: 0,
+            txid: 1,
+            keys,
+            last_seen: Mutex::new(None),
+            pin_next: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> (u128, u128) {
+        self.keys
+    }
+
+    // Consume the tree of this superblock. If nothing else refers to it, the nodes are
+    // ours to move the entries out of, and otherwise every entry is cloned.
+    pub(crate) fn consume(this: Arc<Self>) -> IntoIter<K, V> {
+        match Arc::try_unwrap(this) {
+            Ok(mut sb) => {
+                let iter = IntoIter::new(sb.root, sb.size, None);
+                // The leaves now belong to the iterator, and the branches are no
+                // longer needed.
+                let mut nodes = vec![sb.root];
+                unsafe { (*sb.root).sblock_collect(&mut nodes) };
+                nodes
+                    .into_iter()
+                    .filter(|n| unsafe { (**n).meta.is_branch() })
+                    .for_each(Node::free);
+                // Nothing is left for the superblock to free.
+                *sb.last_seen.get_mut() = Some(Vec::new());
+                iter
+            }
+            Err(this) => IntoIter::new(this.root, this.size, Some(this)),
+        }
+    }
+
     pub(crate) fn commit_prep(&self, older: &Self) {
         // println!("commit_prep {:?} -> {:?}", self.txid, older.txid);
         let mut active_last_seen = older.last_seen.lock();
@@ -55,14 +96,7 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> SuperBlock<K, V> {
 
 impl<K: Hash + Eq + Clone + Debug, V: Clone> Default for SuperBlock<K, V> {
     fn default() -> Self {
-        let leaf: *mut Leaf<K, V> = Node::new_leaf(1);
-        SuperBlock {
-            root: leaf as *mut Node<K, V>,
-            size: 0,
-            txid: 1,
-            last_seen: Mutex::new(None),
-            pin_next: Mutex::new(None),
-        }
+        Self::new((0, 0))
     }
 }
 
@@ -87,6 +121,7 @@ where
     txid: u64,
     length: usize,
     root: *mut Node<K, V>,
+    keys: (u128, u128),
     last_seen: Vec<*mut Node<K, V>>,
     first_seen: Vec<*mut Node<K, V>>,
 }
@@ -133,6 +168,48 @@ pub(crate) trait CursorReadOps<K: Clone + Hash + Eq + Debug, V: Clone> {
         panic!("Tree depth exceeded max limit (65536). This may indicate memory corruption.");
     }
 
+    fn search_kv<'a, 'b, Q: ?Sized>(&'a self, h: u64, k: &'b Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let mut node = self.get_root();
+        for _i in 0..65536 {
+            if unsafe { (*node).is_leaf() } {
+                let lref = leaf_ref!(node, K, V);
+                // As in search, these nodes will not be altered during the lifetime of
+                // this txn, so the references may be rebound to 'a.
+                return lref
+                    .get_kv_ref(h, k)
+                    .map(|(k, v)| unsafe { (&*(k as *const K), &*(v as *const V)) });
+            } else {
+                let bref = branch_ref!(node, K, V);
+                let idx = bref.locate_node(h);
+                node = bref.get_idx_unchecked(idx);
+            }
+        }
+        panic!("Tree depth exceeded max limit (65536). This may indicate memory corruption.");
+    }
+
+    fn search_slot(&self, h: u64) -> Option<&[Datum<K, V>]> {
+        let mut node = self.get_root();
+        for _i in 0..65536 {
+            if unsafe { (*node).is_leaf() } {
+                let lref = leaf_ref!(node, K, V);
+                // As in search, these nodes will not be altered during the lifetime of
+                // this txn, so the reference may be rebound to self.
+                return lref
+                    .get_slot_ref(h)
+                    .map(|slot| unsafe { &*(slot as *const [Datum<K, V>]) });
+            } else {
+                let bref = branch_ref!(node, K, V);
+                let idx = bref.locate_node(h);
+                node = bref.get_idx_unchecked(idx);
+            }
+        }
+        panic!("Tree depth exceeded max limit (65536). This may indicate memory corruption.");
+    }
+
     #[allow(clippy::needless_lifetimes)]
     fn contains_key<'a, 'b, Q: ?Sized>(&'a self, h: u64, k: &'b Q) -> bool
     where
@@ -179,6 +256,7 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorWrite<K, V> {
             txid,
             length,
             root,
+            keys: sblock.keys,
             last_seen,
             first_seen,
         }
@@ -209,6 +287,7 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorWrite<K, V> {
             txid,
             length,
             root,
+            keys: (0, 0),
             last_seen,
             first_seen,
         }
@@ -234,11 +313,18 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorWrite<K, V> {
             root: self.root,
             size: self.length,
             txid: self.txid,
+            keys: self.keys,
             last_seen: Mutex::new(Some(dummy)),
             pin_next: Mutex::new(None),
         }
     }
 
+    // Set the keys of the hasher that this tree is committed with. The caller must
+    // have placed every node by these keys.
+    pub(crate) fn set_keys(&mut self, keys: (u128, u128)) {
+        self.keys = keys;
+    }
+
     pub(crate) fn clear(&mut self) {
         // Reset the values in this tree.
         // We need to mark everything as disposable, and create a new root!
@@ -252,12 +338,20 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorWrite<K, V> {
 
     // Functions as insert_or_update
     pub(crate) fn insert(&mut self, h: u64, k: K, v: V) -> Option<V> {
+        self.insert_ref(h, k, v).0
+    }
+
+    // As insert, also returning a reference to the value where it was placed, so that
+    // it can be used without searching for the key again.
+    pub(crate) fn insert_ref(&mut self, h: u64, k: K, v: V) -> (Option<V>, &mut V) {
+        let mut slot = ptr::null_mut();
         let r = match clone_and_insert(
             self.root,
             self.txid,
             h,
             k,
             v,
+            &mut slot,
             &mut self.last_seen,
             &mut self.first_seen,
         ) {
@@ -319,7 +413,7 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorWrite<K, V> {
         if r.is_none() {
             self.length += 1;
         }
-        r
+        (r, unsafe { &mut *slot })
     }
 
     pub(crate) fn remove(&mut self, h: u64, k: &K) -> Option<V> {
@@ -389,7 +483,11 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorWrite<K, V> {
         };
     }
 
-    pub(crate) fn get_mut_ref(&mut self, h: u64, k: &K) -> Option<&mut V> {
+    pub(crate) fn get_mut_ref<Q: ?Sized>(&mut self, h: u64, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq,
+    {
         match path_clone(
             self.root,
             self.txid,
@@ -407,6 +505,12 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorWrite<K, V> {
         path_get_mut_ref(self.root, h, k)
     }
 
+    pub(crate) fn v_iter_mut(&mut self) -> ValueIterMut<K, V> {
+        let root = self.root;
+        let length = self.length;
+        ValueIterMut::new(self, root, length)
+    }
+
     pub(crate) unsafe fn get_slot_mut_ref(&mut self, h: u64) -> Option<&mut [Datum<K, V>]> {
         match path_clone(
             self.root,
@@ -539,12 +643,14 @@ impl<K: Clone + Hash + Eq + Debug, V: Clone> CursorReadOps<K, V> for CursorWrite
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn clone_and_insert<K: Clone + Hash + Eq + Debug, V: Clone>(
     node: *mut Node<K, V>,
     txid: u64,
     h: u64,
     k: K,
     v: V,
+    slot: &mut *mut V,
     last_seen: &mut Vec<*mut Node<K, V>>,
     first_seen: &mut Vec<*mut Node<K, V>>,
 ) -> CRInsertState<K, V> {
@@ -578,7 +684,7 @@ fn clone_and_insert<K: Clone + Hash + Eq + Debug, V: Clone>(
                 // Clone was required.
                 let mref = leaf_ref!(cnode, K, V);
                 // insert to the new node.
-                match mref.insert_or_update(h, k, v) {
+                match mref.insert_or_update_slot(h, k, v, slot) {
                     LeafInsertState::Ok(res) => CRInsertState::Clone(res, cnode),
                     LeafInsertState::Split(rnode) => {
                         first_seen.push(rnode as *mut Node<K, V>);
@@ -595,7 +701,7 @@ fn clone_and_insert<K: Clone + Hash + Eq + Debug, V: Clone>(
                 // No clone required.
                 // simply do the insert.
                 let mref = leaf_ref!(node, K, V);
-                match mref.insert_or_update(h, k, v) {
+                match mref.insert_or_update_slot(h, k, v, slot) {
                     LeafInsertState::Ok(res) => CRInsertState::NoClone(res),
                     LeafInsertState::Split(rnode) => {
                         // We split, but left is already part of the txn group, so lets
@@ -632,7 +738,7 @@ fn clone_and_insert<K: Clone + Hash + Eq + Debug, V: Clone>(
                 let anode_idx = nmref.locate_node(h);
                 let anode = nmref.get_idx_unchecked(anode_idx);
 
-                match clone_and_insert(anode, txid, h, k, v, last_seen, first_seen) {
+                match clone_and_insert(anode, txid, h, k, v, slot, last_seen, first_seen) {
                     CRInsertState::Clone(res, lnode) => {
                         nmref.replace_by_idx(anode_idx, lnode);
                         // Pass back up that we cloned.
@@ -687,7 +793,7 @@ fn clone_and_insert<K: Clone + Hash + Eq + Debug, V: Clone>(
                 let anode_idx = nmref.locate_node(h);
                 let anode = nmref.get_idx_unchecked(anode_idx);
 
-                match clone_and_insert(anode, txid, h, k, v, last_seen, first_seen) {
+                match clone_and_insert(anode, txid, h, k, v, slot, last_seen, first_seen) {
                     CRInsertState::Clone(res, lnode) => {
                         nmref.replace_by_idx(anode_idx, lnode);
                         // We did not clone, and no further work needed.
@@ -980,13 +1086,14 @@ fn clone_and_remove<K: Clone + Hash + Eq + Debug, V: Clone>(
     }
 }
 
-fn path_get_mut_ref<'a, K: Clone + Hash + Eq + Debug, V: Clone>(
+fn path_get_mut_ref<'a, K: Clone + Hash + Eq + Debug, V: Clone, Q: ?Sized>(
     node: *mut Node<K, V>,
     h: u64,
-    k: &K,
+    k: &Q,
 ) -> Option<&'a mut V>
 where
-    K: 'a,
+    K: 'a + Borrow<Q>,
+    Q: Eq,
 {
     if self_meta!(node).is_leaf() {
         leaf_ref!(node, K, V).get_mut_ref(h, k)
@@ -1,11 +1,14 @@
 //! Iterators for the map.
 
 // Iterators for the bptree
-use super::node::{Branch, Leaf, Meta, Node};
+use super::cursor::{CursorWrite, SuperBlock};
+use super::node::{Branch, Datum, Leaf, Meta, Node};
 use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
+use std::slice::IterMut;
+use std::sync::Arc;
 
 pub(crate) struct LeafIter<'a, K, V>
 where
@@ -247,6 +250,143 @@ impl<'a, K: Clone + Hash + Eq + Debug, V: Clone> Iterator for ValueIter<'a, K, V
     }
 }
 
+/// Iterator over mutable references to Values stored in the map. Each bucket is
+/// cloned into the write transaction as the iterator reaches it, so only the
+/// parts of the tree that are visited are copied.
+pub struct ValueIterMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug,
+    V: Clone,
+{
+    length: usize,
+    work: *mut CursorWrite<K, V>,
+    hashes: std::vec::IntoIter<u64>,
+    slot: Option<IterMut<'a, Datum<K, V>>>,
+    phantom_v: PhantomData<&'a mut V>,
+}
+
+impl<'a, K: Clone + Hash + Eq + Debug, V: Clone> ValueIterMut<'a, K, V> {
+    pub(crate) fn new(
+        work: &'a mut CursorWrite<K, V>,
+        root: *mut Node<K, V>,
+        length: usize,
+    ) -> Self {
+        // Collect the hashes up front from the current tree. As we clone the
+        // path to each slot, the tree is altered, so we can't hold a leaf iter.
+        let hashes: Vec<u64> = LeafIter::new(root, false)
+            .flat_map(|leaf| leaf.slot_hashes().iter().cloned())
+            .collect();
+        ValueIterMut {
+            length,
+            work: work as *mut _,
+            hashes: hashes.into_iter(),
+            slot: None,
+            phantom_v: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: Clone + Hash + Eq + Debug, V: Clone> Iterator for ValueIterMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    /// Yield the next mutable value reference, or `None` if exhausted.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(d) = self.slot.as_mut().and_then(|s| s.next()) {
+                self.length -= 1;
+                return Some(&mut d.v);
+            }
+            let h = self.hashes.next()?;
+            // Once a leaf has been cloned into this txn it is not moved again by
+            // later path clones, so the references we have already yielded remain
+            // valid. The exclusive borrow of the cursor is held by our lifetime.
+            self.slot = unsafe { (*self.work).get_slot_mut_ref(h) }.map(|s| s.iter_mut());
+        }
+    }
+
+    /// Provide a hint as to the number of items this iterator will yield.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length, Some(self.length))
+    }
+}
+
+/// Consumes a map, yielding its entries in no particular order. See
+/// `HashMap::into_iter`.
+pub struct IntoIter<K, V>
+where
+    K: Hash + Eq + Clone + Debug,
+    V: Clone,
+{
+    length: usize,
+    leaves: std::vec::IntoIter<*mut Leaf<K, V>>,
+    entries: std::vec::IntoIter<(K, V)>,
+    // If the tree is shared, this keeps it alive until we are done with it, and the
+    // entries are cloned. Otherwise the leaves are ours alone, so their entries are
+    // moved out, and they are freed as we go.
+    pin: Option<Arc<SuperBlock<K, V>>>,
+}
+
+unsafe impl<K: Hash + Eq + Clone + Debug + Send + Sync, V: Clone + Send + Sync> Send
+    for IntoIter<K, V>
+{
+}
+
+impl<K: Clone + Hash + Eq + Debug, V: Clone> IntoIter<K, V> {
+    pub(crate) fn new(
+        root: *mut Node<K, V>,
+        length: usize,
+        pin: Option<Arc<SuperBlock<K, V>>>,
+    ) -> Self {
+        let leaves: Vec<_> = LeafIter::new(root, false)
+            .map(|l| l as *const Leaf<K, V> as *mut Leaf<K, V>)
+            .collect();
+        IntoIter {
+            length,
+            leaves: leaves.into_iter(),
+            entries: Vec::new().into_iter(),
+            pin,
+        }
+    }
+}
+
+impl<K: Clone + Hash + Eq + Debug, V: Clone> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.entries.next() {
+                self.length -= 1;
+                return Some(e);
+            }
+            let leaf = self.leaves.next()?;
+            let mut entries = Vec::new();
+            if self.pin.is_none() {
+                Leaf::take_all(leaf, &mut entries);
+            } else {
+                unsafe { (*leaf).clone_all(&mut entries) };
+            }
+            self.entries = entries.into_iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length, Some(self.length))
+    }
+}
+
+impl<K: Clone + Hash + Eq + Debug, V: Clone> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K: Clone + Hash + Eq + Debug, V: Clone> Drop for IntoIter<K, V> {
+    fn drop(&mut self) {
+        // The leaves of ours that were not reached are freed with their entries.
+        if self.pin.is_none() {
+            for leaf in self.leaves.by_ref() {
+                Node::free(leaf as *mut Node<K, V>);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::cursor::CursorWrite;
@@ -10,16 +10,25 @@ use super::cursor::CursorReadOps;
 use super::cursor::{CursorRead, CursorWrite, SuperBlock};
 use super::iter::*;
 use super::node::Datum;
+use crate::{CommitSummary, Versioned};
 use parking_lot::{Mutex, MutexGuard};
 use rand::Rng;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
+use std::ops::AddAssign;
 use std::sync::Arc;
 
 // #[cfg(feature = "simd_support")] use packed_simd::*;
 // #[cfg(feature = "simd_support")]
 
+fn random_keys() -> (u128, u128) {
+    (
+        rand::thread_rng().gen::<u128>(),
+        rand::thread_rng().gen::<u128>(),
+    )
+}
+
 macro_rules! hash_key {
     ($k:expr, $key1:expr, $key2:expr) => {{
         // let mut hasher = DefaultHasher::new();
@@ -45,6 +54,16 @@ macro_rules! hash_key {
 /// later. This may cause memory usage to increase in exchange for a gain
 /// in concurrent behaviour.
 ///
+/// Cloning a `HashMap` creates another handle to the same map, in the manner of an
+/// `Arc`. Commits through either handle are visible to readers of both, and the
+/// content of the map is not copied.
+///
+/// The map does not use open addressing. A remove takes the key out of its bucket
+/// immediately, and a leaf that becomes empty is removed from the tree, so no
+/// tombstones are left behind, and churn of removes and inserts does not degrade
+/// lookups. Only keys that share a hash are compared in turn, as reported by
+/// `HashMapReadTxn::probe_length`.
+///
 /// Transactions can be rolled-back (aborted) without penalty by dropping
 /// the `HashMapWriteTxn` without calling `commit()`.
 pub struct HashMap<K, V>
@@ -52,10 +71,9 @@ where
     K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
     V: Clone + Sync + Send + 'static,
 {
-    write: Mutex<()>,
-    active: Mutex<Arc<SuperBlock<K, V>>>,
-    key1: u128,
-    key2: u128,
+    write: Arc<Mutex<()>>,
+    active: Arc<Mutex<Arc<SuperBlock<K, V>>>>,
+    net_summary: bool,
 }
 
 unsafe impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
@@ -85,8 +103,12 @@ where
 /// An active write transaction for a `HashMap`. The data in this tree
 /// may be modified exclusively through this transaction without affecting
 /// readers. The write may be rolledback/aborted by dropping this guard
-/// without calling `commit()`. Once `commit()` is called, readers will be
-/// able to access and percieve changes in new transactions.
+/// without calling `commit()`, or by calling `discard()`. Once `commit()` is
+/// called, readers will be able to access and percieve changes in new
+/// transactions.
+///
+/// A panic while the transaction is held discards it without poisoning the lock, see
+/// [the crate documentation](crate#panics-in-write-transactions).
 pub struct HashMapWriteTxn<'a, K, V>
 where
     K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
@@ -97,6 +119,72 @@ where
     _guard: MutexGuard<'a, ()>,
     key1: u128,
     key2: u128,
+    changes: Changes<K>,
+}
+
+// The changes that a write transaction has made, for the `CommitSummary` of its commit.
+// Each operation is counted from its result as it is made. A map built with
+// `net_commit_summary` also keeps each key that the transaction changes, with whether
+// it was present before the first change, and the summary is found from these instead.
+struct Changes<K> {
+    counts: CommitSummary,
+    keys: Option<std::collections::HashMap<K, bool>>,
+}
+
+impl<K: Hash + Eq + Clone> Changes<K> {
+    fn new(net: bool) -> Self {
+        Changes {
+            counts: CommitSummary::default(),
+            keys: if net {
+                Some(std::collections::HashMap::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    // Keep k, which was `present` before the change, if the keys are kept at all.
+    fn track(&mut self, k: &K, present: bool) {
+        if let Some(keys) = self.keys.as_mut() {
+            if !keys.contains_key(k) {
+                keys.insert(k.clone(), present);
+            }
+        }
+    }
+
+    // For an insert that consumes its key, the copy of the key to give to `inserted`
+    // once the insert is made, if the key is kept and has not already been changed.
+    fn unseen(&self, k: &K) -> Option<K> {
+        match self.keys.as_ref() {
+            Some(keys) if !keys.contains_key(k) => Some(k.clone()),
+            _ => None,
+        }
+    }
+
+    fn inserted(&mut self, unseen: Option<K>, replaced: bool) {
+        self.counts.record_insert(replaced);
+        if let (Some(keys), Some(k)) = (self.keys.as_mut(), unseen) {
+            keys.insert(k, replaced);
+        }
+    }
+
+    // A write of k, which was `present` before it, as an insert or an update.
+    fn wrote(&mut self, k: &K, present: bool) {
+        self.counts.record_insert(present);
+        self.track(k, present);
+    }
+
+    fn removed(&mut self, k: &K) {
+        self.counts.record_removed(1);
+        self.track(k, true);
+    }
+
+    fn summary<F: Fn(&K) -> bool>(&self, present: F) -> CommitSummary {
+        match self.keys.as_ref() {
+            Some(keys) => CommitSummary::from_changes(keys.iter(), present),
+            None => self.counts,
+        }
+    }
 }
 
 enum SnapshotType<'a, K, V>
@@ -126,6 +214,102 @@ where
     key2: u128,
 }
 
+/// A builder for configuring a `HashMap`.
+///
+/// There are no initial capacity or growth factor settings, as unlike a table based
+/// hash map this map never rehashes. It is a tree of hash buckets that grows a node
+/// at a time as keys are added, so existing entries are never moved or reallocated
+/// as the map grows, and an empty map holds only a single node.
+///
+/// # Examples
+/// ```
+/// use concread::hashmap::{HashMap, HashMapBuilder};
+///
+/// let a: HashMap<usize, usize> = HashMapBuilder::new().with_seed(7).build();
+/// let b: HashMap<usize, usize> = HashMapBuilder::new().with_seed(7).build();
+/// assert_eq!(a.prehash(&1), b.prehash(&1));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct HashMapBuilder {
+    seed: Option<u64>,
+    net_summary: bool,
+}
+
+impl HashMapBuilder {
+    /// Create a new builder. By default the hasher of each map is randomly keyed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Key the hasher from `seed` rather than randomly. Maps built with the same
+    /// seed hash keys identically, so the same sequence of inserts yields the same
+    /// iteration order on every run. This is intended for reproducible tests and
+    /// debugging.
+    ///
+    /// Random keys are what protect the map from hash flooding, where an attacker
+    /// who can choose keys forces them to collide and degrades every operation on
+    /// the map. A fixed seed removes this protection, so should not be used for maps
+    /// holding untrusted keys.
+    pub fn with_seed(self, seed: u64) -> Self {
+        HashMapBuilder {
+            seed: Some(seed),
+            ..self
+        }
+    }
+
+    /// Report the net change of each key in the `CommitSummary` of each write
+    /// transaction, rather than a count of each operation. See `CommitSummary` for the
+    /// difference. To find the net changes, a transaction keeps a copy of each key
+    /// that it changes until it commits, so this makes each write more expensive.
+    pub fn net_commit_summary(self) -> Self {
+        HashMapBuilder {
+            net_summary: true,
+            ..self
+        }
+    }
+
+    /// Build the `HashMap`.
+    pub fn build<K, V>(self) -> HashMap<K, V>
+    where
+        K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+        V: Clone + Sync + Send + 'static,
+    {
+        let keys = match self.seed {
+            Some(seed) => {
+                // Expand the seed with splitmix64, which unlike the rand generators is
+                // guaranteed to be stable.
+                let mut state = seed;
+                let mut next = || {
+                    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut z = state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    (z ^ (z >> 31)) as u128
+                };
+                ((next() << 64) | next(), (next() << 64) | next())
+            }
+            None => random_keys(),
+        };
+        HashMap {
+            write: Arc::new(Mutex::new(())),
+            active: Arc::new(Mutex::new(Arc::new(SuperBlock::new(keys)))),
+            net_summary: self.net_summary,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Clone
+    for HashMap<K, V>
+{
+    fn clone(&self) -> Self {
+        HashMap {
+            write: self.write.clone(),
+            active: self.active.clone(),
+            net_summary: self.net_summary,
+        }
+    }
+}
+
 impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static> Default
     for HashMap<K, V>
 {
@@ -139,26 +323,45 @@ impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Sen
 {
     /// Construct a new concurrent hashmap
     pub fn new() -> Self {
+        HashMapBuilder::new().build()
+    }
+
+    /// Construct a new, empty map that hashes keys identically to this map. A hash
+    /// computed by `prehash` on either map may then be used with `get_precomputed`
+    /// on both, until either map is reseeded with `HashMapWriteTxn::reseed`.
+    pub fn new_sharing_hasher(&self) -> Self {
+        let keys = self.active.lock().keys();
         HashMap {
-            write: Mutex::new(()),
-            active: Mutex::new(Arc::new(SuperBlock::default())),
-            key1: rand::thread_rng().gen::<u128>(),
-            key2: rand::thread_rng().gen::<u128>(),
+            write: Arc::new(Mutex::new(())),
+            active: Arc::new(Mutex::new(Arc::new(SuperBlock::new(keys)))),
+            net_summary: self.net_summary,
         }
     }
 
+    /// Compute the hash of a key as this map would, so that it may be reused
+    /// with `get_precomputed` rather than hashing an expensive key on every lookup.
+    pub fn prehash<Q: ?Sized>(&self, k: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let (key1, key2) = self.active.lock().keys();
+        hash_key!(k, key1, key2)
+    }
+
     /// Initiate a read transaction for the Hashmap, concurrent to any
     /// other readers or writers.
     pub fn read(&self) -> HashMapReadTxn<K, V> {
         let rguard = self.active.lock();
         let pin = rguard.clone();
         let work = CursorRead::new(pin.as_ref());
+        let (key1, key2) = pin.keys();
         HashMapReadTxn {
             _caller: self,
             _pin: pin,
             work,
-            key1: self.key1,
-            key2: self.key2,
+            key1,
+            key2,
         }
     }
 
@@ -178,13 +381,15 @@ impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Sen
         let sblock: &SuperBlock<K, V> = rguard.as_ref();
         /* Setup the cursor that will work on the tree */
         let cursor = CursorWrite::new(sblock);
+        let (key1, key2) = sblock.keys();
         /* Now build the write struct */
         HashMapWriteTxn {
             work: cursor,
             caller: self,
             _guard: mguard,
-            key1: self.key1,
-            key2: self.key2,
+            key1,
+            key2,
+            changes: Changes::new(self.net_summary),
         }
         /* rguard dropped here */
     }
@@ -196,17 +401,19 @@ impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Sen
             let rguard = self.active.lock();
             let sblock: &SuperBlock<K, V> = rguard.as_ref();
             let cursor = CursorWrite::new(sblock);
+            let (key1, key2) = sblock.keys();
             HashMapWriteTxn {
                 work: cursor,
                 caller: self,
                 _guard: mguard,
-                key1: self.key1,
-                key2: self.key2,
+                key1,
+                key2,
+                changes: Changes::new(self.net_summary),
             }
         })
     }
 
-    fn commit(&self, newdata: SuperBlock<K, V>) {
+    fn commit(&self, newdata: SuperBlock<K, V>) -> Arc<SuperBlock<K, V>> {
         // println!("commit wr");
         let mut rwguard = self.active.lock();
         // Now we need to setup the sb pointers properly.
@@ -222,7 +429,8 @@ impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Sen
         }
 
         // Now push the new SB.
-        *rwguard = arc_newdata;
+        *rwguard = arc_newdata.clone();
+        arc_newdata
     }
 }
 
@@ -238,12 +446,37 @@ impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Sen
     }
 }
 
+impl<K: Hash + Eq + Clone + Debug + Sync + Send + 'static, V: Clone + Sync + Send + 'static>
+    IntoIterator for HashMap<K, V>
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Consume this handle of the map, yielding every entry in no particular order.
+    ///
+    /// If no other handle shares the current tree, the entries are moved out, and each
+    /// leaf is freed as soon as it is exhausted. Otherwise, the entries are cloned and
+    /// the map is unchanged for the others.
+    fn into_iter(self) -> IntoIter<K, V> {
+        let sblock = match Arc::try_unwrap(self.active) {
+            Ok(active) => active.into_inner(),
+            Err(active) => active.lock().clone(),
+        };
+        SuperBlock::consume(sblock)
+    }
+}
+
 impl<
         'a,
         K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
         V: Clone + Sync + Send + 'static,
     > Extend<(K, V)> for HashMapWriteTxn<'a, K, V>
 {
+    /// Insert each pair in turn. There is no parallel form of this, as the map is not
+    /// sharded: every node is reached from a single root, and a write transaction
+    /// holds the only cursor that may modify it, so the inserts can not be split
+    /// across threads. To populate a large map from many threads, partition the keys
+    /// over several maps instead.
     fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
         iter.into_iter().for_each(|(k, v)| {
             let _ = self.insert(k, v);
@@ -261,7 +494,8 @@ impl<
         self.work.get_txid()
     }
 
-    pub(crate) fn prehash<'b, Q: ?Sized>(&'a self, k: &'b Q) -> u64
+    /// Compute the hash of a key as this map would. See `HashMap::prehash`.
+    pub fn prehash<Q: ?Sized>(&self, k: &Q) -> u64
     where
         K: Borrow<Q>,
         Q: Hash + Eq,
@@ -277,6 +511,27 @@ impl<
         self.work.search(k_hash, k)
     }
 
+    /// Retrieve a value from the map with a hash previously computed by `prehash`,
+    /// avoiding the cost of hashing the key again.
+    ///
+    /// Keys are always compared, so an incorrect hash can never return the value of
+    /// another key. If the lookup misses, the hash is verified before `None` is returned,
+    /// so a miss still costs a hash of the key.
+    pub fn get_precomputed<'b, Q: ?Sized>(&'a self, k: &'b Q, k_hash: u64) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.get_prehashed(k, k_hash).or_else(|| {
+            let real_hash = hash_key!(k, self.key1, self.key2);
+            if real_hash != k_hash {
+                self.get_prehashed(k, real_hash)
+            } else {
+                None
+            }
+        })
+    }
+
     /// Retrieve a value from the map. If the value exists, a reference is returned
     /// as `Some(&V)`, otherwise if not present `None` is returned.
     pub fn get<'b, Q: ?Sized>(&'a self, k: &'b Q) -> Option<&'a V>
@@ -326,6 +581,12 @@ impl<
     /// change only takes effect once commited. Once cleared, you can begin adding
     /// new writes and changes, again, that will only be visible once commited.
     pub fn clear(&mut self) {
+        self.changes.counts.record_removed(self.work.len());
+        if self.changes.keys.is_some() {
+            for k in self.work.k_iter() {
+                self.changes.track(k, true);
+            }
+        }
         self.work.clear();
     }
 
@@ -334,14 +595,78 @@ impl<
     pub fn insert(&mut self, k: K, v: V) -> Option<V> {
         // Hash the key.
         let k_hash = hash_key!(k, self.key1, self.key2);
-        self.work.insert(k_hash, k, v)
+        let unseen = self.changes.unseen(&k);
+        let prev = self.work.insert(k_hash, k, v);
+        self.changes.inserted(unseen, prev.is_some());
+        prev
+    }
+
+    /// Retrieve the value of a key, inserting the result of `f` if the key is not
+    /// present. `f` is only called when the key is absent, and the key is only hashed
+    /// once. Unlike a `get_mut`, finding an existing value does not clone any nodes into
+    /// this transaction.
+    pub fn get_or_insert_with<F>(&mut self, k: K, f: F) -> &V
+    where
+        F: FnOnce() -> V,
+    {
+        let k_hash = hash_key!(k, self.key1, self.key2);
+        if let Some(v) = self.work.search(k_hash, &k) {
+            // As in get_or_insert_default_inner, rebind the reference to return it.
+            let v = v as *const V;
+            return unsafe { &*v };
+        }
+        self.changes.wrote(&k, false);
+        self.work.insert_ref(k_hash, k, f()).1
+    }
+
+    /// Retrieve a mutable reference to the value of a key, inserting `V::default()` if
+    /// the key is not present. As with `get_mut`, the value is cloned into this
+    /// transaction before it is returned. A present key is found in a single traversal
+    /// of the map, rather than the search and insert of a `get` followed by `insert`.
+    pub fn get_or_insert_default(&mut self, k: K) -> &mut V
+    where
+        V: Default,
+    {
+        self.get_or_insert_default_inner(k).0
+    }
+
+    /// Add `by` to the value of a key, treating a key that is not present as holding
+    /// `V::default()`. This suits counters, as a present key is updated in a single
+    /// traversal of the map. As with `get_mut`, the change is counted by the
+    /// `CommitSummary` as an update of a present key, or an insert of an absent one.
+    pub fn increment(&mut self, k: K, by: V)
+    where
+        V: AddAssign + Default,
+    {
+        let (v, _) = self.get_or_insert_default_inner(k);
+        *v += by;
+    }
+
+    fn get_or_insert_default_inner(&mut self, k: K) -> (&mut V, bool)
+    where
+        V: Default,
+    {
+        let k_hash = hash_key!(k, self.key1, self.key2);
+        if let Some(v) = self.work.get_mut_ref(k_hash, &k) {
+            self.changes.wrote(&k, true);
+            // The borrow checker can not see that the borrow ends here when the key is
+            // absent, so rebind the reference to return it.
+            let v = v as *mut V;
+            return (unsafe { &mut *v }, true);
+        }
+        self.changes.wrote(&k, false);
+        (self.work.insert_ref(k_hash, k, V::default()).1, false)
     }
 
     /// Remove a key if it exists in the tree. If the value exists, we return it as `Some(V)`,
     /// and if it did not exist, we return `None`
     pub fn remove(&mut self, k: &K) -> Option<V> {
         let k_hash = hash_key!(k, self.key1, self.key2);
-        self.work.remove(k_hash, k)
+        let prev = self.work.remove(k_hash, k);
+        if prev.is_some() {
+            self.changes.removed(k);
+        }
+        prev
     }
 
     /// Get a mutable reference to a value in the tree. This is correctly, and
@@ -349,7 +674,83 @@ impl<
     /// other transactions.
     pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
         let k_hash = hash_key!(k, self.key1, self.key2);
-        self.work.get_mut_ref(k_hash, k)
+        let v = self.work.get_mut_ref(k_hash, k)?;
+        self.changes.wrote(k, true);
+        Some(v)
+    }
+
+    /// Find the entry of a key by reference. The key is only converted to an owned
+    /// `K` if a value is inserted into a vacant entry, so finding an existing key never
+    /// clones it.
+    pub fn entry_ref<'q, Q>(&mut self, k: &'q Q) -> EntryRef<'_, 'q, K, V, Q>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let k_hash = hash_key!(k, self.key1, self.key2);
+        let work = &mut self.work;
+        if work.search(k_hash, k).is_some() {
+            EntryRef::Occupied(OccupiedEntryRef {
+                work,
+                changes: &mut self.changes,
+                k_hash,
+                k,
+            })
+        } else {
+            EntryRef::Vacant(VacantEntryRef {
+                work,
+                changes: &mut self.changes,
+                k_hash,
+                k,
+            })
+        }
+    }
+
+    /// Begin a lookup of an entry that can be found by a hash computed in advance, or by
+    /// a predicate over the keys of that hash, rather than by a key alone. This avoids
+    /// hashing a key more than once, and allows the key to be built only when a
+    /// vacant entry is filled. Hashes must be computed with `prehash`.
+    ///
+    /// As with `get_mut`, a value is only cloned into this transaction once it can be
+    /// mutated, so the changes are not visible to readers until this commits.
+    ///
+    /// # Examples
+    /// ```
+    /// use concread::hashmap::{HashMap, RawEntryMut};
+    ///
+    /// let map: HashMap<String, usize> = HashMap::new();
+    /// let mut wr = map.write();
+    /// let hash = wr.prehash("key");
+    /// match wr.raw_entry_mut().from_hash(hash, |k| k == "key") {
+    ///     RawEntryMut::Occupied(_) => unreachable!(),
+    ///     RawEntryMut::Vacant(e) => {
+    ///         // The owned key is only built here.
+    ///         e.insert_hashed_nocheck(hash, "key".to_string(), 1);
+    ///     }
+    /// }
+    /// assert_eq!(wr.get("key"), Some(&1));
+    /// ```
+    pub fn raw_entry_mut(&mut self) -> RawEntryBuilderMut<'_, K, V> {
+        RawEntryBuilderMut {
+            work: &mut self.work,
+            changes: &mut self.changes,
+            key1: self.key1,
+            key2: self.key2,
+        }
+    }
+
+    /// Iterator over `&mut V` of the set. Keys can not be mutated, as this would
+    /// break the hashing of the map. Values are cloned into this transaction as
+    /// they are reached, and changes are only visible to readers once commited.
+    pub fn values_mut(&mut self) -> ValueIterMut<K, V> {
+        // As with get_mut, each value is counted as updated by the commit summary.
+        self.changes.counts.updated += self.work.len() as u64;
+        if self.changes.keys.is_some() {
+            for k in self.work.k_iter() {
+                self.changes.track(k, true);
+            }
+        }
+        self.work.v_iter_mut()
     }
 
     /// This is *unsafe* because changing the key CAN and WILL break hashing, which can
@@ -360,6 +761,32 @@ impl<
         self.work.get_slot_mut_ref(k_hash)
     }
 
+    /// Replace the hasher of the map with one of fresh random keys, and rehash every
+    /// entry by it. Periodically reseeding a long lived map that holds untrusted keys
+    /// limits how long an attacker may exploit collisions that they have discovered.
+    /// This clones every entry into this transaction, so is `O(n)` in time and memory.
+    ///
+    /// Readers that began before this commits continue to use the previous hasher.
+    /// Hashes computed by `prehash` before the reseed no longer match the map, so
+    /// `get_precomputed` with such a hash must fall back to hashing the key, and maps
+    /// made by `HashMap::new_sharing_hasher` no longer share the hasher of this map.
+    pub fn reseed(&mut self) {
+        let entries: Vec<(K, V)> = self
+            .work
+            .kv_iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self.work.clear();
+        let (key1, key2) = random_keys();
+        self.key1 = key1;
+        self.key2 = key2;
+        self.work.set_keys((key1, key2));
+        entries.into_iter().for_each(|(k, v)| {
+            let k_hash = hash_key!(k, key1, key2);
+            self.work.insert(k_hash, k, v);
+        });
+    }
+
     /// Create a read-snapshot of the current map. This does NOT guarantee the map may
     /// not be mutated during the read, so you MUST guarantee that no functions of the
     /// write txn are called while this snapshot is active.
@@ -372,11 +799,37 @@ impl<
     }
 
     /// Commit the changes from this write transaction. Readers after this point
-    /// will be able to percieve these changes.
+    /// will be able to percieve these changes. Returns the counts of the inserts,
+    /// updates and removes this transaction made, as described by `CommitSummary`.
     ///
     /// To abort (unstage changes), just do not call this function.
-    pub fn commit(self) {
-        self.caller.commit(self.work.finalise())
+    pub fn commit(self) -> CommitSummary {
+        let changes = self.changes.summary(|k| self.contains_key(k));
+        self.caller.commit(self.work.finalise());
+        changes
+    }
+
+    /// Abandon the changes of this write transaction. This is equivalent to dropping
+    /// it without calling `commit()`: the committed map and its version are unchanged.
+    pub fn discard(self) {
+        drop(self)
+    }
+
+    /// Commit the changes from this write transaction, and begin a read transaction
+    /// of exactly the version that was committed. Unlike a `commit` followed by a
+    /// `read`, another writer can not commit in between, so the reader is guaranteed
+    /// to observe the changes of this transaction.
+    pub fn commit_and_read(self) -> HashMapReadTxn<'a, K, V> {
+        let caller = self.caller;
+        let pin = caller.commit(self.work.finalise());
+        let work = CursorRead::new(pin.as_ref());
+        HashMapReadTxn {
+            _caller: caller,
+            _pin: pin,
+            work,
+            key1: self.key1,
+            key2: self.key2,
+        }
     }
 }
 
@@ -390,7 +843,8 @@ impl<
         self.work.get_txid()
     }
 
-    pub(crate) fn prehash<'b, Q: ?Sized>(&'a self, k: &'b Q) -> u64
+    /// Compute the hash of a key as this map would. See `HashMap::prehash`.
+    pub fn prehash<'b, Q: ?Sized>(&'a self, k: &'b Q) -> u64
     where
         K: Borrow<Q>,
         Q: Hash + Eq,
@@ -406,6 +860,27 @@ impl<
         self.work.search(k_hash, k)
     }
 
+    /// Retrieve a value from the map with a hash previously computed by `prehash`,
+    /// avoiding the cost of hashing the key again.
+    ///
+    /// Keys are always compared, so an incorrect hash can never return the value of
+    /// another key. If the lookup misses, the hash is verified before `None` is returned,
+    /// so a miss still costs a hash of the key.
+    pub fn get_precomputed<'b, Q: ?Sized>(&'a self, k: &'b Q, k_hash: u64) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.get_prehashed(k, k_hash).or_else(|| {
+            let real_hash = hash_key!(k, self.key1, self.key2);
+            if real_hash != k_hash {
+                self.get_prehashed(k, real_hash)
+            } else {
+                None
+            }
+        })
+    }
+
     /// Retrieve a value from the tree. If the value exists, a reference is returned
     /// as `Some(&V)`, otherwise if not present `None` is returned.
     pub fn get<'b, Q: ?Sized>(&'a self, k: &'b Q) -> Option<&'a V>
@@ -426,7 +901,47 @@ impl<
         self.get(k).is_some()
     }
 
-    /// Returns the current number of k:v pairs in the tree
+    /// Retrieve the key as it is stored in the map, and its value. This allows an
+    /// equal key to be exchanged for the stored instance, such as when interning.
+    pub fn get_key_value<Q: ?Sized>(&'a self, k: &Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let k_hash = hash_key!(k, self.key1, self.key2);
+        self.work.search_kv(k_hash, k)
+    }
+
+    /// Determine if every key of `keys` is present in the map. This stops at the first
+    /// key that is not found. An empty set of keys is always present.
+    pub fn contains_all(&'a self, keys: &[K]) -> bool {
+        keys.iter().all(|k| self.contains_key(k))
+    }
+
+    /// The number of keys that are compared to resolve `k`, for diagnosing a hasher
+    /// that produces too many collisions. Keys with the same hash share a bucket that
+    /// is searched in order, so this is the position of `k` in its bucket, or the
+    /// length of the bucket if `k` is absent. A hash that no key shares always gives
+    /// one for a present key, and this is zero if no key has the hash of `k`. This is
+    /// only for introspection, and does not alter the map.
+    pub fn probe_length<Q: ?Sized>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let k_hash = hash_key!(k, self.key1, self.key2);
+        self.work.search_slot(k_hash).map_or(0, |bucket| {
+            bucket
+                .iter()
+                .position(|d| d.k.borrow() == k)
+                .map_or(bucket.len(), |idx| idx + 1)
+        })
+    }
+
+    /// Returns the number of k:v pairs in the snapshot of this transaction. The count
+    /// is recorded by each commit along with the root of the map, so it always matches
+    /// the content that this transaction iterates over, regardless of any writes that
+    /// commit after it began.
     pub fn len(&self) -> usize {
         self.work.len()
     }
@@ -436,6 +951,25 @@ impl<
         self.work.len() == 0
     }
 
+    /// Clone the content of this snapshot into a vector of pairs, in no particular
+    /// order. After this the read transaction can be dropped, releasing the snapshot,
+    /// in exchange for holding a full copy of the data.
+    pub fn to_owned_vec(&self) -> Vec<(K, V)> {
+        let mut v = Vec::with_capacity(self.len());
+        v.extend(self.iter().map(|(k, v)| (k.clone(), v.clone())));
+        v
+    }
+
+    /// Fold every pair of this snapshot into an accumulator, in no particular order.
+    /// This consumes the read transaction, so the snapshot is released as soon as the
+    /// fold completes, and only the result outlives it.
+    pub fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, &K, &V) -> B,
+    {
+        self.iter().fold(init, |acc, (k, v)| f(acc, k, v))
+    }
+
     /// Iterator over `(&K, &V)` of the set
     pub fn iter(&self) -> Iter<K, V> {
         self.work.kv_iter()
@@ -462,6 +996,19 @@ impl<
     }
 }
 
+impl<
+        'a,
+        K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+        V: Clone + Sync + Send + 'static,
+    > Versioned for HashMapReadTxn<'a, K, V>
+{
+    /// The transaction id of the map this transaction observes. This increases with
+    /// each commit.
+    fn version(&self) -> u64 {
+        self.work.get_txid()
+    }
+}
+
 impl<
         'a,
         K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
@@ -531,9 +1078,352 @@ impl<
     }
 }
 
+/// An entry of a `HashMapWriteTxn`, found by a borrowed key. See
+/// `HashMapWriteTxn::entry_ref`.
+pub enum EntryRef<'a, 'q, K, V, Q>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+    Q: ?Sized,
+{
+    /// The key is present in the map.
+    Occupied(OccupiedEntryRef<'a, 'q, K, V, Q>),
+    /// The key is not present in the map.
+    Vacant(VacantEntryRef<'a, 'q, K, V, Q>),
+}
+
+/// An entry of a key that is present in the map.
+pub struct OccupiedEntryRef<'a, 'q, K, V, Q>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+    Q: ?Sized,
+{
+    work: &'a mut CursorWrite<K, V>,
+    changes: &'a mut Changes<K>,
+    k_hash: u64,
+    k: &'q Q,
+}
+
+/// An entry of a key that is not present in the map.
+pub struct VacantEntryRef<'a, 'q, K, V, Q>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+    Q: ?Sized,
+{
+    work: &'a mut CursorWrite<K, V>,
+    changes: &'a mut Changes<K>,
+    k_hash: u64,
+    k: &'q Q,
+}
+
+impl<'a, 'q, K, V, Q> EntryRef<'a, 'q, K, V, Q>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static + Borrow<Q>,
+    V: Clone + Sync + Send + 'static,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+{
+    /// The key that this entry was found by.
+    pub fn key(&self) -> &'q Q {
+        match self {
+            EntryRef::Occupied(e) => e.k,
+            EntryRef::Vacant(e) => e.k,
+        }
+    }
+
+    /// Access the value of this entry, inserting `v` if it is vacant.
+    pub fn or_insert(self, v: V) -> &'a mut V {
+        self.or_insert_with(|| v)
+    }
+
+    /// Access the value of this entry, inserting the result of `f` if it is vacant.
+    /// `f` is only called when the entry is vacant.
+    pub fn or_insert_with<F>(self, f: F) -> &'a mut V
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            EntryRef::Occupied(e) => e.into_mut(),
+            EntryRef::Vacant(e) => e.insert(f()),
+        }
+    }
+}
+
+impl<'a, 'q, K, V, Q> OccupiedEntryRef<'a, 'q, K, V, Q>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static + Borrow<Q>,
+    V: Clone + Sync + Send + 'static,
+    Q: Hash + Eq + ?Sized,
+{
+    /// Access the value of this entry. This does not clone any nodes into the
+    /// transaction.
+    pub fn get(&self) -> &V {
+        self.work
+            .search(self.k_hash, self.k)
+            .expect("Occupied entry is missing from the map")
+    }
+
+    /// Access the value of this entry mutably. As with `HashMapWriteTxn::get_mut`,
+    /// the value is cloned into this transaction before it is returned.
+    pub fn into_mut(self) -> &'a mut V {
+        // The stored key is found with the value, so that the change can be noted
+        // without converting the key of the entry to an owned `K`.
+        let k = self.k;
+        let d = unsafe { self.work.get_slot_mut_ref(self.k_hash) }
+            .and_then(|slot| slot.iter_mut().find(|d| d.k.borrow() == k))
+            .expect("Occupied entry is missing from the map");
+        self.changes.wrote(&d.k, true);
+        &mut d.v
+    }
+}
+
+impl<'a, 'q, K, V, Q> VacantEntryRef<'a, 'q, K, V, Q>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static + Borrow<Q>,
+    V: Clone + Sync + Send + 'static,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+{
+    /// Insert a value into this entry. This is the only point where the key is
+    /// converted to an owned `K`.
+    pub fn insert(self, v: V) -> &'a mut V {
+        let k = self.k.to_owned();
+        self.changes.wrote(&k, false);
+        self.work.insert(self.k_hash, k, v);
+        self.work
+            .get_mut_ref(self.k_hash, self.k)
+            .expect("Inserted key is missing from the map")
+    }
+}
+
+/// A builder of a `RawEntryMut`. See `HashMapWriteTxn::raw_entry_mut`.
+pub struct RawEntryBuilderMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    work: &'a mut CursorWrite<K, V>,
+    changes: &'a mut Changes<K>,
+    key1: u128,
+    key2: u128,
+}
+
+/// An entry of a `HashMapWriteTxn`, found by a hash. See
+/// `HashMapWriteTxn::raw_entry_mut`.
+pub enum RawEntryMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    /// A matching key is present in the map.
+    Occupied(RawOccupiedEntryMut<'a, K, V>),
+    /// No matching key is present in the map.
+    Vacant(RawVacantEntryMut<'a, K, V>),
+}
+
+/// An entry of a key that is present in the map, found by a hash.
+pub struct RawOccupiedEntryMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    work: &'a mut CursorWrite<K, V>,
+    changes: &'a mut Changes<K>,
+    k_hash: u64,
+    // The position of the entry within the bucket of its hash.
+    idx: usize,
+}
+
+/// An entry of a key that is not present in the map, found by a hash.
+pub struct RawVacantEntryMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    work: &'a mut CursorWrite<K, V>,
+    changes: &'a mut Changes<K>,
+    key1: u128,
+    key2: u128,
+}
+
+impl<'a, K, V> RawEntryBuilderMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    /// Find the entry of a key, hashing it as the map would.
+    pub fn from_key<Q>(self, k: &Q) -> RawEntryMut<'a, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let k_hash = hash_key!(k, self.key1, self.key2);
+        self.from_key_hashed_nocheck(k_hash, k)
+    }
+
+    /// Find the entry of a key with a hash computed by `prehash`. The hash is not
+    /// checked against the key, so a hash of another key finds no entry.
+    pub fn from_key_hashed_nocheck<Q>(self, k_hash: u64, k: &Q) -> RawEntryMut<'a, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.from_hash(k_hash, |q| q.borrow() == k)
+    }
+
+    /// Find the entry of the first key of a hash that `is_match` accepts. The hash
+    /// must have been computed by `prehash`, and `is_match` must accept at most the
+    /// key that the hash was computed from, else the map may be left with keys that
+    /// can not be found.
+    pub fn from_hash<F>(self, k_hash: u64, mut is_match: F) -> RawEntryMut<'a, K, V>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let found = self
+            .work
+            .search_slot(k_hash)
+            .and_then(|slot| slot.iter().position(|d| is_match(&d.k)));
+        match found {
+            Some(idx) => RawEntryMut::Occupied(RawOccupiedEntryMut {
+                work: self.work,
+                changes: self.changes,
+                k_hash,
+                idx,
+            }),
+            None => RawEntryMut::Vacant(RawVacantEntryMut {
+                work: self.work,
+                changes: self.changes,
+                key1: self.key1,
+                key2: self.key2,
+            }),
+        }
+    }
+}
+
+impl<'a, K, V> RawEntryMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    /// Access the key and value of this entry, inserting the result of `f` if it is
+    /// vacant. `f` is only called when the entry is vacant.
+    pub fn or_insert_with<F>(self, f: F) -> (&'a K, &'a mut V)
+    where
+        F: FnOnce() -> (K, V),
+    {
+        match self {
+            RawEntryMut::Occupied(e) => e.into_key_value(),
+            RawEntryMut::Vacant(e) => {
+                let (k, v) = f();
+                e.insert(k, v)
+            }
+        }
+    }
+}
+
+impl<'a, K, V> RawOccupiedEntryMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    fn datum(&self) -> &Datum<K, V> {
+        &self
+            .work
+            .search_slot(self.k_hash)
+            .expect("Occupied entry is missing from the map")[self.idx]
+    }
+
+    fn datum_mut(work: &mut CursorWrite<K, V>, k_hash: u64, idx: usize) -> &mut Datum<K, V> {
+        // Only the value is ever changed, so the hashing of the key is not affected.
+        let slot = unsafe { work.get_slot_mut_ref(k_hash) };
+        &mut slot.expect("Occupied entry is missing from the map")[idx]
+    }
+
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        &self.datum().k
+    }
+
+    /// Access the value of this entry. This does not clone any nodes into the
+    /// transaction.
+    pub fn get(&self) -> &V {
+        &self.datum().v
+    }
+
+    /// Access the value of this entry mutably. As with `HashMapWriteTxn::get_mut`,
+    /// the value is cloned into this transaction before it is returned.
+    pub fn get_mut(&mut self) -> &mut V {
+        let d = Self::datum_mut(self.work, self.k_hash, self.idx);
+        self.changes.wrote(&d.k, true);
+        &mut d.v
+    }
+
+    /// Access the key and the mutable value of this entry, for the remaining lifetime
+    /// of the transaction borrow.
+    pub fn into_key_value(self) -> (&'a K, &'a mut V) {
+        let d = Self::datum_mut(self.work, self.k_hash, self.idx);
+        self.changes.wrote(&d.k, true);
+        (&d.k, &mut d.v)
+    }
+
+    /// Access the value of this entry mutably, for the remaining lifetime of the
+    /// transaction borrow.
+    pub fn into_mut(self) -> &'a mut V {
+        self.into_key_value().1
+    }
+
+    /// Replace the value of this entry, returning the previous value.
+    pub fn insert(&mut self, v: V) -> V {
+        std::mem::replace(self.get_mut(), v)
+    }
+
+    /// Remove this entry from the map, returning its key and value.
+    pub fn remove_entry(self) -> (K, V) {
+        let k = self.key().clone();
+        let v = self
+            .work
+            .remove(self.k_hash, &k)
+            .expect("Occupied entry is missing from the map");
+        self.changes.removed(&k);
+        (k, v)
+    }
+}
+
+impl<'a, K, V> RawVacantEntryMut<'a, K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + Sync + Send + 'static,
+{
+    /// Insert a key and value into this entry, hashing the key as the map would.
+    pub fn insert(self, k: K, v: V) -> (&'a K, &'a mut V) {
+        let k_hash = hash_key!(k, self.key1, self.key2);
+        self.insert_hashed_nocheck(k_hash, k, v)
+    }
+
+    /// Insert a key and value into this entry with a hash computed by `prehash`. The
+    /// hash is not checked against the key, and a key inserted by the wrong hash can
+    /// not be found. The key must not already be present in the map.
+    pub fn insert_hashed_nocheck(self, k_hash: u64, k: K, v: V) -> (&'a K, &'a mut V) {
+        self.changes.wrote(&k, false);
+        let prev = self.work.insert(k_hash, k, v);
+        assert!(
+            prev.is_none(),
+            "The key of a vacant entry is already present in the map"
+        );
+        // A new key is always the last of the bucket of its hash.
+        let slot = unsafe { self.work.get_slot_mut_ref(k_hash) };
+        let d = slot
+            .and_then(|slot| slot.last_mut())
+            .expect("Inserted key is missing from the map");
+        (&d.k, &mut d.v)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::HashMap;
+    use super::super::node::assert_released;
+    use super::{EntryRef, HashMap, HashMapBuilder, RawEntryMut};
+    use crate::{CommitSummary, Versioned};
 
     #[test]
     fn test_hashmap_basic_write() {
@@ -618,6 +1508,363 @@ mod tests {
         assert!(hmap_w1.iter().count() == 2);
     }
 
+    #[test]
+    fn test_hashmap_values_mut() {
+        let hmap: HashMap<usize, usize> = (0..100).map(|i| (i, i)).collect();
+        let hmap_r1 = hmap.read();
+
+        let mut hmap_w1 = hmap.write();
+        assert!(hmap_w1.values_mut().size_hint() == (100, Some(100)));
+        hmap_w1.values_mut().for_each(|v| *v += 1);
+        assert!((0..100).all(|i| hmap_w1.get(&i) == Some(&(i + 1))));
+        // The reader is not affected by the pending changes.
+        assert!((0..100).all(|i| hmap_r1.get(&i) == Some(&i)));
+        hmap_w1.commit();
+
+        assert!((0..100).all(|i| hmap_r1.get(&i) == Some(&i)));
+        let hmap_r2 = hmap.read();
+        assert!((0..100).all(|i| hmap_r2.get(&i) == Some(&(i + 1))));
+    }
+
+    #[test]
+    fn test_hashmap_get_precomputed() {
+        let hmap: HashMap<String, usize> = (0..512).map(|i| (format!("key-{}", i), i)).collect();
+        let other = hmap.new_sharing_hasher();
+        let mut wr = other.write();
+        wr.extend((256..768).map(|i| (format!("key-{}", i), i * 2)));
+
+        let rd = hmap.read();
+        for i in 0..1024 {
+            let k = format!("key-{}", i);
+            let h = hmap.prehash(k.as_str());
+            assert!(h == rd.prehash(&k) && h == wr.prehash(&k));
+            assert!(rd.get_precomputed(k.as_str(), h) == rd.get(&k));
+            // The same hash is reused with a map sharing the hasher.
+            assert!(wr.get_precomputed(k.as_str(), h) == wr.get(&k));
+            // An incorrect hash still finds the correct value.
+            assert!(rd.get_precomputed(k.as_str(), h ^ 1) == rd.get(&k));
+            assert!(rd.get_precomputed(k.as_str(), 0) == rd.get(&k));
+        }
+        wr.commit();
+    }
+
+    #[test]
+    fn test_hashmap_builder_seed() {
+        let build = |seed| {
+            let hmap: HashMap<String, usize> = HashMapBuilder::new().with_seed(seed).build();
+            let mut wr = hmap.write();
+            wr.extend((0..512).map(|i| (format!("key-{}", i), i)));
+            wr.commit();
+            hmap
+        };
+        let order = |hmap: &HashMap<String, usize>| -> Vec<usize> {
+            hmap.read().values().copied().collect()
+        };
+        let a = build(42);
+        let b = build(42);
+        assert!(order(&a) == order(&b));
+        assert!(a.prehash("key-1") == b.prehash("key-1"));
+        // A different seed hashes differently.
+        let c = build(43);
+        assert!(a.prehash("key-1") != c.prehash("key-1"));
+        assert!(order(&a) != order(&c));
+    }
+
+    #[test]
+    fn test_hashmap_entry_ref() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, Hash, PartialEq, Eq)]
+        struct Key(usize);
+        impl Clone for Key {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                Key(self.0)
+            }
+        }
+
+        let hmap: HashMap<Key, usize> = HashMap::new();
+        let mut wr = hmap.write();
+        // A vacant key is converted to an owned key exactly once when inserted.
+        CLONES.store(0, Ordering::SeqCst);
+        *wr.entry_ref(&Key(1)).or_insert(1) += 10;
+        assert!(CLONES.load(Ordering::SeqCst) == 1);
+        // The leaf now belongs to this transaction, so mutating again clones nothing,
+        // and f is not called.
+        let calls = std::cell::Cell::new(0);
+        *wr.entry_ref(&Key(1)).or_insert_with(|| {
+            calls.set(calls.get() + 1);
+            0
+        }) += 1;
+        assert!(calls.get() == 0);
+        assert!(CLONES.load(Ordering::SeqCst) == 1);
+        wr.commit();
+
+        // A present key is found without cloning it, or any node.
+        let mut wr = hmap.write();
+        CLONES.store(0, Ordering::SeqCst);
+        match wr.entry_ref(&Key(1)) {
+            EntryRef::Occupied(e) => assert!(*e.get() == 12),
+            EntryRef::Vacant(_) => panic!("Key should be occupied"),
+        }
+        assert!(wr.entry_ref(&Key(2)).key() == &Key(2));
+        assert!(CLONES.load(Ordering::SeqCst) == 0);
+        std::mem::drop(wr);
+
+        assert!(hmap.read().get(&Key(1)) == Some(&12));
+    }
+
+    #[test]
+    fn test_hashmap_to_owned_vec() {
+        let hmap: HashMap<usize, usize> = (0..256).map(|i| (i, i * 2)).collect();
+        let rd = hmap.read();
+        let mut owned = rd.to_owned_vec();
+        assert!(owned.len() == rd.len());
+        assert!(owned.iter().all(|(k, v)| rd.get(k) == Some(v)));
+        std::mem::drop(rd);
+
+        let mut wr = hmap.write();
+        wr.clear();
+        wr.commit();
+        owned.sort_unstable();
+        assert!(owned == (0..256).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_hashmap_into_iter() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq)]
+        struct Counted(usize);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Counted(self.0)
+            }
+        }
+
+        let count = 1024;
+        let collect = |hmap: HashMap<usize, Counted>| {
+            let mut seen: Vec<_> = hmap.into_iter().map(|(k, v)| (k, v.0)).collect();
+            seen.sort_unstable();
+            seen
+        };
+        let expect: Vec<_> = (0..count).map(|i| (i, i)).collect();
+
+        // A map that is not shared is moved out of.
+        let hmap: HashMap<usize, Counted> = (0..count).map(|i| (i, Counted(i))).collect();
+        let before = CLONES.load(Ordering::Relaxed);
+        assert!(hmap.clone().into_iter().len() == count);
+        assert!(CLONES.load(Ordering::Relaxed) == before);
+        assert!(collect(hmap) == expect);
+        assert!(CLONES.load(Ordering::Relaxed) == before);
+
+        // Another handle keeps the tree, so it is cloned from.
+        let hmap: HashMap<usize, Counted> = (0..count).map(|i| (i, Counted(i))).collect();
+        let before = CLONES.load(Ordering::Relaxed);
+        assert!(collect(hmap.clone()) == expect);
+        assert!(CLONES.load(Ordering::Relaxed) == before + count);
+        assert!(hmap.read().len() == count);
+
+        // Dropping a partially consumed iterator releases the rest of the tree.
+        let mut iter = hmap.into_iter();
+        assert!(iter.nth(count / 2).is_some());
+        assert!(iter.len() == count / 2 - 1);
+        std::mem::drop(iter);
+        assert_released();
+    }
+
+    #[test]
+    fn test_hashmap_fold() {
+        let hmap: HashMap<usize, usize> = (0..256).map(|i| (i, i * 2)).collect();
+        let sum = hmap.read().fold(0, |acc, _, v| acc + v);
+        assert!(sum == (0..256).map(|i| i * 2).sum::<usize>());
+        let count = hmap
+            .read()
+            .fold(0, |acc, k, v| acc + (*v == k * 2) as usize);
+        assert!(count == 256);
+        // Neither fold holds the snapshot any longer.
+        assert!(std::sync::Arc::strong_count(&*hmap.active.lock()) == 1);
+    }
+
+    #[test]
+    fn test_hashmap_probe_length() {
+        // Every key of this type has the same hash.
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        struct Colliding(usize);
+
+        impl std::hash::Hash for Colliding {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                0.hash(state)
+            }
+        }
+
+        let good: HashMap<usize, usize> = (0..64).map(|i| (i, i)).collect();
+        let bad: HashMap<Colliding, usize> = (0..64).map(|i| (Colliding(i), i)).collect();
+        let (grd, brd) = (good.read(), bad.read());
+        assert!((0..64).all(|i| grd.probe_length(&i) == 1));
+        assert!(grd.probe_length(&64) <= 1);
+        assert!(HashMap::<usize, usize>::new().read().probe_length(&0) == 0);
+
+        let lengths: Vec<usize> = (0..64).map(|i| brd.probe_length(&Colliding(i))).collect();
+        assert!(lengths.iter().sum::<usize>() == (1..=64).sum::<usize>());
+        assert!(lengths.iter().max() == Some(&64));
+        assert!(brd.probe_length(&Colliding(64)) == 64);
+        assert!(brd.get(&Colliding(63)) == Some(&63));
+    }
+
+    #[test]
+    fn test_hashmap_write_panic() {
+        let hmap: HashMap<usize, usize> = (0..256).map(|i| (i, i)).collect();
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut wr = hmap.write();
+            for i in 0..256 {
+                wr.insert(i, i + 1);
+            }
+            wr.remove(&0);
+            panic!("Panic during a write");
+        }));
+        assert!(r.is_err());
+        // The uncommitted changes were discarded, and the lock was released.
+        let mut wr = hmap.try_write().expect("The write lock was not released");
+        assert!(wr.len() == 256);
+        assert!(wr.iter().all(|(k, v)| k == v));
+        wr.insert(256, 256);
+        wr.commit();
+        assert!(hmap.read().len() == 257);
+    }
+
+    #[test]
+    fn test_hashmap_get_key_value() {
+        let hmap: HashMap<String, usize> = HashMap::new();
+        let mut wr = hmap.write();
+        let mut stored = Vec::new();
+        for i in 0..256 {
+            let k = format!("key{:04}", i);
+            // Moving the key does not move its buffer.
+            stored.push(k.as_ptr());
+            wr.insert(k, i);
+        }
+        wr.commit();
+
+        let rd = hmap.read();
+        for (i, ptr) in stored.iter().enumerate() {
+            let k = format!("key{:04}", i);
+            let (sk, v) = rd.get_key_value(k.as_str()).unwrap();
+            assert!(*sk == k && *v == i);
+            // The stored key is returned, rather than the one that was searched for.
+            assert!(sk.as_ptr() == *ptr);
+            assert!(sk.as_ptr() != k.as_ptr());
+        }
+        assert!(rd.get_key_value("missing").is_none());
+    }
+
+    #[test]
+    fn test_hashmap_version() {
+        let hmap: HashMap<usize, usize> = HashMap::new();
+        let other: HashMap<usize, usize> = HashMap::new();
+        let rd = hmap.read();
+        let mut prev = rd.version();
+        for i in 0..4 {
+            let mut wr = hmap.write();
+            wr.insert(i, i);
+            wr.commit();
+            let version = hmap.read().version();
+            assert!(version > prev);
+            prev = version;
+        }
+        // Existing readers, and other maps, are unaffected.
+        assert!(rd.version() < prev);
+        assert!(other.read().version() == rd.version());
+    }
+
+    #[test]
+    fn test_hashmap_get_or_insert_with() {
+        let hmap: HashMap<usize, usize> = (0..8).map(|i| (i, i)).collect();
+        let mut calls = 0;
+        let mut wr = hmap.write();
+        assert!(
+            *wr.get_or_insert_with(1, || {
+                calls += 1;
+                100
+            }) == 1
+        );
+        assert!(calls == 0);
+        for k in 8..256 {
+            assert!(
+                *wr.get_or_insert_with(k, || {
+                    calls += 1;
+                    k * 2
+                }) == k * 2
+            );
+        }
+        assert!(calls == 248);
+        assert!(*wr.get_or_insert_with(8, || 0) == 16);
+        assert!(wr.len() == 256);
+        wr.commit();
+        assert!(hmap.read().get(&255) == Some(&510));
+
+        // The value is returned from where the insert placed it, rather than by a second
+        // search, however the leaves split.
+        let mut wr = hmap.write();
+        for k in 256..4096 {
+            let v = wr.get_or_insert_with(k, || k) as *const usize;
+            assert!(unsafe { *v } == k);
+            assert!(std::ptr::eq(v, wr.get(&k).unwrap()));
+        }
+        assert!(wr.len() == 4096);
+    }
+
+    #[test]
+    fn test_hashmap_clone_handle() {
+        let hmap: HashMap<usize, usize> = HashMap::new();
+        let handle = hmap.clone();
+
+        let mut wr = handle.write();
+        wr.insert(1, 1);
+        wr.commit();
+        assert!(hmap.read().get(&1) == Some(&1));
+
+        let mut wr = hmap.write();
+        wr.insert(2, 2);
+        wr.commit();
+        assert!(handle.read().get(&2) == Some(&2));
+        assert!(handle.read().len() == 2);
+    }
+
+    #[test]
+    fn test_hashmap_commit_and_read() {
+        let map: HashMap<usize, usize> = HashMap::new();
+        let mut wr = map.write();
+        wr.insert(1, 1);
+        let rd = wr.commit_and_read();
+
+        // A competing writer commits after, but the reader stays on our version.
+        let mut wr = map.write();
+        wr.insert(1, 2);
+        wr.insert(2, 2);
+        wr.commit();
+
+        assert!(rd.get(&1) == Some(&1));
+        assert!(rd.get(&2).is_none());
+        assert!(rd.len() == 1);
+        assert!(map.read().get(&1) == Some(&2));
+    }
+
+    #[test]
+    fn test_hashmap_contains_all() {
+        let hmap: HashMap<usize, usize> = (0..64).map(|i| (i * 2, i)).collect();
+        let rd = hmap.read();
+        let sets: [&[usize]; 5] = [&[], &[4, 0, 126, 4], &[0, 2, 3], &[1, 3, 5], &[128]];
+        for keys in sets.iter() {
+            let expect = keys.iter().all(|k| k % 2 == 0 && *k < 128);
+            assert!(rd.contains_all(keys) == expect);
+        }
+    }
+
     #[test]
     fn test_hashmap_from_iter() {
         let hmap: HashMap<usize, usize> = vec![(10, 10), (15, 15), (20, 20)].into_iter().collect();
@@ -626,4 +1873,282 @@ mod tests {
         assert!(hmap_r2.contains_key(&15));
         assert!(hmap_r2.contains_key(&20));
     }
+
+    #[test]
+    fn test_hashmap_len_snapshot() {
+        let hmap: HashMap<usize, usize> = (0..256).map(|i| (i, i)).collect();
+        // Hold each snapshot across the commits of a later writer.
+        let mut readers = Vec::new();
+        for i in 0..64 {
+            readers.push(hmap.read());
+            let mut wr = hmap.write();
+            wr.insert(256 + i, i);
+            wr.remove(&(i * 2));
+            wr.remove(&(i * 2 + 1));
+            wr.commit();
+            for rd in readers.iter() {
+                assert!(rd.len() == rd.iter().count());
+            }
+        }
+        assert!(readers[0].len() == 256);
+        assert!(readers[63].len() == 256 - 63);
+        assert!(hmap.read().len() == 256 - 64);
+    }
+
+    #[test]
+    fn test_hashmap_reseed() {
+        let hmap: HashMap<usize, usize> = (0..256).map(|i| (i, i)).collect();
+        let before = hmap.prehash(&1);
+        let rd = hmap.read();
+
+        let mut wr = hmap.write();
+        wr.reseed();
+        assert!(wr.prehash(&1) != before);
+        assert!(wr.len() == 256);
+        assert!((0..256).all(|i| wr.get(&i) == Some(&i)));
+        wr.insert(256, 256);
+        wr.commit();
+
+        // The earlier reader is unaffected, and a stale hash still finds its key.
+        assert!(rd.prehash(&1) == before);
+        assert!((0..256).all(|i| rd.get(&i) == Some(&i)));
+        let rd = hmap.read();
+        assert!(hmap.prehash(&1) == rd.prehash(&1));
+        assert!(rd.prehash(&1) != before);
+        assert!((0..257).all(|i| rd.get(&i) == Some(&i)));
+        assert!(rd.get_precomputed(&1, before) == Some(&1));
+        assert!(rd.len() == rd.iter().count());
+    }
+
+    #[test]
+    fn test_hashmap_increment() {
+        let hmap: HashMap<usize, u64> = HashMap::new();
+        let mut wr = hmap.write();
+        assert!(*wr.get_or_insert_default(1) == 0);
+        *wr.get_or_insert_default(1) += 5;
+        wr.commit();
+
+        let rd = hmap.read();
+        let mut wr = hmap.write();
+        for i in 0..1000 {
+            wr.increment(i % 10, 1);
+        }
+        assert!(wr.get(&1) == Some(&105));
+        assert!(wr.get(&2) == Some(&100));
+        // The values were cloned into the transaction, so nothing is visible until commit.
+        assert!(rd.get(&1) == Some(&5));
+        assert!(hmap.read().get(&2).is_none());
+        // Each increment is counted, as an insert of an absent key or an update.
+        let summary = wr.commit();
+        assert!(summary.inserted == 9 && summary.updated == 991);
+
+        assert!(rd.get(&1) == Some(&5));
+        let rd = hmap.read();
+        assert!(rd.get(&1) == Some(&105));
+        assert!((0..10)
+            .filter(|i| *i != 1)
+            .all(|i| rd.get(&i) == Some(&100)));
+    }
+
+    #[test]
+    fn test_hashmap_commit_summary() {
+        let hmap: HashMap<usize, usize> = HashMap::new();
+        let mut wr = hmap.write();
+        wr.extend((0..10).map(|k| (k, k)));
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 10,
+                    updated: 0,
+                    removed: 0,
+                }
+        );
+
+        let mut wr = hmap.write();
+        // Updates and removes of present keys are distinguished from no-ops.
+        wr.insert(0, 1);
+        wr.insert(10, 10);
+        assert!(wr.remove(&1).is_some());
+        assert!(wr.remove(&100).is_none());
+        wr.get_or_insert_with(2, || 0);
+        wr.get_or_insert_with(11, || 11);
+        *wr.entry_ref(&3).or_insert(0) += 1;
+        *wr.entry_ref(&12).or_insert(0) += 1;
+        *wr.get_mut(&4).unwrap() += 1;
+        // Reseeding moves every entry, but changes none of them.
+        wr.reseed();
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 3,
+                    updated: 3,
+                    removed: 1,
+                }
+        );
+
+        // Each operation is counted from its result, however often the key changed.
+        let changes = |wr: &mut super::HashMapWriteTxn<usize, usize>| {
+            wr.insert(100, 100);
+            wr.remove(&100);
+            wr.remove(&5);
+            wr.insert(5, 50);
+            for i in 0..4 {
+                wr.insert(6, i);
+                wr.increment(7, 1);
+            }
+        };
+        let mut wr = hmap.write();
+        changes(&mut wr);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 2,
+                    updated: 8,
+                    removed: 2,
+                }
+        );
+
+        let mut wr = hmap.write();
+        let len = wr.len() as u64;
+        wr.clear();
+        wr.insert(0, 0);
+        wr.insert(100, 100);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 2,
+                    updated: 0,
+                    removed: len,
+                }
+        );
+
+        // With net counts, an insert that is removed again cancels, a removed key that
+        // is inserted again is an update, and a key changed many times is counted once.
+        let net_map: HashMap<usize, usize> = HashMapBuilder::new().net_commit_summary().build();
+        let mut wr = net_map.write();
+        wr.extend((0..10).map(|k| (k, k)));
+        wr.commit();
+        let mut wr = net_map.write();
+        changes(&mut wr);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 0,
+                    updated: 3,
+                    removed: 0,
+                }
+        );
+
+        let mut wr = net_map.write();
+        let len = wr.len() as u64;
+        wr.clear();
+        wr.insert(0, 0);
+        wr.insert(100, 100);
+        assert!(
+            wr.commit()
+                == CommitSummary {
+                    inserted: 1,
+                    updated: 1,
+                    removed: len - 1,
+                }
+        );
+        // A clone shares the setting of the map.
+        let net_clone = net_map.clone();
+        let mut wr = net_clone.write();
+        wr.insert(1, 1);
+        wr.remove(&1);
+        assert!(wr.commit() == CommitSummary::default());
+    }
+
+    #[test]
+    fn test_hashmap_discard() {
+        let hmap: HashMap<usize, usize> = HashMap::new();
+        let mut wr = hmap.write();
+        wr.extend((0..10).map(|k| (k, k)));
+        wr.commit();
+        let version = hmap.read().version();
+
+        let mut wr = hmap.write();
+        wr.extend((10..100).map(|k| (k, k)));
+        wr.remove(&0);
+        drop(wr);
+        let rd = hmap.read();
+        assert!(rd.len() == 10 && rd.get(&0) == Some(&0));
+        assert!(rd.version() == version);
+        drop(rd);
+
+        let mut wr = hmap.write();
+        wr.extend((10..100).map(|k| (k, k)));
+        wr.remove(&0);
+        wr.discard();
+        let rd = hmap.read();
+        assert!(rd.len() == 10 && rd.get(&0) == Some(&0));
+        assert!(rd.version() == version);
+        drop(rd);
+        drop(hmap);
+        assert_released();
+    }
+
+    #[test]
+    fn test_hashmap_raw_entry() {
+        let hmap: HashMap<String, usize> = HashMap::new();
+        let mut wr = hmap.write();
+        wr.insert("a".to_string(), 1);
+        wr.commit();
+
+        let rd = hmap.read();
+        let mut wr = hmap.write();
+        // An occupied entry is modified in place, only within this transaction.
+        match wr.raw_entry_mut().from_key("a") {
+            RawEntryMut::Occupied(mut e) => {
+                assert!(e.key() == "a" && *e.get() == 1);
+                *e.get_mut() += 10;
+                assert!(e.insert(20) == 11);
+            }
+            RawEntryMut::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert!(wr.get("a") == Some(&20));
+        assert!(rd.get("a") == Some(&1));
+
+        // A vacant entry found by a hash is filled with a key built only then.
+        let hash = wr.prehash("b");
+        let mut built = 0;
+        for _ in 0..2 {
+            let (k, v) = wr
+                .raw_entry_mut()
+                .from_hash(hash, |k| k == "b")
+                .or_insert_with(|| {
+                    built += 1;
+                    ("b".to_string(), 2)
+                });
+            assert!(k == "b");
+            *v += 1;
+        }
+        assert!(built == 1);
+        // A hash computed by the map matches that of the transaction.
+        let c_hash = hmap.prehash("c");
+        let (k, v) = match wr.raw_entry_mut().from_hash(c_hash, |k| k == "c") {
+            RawEntryMut::Vacant(e) => e.insert_hashed_nocheck(c_hash, "c".to_string(), 3),
+            RawEntryMut::Occupied(_) => panic!("Entry should be vacant"),
+        };
+        assert!(k == "c" && *v == 3);
+        assert!(wr.get("b") == Some(&4) && wr.get("c") == Some(&3));
+
+        // An entry of a hash that no key matches is vacant.
+        assert!(matches!(
+            wr.raw_entry_mut().from_hash(hash, |k| k == "c"),
+            RawEntryMut::Vacant(_)
+        ));
+        match wr.raw_entry_mut().from_key_hashed_nocheck(hash, "b") {
+            RawEntryMut::Occupied(e) => assert!(e.remove_entry() == ("b".to_string(), 4)),
+            RawEntryMut::Vacant(_) => panic!("Entry should be occupied"),
+        }
+        assert!(wr.get("b").is_none() && wr.len() == 2);
+        // Each access of a value is counted, as is the key that was inserted and then
+        // removed.
+        let summary = wr.commit();
+        assert!(summary.inserted == 2 && summary.updated == 3 && summary.removed == 1);
+        assert!(rd.get("a") == Some(&1) && rd.len() == 1);
+        assert!(hmap.read().get("a") == Some(&20));
+    }
 }
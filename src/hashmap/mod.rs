@@ -26,4 +26,8 @@ mod node;
 mod simd;
 mod states;
 
-pub use self::map::{HashMap, HashMapReadSnapshot, HashMapReadTxn, HashMapWriteTxn};
+pub use self::map::{
+    EntryRef, HashMap, HashMapBuilder, HashMapReadSnapshot, HashMapReadTxn, HashMapWriteTxn,
+    OccupiedEntryRef, RawEntryBuilderMut, RawEntryMut, RawOccupiedEntryMut, RawVacantEntryMut,
+    VacantEntryRef,
+};
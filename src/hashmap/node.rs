@@ -578,6 +578,21 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
             })
     }
 
+    pub(crate) fn get_kv_ref<Q: ?Sized>(&self, h: u64, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq,
+    {
+        debug_assert_leaf!(self);
+        leaf_simd_search(self, h, k)
+            .ok()
+            .map(|(slot_idx, bk_idx)| unsafe {
+                let bucket = (*self.values[slot_idx].as_ptr()).as_slice();
+                let datum = bucket.get_unchecked(bk_idx);
+                (&datum.k, &datum.v)
+            })
+    }
+
     pub(crate) fn get_mut_ref<Q: ?Sized>(&mut self, h: u64, k: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
@@ -605,6 +620,12 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
             .map(|slot_idx| (*self.values[slot_idx].as_mut_ptr()).as_mut_slice())
     }
 
+    pub(crate) fn get_slot_ref(&self, h: u64) -> Option<&[Datum<K, V>]> {
+        debug_assert_leaf!(self);
+        leaf_simd_get_slot(self, h)
+            .map(|slot_idx| unsafe { (*self.values[slot_idx].as_ptr()).as_slice() })
+    }
+
     #[inline(always)]
     pub(crate) fn get_kv_idx_checked(&self, slot_idx: usize, bk_idx: usize) -> Option<(&K, &V)> {
         debug_assert_leaf!(self);
@@ -616,6 +637,11 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
         }
     }
 
+    pub(crate) fn slot_hashes(&self) -> &[u64] {
+        debug_assert_leaf!(self);
+        &self.key[..self.slots()]
+    }
+
     pub(crate) fn min(&self) -> u64 {
         debug_assert!(self.slots() > 0);
         self.key[0]
@@ -678,23 +704,43 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
         }
     }
 
-    pub(crate) fn insert_or_update(&mut self, h: u64, k: K, mut v: V) -> LeafInsertState<K, V> {
+    #[cfg(test)]
+    pub(crate) fn insert_or_update(&mut self, h: u64, k: K, v: V) -> LeafInsertState<K, V> {
+        let mut slot = ptr::null_mut();
+        self.insert_or_update_slot(h, k, v, &mut slot)
+    }
+
+    // As insert_or_update, where slot is set to the location of the value in this or
+    // the new leaf. Leaves never move their values once the insert is complete, so this
+    // remains valid until the leaf is next changed.
+    pub(crate) fn insert_or_update_slot(
+        &mut self,
+        h: u64,
+        k: K,
+        mut v: V,
+        slot: &mut *mut V,
+    ) -> LeafInsertState<K, V> {
         debug_assert_leaf!(self);
         // Find the location we need to update
         let r = leaf_simd_search(self, h, &k);
         match r {
             KeyLoc::Ok(slot_idx, bk_idx) => {
                 // It exists at idx, replace the value.
-                let bucket = unsafe { &mut (*self.values[slot_idx].as_mut_ptr()) };
+                let bucket = unsafe { &mut *self.values[slot_idx].as_mut_ptr() };
                 let prev = unsafe { bucket.as_mut_slice().get_unchecked_mut(bk_idx) };
                 std::mem::swap(&mut prev.v, &mut v);
+                *slot = &mut prev.v;
                 // Prev now contains the original value, return it!
                 LeafInsertState::Ok(Some(v))
             }
             KeyLoc::Collision(slot_idx) => {
                 // The hash collided, but that's okay! We just append to the slice.
-                let bucket = unsafe { &mut (*self.values[slot_idx].as_mut_ptr()) };
+                let bucket = unsafe { &mut *self.values[slot_idx].as_mut_ptr() };
                 bucket.push(Datum { k, v });
+                *slot = &mut bucket
+                    .last_mut()
+                    .expect("A bucket can not be empty after a push")
+                    .v;
                 LeafInsertState::Ok(None)
             }
             KeyLoc::Missing(idx) => {
@@ -703,11 +749,17 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
                     if idx >= self.slots() {
                         // Greate than all else, split right
                         let rnode = Node::new_leaf_ins(self.meta.0, h, k, v);
+                        let nref = unsafe { &mut *rnode };
+                        let bucket = unsafe { &mut *nref.values[0].as_mut_ptr() };
+                        *slot = &mut bucket[0].v;
                         LeafInsertState::Split(rnode)
                     } else if idx == 0 {
                         // Lower than all else, split left.
                         // let lnode = ...;
                         let lnode = Node::new_leaf_ins(self.meta.0, h, k, v);
+                        let nref = unsafe { &mut *lnode };
+                        let bucket = unsafe { &mut *nref.values[0].as_mut_ptr() };
+                        *slot = &mut bucket[0].v;
                         LeafInsertState::RevSplit(lnode)
                     } else {
                         // Within our range, pop max, insert, and split right.
@@ -726,6 +778,8 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
                                 idx,
                             );
                         }
+                        let bucket = unsafe { &mut *self.values[idx].as_mut_ptr() };
+                        *slot = &mut bucket[0].v;
 
                         #[cfg(all(test, not(miri)))]
                         debug_assert!(self.poison == FLAG_POISON);
@@ -743,6 +797,8 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
                             idx,
                         );
                     }
+                    let bucket = unsafe { &mut *self.values[idx].as_mut_ptr() };
+                    *slot = &mut bucket[0].v;
                     #[cfg(all(test, not(miri)))]
                     debug_assert!(self.poison == FLAG_POISON);
                     self.inc_slots();
@@ -888,6 +944,28 @@ impl<K: Hash + Eq + Clone + Debug, V: Clone> Leaf<K, V> {
         debug_assert!(self.verify());
     }
 
+    // Move every entry out of this leaf, and free it. The leaf must not be reachable
+    // from any other tree.
+    pub(crate) fn take_all(node: *mut Self, out: &mut Vec<(K, V)>) {
+        let leaf = unsafe { &mut *node };
+        debug_assert_leaf!(leaf);
+        for idx in 0..leaf.slots() {
+            let bucket = unsafe { ptr::read(leaf.values[idx].as_ptr()) };
+            out.extend(bucket.into_iter().map(|d| (d.k, d.v)));
+        }
+        // The buckets were moved out, so they must not be dropped with the leaf.
+        leaf.meta.set_slots(0);
+        Self::free(node)
+    }
+
+    pub(crate) fn clone_all(&self, out: &mut Vec<(K, V)>) {
+        debug_assert_leaf!(self);
+        for idx in 0..self.slots() {
+            let bucket = unsafe { &*self.values[idx].as_ptr() };
+            out.extend(bucket.iter().map(|d| (d.k.clone(), d.v.clone())));
+        }
+    }
+
     pub(crate) fn verify(&self) -> bool {
         debug_assert_leaf!(self);
         #[cfg(all(test, not(miri)))]
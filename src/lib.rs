@@ -22,6 +22,22 @@
 //!
 //! In the future, a concurrent BTree and HashTree will be added, that can be used inplace
 //! of a `RwLock<BTreeMap>` or `RwLock<HashMap>`. Stay tuned!
+//!
+//! # Panics in write transactions
+//!
+//! A panic while a write transaction is held drops the transaction, as any other
+//! unwind would. Its uncommitted changes are discarded and the last committed value
+//! remains. Unlike a `std::sync::Mutex`, the lock of the writer is not poisoned, and
+//! the next writer proceeds as normal.
+//!
+//! # Single threaded targets
+//!
+//! With the `single_threaded` feature, `BptreeMap` and `CowCell` are built on cells
+//! rather than locks, for targets without threads such as `wasm32-unknown-unknown`.
+//! They are then neither `Send` nor `Sync`, and none of the concurrency guarantees
+//! apply. A second write transaction panics rather than waiting for the first, as does
+//! `CowCell::read_at_least` for a version not yet committed. The other structures are
+//! unchanged by this feature.
 
 #![deny(warnings)]
 #![warn(unused_extern_crates)]
@@ -39,13 +55,20 @@ extern crate smallvec;
 #[cfg(feature = "simd_support")]
 extern crate packed_simd;
 
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
 // This is where the gud rust lives.
 mod utils;
 
+mod lock;
+
 // pub mod hpcell;
 pub mod cowcell;
 pub mod ebrcell;
 
+pub mod covec;
+
 pub mod arcache;
 pub mod bptree;
 pub mod hashmap;
@@ -59,3 +82,122 @@ mod lincowcell;
 
 pub use cowcell::CowCell;
 pub use ebrcell::EbrCell;
+
+/// A read transaction that observes a particular version of a structure.
+///
+/// Each structure keeps its own counter, which increases monotonically as writes are
+/// committed to it, so two read transactions of the same structure can be compared to
+/// determine which observes the later version. Versions of different structures are
+/// independent, and are not comparable to each other.
+///
+/// The counter of a cell never wraps, so a version is never repeated by a later commit.
+/// A commit that would exhaust the counter panics, leaving the cell unchanged, however
+/// at a billion commits per second this takes more than five hundred years.
+///
+/// This is implemented by the read transactions of `CowCell`, `EbrCell`, `BptreeMap`
+/// and `HashMap`. The trait is object safe, however the read transactions of the maps
+/// borrow from the map, so a boxed trait object of one is bound by that lifetime.
+///
+/// # Examples
+/// ```
+/// use concread::bptree::BptreeMap;
+/// use concread::{CowCell, EbrCell, Versioned};
+///
+/// let cowcell = CowCell::new(0);
+/// let ebrcell = EbrCell::new(0);
+/// let map: BptreeMap<usize, usize> = BptreeMap::new();
+///
+/// let before: Vec<Box<dyn Versioned + '_>> = vec![
+///     Box::new(cowcell.read()),
+///     Box::new(ebrcell.read()),
+///     Box::new(map.read()),
+/// ];
+///
+/// let mut write_txn = cowcell.write();
+/// *write_txn = 1;
+/// write_txn.commit();
+///
+/// let after: Vec<Box<dyn Versioned + '_>> = vec![
+///     Box::new(cowcell.read()),
+///     Box::new(ebrcell.read()),
+///     Box::new(map.read()),
+/// ];
+/// // Only the cell that was written to has advanced.
+/// assert!(after[0].version() > before[0].version());
+/// assert!(after[1].version() == before[1].version());
+/// assert!(after[2].version() == before[2].version());
+/// ```
+pub trait Versioned {
+    /// The version of the structure that this read transaction observes.
+    fn version(&self) -> u64;
+}
+
+/// The changes made by a write transaction of a `BptreeMap` or `HashMap`, as returned
+/// by its `commit`.
+///
+/// By default each operation is counted from its result: an insert of an absent key
+/// is `inserted`, an insert over a present key is `updated`, and each key that a
+/// remove, `clear` or similar operation takes out of the map is `removed`. A key
+/// changed many times is counted each time, so an insert of a key that the same
+/// transaction later removes counts as both. Operations that change nothing, such as
+/// removing an absent key, are not counted. A value accessed mutably, such as through
+/// `get_mut`, counts as updated, as the transaction can not tell whether it was
+/// changed.
+///
+/// A map built with `net_commit_summary` instead counts the net change of each key,
+/// from the committed map to the map this transaction commits: a key that was absent
+/// and is now present is `inserted`, one that was present and is now absent is
+/// `removed`, and one that is present in both and was written by the transaction is
+/// `updated`. Each key is then counted once however many times it was changed, so an
+/// insert of a key that is later removed is not counted at all, and a removed key that
+/// is inserted again is an update. To do so the transaction keeps a copy of every key
+/// that it changes until it commits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitSummary {
+    /// The number of keys that were inserted.
+    pub inserted: u64,
+    /// The number of present keys whose value was replaced.
+    pub updated: u64,
+    /// The number of present keys that were removed.
+    pub removed: u64,
+}
+
+impl CommitSummary {
+    pub(crate) fn record_insert(&mut self, replaced: bool) {
+        if replaced {
+            self.updated += 1;
+        } else {
+            self.inserted += 1;
+        }
+    }
+
+    pub(crate) fn record_removed(&mut self, count: usize) {
+        self.removed += count as u64;
+    }
+
+    // An extend of `count` pairs that grew the map from `before` to `after` entries.
+    pub(crate) fn record_extend(&mut self, count: usize, before: usize, after: usize) {
+        let inserted = after - before;
+        self.inserted += inserted as u64;
+        self.updated += (count - inserted) as u64;
+    }
+
+    // The summary of the keys a transaction changed, given whether each was present
+    // before the transaction first changed it, and whether it is `present` now.
+    pub(crate) fn from_changes<'k, K: 'k, I, F>(changed: I, present: F) -> Self
+    where
+        I: IntoIterator<Item = (&'k K, &'k bool)>,
+        F: Fn(&K) -> bool,
+    {
+        let mut summary = CommitSummary::default();
+        for (k, before) in changed {
+            match (*before, present(k)) {
+                (false, true) => summary.inserted += 1,
+                (true, true) => summary.updated += 1,
+                (true, false) => summary.removed += 1,
+                (false, false) => {}
+            }
+        }
+        summary
+    }
+}
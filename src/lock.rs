@@ -0,0 +1,63 @@
+//! The locks that `BptreeMap` and `CowCell` are built on. By default these are those of
+//! parking_lot. With the `single_threaded` feature they are replaced by cells, for
+//! targets such as `wasm32-unknown-unknown` where threads are not available.
+
+#[cfg(not(feature = "single_threaded"))]
+pub(crate) use parking_lot::{Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "single_threaded")]
+pub(crate) use self::cell::{Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "single_threaded")]
+mod cell {
+    use std::cell::{RefCell, RefMut};
+
+    pub(crate) type MutexGuard<'a, T> = RefMut<'a, T>;
+
+    // A lock of a single thread. Where a mutex would block until it is released, the
+    // holder can only be this thread, so that would never return, and lock panics
+    // instead.
+    #[derive(Debug)]
+    pub(crate) struct Mutex<T>(RefCell<T>);
+
+    impl<T> Mutex<T> {
+        pub(crate) fn new(t: T) -> Self {
+            Mutex(RefCell::new(t))
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            self.0
+                .try_borrow_mut()
+                .expect("The lock is already held by this thread, and would never be released")
+        }
+
+        pub(crate) fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+            self.0.try_borrow_mut().ok()
+        }
+
+        pub(crate) fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut()
+        }
+
+        pub(crate) fn into_inner(self) -> T {
+            self.0.into_inner()
+        }
+    }
+
+    // Without other threads there is nothing to wait for, so a wait panics rather than
+    // never returning.
+    #[derive(Debug)]
+    pub(crate) struct Condvar;
+
+    impl Condvar {
+        pub(crate) fn new() -> Self {
+            Condvar
+        }
+
+        pub(crate) fn wait<T>(&self, _guard: &mut MutexGuard<'_, T>) {
+            panic!("A wait can not be notified without another thread");
+        }
+
+        pub(crate) fn notify_all(&self) {}
+    }
+}
@@ -3,6 +3,76 @@ use std::cmp::Ordering;
 // use std::mem::MaybeUninit;
 use std::ptr;
 
+// The version that follows `v`. Versions order the commits of a cell, so they must not
+// wrap around and repeat an earlier version, and nor may they saturate and repeat the
+// last. See `Versioned`.
+pub(crate) fn next_version(v: u64) -> u64 {
+    v.checked_add(1).expect("The version counter is exhausted")
+}
+
+// Check that the readers and the writer of a cell do not wait for each other, so that
+// neither starves the other however the threads are scheduled. `write` begins a write
+// transaction that is held and then dropped, and `update` commits a change, so that
+// the version increases by one. Rather than a measure of progress over a period of
+// time, each side must complete with the other held, so a cell where one waits for
+// the other deadlocks this check.
+#[cfg(test)]
+pub(crate) fn assert_no_starvation<R, W, FR, FW, FU>(read: FR, write: FW, update: FU)
+where
+    R: crate::Versioned,
+    FR: Fn() -> R + Sync,
+    FW: Fn() -> W,
+    FU: Fn() + Sync,
+{
+    use crossbeam_utils::thread::scope;
+
+    const READERS: usize = 8;
+    const ROUNDS: u64 = 100;
+    let start = read().version();
+
+    // Readers complete while the writer is held, and observe no change.
+    let held = write();
+    scope(|scope| {
+        for _ in 0..READERS {
+            scope.spawn(|_| {
+                for _ in 0..ROUNDS {
+                    assert!(read().version() == start);
+                }
+            });
+        }
+    })
+    .unwrap();
+    std::mem::drop(held);
+
+    // The writer commits while readers are held, and they observe no change.
+    let held: Vec<R> = (0..READERS).map(|_| read()).collect();
+    scope(|scope| {
+        scope.spawn(|_| (0..ROUNDS).for_each(|_| update()));
+    })
+    .unwrap();
+    assert!(held.iter().all(|r| r.version() == start));
+    assert!(read().version() == start + ROUNDS);
+    std::mem::drop(held);
+
+    // Under contention, each reader observes the versions in order, and continues
+    // until it observes the last.
+    let last = start + 2 * ROUNDS;
+    scope(|scope| {
+        for _ in 0..READERS {
+            scope.spawn(|_| {
+                let mut prev = start;
+                while prev != last {
+                    let v = read().version();
+                    assert!(v >= prev);
+                    prev = v;
+                }
+            });
+        }
+        scope.spawn(|_| (0..ROUNDS).for_each(|_| update()));
+    })
+    .unwrap();
+}
+
 pub(crate) unsafe fn slice_insert<T>(slice: &mut [T], new: T, idx: usize) {
     ptr::copy(
         slice.as_ptr().add(idx),
@@ -80,3 +150,11 @@ where
     }
     Err(slice.len())
 }
+
+pub(crate) fn slice_search_binary<K, Q: ?Sized>(slice: &[K], k: &Q) -> Result<usize, usize>
+where
+    K: Borrow<Q>,
+    Q: Ord,
+{
+    slice.binary_search_by(|nk| nk.borrow().cmp(k))
+}
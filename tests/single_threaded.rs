@@ -0,0 +1,62 @@
+// The API of BptreeMap and CowCell with the single_threaded feature, where their
+// locks are cells. The rest of the test suite uses threads, so this is run alone:
+//
+//     cargo test --features single_threaded --test single_threaded
+#![cfg(feature = "single_threaded")]
+
+extern crate concread;
+
+use concread::bptree::BptreeMap;
+use concread::cowcell::CowCell;
+
+#[test]
+fn test_single_threaded_bptree() {
+    let map: BptreeMap<usize, usize> = BptreeMap::new();
+    let mut wr = map.write();
+    for k in 0..100 {
+        wr.insert(k, k);
+    }
+    // Only one writer may be held, so another can not begin.
+    assert!(map.try_write().is_none());
+    wr.commit();
+
+    let rd = map.read();
+    let mut wr = map.write();
+    wr.remove(&0);
+    wr.insert(100, 100);
+    wr.commit();
+    // A reader still sees the version it began with.
+    assert!(rd.len() == 100 && rd.get(&0) == Some(&0));
+    let rd = map.read();
+    assert!(rd.len() == 100 && rd.get(&0).is_none());
+    assert!(rd.iter().map(|(k, _)| *k).eq(1..101));
+}
+
+#[test]
+#[should_panic]
+fn test_single_threaded_bptree_second_write() {
+    let map: BptreeMap<usize, usize> = BptreeMap::new();
+    let _wr = map.write();
+    // Without other threads, waiting for the first writer would never return.
+    let _wr2 = map.write();
+}
+
+#[test]
+fn test_single_threaded_cowcell() {
+    let cell = CowCell::new(0);
+    let rd = cell.read();
+    let mut wr = cell.write();
+    *wr.get_mut() += 1;
+    assert!(cell.try_write().is_none());
+    let token = wr.commit();
+    assert!(*rd == 0);
+    assert!(*cell.read() == 1);
+    // A version that is already committed is read without waiting.
+    assert!(*cell.read_at_least(&token) == 1);
+
+    // A merge while a writer is held is applied by the next commit.
+    let wr = cell.write();
+    cell.merge(2, |v, d| *v += d);
+    wr.commit();
+    assert!(*cell.read() == 3);
+}